@@ -0,0 +1,139 @@
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{Page, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+use crate::interrupts::{InterruptIndex, PICS, PIC_1_MASK_WITHOUT_TIMER};
+
+// IA32_APIC_BASE model-specific register and its flags
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const APIC_BASE_ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+// Local APIC register offsets
+const REG_EOI: usize = 0xB0;
+const REG_SPURIOUS: usize = 0xF0;
+const REG_LVT_TIMER: usize = 0x320;
+const REG_TIMER_INITIAL: usize = 0x380;
+const REG_TIMER_CURRENT: usize = 0x390;
+const REG_TIMER_DIVIDE: usize = 0x3E0;
+
+// Spurious vector register: software enable bit
+const SPURIOUS_ENABLE: u32 = 1 << 8;
+// LVT timer: masked, and periodic instead of one-shot
+const LVT_MASKED: u32 = 1 << 16;
+const LVT_PERIODIC: u32 = 1 << 17;
+// Timer divide configuration value for divide-by-16
+const DIVIDE_BY_16: u32 = 0b0011;
+
+// Length of the calibration window in milliseconds
+const CALIBRATION_MS: u64 = 10;
+
+// Virtual address of the local APIC registers, 0 until mapped
+static LAPIC_BASE: AtomicU64 = AtomicU64::new(0);
+// Whether the APIC timer has replaced the PIT as the tick source
+static TIMER_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Maps and enables the local APIC and makes its timer the tick source in
+/// place of the PIT, which is masked.
+///
+/// The timer is calibrated against the TSC (`time::init` must have run) and
+/// programmed to tick at the PIT's rate, so tick-based timeouts keep their
+/// meaning. On error the PIT is left running.
+pub fn init() -> Result<(), &'static str> {
+    if !crate::cpu::features().apic {
+        return Err("No local APIC");
+    }
+    
+    let mut base_msr = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { base_msr.read() };
+    if base & APIC_BASE_X2APIC != 0 {
+        return Err("Local APIC is in x2APIC mode");
+    }
+    
+    map_registers(PhysAddr::new(base & APIC_BASE_ADDR_MASK))?;
+    
+    unsafe {
+        base_msr.write(base | APIC_BASE_ENABLE);
+    }
+    write(REG_SPURIOUS, SPURIOUS_ENABLE | InterruptIndex::ApicSpurious.as_u8() as u32);
+    
+    let counts_per_ms = calibrate();
+    let counts_per_tick = counts_per_ms * crate::time::TICK_NS / 1_000_000;
+    if counts_per_tick == 0 || counts_per_tick > u32::MAX as u64 {
+        return Err("APIC timer calibration failed");
+    }
+    
+    // Hand the tick over: start the APIC timer, then silence the PIT
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        write(REG_TIMER_DIVIDE, DIVIDE_BY_16);
+        write(REG_LVT_TIMER, LVT_PERIODIC | InterruptIndex::ApicTimer.as_u8() as u32);
+        write(REG_TIMER_INITIAL, counts_per_tick as u32);
+        unsafe {
+            PICS.lock().write_masks(PIC_1_MASK_WITHOUT_TIMER, 0xFF);
+        }
+        TIMER_ACTIVE.store(true, Ordering::SeqCst);
+    });
+    
+    Ok(())
+}
+
+/// Returns true once the APIC timer drives the tick count.
+pub fn timer_active() -> bool {
+    TIMER_ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Signals the end of an interrupt delivered by the local APIC.
+pub fn end_of_interrupt() {
+    write(REG_EOI, 0);
+}
+
+// Map the register page into the reserve arena as device memory
+fn map_registers(phys: PhysAddr) -> Result<(), &'static str> {
+    let mut mapper = crate::memory::MAPPER.lock();
+    let mut frame_allocator = crate::memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().ok_or("Mapper not installed")?;
+    let frame_allocator = frame_allocator.as_mut().ok_or("Frame allocator not installed")?;
+    
+    let virt = crate::memory::reserve(mapper, 1)?;
+    crate::memory::map_device(
+        mapper,
+        frame_allocator,
+        Page::containing_address(virt),
+        PhysFrame::containing_address(phys),
+    )?;
+    
+    let offset = phys.as_u64() & 0xFFF;
+    LAPIC_BASE.store(virt.as_u64() + offset, Ordering::SeqCst);
+    Ok(())
+}
+
+// Count how far the timer runs down (divided by 16) per millisecond of TSC time
+fn calibrate() -> u64 {
+    write(REG_TIMER_DIVIDE, DIVIDE_BY_16);
+    write(REG_LVT_TIMER, LVT_MASKED);
+    write(REG_TIMER_INITIAL, u32::MAX);
+    
+    let deadline = crate::time::monotonic_ns() + CALIBRATION_MS * 1_000_000;
+    while crate::time::monotonic_ns() < deadline {
+        core::hint::spin_loop();
+    }
+    
+    let elapsed = u32::MAX - read(REG_TIMER_CURRENT);
+    write(REG_TIMER_INITIAL, 0);
+    elapsed as u64 / CALIBRATION_MS
+}
+
+fn register(offset: usize) -> *mut u32 {
+    let base = LAPIC_BASE.load(Ordering::SeqCst);
+    debug_assert!(base != 0, "local APIC not mapped");
+    VirtAddr::new(base + offset as u64).as_mut_ptr()
+}
+
+fn read(offset: usize) -> u32 {
+    unsafe { register(offset).read_volatile() }
+}
+
+fn write(offset: usize, value: u32) {
+    unsafe { register(offset).write_volatile(value) }
+}
@@ -0,0 +1,52 @@
+use core::arch::asm;
+use core::fmt::Write;
+use crate::panic::{emergency_write, StackBuffer};
+
+/// Maximum number of frames walked before giving up.
+pub const MAX_DEPTH: usize = 32;
+
+/// Walks the frame-pointer (RBP) chain starting at the caller's frame.
+///
+/// `f` is called with the depth and return address of each frame. The walk
+/// stops at a null or misaligned RBP, when the chain stops moving up the
+/// stack, or after `MAX_DEPTH` frames. Returns the number of frames visited.
+#[inline(never)]
+pub fn walk(mut f: impl FnMut(usize, u64)) -> usize {
+    let mut rbp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+
+    let mut depth = 0;
+    while depth < MAX_DEPTH && rbp != 0 && rbp % 8 == 0 {
+        // Frame layout: [rbp] = caller's rbp, [rbp + 8] = return address
+        let frame = rbp as *const u64;
+        let (next_rbp, return_address) = unsafe { (*frame, *frame.add(1)) };
+
+        if return_address == 0 {
+            break;
+        }
+        f(depth, return_address);
+        depth += 1;
+
+        // The stack grows down, so callers' frames must be at higher addresses
+        if next_rbp <= rbp {
+            break;
+        }
+        rbp = next_rbp;
+    }
+
+    depth
+}
+
+/// Prints the current call chain's return addresses over serial.
+///
+/// Uses the allocation-free COM1 path so it is safe to call from a panic.
+pub fn unwind() {
+    emergency_write(b"Backtrace:\n");
+    walk(|depth, return_address| {
+        let mut line = StackBuffer::<48>::new();
+        let _ = writeln!(line, "  #{:<2} {:#018x}", depth, return_address);
+        emergency_write(line.as_bytes());
+    });
+}
@@ -0,0 +1,130 @@
+/// A fixed-length bit set over borrowed storage.
+///
+/// Bit `i` lives in byte `i / 8` at bit position `i % 8`. Indices at or past
+/// `len` are ignored by `set`/`clear` and read back as clear.
+pub struct Bitmap<'a> {
+    bits: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Bitmap<'a> {
+    /// Wraps `bits` as a bitmap of `len` bits, capped to the storage size.
+    pub fn new(bits: &'a mut [u8], len: usize) -> Self {
+        let len = len.min(bits.len() * 8);
+        Bitmap { bits, len }
+    }
+    
+    /// Returns the number of bits in the bitmap.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    
+    /// Returns true if the bitmap has no bits.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    
+    /// Sets bit `index`.
+    pub fn set(&mut self, index: usize) {
+        if index < self.len {
+            self.bits[index / 8] |= 1 << (index % 8);
+        }
+    }
+    
+    /// Clears bit `index`.
+    pub fn clear(&mut self, index: usize) {
+        if index < self.len {
+            self.bits[index / 8] &= !(1 << (index % 8));
+        }
+    }
+    
+    /// Returns whether bit `index` is set.
+    pub fn get(&self, index: usize) -> bool {
+        index < self.len && self.bits[index / 8] & (1 << (index % 8)) != 0
+    }
+    
+    /// Returns the index of the first clear bit.
+    pub fn find_first_clear(&self) -> Option<usize> {
+        let mut index = 0;
+        while index < self.len {
+            // Skip over whole bytes that are fully set
+            if index % 8 == 0 && self.bits[index / 8] == 0xFF {
+                index += 8;
+                continue;
+            }
+            if !self.get(index) {
+                return Some(index);
+            }
+            index += 1;
+        }
+        None
+    }
+    
+    /// Returns the start of the first run of `count` consecutive clear bits.
+    pub fn find_first_clear_run(&self, count: usize) -> Option<usize> {
+        if count == 0 {
+            return Some(0);
+        }
+        
+        let mut run_start = 0;
+        let mut run_length = 0;
+        for index in 0..self.len {
+            if self.get(index) {
+                run_start = index + 1;
+                run_length = 0;
+                continue;
+            }
+            run_length += 1;
+            if run_length == count {
+                return Some(run_start);
+            }
+        }
+        None
+    }
+}
+
+#[test_case]
+fn test_bitmap_byte_boundary() {
+    let mut bits = [0u8; 2];
+    let mut bitmap = Bitmap::new(&mut bits, 16);
+    
+    bitmap.set(7);
+    bitmap.set(8);
+    assert!(bitmap.get(7));
+    assert!(bitmap.get(8));
+    assert!(!bitmap.get(6));
+    assert!(!bitmap.get(9));
+    
+    bitmap.clear(7);
+    assert!(!bitmap.get(7));
+    assert!(bitmap.get(8));
+    assert_eq!(bits, [0x00, 0x01]);
+}
+
+#[test_case]
+fn test_bitmap_find_first_clear() {
+    let mut bits = [0xFF, 0x03];
+    let mut bitmap = Bitmap::new(&mut bits, 16);
+    assert_eq!(bitmap.find_first_clear(), Some(10));
+    
+    bitmap.clear(3);
+    assert_eq!(bitmap.find_first_clear(), Some(3));
+    
+    // Bits past `len` never count as free
+    let mut full = [0xFF, 0x0F];
+    let bitmap = Bitmap::new(&mut full, 12);
+    assert_eq!(bitmap.find_first_clear(), None);
+}
+
+#[test_case]
+fn test_bitmap_find_first_clear_run() {
+    // Clear bits: 2..4, then 6..11 (crossing the byte boundary)
+    let mut bits = [0b1100_0011, 0b1111_1000];
+    let bitmap = Bitmap::new(&mut bits, 16);
+    
+    assert_eq!(bitmap.find_first_clear_run(1), Some(2));
+    assert_eq!(bitmap.find_first_clear_run(2), Some(2));
+    assert_eq!(bitmap.find_first_clear_run(3), Some(6));
+    assert_eq!(bitmap.find_first_clear_run(5), Some(6));
+    assert_eq!(bitmap.find_first_clear_run(6), None);
+}
@@ -0,0 +1,116 @@
+use spin::Mutex;
+use crate::task::yield_task;
+use crate::vga_buffer::{Writer, WRITER};
+use x86_64::instructions::interrupts;
+
+// Capacity of the pending input queue
+const INPUT_BUFFER_SIZE: usize = 256;
+
+// ASCII control characters handled by the line editor
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+// Fixed-size FIFO of input bytes, filled by input drivers
+struct InputBuffer {
+    bytes: [u8; INPUT_BUFFER_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl InputBuffer {
+    const fn new() -> Self {
+        InputBuffer {
+            bytes: [0; INPUT_BUFFER_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+    
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == INPUT_BUFFER_SIZE {
+            return false;
+        }
+        self.bytes[(self.head + self.len) % INPUT_BUFFER_SIZE] = byte;
+        self.len += 1;
+        true
+    }
+    
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.bytes[self.head];
+        self.head = (self.head + 1) % INPUT_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+// Does not allocate, so input drivers may call in from interrupt handlers.
+// Locked with interrupts off on both sides, so a handler never finds it held.
+static INPUT: Mutex<InputBuffer> = Mutex::new(InputBuffer::new());
+
+/// Queues a byte of input for `read_line`. Returns false if the queue is full.
+pub fn push_input(byte: u8) -> bool {
+    interrupts::without_interrupts(|| INPUT.lock().push(byte))
+}
+
+// Take the next input byte, if any
+fn pop_input() -> Option<u8> {
+    interrupts::without_interrupts(|| INPUT.lock().pop())
+}
+
+// Update the screen with interrupts off, like `print!`
+fn echo(f: impl FnOnce(&mut Writer)) {
+    interrupts::without_interrupts(|| f(&mut WRITER.lock()));
+}
+
+/// Reads a line of input into `buf`, echoing it to the screen.
+///
+/// Backspace erases the last character; Enter ends the line and is not
+/// stored. Input beyond the buffer's length is dropped. Yields to other
+/// tasks while waiting. Returns the number of bytes stored.
+pub fn read_line(buf: &mut [u8]) -> usize {
+    let mut len = 0;
+    
+    loop {
+        let byte = match pop_input() {
+            Some(byte) => byte,
+            None => {
+                yield_task();
+                continue;
+            }
+        };
+        
+        match byte {
+            b'\n' | b'\r' => {
+                echo(|writer| writer.write_byte(b'\n'));
+                return len;
+            }
+            BACKSPACE | DELETE => {
+                if len > 0 {
+                    len -= 1;
+                    echo(|writer| writer.backspace());
+                }
+            }
+            byte => {
+                if len < buf.len() {
+                    buf[len] = byte;
+                    len += 1;
+                    echo(|writer| writer.write_byte(byte));
+                }
+            }
+        }
+    }
+}
+
+#[test_case]
+fn test_read_line_backspace() {
+    for &byte in b"ab\x08c\n" {
+        assert!(push_input(byte));
+    }
+    
+    let mut buf = [0u8; 16];
+    let len = read_line(&mut buf);
+    assert_eq!(&buf[..len], b"ac");
+}
@@ -0,0 +1,22 @@
+use core::arch::x86_64::__cpuid;
+
+/// Processor features the kernel cares about, as reported by CPUID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features {
+    /// On-chip local APIC
+    pub apic: bool,
+    /// Local APIC can run in x2APIC (MSR) mode
+    pub x2apic: bool,
+    /// Time-stamp counter (`rdtsc`)
+    pub tsc: bool,
+}
+
+/// Queries CPUID leaf 1 for the features in `Features`.
+pub fn features() -> Features {
+    let leaf1 = unsafe { __cpuid(1) };
+    Features {
+        apic: leaf1.edx & (1 << 9) != 0,
+        x2apic: leaf1.ecx & (1 << 21) != 0,
+        tsc: leaf1.edx & (1 << 4) != 0,
+    }
+}
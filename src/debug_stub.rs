@@ -0,0 +1,349 @@
+use core::arch::global_asm;
+use core::sync::atomic::{AtomicBool, Ordering};
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::structures::paging::Translate;
+use x86_64::VirtAddr;
+use core::fmt::Write;
+use crate::io::serial::COM2_BASE;
+use crate::panic::{write_registers, RegisterDump, StackBuffer};
+
+// Largest packet accepted from the debugger
+const MAX_PACKET: usize = 256;
+// Largest memory read answered in one `m` packet
+const MAX_READ: usize = 256;
+// Reply buffer size, enough for a `g` reply or a full `m` read in hex
+const MAX_REPLY: usize = 2 * MAX_READ + 16;
+
+lazy_static! {
+    // COM2, kept free for the debugger so it doesn't mix with kernel output on COM1
+    static ref COM2: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM2_BASE) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+// Whether a breakpoint hands control to the debugger on COM2
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Registers saved by the most recent breakpoint
+static LAST_BREAKPOINT: Mutex<Option<RegisterDump>> = Mutex::new(None);
+
+/// Makes breakpoints (`int3`) stop and wait for GDB on COM2 when `true`.
+///
+/// With the stub disabled a breakpoint prints its registers over serial and
+/// continues; they are also kept for `last_breakpoint`. Connect with
+/// `target remote` to QEMU's second serial port, e.g.
+/// `-serial stdio -serial tcp::1234,server`.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Returns the registers saved by the most recent breakpoint, if any.
+pub fn last_breakpoint() -> Option<RegisterDump> {
+    *LAST_BREAKPOINT.lock()
+}
+
+/// Address of the breakpoint exception entry point for the IDT.
+pub fn breakpoint_entry_addr() -> VirtAddr {
+    VirtAddr::new(debug_stub_breakpoint_entry as usize as u64)
+}
+
+unsafe extern "C" {
+    fn debug_stub_breakpoint_entry();
+}
+
+// Breakpoint entry point. The `x86-interrupt` ABI only exposes the interrupt
+// stack frame, so this saves every general-purpose register into a
+// `RegisterDump` on the stack (plus the SSE state the Rust handler may
+// clobber), and writes RIP and RFLAGS back from it so the debugger can change
+// where and how execution continues.
+global_asm!(
+    ".global debug_stub_breakpoint_entry",
+    "debug_stub_breakpoint_entry:",
+    // RegisterDump at [rsp], padded to keep the stack 16-byte aligned
+    "sub rsp, 0x98",
+    "mov [rsp + 0x00], rax",
+    "mov [rsp + 0x08], rbx",
+    "mov [rsp + 0x10], rcx",
+    "mov [rsp + 0x18], rdx",
+    "mov [rsp + 0x20], rsi",
+    "mov [rsp + 0x28], rdi",
+    "mov [rsp + 0x30], rbp",
+    "mov [rsp + 0x40], r8",
+    "mov [rsp + 0x48], r9",
+    "mov [rsp + 0x50], r10",
+    "mov [rsp + 0x58], r11",
+    "mov [rsp + 0x60], r12",
+    "mov [rsp + 0x68], r13",
+    "mov [rsp + 0x70], r14",
+    "mov [rsp + 0x78], r15",
+    
+    // RIP, RFLAGS and RSP come from the interrupt stack frame above the dump
+    "mov rax, [rsp + 0x98]",
+    "mov [rsp + 0x80], rax",
+    "mov rax, [rsp + 0xA8]",
+    "mov [rsp + 0x88], rax",
+    "mov rax, [rsp + 0xB0]",
+    "mov [rsp + 0x38], rax",
+    
+    "mov rdi, rsp",
+    "sub rsp, 512",
+    "fxsave [rsp]",
+    "cld",
+    "call {handler}",
+    "fxrstor [rsp]",
+    "add rsp, 512",
+    
+    "mov rax, [rsp + 0x80]",
+    "mov [rsp + 0x98], rax",
+    "mov rax, [rsp + 0x88]",
+    "mov [rsp + 0xA8], rax",
+    
+    "mov rax, [rsp + 0x00]",
+    "mov rbx, [rsp + 0x08]",
+    "mov rcx, [rsp + 0x10]",
+    "mov rdx, [rsp + 0x18]",
+    "mov rsi, [rsp + 0x20]",
+    "mov rdi, [rsp + 0x28]",
+    "mov rbp, [rsp + 0x30]",
+    "mov r8, [rsp + 0x40]",
+    "mov r9, [rsp + 0x48]",
+    "mov r10, [rsp + 0x50]",
+    "mov r11, [rsp + 0x58]",
+    "mov r12, [rsp + 0x60]",
+    "mov r13, [rsp + 0x68]",
+    "mov r14, [rsp + 0x70]",
+    "mov r15, [rsp + 0x78]",
+    "add rsp, 0x98",
+    "iretq",
+    handler = sym breakpoint_handler,
+);
+
+extern "C" fn breakpoint_handler(regs: &mut RegisterDump) {
+    crate::interrupts::record(crate::interrupts::BREAKPOINT_VECTOR);
+    *LAST_BREAKPOINT.lock() = Some(*regs);
+    
+    if ENABLED.load(Ordering::SeqCst) {
+        let mut port = COM2.lock();
+        session(&mut port, regs);
+    } else {
+        // No debugger: report where we are and carry on
+        let mut report = StackBuffer::<512>::new();
+        let _ = writeln!(report, "EXCEPTION: BREAKPOINT at {:#x}", regs.rip);
+        let _ = write_registers(&mut report, regs);
+        crate::serial_print!("{}", report.as_str());
+    }
+}
+
+// Talk to GDB until it tells us to continue
+fn session(port: &mut SerialPort, regs: &mut RegisterDump) {
+    // Report the stop right away, GDB may already be waiting
+    write_packet(port, b"S05");
+    
+    let mut packet = [0u8; MAX_PACKET];
+    loop {
+        let len = read_packet(port, &mut packet);
+        let mut reply = Reply::new();
+        if handle_packet(&packet[..len], regs, &mut reply) {
+            return;
+        }
+        write_packet(port, reply.as_bytes());
+    }
+}
+
+// Handle one packet, returning true when execution should resume
+fn handle_packet(packet: &[u8], regs: &mut RegisterDump, reply: &mut Reply) -> bool {
+    match packet.first() {
+        Some(b'?') => reply.push_bytes(b"S05"),
+        Some(b'g') => reply_registers(regs, reply),
+        Some(b'm') => read_memory(&packet[1..], reply),
+        Some(b'c') => {
+            // An optional address to resume at
+            if let Some(addr) = parse_hex(&packet[1..]) {
+                regs.rip = addr;
+            }
+            return true;
+        }
+        // Unsupported packets get an empty reply
+        _ => {}
+    }
+    false
+}
+
+// Registers in the order GDB's amd64 target expects: 16 general-purpose
+// registers and RIP as 64 bits, then EFLAGS and six segment selectors as 32
+fn reply_registers(regs: &RegisterDump, reply: &mut Reply) {
+    let gprs = [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+        regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15,
+        regs.rip,
+    ];
+    for value in gprs {
+        reply.push_hex(&value.to_le_bytes());
+    }
+    
+    use x86_64::registers::segmentation::{Segment, CS, SS};
+    let segments = [
+        regs.rflags as u32,
+        CS::get_reg().0 as u32,
+        SS::get_reg().0 as u32,
+        0, 0, 0, 0, // DS, ES, FS, GS
+    ];
+    for value in segments {
+        reply.push_hex(&value.to_le_bytes());
+    }
+}
+
+// `m addr,length`: read memory, refusing pages that aren't mapped
+fn read_memory(args: &[u8], reply: &mut Reply) {
+    let mut parts = args.splitn(2, |&c| c == b',');
+    let (addr, len) = match (parts.next().and_then(parse_hex), parts.next().and_then(parse_hex)) {
+        (Some(addr), Some(len)) => (addr, (len as usize).min(MAX_READ)),
+        _ => return reply.push_bytes(b"E01"),
+    };
+    
+    if !is_readable(addr, len) {
+        return reply.push_bytes(b"E14");
+    }
+    
+    let bytes = unsafe { core::slice::from_raw_parts(addr as *const u8, len) };
+    reply.push_hex(bytes);
+}
+
+// Whether every page of `addr..addr + len` is mapped in the kernel page table
+fn is_readable(addr: u64, len: usize) -> bool {
+    let end = match addr.checked_add(len as u64) {
+        Some(end) => end,
+        None => return false,
+    };
+    if VirtAddr::try_new(addr).is_err() || VirtAddr::try_new(end.saturating_sub(1)).is_err() {
+        return false;
+    }
+    
+    // The interrupted code may hold the mapper; don't deadlock on it
+    let mapper = match crate::memory::MAPPER.try_lock() {
+        Some(mapper) => mapper,
+        None => return false,
+    };
+    let mapper = match mapper.as_ref() {
+        Some(mapper) => mapper,
+        None => return false,
+    };
+    
+    let mut page = addr & !0xFFF;
+    while page < end {
+        if mapper.translate_addr(VirtAddr::new(page)).is_none() {
+            return false;
+        }
+        page += 4096;
+    }
+    true
+}
+
+fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    
+    let mut value = 0u64;
+    for &c in digits {
+        value = (value << 4) | hex_value(c)? as u64;
+    }
+    Some(value)
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+// Read a `$data#cs` packet into `buf`, acknowledging it, and return its length.
+// Packets with a bad checksum are NAKed so GDB resends them.
+fn read_packet(port: &mut SerialPort, buf: &mut [u8; MAX_PACKET]) -> usize {
+    loop {
+        // Skip acks and anything else until the start of a packet
+        while port.receive() != b'$' {}
+        
+        let mut len = 0;
+        let mut checksum = 0u8;
+        loop {
+            let c = port.receive();
+            if c == b'#' {
+                break;
+            }
+            if len < MAX_PACKET {
+                buf[len] = c;
+                len += 1;
+            }
+            checksum = checksum.wrapping_add(c);
+        }
+        
+        let expected = (hex_value(port.receive()), hex_value(port.receive()));
+        if let (Some(high), Some(low)) = expected {
+            if (high << 4 | low) == checksum && len < MAX_PACKET {
+                port.send_raw(b'+');
+                return len;
+            }
+        }
+        port.send_raw(b'-');
+    }
+}
+
+// Send `$data#cs`, repeating until GDB acknowledges it
+fn write_packet(port: &mut SerialPort, data: &[u8]) {
+    let checksum = data.iter().fold(0u8, |sum, &c| sum.wrapping_add(c));
+    loop {
+        port.send_raw(b'$');
+        for &c in data {
+            port.send_raw(c);
+        }
+        port.send_raw(b'#');
+        port.send_raw(HEX_DIGITS[(checksum >> 4) as usize]);
+        port.send_raw(HEX_DIGITS[(checksum & 0xF) as usize]);
+        
+        if port.receive() == b'+' {
+            return;
+        }
+    }
+}
+
+// Packet payload being built, truncated if it would overflow
+struct Reply {
+    buf: [u8; MAX_REPLY],
+    len: usize,
+}
+
+impl Reply {
+    fn new() -> Self {
+        Reply {
+            buf: [0; MAX_REPLY],
+            len: 0,
+        }
+    }
+    
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+    
+    fn push_bytes(&mut self, bytes: &[u8]) {
+        let n = bytes.len().min(MAX_REPLY - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+        self.len += n;
+    }
+    
+    // Append bytes as two lowercase hex digits each, in memory order
+    fn push_hex(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.push_bytes(&[HEX_DIGITS[(byte >> 4) as usize], HEX_DIGITS[(byte & 0xF) as usize]]);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+use crate::fs::FileSystem;
+use crate::memory::{self, AddressSpace};
+use crate::task::Task;
+
+// Identification bytes and header values this loader accepts
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_X86_64: u16 = 0x3E;
+
+// Program header type of a segment to load
+const PT_LOAD: u32 = 1;
+
+// Segment permission flags
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+
+// Stack size of tasks created for loaded programs
+const PROGRAM_STACK_SIZE: usize = 4096;
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct ElfHeader {
+    ident: [u8; 16],
+    elf_type: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProgramHeader {
+    pub segment_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub paddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    // Final page flags for the segment's pages
+    fn page_flags(&self) -> PageTableFlags {
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if self.flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if self.flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        flags
+    }
+}
+
+/// A validated ELF64 executable for x86-64, borrowed from its file contents
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    header: ElfHeader,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Parses and validates the ELF header. Only little-endian x86-64
+    /// executables (`ET_EXEC`) are accepted.
+    pub fn parse(data: &'a [u8]) -> Result<Self, &'static str> {
+        if data.len() < core::mem::size_of::<ElfHeader>() {
+            return Err("File too small for an ELF header");
+        }
+        
+        // Safety: the length was checked and ElfHeader matches the on-disk layout
+        let header = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const ElfHeader) };
+        
+        if header.ident[0..4] != ELF_MAGIC {
+            return Err("Not an ELF file");
+        }
+        if header.ident[4] != ELFCLASS64 || header.ident[5] != ELFDATA2LSB {
+            return Err("Not a little-endian ELF64 file");
+        }
+        if header.machine != EM_X86_64 {
+            return Err("Not an x86-64 executable");
+        }
+        if header.elf_type != ET_EXEC {
+            return Err("Not an executable ELF file");
+        }
+        if (header.phentsize as usize) < core::mem::size_of::<ProgramHeader>() {
+            return Err("Invalid program header size");
+        }
+        
+        let table_end = (header.phnum as u64)
+            .checked_mul(header.phentsize as u64)
+            .and_then(|size| size.checked_add(header.phoff));
+        match table_end {
+            Some(end) if end <= data.len() as u64 => {}
+            _ => return Err("Program headers past the end of the file"),
+        }
+        
+        Ok(ElfFile { data, header })
+    }
+    
+    /// Returns the program's entry point
+    pub fn entry(&self) -> VirtAddr {
+        VirtAddr::new(self.header.entry)
+    }
+    
+    /// Iterates over the program headers
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        let phoff = self.header.phoff as usize;
+        let phentsize = self.header.phentsize as usize;
+        (0..self.header.phnum as usize).map(move |i| {
+            let offset = phoff + i * phentsize;
+            // Safety: `parse` checked the whole table lies within the file
+            unsafe { core::ptr::read_unaligned(self.data[offset..].as_ptr() as *const ProgramHeader) }
+        })
+    }
+    
+    /// Maps every `PT_LOAD` segment at its virtual address in `space` with
+    /// fresh, user-accessible frames, copies in the file contents and zeroes
+    /// the rest (`.bss`), with the segment's permissions. Segments may share a
+    /// page, which then gets the permissions of both, but may not overlap.
+    ///
+    /// If a segment fails to load, the pages mapped for the ones before it are
+    /// unmapped again and every frame taken is freed.
+    pub fn load(
+        &self,
+        space: &mut AddressSpace,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    ) -> Result<(), &'static str> {
+        let mut loaded = Vec::new();
+        let mut mapped = Vec::new();
+        for segment in self.program_headers().filter(|ph| ph.segment_type == PT_LOAD) {
+            if let Err(err) = self.load_segment(&segment, space, frame_allocator, &mut loaded, &mut mapped) {
+                // Newest first, so the frames go back in the reverse of the order they were taken
+                for &page in mapped.iter().rev() {
+                    let _ = space.unmap_page(page, frame_allocator);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+    
+    // Map and fill a single PT_LOAD segment. `loaded` holds the address ranges
+    // of the segments loaded so far and `mapped` the pages mapped for them.
+    fn load_segment(
+        &self,
+        segment: &ProgramHeader,
+        space: &mut AddressSpace,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+        loaded: &mut Vec<Range<u64>>,
+        mapped: &mut Vec<Page<Size4KiB>>,
+    ) -> Result<(), &'static str> {
+        if segment.memsz == 0 {
+            return Ok(());
+        }
+        if segment.filesz > segment.memsz {
+            return Err("Segment file size exceeds its memory size");
+        }
+        let file_end = segment.offset.checked_add(segment.filesz).ok_or("Invalid segment")?;
+        if file_end > self.data.len() as u64 {
+            return Err("Segment past the end of the file");
+        }
+        
+        let range = segment.vaddr..segment.vaddr.checked_add(segment.memsz).ok_or("Invalid segment address")?;
+        let start = VirtAddr::try_new(range.start).map_err(|_| "Invalid segment address")?;
+        let end = VirtAddr::try_new(range.end - 1).map_err(|_| "Invalid segment address")?;
+        let pages = Page::range_inclusive(Page::containing_address(start), Page::containing_address(end));
+        
+        if loaded.iter().any(|other| other.start < range.end && range.start < other.end) {
+            return Err("Segments overlap");
+        }
+        
+        let flags = segment.page_flags();
+        for page in pages {
+            let frame = match space.translate_page(page) {
+                // An earlier segment ends on this page: keep its contents and
+                // allow what either segment needs
+                Some((frame, shared_flags)) => {
+                    space.update_flags(page, merge_flags(shared_flags, flags))?;
+                    frame
+                }
+                None => {
+                    let frame = frame_allocator.allocate_frame().ok_or("Failed to allocate frame for segment")?;
+                    unsafe { core::ptr::write_bytes(frame_ptr(frame), 0, 4096) };
+                    if let Err(err) = space.map_page(page, frame, flags, frame_allocator) {
+                        // Any page tables made for the page were taken after its frame
+                        space.clean_up(page, frame_allocator);
+                        unsafe { frame_allocator.deallocate_frame(frame) };
+                        return Err(err);
+                    }
+                    mapped.push(page);
+                    frame
+                }
+            };
+            
+            // Copy in the part of the file contents that lands on this page;
+            // the pages aren't mapped in the running address space, so go
+            // through the physical memory mapping
+            let page_start = page.start_address().as_u64();
+            let copy_start = page_start.max(segment.vaddr);
+            let copy_end = page_start.saturating_add(4096).min(segment.vaddr + segment.filesz);
+            if copy_start < copy_end {
+                let offset = (segment.offset + (copy_start - segment.vaddr)) as usize;
+                let contents = &self.data[offset..offset + (copy_end - copy_start) as usize];
+                unsafe {
+                    let dest = frame_ptr(frame).add((copy_start - page_start) as usize);
+                    core::ptr::copy_nonoverlapping(contents.as_ptr(), dest, contents.len());
+                }
+            }
+        }
+        
+        loaded.push(range);
+        Ok(())
+    }
+}
+
+// Flags for a page two segments share: writable or executable if either is
+fn merge_flags(a: PageTableFlags, b: PageTableFlags) -> PageTableFlags {
+    let no_execute = a & b & PageTableFlags::NO_EXECUTE;
+    ((a | b) - PageTableFlags::NO_EXECUTE) | no_execute
+}
+
+// Pointer to a frame through the physical memory mapping
+fn frame_ptr(frame: PhysFrame) -> *mut u8 {
+    (memory::physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr()
+}
+
+/// Reads the ELF executable at `path`, loads it into an address space of
+/// its own and returns a task (not yet scheduled) that starts at its entry
+/// point in that address space.
+pub fn load_task(fs: &mut dyn FileSystem, path: &str, name: &'static str) -> Result<Task, &'static str> {
+    let data = fs.read_to_vec(path)?;
+    let elf = ElfFile::parse(&data)?;
+    
+    // Make the task first, so the address space shares its stack's mapping
+    // Safety: the entry point lies in the segments loaded below, or the task is dropped
+    let mut task = unsafe { Task::from_entry_address(name, elf.entry(), PROGRAM_STACK_SIZE) };
+    
+    let space = {
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().ok_or("Frame allocator not installed")?;
+        let mut space = AddressSpace::new(frame_allocator)?;
+        if let Err(err) = elf.load(&mut space, frame_allocator) {
+            space.free(frame_allocator);
+            return Err(err);
+        }
+        space
+    };
+    
+    task.set_address_space(Arc::new(space));
+    Ok(task)
+}
+
+/// Loads the ELF executable at `path` and schedules it, returning the task ID.
+pub fn spawn(fs: &mut dyn FileSystem, path: &str, name: &'static str) -> Result<usize, &'static str> {
+    let task = load_task(fs, path, name)?;
+    let id = task.id;
+    crate::task::scheduler::SCHEDULER.lock().add_task(task);
+    Ok(id)
+}
@@ -0,0 +1,83 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::fs::{FileHandle, FileSystem, FsError};
+
+/// Buffered reading from an open file
+///
+/// Reads from the filesystem a buffer at a time and serves bytes and lines
+/// out of it, so small reads don't each fetch a whole cluster. For FAT32 a
+/// buffer of `cluster_size()` bytes is filled by one `read`.
+pub struct BufferedFile<'a, F: FileSystem> {
+    fs: &'a F,
+    handle: FileHandle,
+    buffer: Vec<u8>,
+    // Unread bytes are buffer[pos..len]
+    pos: usize,
+    len: usize,
+}
+
+impl<'a, F: FileSystem> BufferedFile<'a, F> {
+    /// Wraps `handle` with a buffer of `capacity` bytes
+    pub fn new(fs: &'a F, handle: FileHandle, capacity: usize) -> Self {
+        BufferedFile {
+            fs,
+            handle,
+            buffer: vec![0u8; capacity.max(1)],
+            pos: 0,
+            len: 0,
+        }
+    }
+    
+    /// Returns the handle, e.g. to close it. Buffered bytes that weren't read
+    /// are dropped, the handle's position is past them.
+    pub fn into_handle(self) -> FileHandle {
+        self.handle
+    }
+    
+    // Refill the buffer once it is exhausted, returning false at end of file
+    fn fill(&mut self) -> Result<bool, FsError> {
+        if self.pos < self.len {
+            return Ok(true);
+        }
+        
+        self.len = self.fs.read(&mut self.handle, &mut self.buffer)?;
+        self.pos = 0;
+        Ok(self.len > 0)
+    }
+    
+    /// Reads the next byte, `None` at end of file
+    pub fn read_byte(&mut self) -> Result<Option<u8>, FsError> {
+        if !self.fill()? {
+            return Ok(None);
+        }
+        
+        let byte = self.buffer[self.pos];
+        self.pos += 1;
+        Ok(Some(byte))
+    }
+    
+    /// Appends the next line, including its `\n` if it has one, to `line` and
+    /// returns the number of bytes read, 0 at end of file
+    pub fn read_line(&mut self, line: &mut String) -> Result<usize, FsError> {
+        let mut bytes = Vec::new();
+        while self.fill()? {
+            let available = &self.buffer[self.pos..self.len];
+            match available.iter().position(|&b| b == b'\n') {
+                Some(newline) => {
+                    bytes.extend_from_slice(&available[..=newline]);
+                    self.pos += newline + 1;
+                    break;
+                }
+                None => {
+                    bytes.extend_from_slice(available);
+                    self.pos = self.len;
+                }
+            }
+        }
+        
+        let text = core::str::from_utf8(&bytes).map_err(|_| FsError::Io("Line is not valid UTF-8"))?;
+        line.push_str(text);
+        Ok(bytes.len())
+    }
+}
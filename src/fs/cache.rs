@@ -0,0 +1,206 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::fs::fat32::Disk;
+
+/// Number of sectors a `CachedDisk` keeps by default
+pub const DEFAULT_CACHE_SECTORS: usize = 16;
+
+// A cached copy of one sector
+struct CacheLine {
+    sector: u32,
+    data: Vec<u8>,
+    // CRC32 of `data`, checked in paranoid mode
+    crc: u32,
+    dirty: bool,
+    last_used: u64,
+}
+
+impl CacheLine {
+    fn new(sector: u32, data: Vec<u8>, dirty: bool, last_used: u64) -> Self {
+        CacheLine { sector, crc: crc32(&data), data, dirty, last_used }
+    }
+}
+
+// CRC-32 (IEEE), computed bit by bit: paranoid mode is a debugging aid,
+// so a lookup table isn't worth the memory
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Write-back sector cache in front of another disk
+///
+/// Writes stay in the cache until the line is evicted or `flush` is called.
+/// In paranoid mode every cache hit is checked against the disk first, to
+/// catch sectors that changed behind the cache's back.
+pub struct CachedDisk<D: Disk> {
+    disk: D,
+    lines: Mutex<Vec<CacheLine>>,
+    capacity: usize,
+    sector_size: usize,
+    paranoid: bool,
+    // Use counter for least-recently-used eviction
+    clock: Mutex<u64>,
+}
+
+impl<D: Disk> CachedDisk<D> {
+    /// Wraps `disk` with a cache of `DEFAULT_CACHE_SECTORS` sectors
+    pub fn new(disk: D, sector_size: usize) -> Self {
+        Self::with_capacity(disk, sector_size, DEFAULT_CACHE_SECTORS)
+    }
+    
+    /// Wraps `disk` with a cache of `capacity` sectors
+    pub fn with_capacity(disk: D, sector_size: usize, capacity: usize) -> Self {
+        CachedDisk {
+            disk,
+            lines: Mutex::new(Vec::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            sector_size,
+            paranoid: false,
+            clock: Mutex::new(0),
+        }
+    }
+    
+    /// Returns the underlying disk, which may not yet hold cached writes
+    pub fn inner(&self) -> &D {
+        &self.disk
+    }
+    
+    /// Returns the underlying disk for changes the cache won't see
+    pub fn inner_mut(&mut self) -> &mut D {
+        &mut self.disk
+    }
+    
+    /// Turns paranoid mode on or off (the default). When on, each cache hit
+    /// re-checks the line's CRC32 and, for clean lines, re-reads the sector
+    /// and fails if it no longer matches the cached copy.
+    pub fn set_paranoid(&mut self, paranoid: bool) {
+        self.paranoid = paranoid;
+    }
+    
+    /// Writes back every dirty cache line, then flushes the disk's own cache
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        let mut lines = self.lines.lock();
+        for line in lines.iter_mut().filter(|line| line.dirty) {
+            self.disk.write_sector(line.sector, &line.data)?;
+            line.dirty = false;
+        }
+        self.disk.flush()
+    }
+    
+    /// Number of cached sectors not yet written back
+    pub fn dirty_count(&self) -> usize {
+        self.lines.lock().iter().filter(|line| line.dirty).count()
+    }
+    
+    /// Flushes the cache and returns the underlying disk
+    pub fn into_inner(mut self) -> Result<D, &'static str> {
+        self.flush()?;
+        Ok(self.disk)
+    }
+    
+    // Check a cached line against its CRC and, if clean, against the disk
+    fn verify(&self, line: &CacheLine) -> Result<(), &'static str> {
+        if crc32(&line.data) != line.crc {
+            return Err("Cached sector corrupted in memory");
+        }
+        
+        // A dirty line is meant to differ from the disk
+        if !line.dirty {
+            let mut data = vec![0u8; self.sector_size];
+            self.disk.read_sector(line.sector, &mut data)?;
+            if crc32(&data) != line.crc {
+                return Err("Cached sector does not match the disk");
+            }
+        }
+        Ok(())
+    }
+    
+    fn tick(&self) -> u64 {
+        let mut clock = self.clock.lock();
+        *clock += 1;
+        *clock
+    }
+}
+
+impl<D: Disk> Disk for CachedDisk<D> {
+    fn read_sector(&self, sector: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let n = buffer.len().min(self.sector_size);
+        let now = self.tick();
+        let mut lines = self.lines.lock();
+        
+        if let Some(line) = lines.iter_mut().find(|line| line.sector == sector) {
+            if self.paranoid {
+                self.verify(line)?;
+            }
+            line.last_used = now;
+            buffer[..n].copy_from_slice(&line.data[..n]);
+            return Ok(());
+        }
+        
+        let mut data = vec![0u8; self.sector_size];
+        self.disk.read_sector(sector, &mut data)?;
+        buffer[..n].copy_from_slice(&data[..n]);
+        
+        // Reads can't write back, so only a clean line may be replaced
+        let line = CacheLine::new(sector, data, false, now);
+        if lines.len() < self.capacity {
+            lines.push(line);
+        } else if let Some(victim) = lines.iter_mut().filter(|line| !line.dirty).min_by_key(|line| line.last_used) {
+            *victim = line;
+        }
+        
+        Ok(())
+    }
+    
+    fn write_sector(&mut self, sector: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        let n = buffer.len().min(self.sector_size);
+        let now = self.tick();
+        let mut lines = self.lines.lock();
+        
+        if let Some(line) = lines.iter_mut().find(|line| line.sector == sector) {
+            line.data[..n].copy_from_slice(&buffer[..n]);
+            line.crc = crc32(&line.data);
+            line.dirty = true;
+            line.last_used = now;
+            return Ok(());
+        }
+        
+        // Short writes keep the rest of the sector
+        let mut data = vec![0u8; self.sector_size];
+        if n < self.sector_size {
+            self.disk.read_sector(sector, &mut data)?;
+        }
+        data[..n].copy_from_slice(&buffer[..n]);
+        let line = CacheLine::new(sector, data, true, now);
+        
+        if lines.len() < self.capacity {
+            lines.push(line);
+            return Ok(());
+        }
+        
+        // Evict the least recently used line, writing it back if needed
+        let victim = lines.iter_mut().min_by_key(|line| line.last_used).ok_or("Cache empty")?;
+        if victim.dirty {
+            self.disk.write_sector(victim.sector, &victim.data)?;
+        }
+        *victim = line;
+        
+        Ok(())
+    }
+    
+    fn total_sectors(&self) -> u32 {
+        self.disk.total_sectors()
+    }
+    
+    fn flush(&mut self) -> Result<(), &'static str> {
+        CachedDisk::flush(self)
+    }
+}
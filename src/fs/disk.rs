@@ -1,8 +1,8 @@
-
-
+use alloc::vec;
 use alloc::vec::Vec;
 use spin::Mutex;
-use crate::fs::fat32::DiskIO;
+use crate::fs::fat32::{Disk, DiskIO};
+use crate::io::{self, ata::*};
 
 /// Memory-based disk for testing
 pub struct MemoryDisk {
@@ -83,24 +83,228 @@ impl DiskDriver for MemoryDisk {
     }
 }
 
-/// Simple ATA PIO driver for real hardware
-pub struct AtaPioDisk {
+/// RAM disk over a static image, e.g. one embedded with `include_bytes!`
+pub struct RamDisk {
+    image: &'static [u8],
+    // Heap copy of the image, created by the first write when copy-on-write is enabled
+    copy: Mutex<Option<Vec<u8>>>,
+    sector_size: usize,
+    copy_on_write: bool,
+}
+
+impl RamDisk {
+    /// Creates a read-only RAM disk over `data`
+    pub fn from_static(data: &'static [u8], sector_size: usize) -> Self {
+        RamDisk {
+            image: data,
+            copy: Mutex::new(None),
+            sector_size,
+            copy_on_write: false,
+        }
+    }
+    
+    /// Creates a RAM disk over `data` that copies the image to the heap on the first write
+    pub fn from_static_cow(data: &'static [u8], sector_size: usize) -> Self {
+        RamDisk {
+            copy_on_write: true,
+            ..Self::from_static(data, sector_size)
+        }
+    }
+    
+    /// Returns true once a write has copied the image to the heap
+    pub fn is_copied(&self) -> bool {
+        self.copy.lock().is_some()
+    }
+}
+
+impl DiskIO for RamDisk {
+    fn read_sectors(&self, start_sector: u32, sector_count: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let copy = self.copy.lock();
+        let data = copy.as_deref().unwrap_or(self.image);
+        let start_offset = start_sector as usize * self.sector_size;
+        let end_offset = start_offset + (sector_count as usize * self.sector_size);
+        
+        if end_offset > data.len() {
+            return Err("Read beyond disk boundaries");
+        }
+        
+        if buffer.len() < (sector_count as usize * self.sector_size) {
+            return Err("Buffer too small for requested sectors");
+        }
+        
+        buffer[..(sector_count as usize * self.sector_size)]
+            .copy_from_slice(&data[start_offset..end_offset]);
+        
+        Ok(())
+    }
+    
+    fn write_sectors(&self, start_sector: u32, sector_count: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        if !self.copy_on_write {
+            return Err("RAM disk is read-only");
+        }
+        
+        let start_offset = start_sector as usize * self.sector_size;
+        let end_offset = start_offset + (sector_count as usize * self.sector_size);
+        
+        if end_offset > self.image.len() {
+            return Err("Write beyond disk boundaries");
+        }
+        
+        if buffer.len() < (sector_count as usize * self.sector_size) {
+            return Err("Buffer too small for requested sectors");
+        }
+        
+        // The static image is never modified, writes go to a private heap copy
+        let mut copy = self.copy.lock();
+        let data = copy.get_or_insert_with(|| self.image.to_vec());
+        data[start_offset..end_offset]
+            .copy_from_slice(&buffer[..(sector_count as usize * self.sector_size)]);
+        
+        Ok(())
+    }
+}
+
+impl DiskDriver for RamDisk {
+    fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+    
+    fn total_sectors(&self) -> usize {
+        self.image.len() / self.sector_size
+    }
+}
+
+/// Lets any disk driver back a FAT32 filesystem
+impl<T: DiskDriver> Disk for T {
+    fn read_sector(&self, sector: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let sector_size = self.sector_size();
+        if buffer.len() >= sector_size {
+            return self.read_sectors(sector, 1, &mut buffer[..sector_size]);
+        }
+        
+        // Short buffer: read the whole sector and keep the prefix
+        let mut sector_buffer = vec![0u8; sector_size];
+        self.read_sectors(sector, 1, &mut sector_buffer)?;
+        let n = buffer.len();
+        buffer.copy_from_slice(&sector_buffer[..n]);
+        Ok(())
+    }
+    
+    fn write_sector(&mut self, sector: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        let sector_size = self.sector_size();
+        if buffer.len() >= sector_size {
+            return self.write_sectors(sector, 1, &buffer[..sector_size]);
+        }
+        
+        // Short buffer: read-modify-write so the rest of the sector is preserved
+        let mut sector_buffer = vec![0u8; sector_size];
+        self.read_sectors(sector, 1, &mut sector_buffer)?;
+        sector_buffer[..buffer.len()].copy_from_slice(buffer);
+        self.write_sectors(sector, 1, &sector_buffer)
+    }
+    
+    fn total_sectors(&self) -> u32 {
+        DiskDriver::total_sectors(self) as u32
+    }
+    
+    fn flush(&mut self) -> Result<(), &'static str> {
+        DiskIO::flush(self)
+    }
+}
+
+// Highest LBA the 28-bit commands can address
+const LBA28_MAX: u64 = 0x0FFF_FFFF;
+// Most sectors a 28-bit command can transfer, written as a count of 0
+const LBA28_MAX_SECTORS: usize = 256;
+
+/// Register access for `AtaPioDisk`, so the driver can run against a mock
+pub trait AtaBus {
+    /// Reads the 8-bit register at `offset` from the I/O base
+    fn read_register(&mut self, offset: u16) -> u8;
+    /// Writes the 8-bit register at `offset` from the I/O base
+    fn write_register(&mut self, offset: u16, value: u8);
+    /// Reads a word from the data register
+    fn read_data(&mut self) -> u16;
+    /// Writes a word to the data register
+    fn write_data(&mut self, value: u16);
+}
+
+/// The I/O ports of the primary or secondary ATA channel
+pub struct PortBus {
     is_primary: bool,
+}
+
+impl PortBus {
+    pub fn new(is_primary: bool) -> Self {
+        PortBus { is_primary }
+    }
+    
+    fn io_base(&self) -> u16 {
+        if self.is_primary {
+            PRIMARY_IO_BASE
+        } else {
+            SECONDARY_IO_BASE
+        }
+    }
+    
+    #[allow(dead_code)] // Device control register, needed for soft reset and nIEN
+    fn control_base(&self) -> u16 {
+        if self.is_primary {
+            PRIMARY_CONTROL
+        } else {
+            SECONDARY_CONTROL
+        }
+    }
+    
+    fn register<T>(&self, offset: u16) -> io::Port<T> {
+        // Safety: the channel's task file belongs to this driver
+        unsafe { io::Port::new(self.io_base() + offset) }
+    }
+}
+
+impl AtaBus for PortBus {
+    fn read_register(&mut self, offset: u16) -> u8 {
+        self.register(offset).read()
+    }
+    
+    fn write_register(&mut self, offset: u16, value: u8) {
+        self.register(offset).write(value)
+    }
+    
+    fn read_data(&mut self) -> u16 {
+        self.register(REG_DATA).read()
+    }
+    
+    fn write_data(&mut self, value: u16) {
+        self.register(REG_DATA).write(value)
+    }
+}
+
+/// Simple ATA PIO driver for real hardware
+pub struct AtaPioDisk<B: AtaBus = PortBus> {
+    bus: Mutex<B>,
     is_master: bool,
     sector_count: Mutex<usize>,
 }
 
 impl AtaPioDisk {
     pub fn new(is_primary: bool, is_master: bool) -> Self {
-        let mut disk = AtaPioDisk {
-            is_primary,
+        Self::with_bus(PortBus::new(is_primary), is_master)
+    }
+}
+
+impl<B: AtaBus> AtaPioDisk<B> {
+    /// Creates a driver for the master or slave drive on `bus`
+    pub fn with_bus(bus: B, is_master: bool) -> Self {
+        let disk = AtaPioDisk {
+            bus: Mutex::new(bus),
             is_master,
             sector_count: Mutex::new(0),
         };
         
         // Identify device to get sector count
         let mut identify_buffer = [0u8; 512];
-        if let Ok(_) = disk.identify(&mut identify_buffer) {
+        if disk.identify(&mut identify_buffer).is_ok() {
             // Sectors are at words 60-61 for 28-bit LBA
             // or 100-103 for 48-bit LBA
             let lba48_sectors = 
@@ -125,59 +329,29 @@ impl AtaPioDisk {
         disk
     }
     
-    fn io_base(&self) -> u16 {
-        if self.is_primary {
-            0x1F0
-        } else {
-            0x170
-        }
-    }
-    
-    fn control_base(&self) -> u16 {
-        if self.is_primary {
-            0x3F6
-        } else {
-            0x376
-        }
-    }
-    
     fn identify(&self, buffer: &mut [u8; 512]) -> Result<(), &'static str> {
-        let io_base = self.io_base();
+        let mut bus = self.bus.lock();
         let master_bit = if self.is_master { 0 } else { 0x10 };
         
         // Select drive
-        unsafe {
-            x86_64::instructions::port::Port::new(io_base + 6).write(master_bit as u8);
-        }
+        bus.write_register(REG_DRIVE, master_bit);
         
         // Set unused bits to zero
-        unsafe {
-            x86_64::instructions::port::Port::new(io_base + 1).write(0u8);
-            x86_64::instructions::port::Port::new(io_base + 2).write(0u8);
-            x86_64::instructions::port::Port::new(io_base + 3).write(0u8);
-            x86_64::instructions::port::Port::new(io_base + 4).write(0u8);
-            x86_64::instructions::port::Port::new(io_base + 5).write(0u8);
+        for register in REG_FEATURES..=REG_LBA_HIGH {
+            bus.write_register(register, 0);
         }
         
         // Send IDENTIFY command
-        unsafe {
-            x86_64::instructions::port::Port::new(io_base + 7).write(0xECu8);
-        }
+        bus.write_register(REG_COMMAND, 0xEC);
         
         // Check if device exists
-        let status: u8 = unsafe {
-            x86_64::instructions::port::Port::new(io_base + 7).read()
-        };
-        
-        if status == 0 {
+        if bus.read_register(REG_STATUS) == 0 {
             return Err("Drive does not exist");
         }
         
         // Wait for data ready
         loop {
-            let status: u8 = unsafe {
-                x86_64::instructions::port::Port::new(io_base + 7).read()
-            };
+            let status = bus.read_register(REG_STATUS);
             
             if status & 0x08 != 0 {
                 // DRQ is set, data is ready
@@ -191,22 +365,59 @@ impl AtaPioDisk {
         }
         
         // Read data
-        let data_port = unsafe {
-            x86_64::instructions::port::Port::<u16>::new(io_base)
-        };
-        
         for i in 0..256 {
-            let data: u16 = unsafe { data_port.read() };
+            let data = bus.read_data();
             buffer[i * 2] = (data & 0xFF) as u8;
             buffer[i * 2 + 1] = (data >> 8) as u8;
         }
         
         Ok(())
     }
-}
-
-impl DiskIO for AtaPioDisk {
-    fn read_sectors(&self, start_sector: u32, sector_count: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+    
+    // Select the drive and load the address and length of a transfer of
+    // `count` sectors at `lba`, returning whether the 48-bit command set has
+    // to be used
+    fn select_sectors(&self, bus: &mut B, lba: u64, count: usize) -> Result<bool, &'static str> {
+        let master_bit = if self.is_master { 0 } else { 0x10 };
+        let last = lba + count as u64 - 1;
+        
+        // A count of 0 means the maximum, 256 or 65536 sectors
+        if last <= LBA28_MAX && count <= LBA28_MAX_SECTORS {
+            // Select drive, LBA mode and upper LBA bits
+            bus.write_register(REG_DRIVE, 0xE0 | master_bit | ((lba >> 24) & 0x0F) as u8);
+            
+            bus.write_register(REG_SECTOR_COUNT, count as u8);
+            
+            // Send LBA address
+            bus.write_register(REG_LBA_LOW, lba as u8);
+            bus.write_register(REG_LBA_MID, (lba >> 8) as u8);
+            bus.write_register(REG_LBA_HIGH, (lba >> 16) as u8);
+            return Ok(false);
+        }
+        
+        if last >> 48 != 0 {
+            return Err("LBA beyond the 48-bit range");
+        }
+        
+        // LBA mode, no address bits in the drive register
+        bus.write_register(REG_DRIVE, 0x40 | master_bit);
+        
+        // Each register is a two-byte FIFO: high bytes first, then low bytes
+        bus.write_register(REG_SECTOR_COUNT, (count >> 8) as u8);
+        bus.write_register(REG_LBA_LOW, (lba >> 24) as u8);
+        bus.write_register(REG_LBA_MID, (lba >> 32) as u8);
+        bus.write_register(REG_LBA_HIGH, (lba >> 40) as u8);
+        bus.write_register(REG_SECTOR_COUNT, count as u8);
+        bus.write_register(REG_LBA_LOW, lba as u8);
+        bus.write_register(REG_LBA_MID, (lba >> 8) as u8);
+        bus.write_register(REG_LBA_HIGH, (lba >> 16) as u8);
+        Ok(true)
+    }
+    
+    /// Reads `sector_count` sectors starting at a 48-bit `start_sector`,
+    /// up to 256 sectors per command. Sectors past the 28-bit range use
+    /// READ SECTORS EXT.
+    pub fn read_sectors_lba(&self, start_sector: u64, sector_count: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
         if sector_count == 0 {
             return Ok(());
         }
@@ -215,70 +426,52 @@ impl DiskIO for AtaPioDisk {
             return Err("Buffer too small for requested sectors");
         }
         
-        let io_base = self.io_base();
-        let master_bit = if self.is_master { 0 } else { 0x10 };
+        let mut bus = self.bus.lock();
+        let mut done = 0;
         
-        for sector_idx in 0..sector_count {
-            let lba = start_sector + sector_idx;
-            
-            // Select drive and upper LBA bits
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 6).write(
-                    (master_bit | ((lba >> 24) & 0x0F)) as u8
-                );
-            }
-            
-            // Set sector count to 1 (we read one sector at a time)
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 2).write(1u8);
-            }
+        while done < sector_count as usize {
+            let count = (sector_count as usize - done).min(LBA28_MAX_SECTORS);
             
-            // Send LBA address
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 3).write((lba & 0xFF) as u8);
-                x86_64::instructions::port::Port::new(io_base + 4).write(((lba >> 8) & 0xFF) as u8);
-                x86_64::instructions::port::Port::new(io_base + 5).write(((lba >> 16) & 0xFF) as u8);
-            }
-            
-            // Send READ SECTORS command
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 7).write(0x20u8);
-            }
+            // Send READ SECTORS (EXT) command
+            let lba48 = self.select_sectors(&mut bus, start_sector + done as u64, count)?;
+            bus.write_register(REG_COMMAND, if lba48 { 0x24 } else { 0x20 });
             
-            // Wait for data ready
-            loop {
-                let status: u8 = unsafe {
-                    x86_64::instructions::port::Port::new(io_base + 7).read()
-                };
-                
-                if status & 0x08 != 0 {
-                    // DRQ is set, data is ready
-                    break;
+            // The drive raises DRQ once per sector
+            for sector_idx in done..done + count {
+                // Wait for data ready
+                loop {
+                    let status = bus.read_register(REG_STATUS);
+                    
+                    if status & 0x08 != 0 {
+                        // DRQ is set, data is ready
+                        break;
+                    }
+                    
+                    if status & 0x01 != 0 {
+                        // Error
+                        return Err("Error during read");
+                    }
                 }
                 
-                if status & 0x01 != 0 {
-                    // Error
-                    return Err("Error during read");
+                // Read data
+                let offset = sector_idx * 512;
+                for i in 0..256 {
+                    let data = bus.read_data();
+                    buffer[offset + i * 2] = (data & 0xFF) as u8;
+                    buffer[offset + i * 2 + 1] = (data >> 8) as u8;
                 }
             }
             
-            // Read data
-            let data_port = unsafe {
-                x86_64::instructions::port::Port::<u16>::new(io_base)
-            };
-            
-            let offset = sector_idx as usize * 512;
-            for i in 0..256 {
-                let data: u16 = unsafe { data_port.read() };
-                buffer[offset + i * 2] = (data & 0xFF) as u8;
-                buffer[offset + i * 2 + 1] = (data >> 8) as u8;
-            }
+            done += count;
         }
         
         Ok(())
     }
     
-    fn write_sectors(&self, start_sector: u32, sector_count: u32, buffer: &[u8]) -> Result<(), &'static str> {
+    /// Writes `sector_count` sectors starting at a 48-bit `start_sector`,
+    /// up to 256 sectors per command. Sectors past the 28-bit range use
+    /// WRITE SECTORS EXT.
+    pub fn write_sectors_lba(&self, start_sector: u64, sector_count: u32, buffer: &[u8]) -> Result<(), &'static str> {
         if sector_count == 0 {
             return Ok(());
         }
@@ -287,94 +480,87 @@ impl DiskIO for AtaPioDisk {
             return Err("Buffer too small for requested sectors");
         }
         
-        let io_base = self.io_base();
-        let master_bit = if self.is_master { 0 } else { 0x10 };
+        let mut bus = self.bus.lock();
+        let mut done = 0;
         
-        for sector_idx in 0..sector_count {
-            let lba = start_sector + sector_idx;
+        while done < sector_count as usize {
+            let count = (sector_count as usize - done).min(LBA28_MAX_SECTORS);
             
-            // Select drive and upper LBA bits
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 6).write(
-                    (master_bit | ((lba >> 24) & 0x0F)) as u8
-                );
-            }
-            
-            // Set sector count to 1 (we write one sector at a time)
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 2).write(1u8);
-            }
-            
-            // Send LBA address
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 3).write((lba & 0xFF) as u8);
-                x86_64::instructions::port::Port::new(io_base + 4).write(((lba >> 8) & 0xFF) as u8);
-                x86_64::instructions::port::Port::new(io_base + 5).write(((lba >> 16) & 0xFF) as u8);
-            }
+            // Send WRITE SECTORS (EXT) command
+            let lba48 = self.select_sectors(&mut bus, start_sector + done as u64, count)?;
+            bus.write_register(REG_COMMAND, if lba48 { 0x34 } else { 0x30 });
             
-            // Send WRITE SECTORS command
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 7).write(0x30u8);
-            }
-            
-            // Wait for ready to accept data
-            loop {
-                let status: u8 = unsafe {
-                    x86_64::instructions::port::Port::new(io_base + 7).read()
-                };
-                
-                if status & 0x08 != 0 {
-                    // DRQ is set, ready for data
-                    break;
+            // The drive raises DRQ once per sector
+            for sector_idx in done..done + count {
+                // Wait for ready to accept data
+                loop {
+                    let status = bus.read_register(REG_STATUS);
+                    
+                    if status & 0x08 != 0 {
+                        // DRQ is set, ready for data
+                        break;
+                    }
+                    
+                    if status & 0x01 != 0 {
+                        // Error
+                        return Err("Error during write preparation");
+                    }
                 }
                 
-                if status & 0x01 != 0 {
-                    // Error
-                    return Err("Error during write preparation");
+                // Write data
+                let offset = sector_idx * 512;
+                for i in 0..256 {
+                    let low_byte = buffer[offset + i * 2] as u16;
+                    let high_byte = buffer[offset + i * 2 + 1] as u16;
+                    bus.write_data(low_byte | (high_byte << 8));
                 }
             }
             
-            // Write data
-            let data_port = unsafe {
-                x86_64::instructions::port::Port::<u16>::new(io_base)
-            };
+            // Flush cache (CACHE FLUSH EXT after a 48-bit write)
+            Self::flush_cache(&mut bus, lba48)?;
             
-            let offset = sector_idx as usize * 512;
-            for i in 0..256 {
-                let low_byte = buffer[offset + i * 2] as u16;
-                let high_byte = buffer[offset + i * 2 + 1] as u16;
-                let word = low_byte | (high_byte << 8);
-                unsafe { data_port.write(word); }
-            }
+            done += count;
+        }
+        
+        Ok(())
+    }
+    
+    // Send CACHE FLUSH (EXT) and wait for the drive to finish writing
+    fn flush_cache(bus: &mut B, lba48: bool) -> Result<(), &'static str> {
+        bus.write_register(REG_COMMAND, if lba48 { 0xEA } else { 0xE7 });
+        
+        loop {
+            let status = bus.read_register(REG_STATUS);
             
-            // Flush cache
-            unsafe {
-                x86_64::instructions::port::Port::new(io_base + 7).write(0xE7u8);
+            if status & 0x80 == 0 && status & 0x40 != 0 {
+                // BSY clear and RDY set
+                return Ok(());
             }
             
-            // Wait for operation to complete
-            loop {
-                let status: u8 = unsafe {
-                    x86_64::instructions::port::Port::new(io_base + 7).read()
-                };
-                
-                if status & 0x80 == 0 && status & 0x40 != 0 {
-                    // BSY clear and RDY set
-                    break;
-                }
-                
-                if status & 0x01 != 0 {
-                    // Error
-                    return Err("Error during write");
-                }
+            if status & 0x01 != 0 {
+                // Error
+                return Err("Error during write");
             }
         }
-        
-        Ok(())
     }
 }
 
-impl DiskDriver for AtaPioDisk {
+impl<B: AtaBus> DiskIO for AtaPioDisk<B> {
+    fn read_sectors(&self, start_sector: u32, sector_count: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+        self.read_sectors_lba(u64::from(start_sector), sector_count, buffer)
+    }
+    
+    fn write_sectors(&self, start_sector: u32, sector_count: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        self.write_sectors_lba(u64::from(start_sector), sector_count, buffer)
+    }
+    
+    fn flush(&self) -> Result<(), &'static str> {
+        // Plain CACHE FLUSH covers the whole cache, whatever wrote to it
+        Self::flush_cache(&mut self.bus.lock(), false)
+    }
+}
+
+impl<B: AtaBus> DiskDriver for AtaPioDisk<B> {
     fn sector_size(&self) -> usize {
         512 // ATA sectors are always 512 bytes
     }
@@ -382,4 +568,4 @@ impl DiskDriver for AtaPioDisk {
     fn total_sectors(&self) -> usize {
         *self.sector_count.lock()
     }
-}
\ No newline at end of file
+}
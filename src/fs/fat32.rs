@@ -3,14 +3,56 @@ use alloc::vec::Vec;
 use alloc::string::String;
 use core::convert::TryInto;
 use alloc::vec;
-use crate::fs::FileHandle;
+use crate::fs::{FileHandle, FsError};
+use crate::time::DateTime;
 
-// FAT32 Disk Layout Constants
-const BYTES_PER_SECTOR: usize = 512;
-const SECTORS_PER_CLUSTER: usize = 8;
-const RESERVED_SECTORS: usize = 32;
-const NUM_FATS: usize = 2;
-const ROOT_DIR_CLUSTERS: usize = 2;
+// The boot sector's BPB fits in the smallest sector size; everything else
+// about the layout is read from it in `init`
+const BOOT_SECTOR_SIZE: usize = 512;
+
+// Geometry used by `format` for new volumes
+const FORMAT_BYTES_PER_SECTOR: usize = 512;
+const FORMAT_SECTORS_PER_CLUSTER: u8 = 8;
+const FORMAT_RESERVED_SECTORS: u32 = 32;
+const FORMAT_FAT_COUNT: u32 = 2;
+const FORMAT_ROOT_DIR_CLUSTERS: u32 = 2;
+
+// Cluster counts below which a volume is FAT12 or FAT16, per Microsoft's spec
+const FAT12_MAX_CLUSTERS: u32 = 4085;
+const FAT16_MAX_CLUSTERS: u32 = 65525;
+
+// Which FAT variant a boot sector describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat12,
+    Fat16,
+    Fat32,
+}
+
+/// On-disk structures that can be copied straight out of a byte buffer
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C, packed)]` and hold only integers and
+/// arrays of them, so they have no padding and any bytes are a valid value.
+pub unsafe trait FromBytes: Sized {}
+
+// Decode a `T` from the start of `bytes`, None if there are too few of them
+pub fn from_bytes<T: FromBytes>(bytes: &[u8]) -> Option<T> {
+    if bytes.len() < core::mem::size_of::<T>() {
+        return None;
+    }
+    
+    // Safety: the bytes are in bounds, any bytes are a valid T, and the read
+    // doesn't assume any alignment
+    Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
+
+// The bytes of a `T` as they are laid out on disk
+pub fn as_bytes<T: FromBytes>(value: &T) -> &[u8] {
+    // Safety: a FromBytes type has no padding, so all of its bytes are initialized
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>()) }
+}
 
 #[repr(C, packed)]
 pub struct FatBootSector {
@@ -45,6 +87,68 @@ pub struct FatBootSector {
     fs_type: [u8; 8],
 }
 
+impl FatBootSector {
+    // Parse a boot sector, rejecting sectors without the 0x55AA boot signature
+    // or the "FAT32   " type, so an unformatted disk isn't read as FAT32 with
+    // garbage geometry
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < BOOT_SECTOR_SIZE {
+            return Err("Boot sector too short");
+        }
+        if bytes[510..512] != [0x55, 0xAA] {
+            return Err("Not a FAT32 volume");
+        }
+        
+        let boot_sector: FatBootSector = from_bytes(bytes).ok_or("Boot sector too short")?;
+        
+        // Checked first: FAT12/16 keep their type string elsewhere
+        if boot_sector.fat_type() != FatType::Fat32 {
+            return Err("FAT12/16 not supported");
+        }
+        // FAT32 keeps its root directory in clusters, never in a fixed region
+        if boot_sector.fs_type != *b"FAT32   " || boot_sector.root_entry_count != 0 {
+            return Err("Not a FAT32 volume");
+        }
+        Ok(boot_sector)
+    }
+    
+    // Work out the FAT type from the cluster count, as the spec says to. Only
+    // volumes with the FAT12/16 layout (a fixed root directory region or a
+    // 16-bit FAT size) are held to the thresholds: small FAT32 volumes, like
+    // the ones `format` makes, would otherwise count as FAT12.
+    pub fn fat_type(&self) -> FatType {
+        let root_entry_count = self.root_entry_count as u32;
+        let sectors_per_fat_16 = self.sectors_per_fat_16 as u32;
+        if root_entry_count == 0 && sectors_per_fat_16 == 0 {
+            return FatType::Fat32;
+        }
+        
+        let bytes_per_sector = (self.bytes_per_sector as u32).max(1);
+        let root_dir_sectors = (root_entry_count * 32).div_ceil(bytes_per_sector);
+        let fat_size = match sectors_per_fat_16 {
+            0 => self.sectors_per_fat_32,
+            n => n,
+        };
+        let total_sectors = match self.total_sectors_16 {
+            0 => self.total_sectors_32,
+            n => n as u32,
+        };
+        let metadata_sectors = (self.reserved_sector_count as u32)
+            .saturating_add((self.fat_count as u32).saturating_mul(fat_size))
+            .saturating_add(root_dir_sectors);
+        let clusters = total_sectors.saturating_sub(metadata_sectors)
+            / (self.sectors_per_cluster as u32).max(1);
+        
+        if clusters < FAT12_MAX_CLUSTERS {
+            FatType::Fat12
+        } else if clusters < FAT16_MAX_CLUSTERS {
+            FatType::Fat16
+        } else {
+            FatType::Fat32
+        }
+    }
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct DirectoryEntry {
@@ -64,7 +168,87 @@ pub struct DirectoryEntry {
     file_size: u32,
 }
 
+unsafe impl FromBytes for FatBootSector {}
+unsafe impl FromBytes for DirectoryEntry {}
+
+// Flags in DirectoryEntry::reserved marking an all-lowercase 8.3 base name or extension
+const LOWERCASE_BASE: u8 = 0x08;
+const LOWERCASE_EXT: u8 = 0x10;
+
+// Directory entry attribute for a regular file
+const ATTR_ARCHIVE: u8 = 0x20;
+// Directory entry attribute for the volume label, also set on long name entries
+const ATTR_VOLUME_ID: u8 = 0x08;
+
+// FsInfo sector signatures and the value marking an unknown count or hint
+const FSINFO_LEAD_SIGNATURE: u32 = 0x41615252;
+const FSINFO_STRUCT_SIGNATURE: u32 = 0x61417272;
+const FSINFO_TRAIL_SIGNATURE: u32 = 0xAA550000;
+pub const FSINFO_UNKNOWN: u32 = 0xFFFFFFFF;
+
+// Allocation state cached in the FsInfo sector
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsInfo {
+    pub free_count: u32, // Free clusters, or FSINFO_UNKNOWN
+    pub next_free: u32,  // Where to start looking for a free cluster, or FSINFO_UNKNOWN
+}
+
+impl FsInfo {
+    // Parse an FsInfo sector, checking all three signatures
+    fn parse(sector: &[u8]) -> Result<Self, &'static str> {
+        let field = |offset: usize| u32::from_le_bytes([
+            sector[offset], sector[offset + 1], sector[offset + 2], sector[offset + 3],
+        ]);
+        
+        if field(0) != FSINFO_LEAD_SIGNATURE
+            || field(484) != FSINFO_STRUCT_SIGNATURE
+            || field(508) != FSINFO_TRAIL_SIGNATURE
+        {
+            return Err("Invalid FsInfo signature");
+        }
+        
+        Ok(FsInfo {
+            free_count: field(488),
+            next_free: field(492),
+        })
+    }
+    
+    // Write the signatures and fields into an FsInfo sector
+    fn store(&self, sector: &mut [u8]) {
+        sector[0..4].copy_from_slice(&FSINFO_LEAD_SIGNATURE.to_le_bytes());
+        sector[484..488].copy_from_slice(&FSINFO_STRUCT_SIGNATURE.to_le_bytes());
+        sector[488..492].copy_from_slice(&self.free_count.to_le_bytes());
+        sector[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        sector[508..512].copy_from_slice(&FSINFO_TRAIL_SIGNATURE.to_le_bytes());
+    }
+}
+
 impl DirectoryEntry {
+    // Build an entry for a new, empty file with the given 8.3 name
+    fn new_file(name: &str) -> Result<Self, &'static str> {
+        let (short_name, case_flags) = short_name(name)?;
+        let mut base = [0u8; 8];
+        let mut ext = [0u8; 3];
+        base.copy_from_slice(&short_name[..8]);
+        ext.copy_from_slice(&short_name[8..]);
+        
+        Ok(DirectoryEntry {
+            name: base,
+            ext,
+            attributes: ATTR_ARCHIVE,
+            reserved: case_flags,
+            creation_time_tenths: 0,
+            creation_time: 0,
+            creation_date: 0,
+            last_access_date: 0,
+            first_cluster_high: 0,
+            last_modification_time: 0,
+            last_modification_date: 0,
+            first_cluster_low: 0,
+            file_size: 0,
+        })
+    }
+    
     // Check if the entry is free (unused)
     pub fn is_free(&self) -> bool {
         self.name[0] == 0xE5 || self.name[0] == 0x00
@@ -77,19 +261,30 @@ impl DirectoryEntry {
     
     // Check if the entry is a regular file
     pub fn is_file(&self) -> bool {
-        (self.attributes & 0x10) == 0 && (self.attributes & 0x08) == 0
+        (self.attributes & 0x10) == 0 && !self.is_volume_label()
+    }
+    
+    // Check if the entry is the volume label (or a long name entry, which
+    // carries the same attribute bit) rather than a file or directory
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes & ATTR_VOLUME_ID != 0
     }
     
     // Get the file name
     pub fn get_name(&self) -> String {
         let mut name = String::new();
         
+        // Case flags restore all-lowercase components without a long name entry
+        let lower_base = self.reserved & LOWERCASE_BASE != 0;
+        let lower_ext = self.reserved & LOWERCASE_EXT != 0;
+        
         // Copy the base name (trim spaces)
         for i in 0..8 {
             if self.name[i] == b' ' {
                 break;
             }
-            name.push(self.name[i] as char);
+            let c = self.name[i] as char;
+            name.push(if lower_base { c.to_ascii_lowercase() } else { c });
         }
         
         // Add the extension if it exists
@@ -99,7 +294,8 @@ impl DirectoryEntry {
                 if self.ext[i] == b' ' {
                     break;
                 }
-                name.push(self.ext[i] as char);
+                let c = self.ext[i] as char;
+                name.push(if lower_ext { c.to_ascii_lowercase() } else { c });
             }
         }
         
@@ -110,6 +306,81 @@ impl DirectoryEntry {
     pub fn get_first_cluster(&self) -> u32 {
         ((self.first_cluster_high as u32) << 16) | (self.first_cluster_low as u32)
     }
+    
+    // Get the file size in bytes
+    pub fn get_file_size(&self) -> u32 {
+        self.file_size
+    }
+    
+    // Get the creation time, None if the entry has none. The tenths field
+    // carries the odd second that the 2-second time field can't hold
+    pub fn created(&self) -> Option<DateTime> {
+        let mut created = fat_date_time(self.creation_date, self.creation_time)?;
+        if self.creation_time_tenths >= 100 && created.second < 59 {
+            created.second += 1;
+        }
+        Some(created)
+    }
+    
+    // Get the last modification time, None if the entry has none
+    pub fn modified(&self) -> Option<DateTime> {
+        fat_date_time(self.last_modification_date, self.last_modification_time)
+    }
+}
+
+// Decode a FAT date (years since 1980, month, day) and time (hours, minutes,
+// 2-second units) into a DateTime, None for a zero or out-of-range stamp
+fn fat_date_time(date: u16, time: u16) -> Option<DateTime> {
+    let date_time = DateTime {
+        year: 1980 + (date >> 9),
+        month: ((date >> 5) & 0x0F) as u8,
+        day: (date & 0x1F) as u8,
+        hour: (time >> 11) as u8,
+        minute: ((time >> 5) & 0x3F) as u8,
+        second: ((time & 0x1F) * 2) as u8,
+    };
+    
+    if date_time.is_valid() { Some(date_time) } else { None }
+}
+
+// Convert a file name to a space-padded 8.3 name plus the case flags that
+// restore an all-lowercase base or extension
+fn short_name(name: &str) -> Result<([u8; 11], u8), &'static str> {
+    let (base, ext) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 {
+        return Err("Name does not fit in 8.3 format");
+    }
+    
+    let valid = |c: u8| c.is_ascii_alphanumeric() || b"!#$%&'()-@^_`{}~".contains(&c);
+    if !base.bytes().chain(ext.bytes()).all(valid) {
+        return Err("Invalid character in file name");
+    }
+    
+    let mut short_name = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() {
+        short_name[i] = c.to_ascii_uppercase();
+    }
+    for (i, c) in ext.bytes().enumerate() {
+        short_name[8 + i] = c.to_ascii_uppercase();
+    }
+    
+    // Mixed case can't be represented without a long name entry and is stored uppercase
+    let all_lowercase = |part: &str| {
+        part.bytes().any(|c| c.is_ascii_lowercase()) && !part.bytes().any(|c| c.is_ascii_uppercase())
+    };
+    let mut case_flags = 0;
+    if all_lowercase(base) {
+        case_flags |= LOWERCASE_BASE;
+    }
+    if all_lowercase(ext) {
+        case_flags |= LOWERCASE_EXT;
+    }
+    
+    Ok((short_name, case_flags))
 }
 
 // Simple disk interface for reading/writing sectors
@@ -117,6 +388,21 @@ pub trait Disk {
     fn read_sector(&self, sector: u32, buffer: &mut [u8]) -> Result<(), &'static str>;
     fn write_sector(&mut self, sector: u32, buffer: &[u8]) -> Result<(), &'static str>;
     fn total_sectors(&self) -> u32;
+    // Make every completed write durable, for disks that cache writes
+    fn flush(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+}
+
+// Multi-sector disk interface implemented by the drivers in `fs::disk`
+pub trait DiskIO {
+    fn read_sectors(&self, start_sector: u32, sector_count: u32, buffer: &mut [u8]) -> Result<(), &'static str>;
+    fn write_sectors(&self, start_sector: u32, sector_count: u32, buffer: &[u8]) -> Result<(), &'static str>;
+    // Write the device's own cache out to the medium; nothing to do for
+    // disks without one
+    fn flush(&self) -> Result<(), &'static str> {
+        Ok(())
+    }
 }
 
 // Memory-based disk for testing
@@ -133,30 +419,39 @@ impl MemoryDisk {
             sector_size,
         }
     }
+    
+    // Consume the disk and return its raw contents
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
 }
 
 impl Disk for MemoryDisk {
     fn read_sector(&self, sector: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
+        // Transfer at most one sector, less if the buffer is shorter
+        let n = buffer.len().min(self.sector_size);
         let start = (sector as usize) * self.sector_size;
-        let end = start + buffer.len().min(self.sector_size);
+        let end = start + n;
         
         if end > self.data.len() {
             return Err("Sector read out of bounds");
         }
         
-        buffer[..self.sector_size].copy_from_slice(&self.data[start..end]);
+        buffer[..n].copy_from_slice(&self.data[start..end]);
         Ok(())
     }
     
     fn write_sector(&mut self, sector: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        // Transfer at most one sector, less if the buffer is shorter
+        let n = buffer.len().min(self.sector_size);
         let start = (sector as usize) * self.sector_size;
-        let end = start + buffer.len().min(self.sector_size);
+        let end = start + n;
         
         if end > self.data.len() {
             return Err("Sector write out of bounds");
         }
         
-        self.data[start..end].copy_from_slice(&buffer[..self.sector_size]);
+        self.data[start..end].copy_from_slice(&buffer[..n]);
         Ok(())
     }
     
@@ -165,6 +460,24 @@ impl Disk for MemoryDisk {
     }
 }
 
+// Where a directory entry lives on disk
+#[derive(Debug, Clone, Copy)]
+struct EntryLocation {
+    sector: u32,
+    offset: usize,
+}
+
+// Per-file state for an open file
+struct OpenFile {
+    handle: FileHandle,
+    chain: Vec<u32>,
+    entry: EntryLocation,
+    // Cluster being written (index into `chain` and its data), not yet on disk
+    write_buffer: Option<(usize, Vec<u8>)>,
+    // Set once the file has been written, so read-only files never touch the disk
+    dirty: bool,
+}
+
 pub struct FileSystem<D: Disk> {
     disk: D,
     fat_start_sector: u32,
@@ -172,8 +485,14 @@ pub struct FileSystem<D: Disk> {
     root_dir_cluster: u32,
     sectors_per_cluster: u32,
     bytes_per_sector: u32,
+    fat_size: u32,
+    fat_count: u32,
+    total_clusters: u32,
+    next_free_cluster: u32, // Where the next free-cluster scan starts
+    fs_info_sector: u32,    // 0 if the volume has no FsInfo sector
+    free_clusters: Option<u32>, // Cached free cluster count, None until known
     next_file_handle_id: usize,
-    open_files: Vec<(FileHandle, Vec<u32>)>, // FileHandle and cluster chain
+    open_files: Vec<OpenFile>,
 }
 
 impl<D: Disk> FileSystem<D> {
@@ -185,6 +504,12 @@ impl<D: Disk> FileSystem<D> {
             root_dir_cluster: 0,
             sectors_per_cluster: 0,
             bytes_per_sector: 0,
+            fat_size: 0,
+            fat_count: 0,
+            total_clusters: 0,
+            next_free_cluster: 2,
+            fs_info_sector: 0,
+            free_clusters: None,
             next_file_handle_id: 1,
             open_files: Vec::new(),
         }
@@ -192,41 +517,203 @@ impl<D: Disk> FileSystem<D> {
     
     // Initialize the filesystem by reading the boot sector
     fn read_boot_sector(&mut self) -> Result<FatBootSector, &'static str> {
-        let mut buffer = [0u8; BYTES_PER_SECTOR];
+        let mut buffer = [0u8; BOOT_SECTOR_SIZE];
         self.disk.read_sector(0, &mut buffer)?;
-        
-        // Safety: This is unsafe because we're interpreting the bytes as a struct
-        // The FatBootSector struct must match the on-disk layout exactly
-        let boot_sector = unsafe {
-            core::ptr::read_unaligned(buffer.as_ptr() as *const FatBootSector)
-        };
-        
-        Ok(boot_sector)
+        FatBootSector::from_slice(&buffer)
+    }
+    
+    // Make sure a cluster number names a data cluster of this volume
+    fn check_cluster(&self, cluster: u32) -> Result<(), &'static str> {
+        if cluster < 2 {
+            return Err("Reserved cluster number");
+        }
+        if cluster - 2 >= self.total_clusters {
+            return Err("Cluster number beyond the end of the volume");
+        }
+        Ok(())
     }
     
     // Convert a cluster number to a sector number
-    fn cluster_to_sector(&self, cluster: u32) -> u32 {
+    fn cluster_to_sector(&self, cluster: u32) -> Result<u32, &'static str> {
+        self.check_cluster(cluster)?;
+        
         // First data cluster (2) starts at data_start_sector
         // Cluster numbers start at 2 in FAT32
+        // A corrupt or huge volume must not wrap around to an unrelated sector
         let data_cluster = cluster - 2;
-        self.data_start_sector + (data_cluster * self.sectors_per_cluster)
+        data_cluster.checked_mul(self.sectors_per_cluster)
+            .and_then(|offset| offset.checked_add(self.data_start_sector))
+            .ok_or("Cluster's sector number overflows")
     }
     
-    // Read the FAT to get the next cluster in a chain
-    fn get_next_cluster(&self, cluster: u32) -> Result<u32, &'static str> {
-        let fat_offset = cluster * 4; // Each FAT entry is 4 bytes
-        let fat_sector = self.fat_start_sector + (fat_offset / self.bytes_per_sector);
-        let entry_offset = (fat_offset % self.bytes_per_sector) as usize;
+    // The underlying disk
+    pub fn disk(&self) -> &D {
+        &self.disk
+    }
+    
+    // Size of a cluster in bytes, the most a single `read` returns
+    pub fn cluster_size(&self) -> usize {
+        (self.sectors_per_cluster * self.bytes_per_sector) as usize
+    }
+    
+    // Sector (relative to the start of a FAT) and byte offset of a cluster's FAT entry
+    fn fat_entry_location(&self, cluster: u32) -> Result<(u32, usize), &'static str> {
+        // Each FAT entry is 4 bytes
+        let fat_offset = cluster.checked_mul(4).ok_or("Cluster's FAT entry offset overflows")?;
+        Ok((fat_offset / self.bytes_per_sector, (fat_offset % self.bytes_per_sector) as usize))
+    }
+    
+    // Absolute sector of `sector` within FAT copy `fat`
+    fn fat_sector(&self, fat: u32, sector: u32) -> Result<u32, &'static str> {
+        fat.checked_mul(self.fat_size)
+            .and_then(|offset| offset.checked_add(sector))
+            .and_then(|offset| offset.checked_add(self.fat_start_sector))
+            .ok_or("FAT sector number overflows")
+    }
+    
+    // Read a cluster's FAT entry from the first FAT
+    fn read_fat_entry(&self, cluster: u32) -> Result<u32, &'static str> {
+        let (sector, entry_offset) = self.fat_entry_location(cluster)?;
         
-        let mut buffer = [0u8; BYTES_PER_SECTOR];
-        self.disk.read_sector(fat_sector, &mut buffer)?;
+        let mut buffer = vec![0u8; self.bytes_per_sector as usize];
+        self.disk.read_sector(self.fat_sector(0, sector)?, &mut buffer)?;
         
-        let next_cluster = u32::from_le_bytes(buffer[entry_offset..entry_offset+4]
+        let entry = u32::from_le_bytes(buffer[entry_offset..entry_offset+4]
             .try_into()
             .map_err(|_| "Invalid FAT entry")?);
         
         // Mask out the top 4 bits (reserved in FAT32)
-        let next_cluster = next_cluster & 0x0FFFFFFF;
+        Ok(entry & 0x0FFFFFFF)
+    }
+    
+    // Set a cluster's FAT entry in every FAT copy
+    fn write_fat_entry(&mut self, cluster: u32, value: u32) -> Result<(), &'static str> {
+        let (sector, entry_offset) = self.fat_entry_location(cluster)?;
+        
+        let mut buffer = vec![0u8; self.bytes_per_sector as usize];
+        for fat in 0..self.fat_count {
+            let fat_sector = self.fat_sector(fat, sector)?;
+            self.disk.read_sector(fat_sector, &mut buffer)?;
+            
+            // The top 4 bits are reserved and must be preserved
+            let old = u32::from_le_bytes(buffer[entry_offset..entry_offset+4]
+                .try_into()
+                .map_err(|_| "Invalid FAT entry")?);
+            let new = (old & 0xF0000000) | (value & 0x0FFFFFFF);
+            buffer[entry_offset..entry_offset+4].copy_from_slice(&new.to_le_bytes());
+            
+            self.disk.write_sector(fat_sector, &buffer)?;
+        }
+        
+        Ok(())
+    }
+    
+    // Find a free cluster, mark it end-of-chain and return its number
+    pub fn allocate_cluster(&mut self) -> Result<u32, FsError> {
+        // Valid data clusters are 2..total_clusters + 2
+        let first = 2;
+        let end = self.total_clusters + 2;
+        let hint = self.next_free_cluster.clamp(first, end);
+        
+        // Scan from the hint to the end, then wrap around to the start
+        for cluster in (hint..end).chain(first..hint) {
+            if self.read_fat_entry(cluster)? == 0 {
+                self.write_fat_entry(cluster, 0x0FFFFFF8)?;
+                self.next_free_cluster = cluster + 1;
+                if let Some(free) = self.free_clusters.as_mut() {
+                    *free = free.saturating_sub(1);
+                }
+                self.write_fs_info()?;
+                return Ok(cluster);
+            }
+        }
+        
+        Err(FsError::NoSpace)
+    }
+    
+    // Release every cluster in the chain starting at `first`
+    pub fn free_chain(&mut self, first: u32) -> Result<(), &'static str> {
+        let end = self.total_clusters + 2;
+        let mut cluster = first;
+        let mut freed = 0;
+        
+        while (2..end).contains(&cluster) && freed < self.total_clusters {
+            let next = self.get_next_cluster(cluster)?;
+            self.write_fat_entry(cluster, 0)?;
+            freed += 1;
+            cluster = next;
+        }
+        
+        if let Some(free) = self.free_clusters.as_mut() {
+            *free = (*free + freed).min(self.total_clusters);
+        }
+        self.write_fs_info()
+    }
+    
+    // Read the volume's FsInfo sector
+    pub fn read_fs_info(&self) -> Result<FsInfo, &'static str> {
+        if self.fs_info_sector == 0 || self.fs_info_sector == 0xFFFF {
+            return Err("No FsInfo sector");
+        }
+        
+        let mut buffer = vec![0u8; self.bytes_per_sector as usize];
+        self.disk.read_sector(self.fs_info_sector, &mut buffer)?;
+        FsInfo::parse(&buffer)
+    }
+    
+    // Store the cached free count and next-free hint in the FsInfo sector, if any
+    fn write_fs_info(&mut self) -> Result<(), &'static str> {
+        if self.read_fs_info().is_err() {
+            return Ok(()); // Nothing to keep up to date
+        }
+        
+        let info = FsInfo {
+            free_count: self.free_clusters.unwrap_or(FSINFO_UNKNOWN),
+            next_free: self.next_free_cluster,
+        };
+        let mut buffer = vec![0u8; self.bytes_per_sector as usize];
+        self.disk.read_sector(self.fs_info_sector, &mut buffer)?;
+        info.store(&mut buffer);
+        self.disk.write_sector(self.fs_info_sector, &buffer)
+    }
+    
+    // Count free clusters by scanning the whole FAT
+    pub fn count_free_clusters(&self) -> Result<u32, &'static str> {
+        let mut free = 0;
+        for cluster in 2..self.total_clusters + 2 {
+            if self.read_fat_entry(cluster)? == 0 {
+                free += 1;
+            }
+        }
+        Ok(free)
+    }
+    
+    // Number of free clusters, from FsInfo when it was known, else by scanning the FAT
+    pub fn free_cluster_count(&mut self) -> Result<u32, &'static str> {
+        if let Some(free) = self.free_clusters {
+            return Ok(free);
+        }
+        
+        let free = self.count_free_clusters()?;
+        self.free_clusters = Some(free);
+        Ok(free)
+    }
+    
+    // Point `prev`'s FAT entry at `next`
+    pub fn link_clusters(&mut self, prev: u32, next: u32) -> Result<(), &'static str> {
+        let end = self.total_clusters + 2;
+        if prev < 2 || prev >= end || next < 2 || next >= end {
+            return Err("Invalid cluster number");
+        }
+        
+        self.write_fat_entry(prev, next)
+    }
+    
+    // Read the FAT to get the next cluster in a chain
+    pub fn get_next_cluster(&self, cluster: u32) -> Result<u32, &'static str> {
+        self.check_cluster(cluster)?;
+        
+        let next_cluster = self.read_fat_entry(cluster)?;
         
         // Check for end-of-chain marker
         if next_cluster >= 0x0FFFFFF8 {
@@ -238,12 +725,15 @@ impl<D: Disk> FileSystem<D> {
     
     // Read a full cluster into a buffer
     fn read_cluster(&self, cluster: u32, buffer: &mut [u8]) -> Result<(), &'static str> {
-        let start_sector = self.cluster_to_sector(cluster);
+        let start_sector = self.cluster_to_sector(cluster)?;
         let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
         
         if buffer.len() < cluster_size {
             return Err("Buffer too small for cluster");
         }
+        if start_sector.checked_add(self.sectors_per_cluster - 1).is_none() {
+            return Err("Cluster's sector number overflows");
+        }
         
         for i in 0..self.sectors_per_cluster {
             let sector = start_sector + i;
@@ -255,8 +745,29 @@ impl<D: Disk> FileSystem<D> {
         Ok(())
     }
     
+    // Write a full cluster from a buffer
+    fn write_cluster(&mut self, cluster: u32, buffer: &[u8]) -> Result<(), &'static str> {
+        let start_sector = self.cluster_to_sector(cluster)?;
+        let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
+        
+        if buffer.len() < cluster_size {
+            return Err("Buffer too small for cluster");
+        }
+        if start_sector.checked_add(self.sectors_per_cluster - 1).is_none() {
+            return Err("Cluster's sector number overflows");
+        }
+        
+        for i in 0..self.sectors_per_cluster {
+            let sector = start_sector + i;
+            let offset = (i * self.bytes_per_sector) as usize;
+            self.disk.write_sector(sector, &buffer[offset..offset + self.bytes_per_sector as usize])?;
+        }
+        
+        Ok(())
+    }
+    
     // Find a file or directory by name in a directory cluster
-    fn find_in_directory(&self, dir_cluster: u32, name: &str) -> Result<Option<DirectoryEntry>, &'static str> {
+    fn find_in_directory(&self, dir_cluster: u32, name: &str) -> Result<Option<(DirectoryEntry, EntryLocation)>, &'static str> {
         let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
         let mut buffer = vec![0u8; cluster_size];
         let mut current_cluster = dir_cluster;
@@ -271,18 +782,20 @@ impl<D: Disk> FileSystem<D> {
             
             for i in 0..entries_per_cluster {
                 let offset = i * core::mem::size_of::<DirectoryEntry>();
-                let entry_ptr = buffer[offset..].as_ptr() as *const DirectoryEntry;
-                
-                // Safety: This assumes DirectoryEntry matches disk format exactly
-                let entry = unsafe { &*entry_ptr };
+                let entry: DirectoryEntry = from_bytes(&buffer[offset..]).ok_or("Directory entry out of bounds")?;
                 
-                if entry.is_free() {
+                // The volume label isn't a file, even if named like one
+                if entry.is_free() || entry.is_volume_label() {
                     continue;
                 }
                 
                 let entry_name = entry.get_name();
                 if entry_name.to_uppercase() == name_upper {
-                    return Ok(Some(*entry));
+                    let location = EntryLocation {
+                        sector: self.cluster_to_sector(current_cluster)? + (offset as u32 / self.bytes_per_sector),
+                        offset: offset % self.bytes_per_sector as usize,
+                    };
+                    return Ok(Some((entry, location)));
                 }
             }
             
@@ -294,29 +807,29 @@ impl<D: Disk> FileSystem<D> {
     }
     
     // Follow a path to find a file or directory
-    fn find_by_path(&self, path: &str) -> Result<Option<DirectoryEntry>, &'static str> {
+    fn find_by_path(&self, path: &str) -> Result<Option<(DirectoryEntry, EntryLocation)>, FsError> {
         let mut current_cluster = self.root_dir_cluster;
         
         // Split the path into components
         let components: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
         
         if components.is_empty() {
-            return Err("Invalid path");
+            return Err(FsError::InvalidPath);
         }
         
         // Navigate through directories
         for (i, component) in components.iter().enumerate() {
             match self.find_in_directory(current_cluster, component)? {
-                Some(entry) => {
+                Some((entry, location)) => {
                     if i == components.len() - 1 {
                         // Last component, return the entry
-                        return Ok(Some(entry));
+                        return Ok(Some((entry, location)));
                     } else if entry.is_directory() {
                         // Continue to the next directory
                         current_cluster = entry.get_first_cluster();
                     } else {
                         // Not a directory but not the last component
-                        return Err("Not a directory");
+                        return Err(FsError::NotADirectory);
                     }
                 }
                 None => return Ok(None),
@@ -326,6 +839,153 @@ impl<D: Disk> FileSystem<D> {
         Ok(None)
     }
     
+    // Index of an open file by handle ID
+    fn open_file_index(&self, handle: &FileHandle) -> Result<usize, FsError> {
+        self.open_files.iter().position(|file| file.handle.id == handle.id).ok_or(FsError::InvalidHandle)
+    }
+    
+    // Grow an open file's cluster chain until it has a cluster at `cluster_index`
+    fn extend_chain(&mut self, file_index: usize, cluster_index: usize) -> Result<(), FsError> {
+        while self.open_files[file_index].chain.len() <= cluster_index {
+            let cluster = self.allocate_cluster()?;
+            if let Some(&last) = self.open_files[file_index].chain.last() {
+                self.link_clusters(last, cluster)?;
+            }
+            self.open_files[file_index].chain.push(cluster);
+        }
+        Ok(())
+    }
+    
+    // Write an open file's buffered cluster back to disk
+    fn flush_write_buffer(&mut self, file_index: usize) -> Result<(), &'static str> {
+        if let Some((cluster_index, data)) = self.open_files[file_index].write_buffer.take() {
+            let cluster = self.open_files[file_index].chain[cluster_index];
+            self.write_cluster(cluster, &data)?;
+        }
+        Ok(())
+    }
+    
+    // Make the cluster at `cluster_index` the open file's write buffer
+    fn load_write_buffer(&mut self, file_index: usize, cluster_index: usize) -> Result<(), &'static str> {
+        if matches!(self.open_files[file_index].write_buffer, Some((index, _)) if index == cluster_index) {
+            return Ok(());
+        }
+        
+        self.flush_write_buffer(file_index)?;
+        
+        let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
+        let mut data = vec![0u8; cluster_size];
+        self.read_cluster(self.open_files[file_index].chain[cluster_index], &mut data)?;
+        self.open_files[file_index].write_buffer = Some((cluster_index, data));
+        
+        Ok(())
+    }
+    
+    // Update an open file's directory entry with its size and first cluster
+    fn write_directory_entry(&mut self, file_index: usize) -> Result<(), &'static str> {
+        let file = &self.open_files[file_index];
+        let location = file.entry;
+        let size = file.handle.size as u32;
+        let first_cluster = file.chain.first().copied().unwrap_or(0);
+        
+        let mut buffer = vec![0u8; self.bytes_per_sector as usize];
+        self.disk.read_sector(location.sector, &mut buffer)?;
+        
+        // Offsets of the cluster and size fields within a DirectoryEntry
+        let entry = &mut buffer[location.offset..location.offset + 32];
+        entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+        entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        
+        self.disk.write_sector(location.sector, &buffer)
+    }
+    
+    // Look up the directory entry for a path
+    pub fn lookup(&self, path: &str) -> Result<Option<DirectoryEntry>, FsError> {
+        Ok(self.find_by_path(path)?.map(|(entry, _)| entry))
+    }
+    
+    // Iterate over the entries of a directory, reading one cluster at a time
+    pub fn dir_iter(&self, path: &str) -> Result<DirIter<'_, D>, FsError> {
+        let cluster = if path.trim_matches('/').is_empty() {
+            self.root_dir_cluster
+        } else {
+            match self.find_by_path(path)? {
+                Some((entry, _)) if entry.is_directory() => entry.get_first_cluster(),
+                Some(_) => return Err(FsError::NotADirectory),
+                None => return Err(FsError::NotFound),
+            }
+        };
+        
+        Ok(DirIter {
+            fs: self,
+            cluster,
+            buffer: vec![0u8; self.cluster_size()],
+            index: None,
+        })
+    }
+    
+    // Split a path into the cluster of its parent directory and its final component
+    fn parent_directory<'a>(&self, path: &'a str) -> Result<(u32, &'a str), FsError> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = match path.rfind('/') {
+            Some(slash) => (&path[..slash], &path[slash + 1..]),
+            None => ("", path),
+        };
+        
+        if name.is_empty() {
+            return Err(FsError::InvalidPath);
+        }
+        
+        if parent.trim_matches('/').is_empty() {
+            return Ok((self.root_dir_cluster, name));
+        }
+        
+        match self.find_by_path(parent)? {
+            Some((entry, _)) if entry.is_directory() => Ok((entry.get_first_cluster(), name)),
+            Some(_) => Err(FsError::NotADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+    
+    // Store an entry in the first free slot of a directory, growing it if full
+    fn add_directory_entry(&mut self, dir_cluster: u32, entry: &DirectoryEntry) -> Result<EntryLocation, FsError> {
+        let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
+        let entry_size = core::mem::size_of::<DirectoryEntry>();
+        let mut buffer = vec![0u8; cluster_size];
+        let mut current_cluster = dir_cluster;
+        
+        loop {
+            self.read_cluster(current_cluster, &mut buffer)?;
+            
+            let free_slot = (0..cluster_size / entry_size)
+                .map(|i| i * entry_size)
+                .find(|&offset| buffer[offset] == 0x00 || buffer[offset] == 0xE5);
+            
+            if let Some(offset) = free_slot {
+                // The slot is entry_size bytes inside the buffer
+                buffer[offset..offset + entry_size].copy_from_slice(as_bytes(entry));
+                self.write_cluster(current_cluster, &buffer)?;
+                return Ok(EntryLocation {
+                    sector: self.cluster_to_sector(current_cluster)? + (offset as u32 / self.bytes_per_sector),
+                    offset: offset % self.bytes_per_sector as usize,
+                });
+            }
+            
+            let next_cluster = self.get_next_cluster(current_cluster)?;
+            if next_cluster != 0 {
+                current_cluster = next_cluster;
+                continue;
+            }
+            
+            // Every slot is taken: append an empty cluster to the directory
+            let new_cluster = self.allocate_cluster()?;
+            self.write_cluster(new_cluster, &vec![0u8; cluster_size])?;
+            self.link_clusters(current_cluster, new_cluster)?;
+            current_cluster = new_cluster;
+        }
+    }
+    
     // Build a cluster chain for a file
     fn build_cluster_chain(&self, start_cluster: u32) -> Result<Vec<u32>, &'static str> {
         let mut chain = Vec::new();
@@ -343,13 +1003,263 @@ impl<D: Disk> FileSystem<D> {
         
         Ok(chain)
     }
+    
+    // A file's chain only ends at a free cluster if the file is sparse or was
+    // partly written: that cluster and every one after it up to the file size
+    // are holes, marked as cluster 0 in the chain
+    fn mark_holes(&self, chain: &mut Vec<u32>, size: usize) -> Result<(), &'static str> {
+        let last = match chain.last() {
+            Some(&last) => last,
+            None => return Ok(()),
+        };
+        if self.read_fat_entry(last)? != 0 {
+            return Ok(());
+        }
+        
+        let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
+        chain.pop();
+        chain.resize(size.div_ceil(cluster_size).max(chain.len()), 0);
+        Ok(())
+    }
+    
+    // Read the raw FAT entry of a data cluster
+    pub fn fat_entry(&self, cluster: u32) -> Result<u32, &'static str> {
+        self.check_cluster(cluster)?;
+        self.read_fat_entry(cluster)
+    }
+    
+    // Number of clusters in the chain starting at `start`
+    pub fn chain_length(&self, start: u32) -> Result<usize, &'static str> {
+        Ok(self.build_cluster_chain(start)?.len())
+    }
+    
+    // Find clusters that belong to more than one chain, walking every
+    // directory from the root. Returns them sorted, each listed once.
+    pub fn check_crosslinks(&self) -> Result<Vec<u32>, &'static str> {
+        let mut owned = Vec::new();
+        let mut directories = vec![self.root_dir_cluster];
+        let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
+        let mut buffer = vec![0u8; cluster_size];
+        
+        while let Some(dir_cluster) = directories.pop() {
+            let dir_chain = self.build_cluster_chain(dir_cluster)?;
+            owned.extend_from_slice(&dir_chain);
+            
+            for cluster in dir_chain {
+                self.read_cluster(cluster, &mut buffer)?;
+                
+                for offset in (0..cluster_size).step_by(core::mem::size_of::<DirectoryEntry>()) {
+                    let entry: DirectoryEntry = from_bytes(&buffer[offset..]).ok_or("Directory entry out of bounds")?;
+                    
+                    // Skip free slots, "." and "..", long-name entries and the volume label
+                    if entry.is_free() || entry.name[0] == b'.' || entry.is_volume_label() {
+                        continue;
+                    }
+                    
+                    let first = entry.get_first_cluster();
+                    if first < 2 {
+                        continue;
+                    }
+                    
+                    if entry.is_directory() {
+                        // A directory reached twice is itself cross-linked; don't walk it again
+                        if owned.contains(&first) {
+                            owned.push(first);
+                        } else {
+                            directories.push(first);
+                        }
+                    } else {
+                        owned.extend(self.build_cluster_chain(first)?);
+                    }
+                }
+            }
+        }
+        
+        owned.sort_unstable();
+        let mut shared: Vec<u32> = owned.windows(2)
+            .filter(|pair| pair[0] == pair[1])
+            .map(|pair| pair[0])
+            .collect();
+        shared.dedup();
+        Ok(shared)
+    }
+}
+
+// Format a disk as an empty FAT32 volume with the default geometry
+pub fn format<D: Disk>(disk: &mut D) -> Result<(), &'static str> {
+    format_with_cluster_size(disk, FORMAT_SECTORS_PER_CLUSTER)
+}
+
+// Format a disk as an empty FAT32 volume with the given number of sectors per cluster
+pub fn format_with_cluster_size<D: Disk>(disk: &mut D, sectors_per_cluster: u8) -> Result<(), &'static str> {
+    if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+        return Err("Invalid cluster size");
+    }
+    
+    let total_sectors = disk.total_sectors();
+    let cluster_sectors = sectors_per_cluster as u32;
+    let reserved_sectors = FORMAT_RESERVED_SECTORS;
+    let root_dir_sectors = FORMAT_ROOT_DIR_CLUSTERS * cluster_sectors;
+    
+    // Size each FAT to cover every cluster the data region could hold
+    let data_sectors = total_sectors.checked_sub(reserved_sectors).ok_or("Disk too small to format")?;
+    let fat_entries = data_sectors / cluster_sectors + 2;
+    let fat_size = (fat_entries * 4).div_ceil(FORMAT_BYTES_PER_SECTOR as u32);
+    let data_start_sector = reserved_sectors + FORMAT_FAT_COUNT * fat_size;
+    
+    if total_sectors < data_start_sector + root_dir_sectors {
+        return Err("Disk too small to format");
+    }
+    
+    let boot_sector = FatBootSector {
+        jmp_boot: [0xEB, 0x58, 0x90],
+        oem_name: *b"RUSTKRNL",
+        bytes_per_sector: FORMAT_BYTES_PER_SECTOR as u16,
+        sectors_per_cluster,
+        reserved_sector_count: FORMAT_RESERVED_SECTORS as u16,
+        fat_count: FORMAT_FAT_COUNT as u8,
+        root_entry_count: 0,
+        total_sectors_16: 0,
+        media_type: 0xF8,
+        sectors_per_fat_16: 0,
+        sectors_per_track: 0,
+        head_count: 0,
+        hidden_sectors: 0,
+        total_sectors_32: total_sectors,
+        sectors_per_fat_32: fat_size,
+        ext_flags: 0,
+        fs_version: 0,
+        root_cluster: 2,
+        fs_info: 1,
+        backup_boot_sector: 6,
+        reserved: [0; 12],
+        drive_number: 0x80,
+        reserved1: 0,
+        boot_signature: 0x29,
+        volume_id: 0x1234_5678,
+        volume_label: *b"NO NAME    ",
+        fs_type: *b"FAT32   ",
+    };
+    
+    // Zero the reserved area, both FATs and the root directory
+    let zero = [0u8; FORMAT_BYTES_PER_SECTOR];
+    for sector in 0..data_start_sector + root_dir_sectors {
+        disk.write_sector(sector, &zero)?;
+    }
+    
+    // Write the boot sector and its backup
+    let mut buffer = [0u8; FORMAT_BYTES_PER_SECTOR];
+    let boot_bytes = as_bytes(&boot_sector);
+    buffer[..boot_bytes.len()].copy_from_slice(boot_bytes);
+    buffer[510] = 0x55;
+    buffer[511] = 0xAA;
+    disk.write_sector(0, &buffer)?;
+    disk.write_sector(6, &buffer)?;
+    
+    // Everything but the root directory is free
+    let total_clusters = (total_sectors - data_start_sector) / cluster_sectors;
+    let fs_info = FsInfo {
+        free_count: total_clusters - FORMAT_ROOT_DIR_CLUSTERS,
+        next_free: 2 + FORMAT_ROOT_DIR_CLUSTERS,
+    };
+    let mut buffer = [0u8; FORMAT_BYTES_PER_SECTOR];
+    fs_info.store(&mut buffer);
+    disk.write_sector(1, &buffer)?;
+    
+    // FAT entries 0 and 1 are reserved, the root directory chain starts at cluster 2
+    let mut fat_sector = [0u8; FORMAT_BYTES_PER_SECTOR];
+    fat_sector[0..4].copy_from_slice(&0x0FFFFFF8u32.to_le_bytes());
+    fat_sector[4..8].copy_from_slice(&0x0FFFFFFFu32.to_le_bytes());
+    for i in 0..FORMAT_ROOT_DIR_CLUSTERS {
+        let cluster = 2 + i;
+        let next = if i + 1 == FORMAT_ROOT_DIR_CLUSTERS { 0x0FFFFFFF } else { cluster + 1 };
+        let offset = cluster as usize * 4;
+        fat_sector[offset..offset + 4].copy_from_slice(&next.to_le_bytes());
+    }
+    for fat in 0..FORMAT_FAT_COUNT {
+        disk.write_sector(reserved_sectors + fat * fat_size, &fat_sector)?;
+    }
+    
+    Ok(())
+}
+
+// Iterator over the files and subdirectories of a directory, from `dir_iter`.
+// Only the current cluster is kept in memory; free slots, long name entries
+// and the volume label are skipped. Stops after the first error.
+pub struct DirIter<'a, D: Disk> {
+    fs: &'a FileSystem<D>,
+    cluster: u32,
+    buffer: Vec<u8>,
+    // Next entry in `buffer`, None until `cluster` has been read
+    index: Option<usize>,
+}
+
+impl<D: Disk> DirIter<'_, D> {
+    // Stop iterating, passing on the error that ended it
+    fn fail(&mut self, error: &'static str) -> Option<Result<DirectoryEntry, &'static str>> {
+        self.cluster = 0;
+        Some(Err(error))
+    }
+}
+
+impl<D: Disk> Iterator for DirIter<'_, D> {
+    type Item = Result<DirectoryEntry, &'static str>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry_size = core::mem::size_of::<DirectoryEntry>();
+        
+        while self.cluster != 0 {
+            let index = match self.index {
+                Some(index) => index,
+                None => {
+                    if let Err(e) = self.fs.read_cluster(self.cluster, &mut self.buffer) {
+                        return self.fail(e);
+                    }
+                    0
+                }
+            };
+            
+            // Move on to the next cluster in the chain
+            if index * entry_size >= self.buffer.len() {
+                match self.fs.get_next_cluster(self.cluster) {
+                    Ok(next) => self.cluster = next,
+                    Err(e) => return self.fail(e),
+                }
+                self.index = None;
+                continue;
+            }
+            self.index = Some(index + 1);
+            
+            let entry: DirectoryEntry = match from_bytes(&self.buffer[index * entry_size..]) {
+                Some(entry) => entry,
+                None => return self.fail("Directory entry out of bounds"),
+            };
+            
+            // A zero first byte marks the end of the directory
+            if entry.name[0] == 0x00 {
+                self.cluster = 0;
+                break;
+            }
+            
+            if !entry.is_free() && !entry.is_volume_label() {
+                return Some(Ok(entry));
+            }
+        }
+        
+        None
+    }
 }
 
 impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
-    fn init(&mut self) -> Result<(), &'static str> {
+    fn init(&mut self) -> Result<(), FsError> {
         // Read the boot sector
         let boot_sector = self.read_boot_sector()?;
         
+        // All geometry below is derived from these, so reject nonsense early
+        if boot_sector.bytes_per_sector < BOOT_SECTOR_SIZE as u16 || boot_sector.sectors_per_cluster == 0 {
+            return Err(FsError::Io("Invalid boot sector geometry"));
+        }
+        
         // Initialize filesystem parameters
         self.bytes_per_sector = boot_sector.bytes_per_sector as u32;
         self.sectors_per_cluster = boot_sector.sectors_per_cluster as u32;
@@ -357,8 +1267,32 @@ impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
         
         // Calculate important sector locations
         self.fat_start_sector = boot_sector.reserved_sector_count as u32;
-        let fat_size = boot_sector.sectors_per_fat_32;
-        self.data_start_sector = self.fat_start_sector + (NUM_FATS as u32 * fat_size);
+        self.fat_size = boot_sector.sectors_per_fat_32;
+        self.fat_count = boot_sector.fat_count as u32;
+        self.data_start_sector = self.fat_count.checked_mul(self.fat_size)
+            .and_then(|fats| fats.checked_add(self.fat_start_sector))
+            .ok_or("FAT size overflows the volume")?;
+        
+        // Count the clusters that fit in the data region
+        let total_sectors = match boot_sector.total_sectors_16 {
+            0 => boot_sector.total_sectors_32,
+            n => n as u32,
+        };
+        self.total_clusters = total_sectors.saturating_sub(self.data_start_sector)
+            / self.sectors_per_cluster;
+        self.next_free_cluster = 2;
+        
+        // Use FsInfo's cached allocation state, unless it is unknown or implausible
+        self.fs_info_sector = boot_sector.fs_info as u32;
+        self.free_clusters = None;
+        if let Ok(info) = self.read_fs_info() {
+            if info.free_count <= self.total_clusters {
+                self.free_clusters = Some(info.free_count);
+            }
+            if (2..self.total_clusters + 2).contains(&info.next_free) {
+                self.next_free_cluster = info.next_free;
+            }
+        }
         
         println!("FAT32 filesystem initialized:");
         println!("  Bytes per sector: {}", self.bytes_per_sector);
@@ -370,15 +1304,15 @@ impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
         Ok(())
     }
     
-    fn open(&mut self, path: &str) -> Result<FileHandle, &'static str> {
+    fn open(&mut self, path: &str) -> Result<FileHandle, FsError> {
         // Find the file by path
-        let entry = match self.find_by_path(path)? {
-            Some(entry) => entry,
-            None => return Err("File not found"),
+        let (entry, location) = match self.find_by_path(path)? {
+            Some(found) => found,
+            None => return Err(FsError::NotFound),
         };
         
         if entry.is_directory() {
-            return Err("Cannot open a directory as a file");
+            return Err(FsError::IsADirectory);
         }
         
         let handle = FileHandle {
@@ -387,11 +1321,21 @@ impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
             size: entry.file_size as usize,
         };
         
-        // Build the cluster chain for the file
-        let cluster_chain = self.build_cluster_chain(entry.get_first_cluster())?;
+        // Build the cluster chain for the file (empty files have no clusters)
+        let mut cluster_chain = match entry.get_first_cluster() {
+            0 => Vec::new(),
+            first_cluster => self.build_cluster_chain(first_cluster)?,
+        };
+        self.mark_holes(&mut cluster_chain, handle.size)?;
         
         // Store the file handle and its cluster chain
-        self.open_files.push((handle, cluster_chain));  // This now works because handle implements Copy
+        self.open_files.push(OpenFile {
+            handle,  // This now works because handle implements Copy
+            chain: cluster_chain,
+            entry: location,
+            write_buffer: None,
+            dirty: false,
+        });
         
         // Increment the next file handle ID
         self.next_file_handle_id += 1;
@@ -399,12 +1343,26 @@ impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
         Ok(handle)  // Returns a copy of the handle
     }
     
-    fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    fn create(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        let (dir_cluster, name) = self.parent_directory(path)?;
+        if self.find_in_directory(dir_cluster, name)?.is_some() {
+            return Err(FsError::AlreadyExists);
+        }
+        
+        // New files start empty, without any clusters
+        let entry = DirectoryEntry::new_file(name).map_err(|_| FsError::InvalidPath)?;
+        self.add_directory_entry(dir_cluster, &entry)?;
+        
+        self.open(path)
+    }
+    
+    fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
         // Find the file in the open files list
-        let chain = match self.open_files.iter().find(|(h, _)| h.id == handle.id) {
-            Some((_, chain)) => chain,
-            None => return Err("Invalid file handle"),
+        let file = match self.open_files.iter().find(|file| file.handle.id == handle.id) {
+            Some(file) => file,
+            None => return Err(FsError::InvalidHandle),
         };
+        let chain = &file.chain;
         
         // Check if we're at EOF
         if handle.position >= handle.size {
@@ -416,18 +1374,28 @@ impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
         let cluster_index = handle.position / cluster_size;
         
         if cluster_index >= chain.len() {
-            return Err("Invalid cluster index");
+            return Err(FsError::Io("Invalid cluster index"));
         }
         
         let cluster = chain[cluster_index];
         let cluster_offset = handle.position % cluster_size;
         
-        // Calculate how much to read
-        let bytes_to_read = buffer.len().min(handle.size - handle.position);
+        // Calculate how much to read, stopping at the end of the cluster
+        let bytes_to_read = buffer.len()
+            .min(handle.size - handle.position)
+            .min(cluster_size - cluster_offset);
         
         // Read the data
         let mut temp_buffer = vec![0u8; cluster_size];
-        self.read_cluster(cluster, &mut temp_buffer)?;
+        match &file.write_buffer {
+            // Unflushed writes are only in the buffer
+            Some((index, data)) if *index == cluster_index => temp_buffer.copy_from_slice(data),
+            // Holes read as zeros rather than whatever the free cluster holds
+            _ if cluster == 0 => println!(
+                "Warning: reading a hole at cluster {} of file {}", cluster_index, handle.id
+            ),
+            _ => self.read_cluster(cluster, &mut temp_buffer)?,
+        }
         
         buffer[..bytes_to_read].copy_from_slice(&temp_buffer[cluster_offset..cluster_offset + bytes_to_read]);
         
@@ -437,22 +1405,77 @@ impl<D: Disk> crate::fs::FileSystem for FileSystem<D> {
         Ok(bytes_to_read)
     }
     
-    fn write(&mut self, _handle: &mut FileHandle, _buffer: &[u8]) -> Result<usize, &'static str> {
-        // Writing is more complex and involves FAT updates
-        // For simplicity, our initial implementation is read-only
-        Err("Write operations not implemented")
+    fn write(&mut self, handle: &mut FileHandle, buffer: &[u8]) -> Result<usize, FsError> {
+        let file_index = self.open_file_index(handle)?;
+        let cluster_size = (self.sectors_per_cluster * self.bytes_per_sector) as usize;
+        
+        // Data is buffered one cluster at a time; FAT updates go straight to disk
+        let mut written = 0;
+        while written < buffer.len() {
+            let cluster_index = handle.position / cluster_size;
+            let cluster_offset = handle.position % cluster_size;
+            
+            self.extend_chain(file_index, cluster_index)?;
+            self.load_write_buffer(file_index, cluster_index)?;
+            
+            let n = (cluster_size - cluster_offset).min(buffer.len() - written);
+            if let Some((_, data)) = self.open_files[file_index].write_buffer.as_mut() {
+                data[cluster_offset..cluster_offset + n].copy_from_slice(&buffer[written..written + n]);
+            }
+            
+            written += n;
+            handle.position += n;
+            handle.size = handle.size.max(handle.position);
+        }
+        
+        let file = &mut self.open_files[file_index];
+        file.handle.size = handle.size;
+        file.dirty = true;
+        
+        Ok(written)
+    }
+    
+    fn flush(&mut self, handle: &mut FileHandle) -> Result<(), FsError> {
+        let file_index = self.open_file_index(handle)?;
+        if !self.open_files[file_index].dirty {
+            return Ok(());
+        }
+        
+        // File data first, then the directory entry that describes it
+        self.flush_write_buffer(file_index)?;
+        self.open_files[file_index].handle.size = handle.size;
+        self.write_directory_entry(file_index)?;
+        self.disk.flush()?;
+        self.open_files[file_index].dirty = false;
+        
+        Ok(())
     }
     
-    fn close(&mut self, handle: FileHandle) -> Result<(), &'static str> {
+    fn close(&mut self, mut handle: FileHandle) -> Result<(), FsError> {
+        // Write back anything still buffered
+        self.flush(&mut handle)?;
+        
         // Remove the file from the open files list
-        let position = self.open_files.iter().position(|(h, _)| h.id == handle.id);
+        let position = self.open_files.iter().position(|file| file.handle.id == handle.id);
         
         match position {
             Some(index) => {
                 self.open_files.remove(index);
                 Ok(())
             }
-            None => Err("Invalid file handle"),
+            None => Err(FsError::InvalidHandle),
+        }
+    }
+    
+    fn readdir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        let mut names = Vec::new();
+        for entry in self.dir_iter(path)? {
+            let entry = entry?;
+            // Every directory but the root starts with "." and ".."
+            if entry.name[0] != b'.' {
+                names.push(entry.get_name());
+            }
         }
+        Ok(names)
     }
 }
\ No newline at end of file
@@ -1,13 +1,117 @@
 pub mod fat32;
+pub mod disk;
+pub mod cache;
+pub mod tmpfs;
+pub mod shared;
+pub mod buffered;
 
 pub use fat32::FileSystem as Fat32FileSystem;
+pub use tmpfs::TmpFs;
+pub use shared::SharedFileSystem;
+pub use buffered::BufferedFile;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Why a filesystem operation failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+    NotFound,
+    AlreadyExists,
+    NotADirectory,
+    IsADirectory,
+    // A path or file name the filesystem can't represent
+    InvalidPath,
+    InvalidHandle,
+    NoSpace,
+    ReadOnly,
+    Unsupported,
+    // The disk failed, or the volume's on-disk structures are not what they
+    // should be; the message says which
+    Io(&'static str),
+}
+
+impl FsError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FsError::NotFound => "No such file or directory",
+            FsError::AlreadyExists => "File already exists",
+            FsError::NotADirectory => "Not a directory",
+            FsError::IsADirectory => "Is a directory",
+            FsError::InvalidPath => "Invalid path",
+            FsError::InvalidHandle => "Invalid file handle",
+            FsError::NoSpace => "No space left on the volume",
+            FsError::ReadOnly => "Filesystem is read-only",
+            FsError::Unsupported => "Operation not supported",
+            FsError::Io(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for FsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Errors from the disk and on-disk structures, which only have a message
+impl From<&'static str> for FsError {
+    fn from(message: &'static str) -> Self {
+        FsError::Io(message)
+    }
+}
+
+// For callers outside the filesystem, which report errors as strings
+impl From<FsError> for &'static str {
+    fn from(error: FsError) -> Self {
+        error.as_str()
+    }
+}
 
 pub trait FileSystem {
-    fn init(&mut self) -> Result<(), &'static str>;
-    fn open(&mut self, path: &str) -> Result<FileHandle, &'static str>; // Changed from &self to &mut self
-    fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, &'static str>;
-    fn write(&mut self, handle: &mut FileHandle, buffer: &[u8]) -> Result<usize, &'static str>;
-    fn close(&mut self, handle: FileHandle) -> Result<(), &'static str>;
+    fn init(&mut self) -> Result<(), FsError>;
+    fn open(&mut self, path: &str) -> Result<FileHandle, FsError>; // Changed from &self to &mut self
+    // Create an empty file and open it
+    fn create(&mut self, path: &str) -> Result<FileHandle, FsError>;
+    fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError>;
+    // Read until `buffer` is full or the end of the file, across as many
+    // `read` calls (e.g. clusters) as needed. Returns the bytes read.
+    fn read_all(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let mut total = 0;
+        while total < buffer.len() {
+            let n = self.read(handle, &mut buffer[total..])?;
+            if n == 0 {
+                break; // End of file
+            }
+            total += n;
+        }
+        Ok(total)
+    }
+    // Read a whole file into a new buffer, opening and closing it
+    fn read_to_vec(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        let mut handle = self.open(path)?;
+        let mut data = vec![0u8; handle.size];
+        let result = self.read_all(&mut handle, &mut data);
+        self.close(handle)?;
+        
+        // The file may end earlier than its recorded size
+        data.truncate(result?);
+        Ok(data)
+    }
+    fn write(&mut self, handle: &mut FileHandle, buffer: &[u8]) -> Result<usize, FsError>;
+    // Force buffered data and metadata for the file out to the disk
+    fn flush(&mut self, handle: &mut FileHandle) -> Result<(), FsError>;
+    fn close(&mut self, handle: FileHandle) -> Result<(), FsError>;
+    // Create a directory
+    fn mkdir(&mut self, _path: &str) -> Result<(), FsError> {
+        Err(FsError::Unsupported)
+    }
+    // List the names in a directory
+    fn readdir(&self, _path: &str) -> Result<Vec<String>, FsError> {
+        Err(FsError::Unsupported)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]  // Add Copy trait
@@ -0,0 +1,88 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, MutexGuard};
+use crate::fs::{FileHandle, FileSystem, FsError};
+
+/// A filesystem shared between tasks
+///
+/// Clones refer to the same filesystem. Every call locks it for its whole
+/// duration, so opens, reads and writes from different tasks never see each
+/// other's half-updated state (open file tables, cluster chains, write
+/// buffers). Tasks only switch when they yield, and no call yields while
+/// holding the lock, so it can't be held across a task switch; it must not be
+/// used from interrupt handlers, which could interrupt a holder.
+///
+/// Handles stay owned by the task that opened them and are not locked: each
+/// task must only pass its own handles.
+pub struct SharedFileSystem<F: FileSystem> {
+    inner: Arc<Mutex<F>>,
+}
+
+impl<F: FileSystem> SharedFileSystem<F> {
+    pub fn new(fs: F) -> Self {
+        SharedFileSystem {
+            inner: Arc::new(Mutex::new(fs)),
+        }
+    }
+    
+    /// Locks the filesystem for a sequence of calls that must not interleave
+    /// with other tasks'
+    pub fn lock(&self) -> MutexGuard<'_, F> {
+        self.inner.lock()
+    }
+}
+
+impl<F: FileSystem> Clone for SharedFileSystem<F> {
+    fn clone(&self) -> Self {
+        SharedFileSystem {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<F: FileSystem> FileSystem for SharedFileSystem<F> {
+    fn init(&mut self) -> Result<(), FsError> {
+        self.inner.lock().init()
+    }
+    
+    fn open(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        self.inner.lock().open(path)
+    }
+    
+    fn create(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        self.inner.lock().create(path)
+    }
+    
+    fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
+        self.inner.lock().read(handle, buffer)
+    }
+    
+    fn read_all(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
+        self.inner.lock().read_all(handle, buffer)
+    }
+    
+    fn read_to_vec(&mut self, path: &str) -> Result<Vec<u8>, FsError> {
+        self.inner.lock().read_to_vec(path)
+    }
+    
+    fn write(&mut self, handle: &mut FileHandle, buffer: &[u8]) -> Result<usize, FsError> {
+        self.inner.lock().write(handle, buffer)
+    }
+    
+    fn flush(&mut self, handle: &mut FileHandle) -> Result<(), FsError> {
+        self.inner.lock().flush(handle)
+    }
+    
+    fn close(&mut self, handle: FileHandle) -> Result<(), FsError> {
+        self.inner.lock().close(handle)
+    }
+    
+    fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.inner.lock().mkdir(path)
+    }
+    
+    fn readdir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        self.inner.lock().readdir(path)
+    }
+}
@@ -0,0 +1,187 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use crate::fs::{FileHandle, FileSystem, FsError};
+
+// A file or directory in the tree
+enum Node {
+    File(Vec<u8>),
+    Directory(BTreeMap<String, Node>),
+}
+
+// An open file, identified by its path from the root
+struct OpenFile {
+    id: usize,
+    path: Vec<String>,
+}
+
+/// In-memory filesystem backed entirely by heap data structures
+pub struct TmpFs {
+    root: Node,
+    open_files: Vec<OpenFile>,
+    next_file_handle_id: usize,
+}
+
+// Split a path into its non-empty components
+fn components(path: &str) -> Vec<String> {
+    path.split('/').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        TmpFs {
+            root: Node::Directory(BTreeMap::new()),
+            open_files: Vec::new(),
+            next_file_handle_id: 1,
+        }
+    }
+    
+    // Find the node at a path
+    fn node(&self, path: &[String]) -> Option<&Node> {
+        let mut node = &self.root;
+        for name in path {
+            match node {
+                Node::Directory(entries) => node = entries.get(name)?,
+                Node::File(_) => return None,
+            }
+        }
+        Some(node)
+    }
+    
+    // Find the node at a path for modification
+    fn node_mut(&mut self, path: &[String]) -> Option<&mut Node> {
+        let mut node = &mut self.root;
+        for name in path {
+            match node {
+                Node::Directory(entries) => node = entries.get_mut(name)?,
+                Node::File(_) => return None,
+            }
+        }
+        Some(node)
+    }
+    
+    // Add a new node under its parent directory
+    fn insert(&mut self, path: &str, new_node: Node) -> Result<(), FsError> {
+        let mut path = components(path);
+        let name = path.pop().ok_or(FsError::InvalidPath)?;
+        
+        match self.node_mut(&path) {
+            Some(Node::Directory(entries)) => {
+                if entries.contains_key(&name) {
+                    return Err(FsError::AlreadyExists);
+                }
+                entries.insert(name, new_node);
+                Ok(())
+            }
+            Some(Node::File(_)) => Err(FsError::NotADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+    
+    // Path of an open file
+    fn open_file_path(&self, handle: &FileHandle) -> Result<&[String], FsError> {
+        self.open_files.iter()
+            .find(|file| file.id == handle.id)
+            .map(|file| file.path.as_slice())
+            .ok_or(FsError::InvalidHandle)
+    }
+}
+
+impl Default for TmpFs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn init(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+    
+    fn open(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        let path = components(path);
+        let size = match self.node(&path) {
+            Some(Node::File(data)) => data.len(),
+            Some(Node::Directory(_)) => return Err(FsError::IsADirectory),
+            None => return Err(FsError::NotFound),
+        };
+        
+        let handle = FileHandle {
+            id: self.next_file_handle_id,
+            position: 0,
+            size,
+        };
+        self.next_file_handle_id += 1;
+        self.open_files.push(OpenFile { id: handle.id, path });
+        
+        Ok(handle)
+    }
+    
+    fn create(&mut self, path: &str) -> Result<FileHandle, FsError> {
+        self.insert(path, Node::File(Vec::new()))?;
+        self.open(path)
+    }
+    
+    fn read(&self, handle: &mut FileHandle, buffer: &mut [u8]) -> Result<usize, FsError> {
+        let data = match self.node(self.open_file_path(handle)?) {
+            Some(Node::File(data)) => data,
+            _ => return Err(FsError::NotFound),
+        };
+        
+        let start = handle.position.min(data.len());
+        let n = buffer.len().min(data.len() - start);
+        buffer[..n].copy_from_slice(&data[start..start + n]);
+        handle.position += n;
+        
+        Ok(n)
+    }
+    
+    fn write(&mut self, handle: &mut FileHandle, buffer: &[u8]) -> Result<usize, FsError> {
+        let path = self.open_file_path(handle)?.to_vec();
+        let data = match self.node_mut(&path) {
+            Some(Node::File(data)) => data,
+            _ => return Err(FsError::NotFound),
+        };
+        
+        // Writing past the end fills the gap with zeros
+        let end = handle.position + buffer.len();
+        if data.len() < end {
+            data.resize(end, 0);
+        }
+        data[handle.position..end].copy_from_slice(buffer);
+        
+        handle.position = end;
+        handle.size = data.len();
+        
+        Ok(buffer.len())
+    }
+    
+    fn flush(&mut self, handle: &mut FileHandle) -> Result<(), FsError> {
+        // Nothing is buffered, writes land in the tree immediately
+        self.open_file_path(handle).map(|_| ())
+    }
+    
+    fn close(&mut self, handle: FileHandle) -> Result<(), FsError> {
+        let position = self.open_files.iter().position(|file| file.id == handle.id);
+        
+        match position {
+            Some(index) => {
+                self.open_files.remove(index);
+                Ok(())
+            }
+            None => Err(FsError::InvalidHandle),
+        }
+    }
+    
+    fn mkdir(&mut self, path: &str) -> Result<(), FsError> {
+        self.insert(path, Node::Directory(BTreeMap::new()))
+    }
+    
+    fn readdir(&self, path: &str) -> Result<Vec<String>, FsError> {
+        match self.node(&components(path)) {
+            Some(Node::Directory(entries)) => Ok(entries.keys().cloned().collect()),
+            Some(Node::File(_)) => Err(FsError::NotADirectory),
+            None => Err(FsError::NotFound),
+        }
+    }
+}
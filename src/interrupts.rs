@@ -0,0 +1,212 @@
+use lazy_static::lazy_static;
+use pic8259::ChainedPics;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+// Hardware interrupts are remapped to start right after the CPU exceptions
+pub const PIC_1_OFFSET: u8 = 32;
+pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
+
+/// The chained 8259 PICs, remapped to `PIC_1_OFFSET` and `PIC_2_OFFSET`
+pub static PICS: Mutex<ChainedPics> =
+    Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
+
+/// IDT vectors of the hardware interrupts the kernel handles
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum InterruptIndex {
+    Timer = PIC_1_OFFSET,
+    Keyboard = PIC_1_OFFSET + 1,
+    // Local APIC vectors sit above the PICs' range
+    ApicTimer = PIC_2_OFFSET + 8,
+    ApicSpurious = 0xFF,
+}
+
+impl InterruptIndex {
+    pub fn as_u8(self) -> u8 {
+        self as u8
+    }
+    
+    fn as_usize(self) -> usize {
+        usize::from(self.as_u8())
+    }
+}
+
+lazy_static! {
+    static ref IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(page_fault_handler);
+        unsafe {
+            // Saves every register for the debug stub, see `debug_stub`
+            idt.breakpoint.set_handler_addr(crate::debug_stub::breakpoint_entry_addr());
+            
+            // Runs on its own stack so a kernel stack overflow can still be reported
+            idt.double_fault
+                .set_handler_fn(double_fault_handler)
+                .set_stack_index(crate::gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
+        idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::ApicTimer.as_usize()].set_handler_fn(apic_timer_interrupt_handler);
+        idt[InterruptIndex::ApicSpurious.as_usize()].set_handler_fn(apic_spurious_interrupt_handler);
+        idt
+    };
+}
+
+/// Loads the interrupt descriptor table.
+pub fn init_idt() {
+    IDT.load();
+}
+
+// Exception vectors whose handlers are counted
+pub const BREAKPOINT_VECTOR: u8 = 3;
+const DOUBLE_FAULT_VECTOR: u8 = 8;
+const PAGE_FAULT_VECTOR: u8 = 14;
+
+// How many times each vector's handler has run
+static COUNTS: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Counts one run of `vector`'s handler. Handlers call this first thing;
+/// it's public for those living outside this module, like the debug stub's.
+pub fn record(vector: u8) {
+    COUNTS[usize::from(vector)].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Per-vector counts of handled interrupts and exceptions, indexed by vector.
+pub fn counts() -> &'static [AtomicU64] {
+    &COUNTS
+}
+
+/// Prints the count of every vector that has fired at least once, to spot
+/// interrupt storms such as a stuck IRQ.
+pub fn print_interrupt_stats() {
+    crate::println!("Interrupts:");
+    for (vector, count) in COUNTS.iter().enumerate() {
+        let count = count.load(Ordering::Relaxed);
+        if count > 0 {
+            crate::println!("  vector {:#04x}: {}", vector, count);
+        }
+    }
+}
+
+// Extra work for the timer interrupt, a `fn()` stored as a raw pointer
+static TIMER_CALLBACK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Registers a function to run on every timer interrupt, or removes it with
+/// `None`. It runs in interrupt context and must not block.
+pub fn set_timer_callback(callback: Option<fn()>) {
+    let ptr = callback.map_or(core::ptr::null_mut(), |f| f as *mut ());
+    TIMER_CALLBACK.store(ptr, Ordering::SeqCst);
+}
+
+/// First PIC's mask once the local APIC timer has taken over the tick:
+/// everything but the keyboard (IRQ 1) masked
+pub const PIC_1_MASK_WITHOUT_TIMER: u8 = 0xFD;
+
+/// Remaps the PICs and unmasks the timer and keyboard. Interrupts still have
+/// to be enabled with `x86_64::instructions::interrupts::enable`.
+pub fn init_pics() {
+    unsafe {
+        let mut pics = PICS.lock();
+        pics.initialize();
+        // Only the timer (IRQ 0) and keyboard (IRQ 1) have handlers
+        pics.write_masks(0xFC, 0xFF);
+    }
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    record(PAGE_FAULT_VECTOR);
+    let addr = Cr2::read();
+
+    // Give the memory subsystem a chance to resolve the fault (e.g. copy-on-write)
+    if crate::memory::handle_page_fault(addr, error_code).is_ok() {
+        return;
+    }
+
+    // Doesn't return if it was a task overflowing its stack
+    crate::task::scheduler::handle_stack_overflow(addr);
+
+    if crate::slab_allocator::heap_guard_contains(addr) {
+        panic!(
+            "heap overrun: access to the guard page next to the heap\nAccessed Address: {:?}\n{:#?}",
+            addr, stack_frame
+        );
+    }
+
+    panic!(
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        addr, error_code, stack_frame
+    );
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    record(DOUBLE_FAULT_VECTOR);
+    
+    // A fault on the guard page couldn't be delivered on the overflowed stack
+    let addr = Cr2::read();
+    crate::task::scheduler::handle_stack_overflow(addr);
+    if crate::memory::stack_guard_contains(addr) {
+        panic!(
+            "kernel stack overflow\nRSP: {:#x}\nAccessed Address: {:?}",
+            stack_frame.stack_pointer.as_u64(), addr
+        );
+    }
+
+    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+}
+
+// Work done on every tick, whichever timer delivers it
+fn timer_tick() {
+    let ticks = crate::time::on_timer_tick();
+    crate::task::watchdog::check(ticks);
+    
+    let callback = TIMER_CALLBACK.load(Ordering::SeqCst);
+    if !callback.is_null() {
+        // Safety: only `set_timer_callback` stores into TIMER_CALLBACK, and it stores a `fn()`
+        let callback: fn() = unsafe { core::mem::transmute(callback) };
+        callback();
+    }
+}
+
+extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::Timer.as_u8());
+    timer_tick();
+    
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+    
+    record(InterruptIndex::Keyboard.as_u8());
+    
+    // The controller won't raise another interrupt until the byte is read
+    let mut data: Port<u8> = Port::new(0x60);
+    let scancode = unsafe { data.read() };
+    crate::task::keyboard::add_scancode(scancode);
+    
+    unsafe {
+        PICS.lock().notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn apic_timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::ApicTimer.as_u8());
+    timer_tick();
+    crate::apic::end_of_interrupt();
+}
+
+// Spurious interrupts must not be acknowledged
+extern "x86-interrupt" fn apic_spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record(InterruptIndex::ApicSpurious.as_u8());
+}
@@ -0,0 +1,126 @@
+use x86_64::instructions::port::{self, PortRead, PortWrite};
+
+// Port-mapped I/O. Claiming a port is the unsafe part: once a driver holds
+// the port for a register it owns, reading and writing it is safe.
+
+/// A port that can be read and written with `T`-sized accesses
+pub struct Port<T> {
+    port: port::Port<T>,
+}
+
+impl<T> Port<T> {
+    /// # Safety
+    ///
+    /// `number` must be a device register the caller owns, where `T`-sized
+    /// reads and writes can't break memory safety (as a DMA controller could).
+    pub const unsafe fn new(number: u16) -> Self {
+        Port { port: port::Port::new(number) }
+    }
+}
+
+impl<T: PortRead + PortWrite> Port<T> {
+    pub fn read(&mut self) -> T {
+        unsafe { self.port.read() }
+    }
+    
+    pub fn write(&mut self, value: T) {
+        unsafe { self.port.write(value) }
+    }
+}
+
+/// A port that is only ever read, such as a status register
+pub struct PortReadOnly<T> {
+    port: port::PortReadOnly<T>,
+}
+
+impl<T> PortReadOnly<T> {
+    /// # Safety
+    ///
+    /// As for `Port::new`.
+    pub const unsafe fn new(number: u16) -> Self {
+        PortReadOnly { port: port::PortReadOnly::new(number) }
+    }
+}
+
+impl<T: PortRead> PortReadOnly<T> {
+    pub fn read(&mut self) -> T {
+        unsafe { self.port.read() }
+    }
+}
+
+/// A port that is only ever written, such as a command register
+pub struct PortWriteOnly<T> {
+    port: port::PortWriteOnly<T>,
+}
+
+impl<T> PortWriteOnly<T> {
+    /// # Safety
+    ///
+    /// As for `Port::new`.
+    pub const unsafe fn new(number: u16) -> Self {
+        PortWriteOnly { port: port::PortWriteOnly::new(number) }
+    }
+}
+
+impl<T: PortWrite> PortWriteOnly<T> {
+    pub fn write(&mut self, value: T) {
+        unsafe { self.port.write(value) }
+    }
+}
+
+/// ATA channels and their task file registers, as offsets from the I/O base
+pub mod ata {
+    pub const PRIMARY_IO_BASE: u16 = 0x1F0;
+    pub const SECONDARY_IO_BASE: u16 = 0x170;
+    // Device control (written) and alternate status (read)
+    pub const PRIMARY_CONTROL: u16 = 0x3F6;
+    pub const SECONDARY_CONTROL: u16 = 0x376;
+    
+    pub const REG_DATA: u16 = 0;
+    pub const REG_FEATURES: u16 = 1;
+    pub const REG_SECTOR_COUNT: u16 = 2;
+    pub const REG_LBA_LOW: u16 = 3;
+    pub const REG_LBA_MID: u16 = 4;
+    pub const REG_LBA_HIGH: u16 = 5;
+    pub const REG_DRIVE: u16 = 6;
+    // The same register: status when read, command when written
+    pub const REG_STATUS: u16 = 7;
+    pub const REG_COMMAND: u16 = 7;
+}
+
+/// 16550 UART ports, as offsets from a COM port's base
+pub mod serial {
+    pub const COM1_BASE: u16 = 0x3F8;
+    pub const COM2_BASE: u16 = 0x2F8;
+    
+    pub const REG_DATA: u16 = 0;
+    pub const REG_LINE_STATUS: u16 = 5;
+    // Holds any byte written to it, for software's own use
+    pub const REG_SCRATCH: u16 = 7;
+    
+    // Line status bit set when the transmit holding register is empty
+    pub const LSR_TRANSMIT_EMPTY: u8 = 0x20;
+}
+
+/// QEMU's isa-debug-exit device, see `exit_qemu`
+pub mod qemu {
+    pub const DEBUG_EXIT: u16 = 0xF4;
+}
+
+#[test_case]
+fn test_port_round_trip() {
+    // The isa-debug-exit port exits QEMU when written and reads nothing
+    // back, so round-trip through COM1's scratch register instead
+    let mut scratch: Port<u8> = unsafe { Port::new(serial::COM1_BASE + serial::REG_SCRATCH) };
+    let saved = scratch.read();
+    for value in [0x5A, 0xA5] {
+        scratch.write(value);
+        assert_eq!(scratch.read(), value);
+    }
+    scratch.write(saved);
+    
+    // Nothing is ever written to the line status register
+    let mut line_status: PortReadOnly<u8> =
+        unsafe { PortReadOnly::new(serial::COM1_BASE + serial::REG_LINE_STATUS) };
+    assert_ne!(line_status.read(), 0xFF, "no UART at COM1");
+}
@@ -0,0 +1,150 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{self, Arguments, Write};
+use spin::Mutex;
+use crate::vga_buffer::WRITER;
+
+/// Number of lines kept in the ring buffer.
+pub const KLOG_ENTRIES: usize = 64;
+/// Maximum length of a single line; longer lines are truncated.
+pub const ENTRY_LEN: usize = 80;
+
+/// A single line of kernel log output.
+#[derive(Debug, Clone, Copy)]
+pub struct LogEntry {
+    /// Sequence number, starting at 1 and increasing by one per line.
+    pub seq: u64,
+    len: usize,
+    text: [u8; ENTRY_LEN],
+}
+
+impl LogEntry {
+    const EMPTY: LogEntry = LogEntry { seq: 0, len: 0, text: [0; ENTRY_LEN] };
+    
+    /// Returns the text of the line without the trailing newline.
+    pub fn text(&self) -> &str {
+        core::str::from_utf8(&self.text[..self.len]).unwrap_or("")
+    }
+}
+
+struct Ring {
+    entries: Box<[LogEntry]>,
+    // Slot the next committed line goes into
+    head: usize,
+    count: usize,
+    next_seq: u64,
+    // Line being assembled until its newline arrives
+    pending: LogEntry,
+}
+
+impl Ring {
+    fn commit(&mut self) {
+        self.pending.seq = self.next_seq;
+        self.next_seq += 1;
+        self.entries[self.head] = self.pending;
+        self.head = (self.head + 1) % self.entries.len();
+        self.count = (self.count + 1).min(self.entries.len());
+        self.pending = LogEntry::EMPTY;
+    }
+    
+    // Visits the committed lines from oldest to newest
+    fn for_each(&self, mut f: impl FnMut(&LogEntry)) {
+        let oldest = (self.head + self.entries.len() - self.count) % self.entries.len();
+        for i in 0..self.count {
+            f(&self.entries[(oldest + i) % self.entries.len()]);
+        }
+    }
+}
+
+impl Write for Ring {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == '\n' {
+                self.commit();
+                continue;
+            }
+            
+            // Only whole characters are stored so the text stays valid UTF-8
+            let len = self.pending.len;
+            if len + c.len_utf8() <= ENTRY_LEN {
+                c.encode_utf8(&mut self.pending.text[len..]);
+                self.pending.len += c.len_utf8();
+            }
+        }
+        Ok(())
+    }
+}
+
+static KLOG: Mutex<Option<Ring>> = Mutex::new(None);
+
+/// Allocates the ring buffer. Output printed before this is not recorded.
+pub fn init() {
+    // Allocate before taking the lock so allocator output can't deadlock on it
+    let entries = vec![LogEntry::EMPTY; KLOG_ENTRIES].into_boxed_slice();
+    
+    let mut klog = KLOG.lock();
+    if klog.is_none() {
+        *klog = Some(Ring {
+            entries,
+            head: 0,
+            count: 0,
+            next_seq: 1,
+            pending: LogEntry::EMPTY,
+        });
+    }
+}
+
+/// Appends formatted output to the ring buffer.
+///
+/// Never allocates or blocks: if the buffer is busy (e.g. logging from inside
+/// the allocator or an interrupt handler) the output is dropped.
+pub fn record(args: Arguments) {
+    if let Some(mut klog) = KLOG.try_lock() {
+        if let Some(ring) = klog.as_mut() {
+            let _ = ring.write_fmt(args);
+        }
+    }
+}
+
+/// Returns the sequence number of the most recent line, or 0 if there is none.
+pub fn latest_seq() -> u64 {
+    // Hold off interrupt handlers that print, as `dump` does
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        KLOG.lock().as_ref().map_or(0, |ring| ring.next_seq - 1)
+    })
+}
+
+/// Returns the buffered lines with a sequence number greater than `seq`,
+/// oldest first.
+pub fn since(seq: u64) -> Vec<LogEntry> {
+    // Allocate before taking the lock, see `init`
+    let mut lines = Vec::with_capacity(KLOG_ENTRIES);
+    
+    // Hold off interrupt handlers that print, as `dump` does
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        if let Some(ring) = KLOG.lock().as_ref() {
+            ring.for_each(|entry| {
+                if entry.seq > seq {
+                    lines.push(*entry);
+                }
+            });
+        }
+    });
+    
+    lines
+}
+
+/// Reprints the whole ring buffer to the screen without recording it again.
+pub fn dump() {
+    // Hold off interrupt handlers that print, as `print!` does
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let klog = KLOG.lock();
+        if let Some(ring) = klog.as_ref() {
+            let mut writer = WRITER.lock();
+            ring.for_each(|entry| {
+                let _ = writeln!(writer, "[{:>5}] {}", entry.seq, entry.text());
+            });
+        }
+    });
+}
@@ -13,9 +13,25 @@ extern crate alloc;
 pub mod vga_buffer;
 pub mod serial;
 pub mod slab_allocator;
+pub mod pool;      // Fixed-capacity object pools
 pub mod memory;
+pub mod bitmap;    // Bit set used by frame and block allocators
 pub mod fs;        // New filesystem module
 pub mod task;      // New task management module
+pub mod interrupts; // Interrupt descriptor table and exception handlers
+pub mod gdt;        // Global descriptor table and interrupt stacks
+pub mod time;      // Monotonic clock
+pub mod panic;     // Allocation-free panic reporting
+pub mod backtrace; // Frame-pointer stack walker
+pub mod klog;      // Kernel log ring buffer
+pub mod console;   // Line-oriented console input
+pub mod shell;     // Interactive command shell
+pub mod elf;       // ELF64 program loader
+pub mod debug_stub; // GDB remote stub on COM2
+pub mod cpu;       // CPUID feature detection
+pub mod apic;      // Local APIC and its timer
+pub mod power;     // Shutdown and reboot
+pub mod io;        // Port-mapped I/O and device register numbers
 
 use bootloader::BootInfo;
 use core::panic::PanicInfo;
@@ -24,27 +40,95 @@ use x86_64::VirtAddr;
 
 /// Initialize kernel subsystems
 pub fn init(boot_info: &'static BootInfo) {
-    let phys_mem_offset = VirtAddr::new(0xb8000); // Use VGA buffer as a known mapped address
+    init_with_heap(boot_info, HeapSetup::Default);
+}
+
+/// Initialize kernel subsystems with a heap that is mapped on demand
+pub fn init_lazy_heap(boot_info: &'static BootInfo) {
+    init_with_heap(boot_info, HeapSetup::Lazy);
+}
+
+/// Initialize kernel subsystems with the heap at a custom virtual range
+pub fn init_with_heap_at(boot_info: &'static BootInfo, heap_start: VirtAddr, heap_size: usize) {
+    init_with_heap(boot_info, HeapSetup::Eager(heap_start, heap_size));
+}
+
+// How the kernel heap is set up during init
+enum HeapSetup {
+    // Map every page of the default range up front
+    Default,
+    // Map every page of the given range up front
+    Eager(VirtAddr, usize),
+    // Map the default range on demand from the page-fault handler
+    Lazy,
+}
+
+fn init_with_heap(boot_info: &'static BootInfo, heap: HeapSetup) {
+    // Install exception handlers before touching page tables; the double-fault
+    // handler's stack comes from the TSS, so the GDT goes first
+    gdt::init();
+    interrupts::init_idt();
+    
+    // The bootloader maps all physical memory at this offset
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    
+    // Make sure overflowing the kernel stack faults instead of corrupting memory
+    if let Err(e) = memory::install_stack_guard(&mapper) {
+        println!("Warning: {}", e);
+    }
     let mut frame_allocator = unsafe {
         BootInfoFrameAllocator::init(&boot_info.memory_map)
     };
+    println!("Memory: {}", frame_allocator.memory_summary());
     
     // Initialize heap allocator
-    slab_allocator::init_heap(&mut mapper, &mut frame_allocator)
-        .expect("Heap initialization failed");
+    match heap {
+        HeapSetup::Default => {
+            slab_allocator::init_heap_default(&mut mapper, &mut frame_allocator)
+                .expect("Heap initialization failed");
+        }
+        HeapSetup::Eager(heap_start, heap_size) => {
+            slab_allocator::init_heap(&mut mapper, &mut frame_allocator, heap_start, heap_size)
+                .expect("Heap initialization failed");
+        }
+        HeapSetup::Lazy => {
+            slab_allocator::init_heap_lazy(&mut mapper, &mut frame_allocator)
+                .expect("Heap initialization failed");
+        }
+    }
+    
+    // Keep the mapper and frame allocator around for the page-fault handler
+    memory::install(mapper, frame_allocator);
+    
+    // Start recording console output now that the heap is up
+    klog::init();
+    
+    // Calibrate the monotonic clock
+    time::init();
     
     // Initialize task scheduler
     task::scheduler::init();
     
+    // Start the timer interrupt that drives the tick count and watchdog,
+    // preferring the local APIC timer over the PIT
+    interrupts::init_pics();
+    if let Err(e) = apic::init() {
+        println!("APIC timer unavailable, using the PIT: {}", e);
+    }
+    x86_64::instructions::interrupts::enable();
+    
     println!("Kernel initialized successfully!");
 }
 
 // Called when allocation fails
 #[alloc_error_handler]
 fn alloc_error_handler(layout: alloc::alloc::Layout) -> ! {
-    panic!("Allocation error: {:?}", layout);
+    match slab_allocator::last_alloc_error() {
+        Some(err) => panic!("Allocation error: {:?}: {}", layout, err),
+        None => panic!("Allocation error: {:?}", layout),
+    }
 }
 
 pub trait Testable {
@@ -87,7 +171,9 @@ pub extern "C" fn _start() -> ! {
 }
 
 fn init_test() {
-    // Minimal test initialization
+    // Minimal test initialization: a heap, but no interrupts or timer
+    #[cfg(test)]
+    slab_allocator::init_test_heap();
 }
 
 #[cfg(test)]
@@ -104,16 +190,14 @@ pub enum QemuExitCode {
 }
 
 pub fn exit_qemu(exit_code: QemuExitCode) {
-    use x86_64::instructions::port::Port;
-
-    unsafe {
-        let mut port = Port::new(0xf4);
-        port.write(exit_code as u32);
-    }
+    let mut port: io::PortWriteOnly<u32> = unsafe { io::PortWriteOnly::new(io::qemu::DEBUG_EXIT) };
+    port.write(exit_code as u32);
 }
 
 pub fn hlt_loop() -> ! {
     loop {
+        // Halting until the next interrupt is idling, not being stuck
+        task::watchdog::feed();
         x86_64::instructions::hlt();
     }
 }
@@ -12,7 +12,7 @@ use rust_kernel::println;
 //use rust_kernel::task;
 //use x86_64::VirtAddr;
 use alloc::{boxed::Box, vec::Vec};
-use rust_kernel::task::scheduler::{spawn, yield_task, current_task_id};
+use rust_kernel::task::scheduler::{spawn, yield_task, yield_for, current_task_id};
 
 // Define the kernel entry point with bootloader
 entry_point!(kernel_main);
@@ -69,9 +69,7 @@ fn task1() -> ! {
         }
         
         // Slow down the task
-        for _ in 0..10_000_000 {
-            core::hint::spin_loop();
-        }
+        yield_for(100_000_000);
     }
 }
 
@@ -92,9 +90,7 @@ fn task2() -> ! {
         }
         
         // Slow down the task
-        for _ in 0..5_000_000 {
-            core::hint::spin_loop();
-        }
+        yield_for(50_000_000);
     }
 }
 
@@ -102,6 +98,16 @@ fn task2() -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // Capture registers first, then report over serial without allocating
+    let regs = rust_kernel::panic::RegisterDump::capture();
+    
+    // Let subsystems react before the default report
+    rust_kernel::panic::run_hook(info);
+    rust_kernel::panic::report(info, &regs);
+    rust_kernel::backtrace::unwind();
+    
+    // The panic may have happened while the screen was locked
+    unsafe { rust_kernel::vga_buffer::force_unlock() };
     println!("{}", info);
     rust_kernel::hlt_loop();
 }
@@ -0,0 +1,307 @@
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags,
+    PhysFrame, Size4KiB, Translate,
+    mapper::{CleanUp, MappedFrame, TranslateResult},
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+use alloc::vec::Vec;
+
+use super::{
+    active_page_table_frame, flush_all, kernel_pml4, phys_ptr, physical_memory_offset, share_frame,
+    switch_page_table, unshare_frame, walk_table, COW_FLAG, FRAME_ALLOCATOR,
+};
+
+/// A separate set of page tables with the kernel's mappings shared in.
+///
+/// The kernel isn't linked in the higher half, so every level 4 entry in use
+/// when the address space is created is shared with the kernel. Private
+/// mappings go in level 4 slots that were empty at that point.
+///
+/// Frames mapped in the private slots belong to the address space: dropping
+/// it frees them along with its page tables, except frames `fork` shared
+/// that another address space still maps.
+pub struct AddressSpace {
+    pml4: PhysFrame,
+}
+
+impl AddressSpace {
+    /// Allocates a level 4 table and copies the kernel's entries into it.
+    pub fn new(frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<Self, &'static str> {
+        let pml4 = frame_allocator
+            .allocate_frame()
+            .ok_or("Failed to allocate frame for page table")?;
+        
+        let kernel_table = unsafe { &*table_ptr(kernel_pml4()) };
+        let table = unsafe { &mut *table_ptr(pml4) };
+        table.zero();
+        for (entry, kernel_entry) in table.iter_mut().zip(kernel_table.iter()) {
+            if !kernel_entry.is_unused() {
+                entry.set_addr(kernel_entry.addr(), kernel_entry.flags());
+            }
+        }
+        
+        Ok(AddressSpace { pml4 })
+    }
+    
+    /// Makes a copy of this address space whose private pages share their
+    /// frames with this one, copy-on-write: writable pages become read-only
+    /// with `COW_FLAG` on both sides, and the page-fault handler gives
+    /// whichever side writes to one first a copy of its own.
+    ///
+    /// The shared kernel slots stay shared, as in `new`.
+    pub fn fork(
+        &self,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    ) -> Result<Self, &'static str> {
+        // Private 4KiB pages and their flags, walking only the private slots
+        let mut pages = Vec::new();
+        let mut huge = false;
+        let kernel_table = unsafe { &*table_ptr(kernel_pml4()) };
+        let table = unsafe { &*table_ptr(self.pml4) };
+        for (index, entry) in table.iter().enumerate() {
+            if !entry.flags().contains(PageTableFlags::PRESENT) || !kernel_table[index].is_unused() {
+                continue;
+            }
+            
+            let frame = PhysFrame::containing_address(entry.addr());
+            walk_table(frame, 3, (index as u64) << 39, physical_memory_offset(), 3, &mut |entry| {
+                if entry.level == 1 {
+                    let page = Page::containing_address(entry.start);
+                    pages.push((page, PhysFrame::containing_address(entry.phys), entry.flags));
+                } else if entry.flags.contains(PageTableFlags::HUGE_PAGE) {
+                    huge = true;
+                }
+            });
+        }
+        if huge {
+            return Err("Huge pages can't be forked");
+        }
+        
+        // Build the child first, so a failure leaves this address space alone
+        let mut child = AddressSpace::new(frame_allocator)?;
+        for (count, &(page, frame, flags)) in pages.iter().enumerate() {
+            if let Err(err) = child.map_page(page, frame, cow_flags(flags), frame_allocator) {
+                // The frames are still this address space's, so only the
+                // child's page tables are freed
+                child.clean_up(page, frame_allocator);
+                for &(page, _, _) in pages[..count].iter().rev() {
+                    if let Ok((_, tlb)) = unsafe { child.mapper() }.unmap(page) {
+                        tlb.ignore();
+                    }
+                    child.clean_up(page, frame_allocator);
+                }
+                child.free(frame_allocator);
+                return Err(err);
+            }
+        }
+        
+        let mut mapper = unsafe { self.mapper() };
+        for &(page, frame, flags) in pages.iter() {
+            share_frame(frame);
+            if let Ok(tlb) = unsafe { mapper.update_flags(page, cow_flags(flags)) } {
+                tlb.ignore();
+            }
+        }
+        // This address space is likely the active one
+        flush_all();
+        
+        Ok(child)
+    }
+    
+    /// Returns the frame of the level 4 table, i.e. the value to load into CR3.
+    pub fn pml4_frame(&self) -> PhysFrame {
+        self.pml4
+    }
+    
+    /// Maps `page` to `frame` in this address space only. Page tables made on
+    /// the way are writable, and user accessible if the page is, so each page
+    /// under them keeps its own permissions.
+    pub fn map_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        frame: PhysFrame<Size4KiB>,
+        flags: PageTableFlags,
+        frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    ) -> Result<(), &'static str> {
+        // Mapping through a shared entry would change the kernel's tables too
+        if is_shared(page) {
+            return Err("Address is shared with the kernel");
+        }
+        
+        let table_flags = PageTableFlags::PRESENT
+            | PageTableFlags::WRITABLE
+            | (flags & PageTableFlags::USER_ACCESSIBLE);
+        let mut mapper = unsafe { self.mapper() };
+        unsafe {
+            match mapper.map_to_with_table_flags(page, frame, flags, table_flags, frame_allocator) {
+                // Only matters if this address space is the active one
+                Ok(tlb) => tlb.flush(),
+                Err(_) => return Err("Failed to map page"),
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Changes the flags `page` is mapped with in this address space.
+    pub fn update_flags(&mut self, page: Page<Size4KiB>, flags: PageTableFlags) -> Result<(), &'static str> {
+        if is_shared(page) {
+            return Err("Address is shared with the kernel");
+        }
+        
+        let mut mapper = unsafe { self.mapper() };
+        unsafe {
+            match mapper.update_flags(page, flags) {
+                Ok(tlb) => tlb.flush(),
+                Err(_) => return Err("Failed to update page flags"),
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// Unmaps `page` from this address space and frees its frame, after any
+    /// page tables left empty, unless another address space still shares
+    /// it. A batch of pages unmapped newest first thus goes back in the
+    /// reverse of the order `map_page` took the frames.
+    pub fn unmap_page(
+        &mut self,
+        page: Page<Size4KiB>,
+        frame_deallocator: &mut impl FrameDeallocator<Size4KiB>,
+    ) -> Result<(), &'static str> {
+        if is_shared(page) {
+            return Err("Address is shared with the kernel");
+        }
+        
+        let frame = {
+            let mut mapper = unsafe { self.mapper() };
+            let (frame, tlb) = mapper.unmap(page).map_err(|_| "Failed to unmap page")?;
+            tlb.flush();
+            frame
+        };
+        self.clean_up(page, frame_deallocator);
+        if unshare_frame(frame) != Some(false) {
+            unsafe { frame_deallocator.deallocate_frame(frame) };
+        }
+        Ok(())
+    }
+    
+    /// Frees the page tables above `page` that no longer map anything, e.g.
+    /// ones made by a `map_page` that failed part way.
+    pub fn clean_up(&mut self, page: Page<Size4KiB>, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) {
+        // Tables in the shared slots belong to the kernel
+        if is_shared(page) {
+            return;
+        }
+        
+        let mut mapper = unsafe { self.mapper() };
+        unsafe { mapper.clean_up_addr_range(Page::range_inclusive(page, page), frame_deallocator) };
+    }
+    
+    /// Frees the address space through `frame_deallocator`, for callers
+    /// already holding `FRAME_ALLOCATOR`, which dropping it would lock.
+    pub fn free(mut self, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) {
+        self.tear_down(frame_deallocator);
+        core::mem::forget(self);
+    }
+    
+    // Free every private mapping, the page tables under the private slots and
+    // the level 4 table
+    fn tear_down(&mut self, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) {
+        // Never pull the tables out from under the CPU
+        if active_page_table_frame() == self.pml4 {
+            unsafe { switch_page_table(kernel_pml4()) };
+        }
+        
+        let kernel_table = unsafe { &*table_ptr(kernel_pml4()) };
+        let table = unsafe { &mut *table_ptr(self.pml4) };
+        for (entry, kernel_entry) in table.iter_mut().zip(kernel_table.iter()) {
+            if entry.flags().contains(PageTableFlags::PRESENT) && kernel_entry.is_unused() {
+                free_table(PhysFrame::containing_address(entry.addr()), 3, frame_deallocator);
+                entry.set_unused();
+            }
+        }
+        unsafe { frame_deallocator.deallocate_frame(self.pml4) };
+    }
+    
+    /// Translates a virtual address through this address space's tables.
+    pub fn translate_addr(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        unsafe { self.mapper() }.translate_addr(addr)
+    }
+    
+    /// Returns the frame and flags `page` is mapped with in this address space.
+    pub fn translate_page(&self, page: Page<Size4KiB>) -> Option<(PhysFrame<Size4KiB>, PageTableFlags)> {
+        match unsafe { self.mapper() }.translate(page.start_address()) {
+            TranslateResult::Mapped { frame: MappedFrame::Size4KiB(frame), flags, .. } => Some((frame, flags)),
+            _ => None,
+        }
+    }
+    
+    /// Loads this address space into CR3.
+    ///
+    /// # Safety
+    /// The currently executing code, stack and data must be mapped in it;
+    /// anything in the shared kernel slots is.
+    pub unsafe fn activate(&self) {
+        unsafe { switch_page_table(self.pml4) };
+    }
+    
+    // A mapper over this address space's tables
+    unsafe fn mapper(&self) -> OffsetPageTable<'static> {
+        unsafe { OffsetPageTable::new(&mut *table_ptr(self.pml4), physical_memory_offset()) }
+    }
+}
+
+// Freed when the last `Arc` to it goes, e.g. with the task running in it
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        // Without an allocator to give them to, the frames stay leaked
+        if let Some(frame_allocator) = FRAME_ALLOCATOR.lock().as_mut() {
+            self.tear_down(frame_allocator);
+        }
+    }
+}
+
+// Free the page table at `frame` of the given level (3 to 1), the tables
+// under it and the frames it maps that no other address space shares
+fn free_table(frame: PhysFrame, level: u8, frame_deallocator: &mut impl FrameDeallocator<Size4KiB>) {
+    let table = unsafe { &*table_ptr(frame) };
+    for entry in table.iter() {
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        
+        let next = PhysFrame::containing_address(entry.addr());
+        if level == 1 {
+            if unshare_frame(next) != Some(false) {
+                unsafe { frame_deallocator.deallocate_frame(next) };
+            }
+        } else if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            // Huge pages can't be made with `map_page`, so aren't freed
+            free_table(next, level - 1, frame_deallocator);
+        }
+    }
+    unsafe { frame_deallocator.deallocate_frame(frame) };
+}
+
+// Flags for a page shared copy-on-write: writable pages become read-only
+// and marked with `COW_FLAG`
+fn cow_flags(flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE) || flags.contains(COW_FLAG) {
+        (flags - PageTableFlags::WRITABLE) | COW_FLAG
+    } else {
+        flags
+    }
+}
+
+// Whether `page` lies in a level 4 slot shared with the kernel
+fn is_shared(page: Page<Size4KiB>) -> bool {
+    let kernel_table = unsafe { &*table_ptr(kernel_pml4()) };
+    !kernel_table[page.p4_index()].is_unused()
+}
+
+// Pointer to a page table through the physical memory mapping
+fn table_ptr(frame: PhysFrame) -> *mut PageTable {
+    phys_ptr(frame.start_address(), physical_memory_offset())
+}
@@ -1,16 +1,113 @@
 
-use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use crate::bitmap::Bitmap;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
 use x86_64::{
     structures::paging::{
-        FrameAllocator, PhysFrame, Size4KiB,
+        FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB,
     },
-    PhysAddr,
+    PhysAddr, VirtAddr,
 };
 
+/// Number of free frames below which `low_memory` reports memory pressure.
+pub const LOW_MEMORY_THRESHOLD: usize = 64;
+
+/// Bytes of memory per kind of region, aggregated from a memory map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemorySummary {
+    pub total: u64,
+    pub usable: u64,
+    /// Reserved by firmware or hardware: ACPI tables, bad memory, frame zero
+    pub reserved: u64,
+    /// The kernel image and its stack
+    pub kernel: u64,
+    /// Page tables, boot information and other bootloader allocations
+    pub bootloader: u64,
+    pub region_count: usize,
+}
+
+impl fmt::Display for MemorySummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MIB: u64 = 1024 * 1024;
+        write!(f, "{} MiB total, {} MiB usable", self.total / MIB, self.usable / MIB)
+    }
+}
+
+/// Classifies the regions of a memory map and totals their sizes.
+pub fn memory_summary(memory_map: &MemoryMap) -> MemorySummary {
+    let mut summary = MemorySummary::default();
+    
+    for region in memory_map.iter() {
+        let size = region.range.end_addr() - region.range.start_addr();
+        summary.total += size;
+        summary.region_count += 1;
+        
+        match region.region_type {
+            MemoryRegionType::Usable => summary.usable += size,
+            MemoryRegionType::Kernel | MemoryRegionType::KernelStack => summary.kernel += size,
+            MemoryRegionType::Bootloader
+            | MemoryRegionType::PageTable
+            | MemoryRegionType::BootInfo
+            | MemoryRegionType::Package => summary.bootloader += size,
+            MemoryRegionType::Reserved
+            | MemoryRegionType::AcpiReclaimable
+            | MemoryRegionType::AcpiNvs
+            | MemoryRegionType::BadMemory
+            | MemoryRegionType::FrameZero => summary.reserved += size,
+            // In use for other purposes, or empty; only counted in the total
+            _ => {}
+        }
+    }
+    
+    summary
+}
+
+// Physical ranges no frame may be allocated from, frame aligned, sorted and
+// merged so no two overlap or touch
+struct ReservedRanges {
+    ranges: Vec<Range<u64>>,
+}
+
+impl ReservedRanges {
+    const fn new() -> Self {
+        ReservedRanges { ranges: Vec::new() }
+    }
+    
+    // Reserve the frames overlapping `start..end`, merging with any
+    // reservation it overlaps or touches
+    fn insert(&mut self, start: PhysAddr, end: PhysAddr) {
+        let mut range = start.align_down(4096u64).as_u64()..end.align_up(4096u64).as_u64();
+        if range.is_empty() {
+            return;
+        }
+        
+        self.ranges.retain(|other| {
+            let overlaps = other.start <= range.end && range.start <= other.end;
+            if overlaps {
+                range = range.start.min(other.start)..range.end.max(other.end);
+            }
+            !overlaps
+        });
+        let index = self.ranges.partition_point(|other| other.start < range.start);
+        self.ranges.insert(index, range);
+    }
+    
+    // The reservation holding `addr`, if any
+    fn find(&self, addr: u64) -> Option<&Range<u64>> {
+        self.ranges.iter().find(|range| range.contains(&addr))
+    }
+}
+
 /// A frame allocator that returns usable frames from the bootloader's memory map.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
-    next: usize,
+    // Cursor into the memory map: the current region and the next frame address in it
+    region_index: usize,
+    next_addr: u64,
+    allocated_count: usize,
+    reserved: ReservedRanges,
 }
 
 impl BootInfoFrameAllocator {
@@ -24,12 +121,22 @@ impl BootInfoFrameAllocator {
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
         BootInfoFrameAllocator {
             memory_map,
-            next: 0,
+            region_index: 0,
+            next_addr: 0,
+            allocated_count: 0,
+            reserved: ReservedRanges::new(),
         }
     }
     
+    /// Keeps the frames overlapping `start..end` from being allocated, e.g.
+    /// a framebuffer or firmware tables the memory map calls usable.
+    /// Reservations may overlap. Frames already handed out aren't taken back.
+    pub fn reserve_range(&mut self, start: PhysAddr, end: PhysAddr) {
+        self.reserved.insert(start, end);
+    }
+    
     /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
+    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> + '_ {
         // Get usable regions from memory map
         let regions = self.memory_map.iter();
         let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
@@ -40,8 +147,10 @@ impl BootInfoFrameAllocator {
         // Transform to an iterator of frame start addresses
         let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
         
-        // Create PhysFrame objects
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+        // Leave out reserved frames and create PhysFrame objects
+        frame_addresses
+            .filter(|&addr| self.reserved.find(addr).is_none())
+            .map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
     
     /// Returns the number of usable frames available.
@@ -49,6 +158,21 @@ impl BootInfoFrameAllocator {
         self.usable_frames().count()
     }
     
+    /// Returns the number of frames handed out so far.
+    pub fn allocated_count(&self) -> usize {
+        self.allocated_count
+    }
+    
+    /// Returns the number of usable frames not yet handed out.
+    pub fn free_count(&self) -> usize {
+        self.available_frames().saturating_sub(self.allocated_count)
+    }
+    
+    /// Returns true when fewer than `LOW_MEMORY_THRESHOLD` frames are left.
+    pub fn low_memory(&self) -> bool {
+        self.free_count() < LOW_MEMORY_THRESHOLD
+    }
+    
     /// Returns the total memory size in bytes.
     pub fn total_memory_size(&self) -> u64 {
         let mut total = 0;
@@ -58,6 +182,16 @@ impl BootInfoFrameAllocator {
         total
     }
     
+    /// Returns the end of the highest region in the memory map, up to which
+    /// the bootloader maps physical memory.
+    pub fn max_phys_addr(&self) -> PhysAddr {
+        let end = self.memory_map.iter()
+            .map(|region| region.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        PhysAddr::new(end)
+    }
+    
     /// Returns the usable memory size in bytes.
     pub fn usable_memory_size(&self) -> u64 {
         let mut total = 0;
@@ -69,6 +203,54 @@ impl BootInfoFrameAllocator {
         total
     }
     
+    /// Returns the memory map's region sizes aggregated by type.
+    pub fn memory_summary(&self) -> MemorySummary {
+        memory_summary(self.memory_map)
+    }
+    
+    /// Hands the remaining frames over to a `BitmapFrameAllocator`, which can
+    /// take back any frame instead of only the most recent one. Frames
+    /// handed out so far and reserved ranges stay allocated in it.
+    ///
+    /// The bitmap lives in physically contiguous frames taken from this
+    /// allocator and is reached through the bootloader's physical memory
+    /// mapping at `physical_memory_offset`, so the heap isn't needed.
+    /// Returns `None` if there aren't enough frames left for the bitmap.
+    pub fn into_bitmap_allocator(mut self, physical_memory_offset: VirtAddr) -> Option<BitmapFrameAllocator> {
+        let frames_count = (self.max_phys_addr().as_u64() / 4096) as usize;
+        let bitmap_frames = frames_count.div_ceil(8).div_ceil(4096) as u64;
+        
+        // Frames come in address order within a region; start over after a gap
+        let mut start = self.allocate_frame()?;
+        let mut len = 1;
+        while len < bitmap_frames {
+            let frame = self.allocate_frame()?;
+            if frame == start + len {
+                len += 1;
+            } else {
+                start = frame;
+                len = 1;
+            }
+        }
+        
+        let bitmap = unsafe {
+            let ptr = (physical_memory_offset + start.start_address().as_u64()).as_mut_ptr::<u8>();
+            core::slice::from_raw_parts_mut(ptr, bitmap_frames as usize * 4096)
+        };
+        let mut allocator = unsafe { BitmapFrameAllocator::from_memory_map(bitmap, self.memory_map) };
+        
+        // The cursor walks the usable frames in order, so the ones handed out
+        // (including the bitmap's) are the first `allocated_count` of them
+        for frame in self.usable_frames().take(self.allocated_count) {
+            allocator.bitmap.set((frame.start_address().as_u64() / 4096) as usize);
+        }
+        for range in &self.reserved.ranges {
+            allocator.reserve_range(PhysAddr::new(range.start), PhysAddr::new(range.end));
+        }
+        allocator.allocated_count = self.allocated_count;
+        Some(allocator)
+    }
+    
     /// Prints memory map information for debugging.
     pub fn print_memory_map(&self) {
         crate::println!("Memory map:");
@@ -85,9 +267,43 @@ impl BootInfoFrameAllocator {
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        // Advance the cursor instead of re-walking the memory map on every call
+        loop {
+            let region = self.memory_map.get(self.region_index)?;
+            
+            if region.region_type == MemoryRegionType::Usable {
+                let addr = self.next_addr.max(region.range.start_addr());
+                
+                // Jump over a reservation, then look again from its end
+                if let Some(range) = self.reserved.find(addr) {
+                    self.next_addr = range.end;
+                    continue;
+                }
+                
+                if addr < region.range.end_addr() {
+                    self.next_addr = addr + 4096;
+                    self.allocated_count += 1;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+                }
+            }
+            
+            // Region exhausted or not usable, move on to the next one
+            self.region_index += 1;
+            self.next_addr = 0;
+        }
+    }
+}
+
+/// Takes back the most recently allocated frame by stepping the cursor back.
+/// Any other frame can't be tracked by a bump allocator and stays leaked, so
+/// rolling back a batch of allocations should free them in reverse order.
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        let addr = frame.start_address().as_u64();
+        if self.allocated_count > 0 && self.next_addr == addr + 4096 {
+            self.next_addr = addr;
+            self.allocated_count -= 1;
+        }
     }
 }
 
@@ -100,14 +316,19 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+impl FrameDeallocator<Size4KiB> for EmptyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, _frame: PhysFrame<Size4KiB>) {}
+}
+
 /// A simple frame allocator that keeps track of allocated frames in a bitmap.
 pub struct BitmapFrameAllocator {
-    // We'll use a bitmap to track which frames are allocated (1 = allocated, 0 = free)
-    bitmap: &'static mut [u8],
+    // Which frames are allocated (1 = allocated, 0 = free), relative to the start frame
+    bitmap: Bitmap<'static>,
     // Start address of the memory region
     start_frame_number: usize,
-    // Number of frames in the region
-    frames_count: usize,
+    // Reserved frames, kept allocated in the bitmap
+    reserved: ReservedRanges,
+    allocated_count: usize,
 }
 
 impl BitmapFrameAllocator {
@@ -123,70 +344,173 @@ impl BitmapFrameAllocator {
         frames_count: usize,
     ) -> Self {
         BitmapFrameAllocator {
-            bitmap,
+            bitmap: Bitmap::new(bitmap, frames_count),
             start_frame_number,
-            frames_count,
+            reserved: ReservedRanges::new(),
+            allocated_count: 0,
         }
     }
     
-    /// Marks a frame as allocated
-    fn mark_frame_allocated(&mut self, frame_number: usize) {
-        let rel_frame = frame_number - self.start_frame_number;
-        if rel_frame >= self.frames_count {
-            return; // Out of range
-        }
-        
-        let byte_index = rel_frame / 8;
-        let bit_index = rel_frame % 8;
-        
-        self.bitmap[byte_index] |= 1 << bit_index;
+    /// Creates a bitmap frame allocator for `frames_count` frames from
+    /// `start_frame_number`, with a zeroed bitmap of the right size allocated
+    /// from the kernel heap. The bitmap is leaked, as the allocator lives for
+    /// the rest of the kernel's run.
+    ///
+    /// # Safety
+    ///
+    /// Caller must ensure that every frame in the range is unused.
+    pub unsafe fn with_heap_bitmap(start_frame_number: usize, frames_count: usize) -> Self {
+        let bitmap = alloc::vec![0u8; frames_count.div_ceil(8)].leak();
+        unsafe { Self::new(bitmap, start_frame_number, frames_count) }
     }
     
-    /// Marks a frame as free
-    fn mark_frame_free(&mut self, frame_number: usize) {
-        let rel_frame = frame_number - self.start_frame_number;
-        if rel_frame >= self.frames_count {
-            return; // Out of range
-        }
+    /// Creates a bitmap frame allocator from the bootloader's memory map.
+    ///
+    /// Every frame outside a `Usable` region (bootloader page tables, the
+    /// kernel image, the VGA buffer, ...) is marked as allocated up front.
+    ///
+    /// # Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid, i.e. that
+    /// all frames marked as `USABLE` in it are really unused.
+    pub unsafe fn from_memory_map(bitmap: &'static mut [u8], memory_map: &MemoryMap) -> Self {
+        let highest_frame = memory_map.iter()
+            .map(|r| r.range.end_frame_number as usize)
+            .max()
+            .unwrap_or(0);
         
-        let byte_index = rel_frame / 8;
-        let bit_index = rel_frame % 8;
+        // Start with every frame allocated, then free the usable ones
+        bitmap.fill(0xFF);
+        let mut allocator = BitmapFrameAllocator {
+            bitmap: Bitmap::new(bitmap, highest_frame),
+            start_frame_number: 0,
+            reserved: ReservedRanges::new(),
+            allocated_count: 0,
+        };
+        let frames_count = allocator.bitmap.len();
         
-        self.bitmap[byte_index] &= !(1 << bit_index);
-    }
-    
-    /// Checks if a frame is allocated
-    fn is_frame_allocated(&self, frame_number: usize) -> bool {
-        let rel_frame = frame_number - self.start_frame_number;
-        if rel_frame >= self.frames_count {
-            return false; // Out of range
+        let frame_range = |r: &MemoryRegion| {
+            let start = r.range.start_frame_number as usize;
+            let end = (r.range.end_frame_number as usize).min(frames_count);
+            start..end
+        };
+        
+        for region in memory_map.iter().filter(|r| r.region_type == MemoryRegionType::Usable) {
+            for frame_number in frame_range(region) {
+                allocator.bitmap.clear(frame_number);
+            }
         }
         
-        let byte_index = rel_frame / 8;
-        let bit_index = rel_frame % 8;
+        // Frames claimed by a non-usable region stay reserved even if a usable one overlaps
+        for region in memory_map.iter().filter(|r| r.region_type != MemoryRegionType::Usable) {
+            for frame_number in frame_range(region) {
+                allocator.bitmap.set(frame_number);
+            }
+        }
         
-        (self.bitmap[byte_index] & (1 << bit_index)) != 0
+        allocator
     }
     
-    /// Find the first free frame
-    fn find_free_frame(&self) -> Option<usize> {
-        for frame_number in self.start_frame_number..(self.start_frame_number + self.frames_count) {
-            if !self.is_frame_allocated(frame_number) {
-                return Some(frame_number);
+    /// Keeps the frames overlapping `start..end` from being allocated, even
+    /// if they are deallocated later. Reservations may overlap. Frames
+    /// already handed out aren't taken back.
+    pub fn reserve_range(&mut self, start: PhysAddr, end: PhysAddr) {
+        self.reserved.insert(start, end);
+        
+        let first = (start.as_u64() / 4096) as usize;
+        let end_frame = end.as_u64().div_ceil(4096) as usize;
+        for frame_number in first..end_frame {
+            if let Some(index) = frame_number.checked_sub(self.start_frame_number) {
+                self.bitmap.set(index);
             }
         }
-        None
+    }
+    
+    /// Returns the number of frames handed out and not yet deallocated.
+    pub fn allocated_count(&self) -> usize {
+        self.allocated_count
     }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        if let Some(frame_number) = self.find_free_frame() {
-            self.mark_frame_allocated(frame_number);
-            let frame_addr = (frame_number * 4096) as u64;
-            Some(PhysFrame::containing_address(PhysAddr::new(frame_addr)))
-        } else {
-            None
+        let index = self.bitmap.find_first_clear()?;
+        self.bitmap.set(index);
+        self.allocated_count += 1;
+        let frame_addr = ((self.start_frame_number + index) * 4096) as u64;
+        Some(PhysFrame::containing_address(PhysAddr::new(frame_addr)))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        // Reserved frames stay allocated
+        if self.reserved.find(frame.start_address().as_u64()).is_some() {
+            return;
+        }
+        
+        let frame_number = (frame.start_address().as_u64() / 4096) as usize;
+        if let Some(index) = frame_number.checked_sub(self.start_frame_number) {
+            if self.bitmap.get(index) {
+                self.bitmap.clear(index);
+                self.allocated_count = self.allocated_count.saturating_sub(1);
+            }
+        }
+    }
+}
+
+/// Wraps a frame allocator so every frame it hands out is zeroed first, so a
+/// page mapped from it can't expose what the frame's previous owner left.
+///
+/// Frames are cleared through the bootloader's physical memory mapping.
+/// Zeroing can be switched off for callers that overwrite the whole frame
+/// right away.
+pub struct ZeroingFrameAllocator<A> {
+    inner: A,
+    physical_memory_offset: VirtAddr,
+    zeroing: bool,
+}
+
+impl<A: FrameAllocator<Size4KiB>> ZeroingFrameAllocator<A> {
+    /// Wraps `inner`, with zeroing enabled.
+    ///
+    /// # Safety
+    ///
+    /// All physical memory must be mapped at `physical_memory_offset`.
+    pub unsafe fn new(inner: A, physical_memory_offset: VirtAddr) -> Self {
+        ZeroingFrameAllocator {
+            inner,
+            physical_memory_offset,
+            zeroing: true,
+        }
+    }
+    
+    /// Turns zeroing of newly allocated frames on or off.
+    pub fn set_zeroing(&mut self, zeroing: bool) {
+        self.zeroing = zeroing;
+    }
+    
+    /// Returns the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+unsafe impl<A: FrameAllocator<Size4KiB>> FrameAllocator<Size4KiB> for ZeroingFrameAllocator<A> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let frame = self.inner.allocate_frame()?;
+        if self.zeroing {
+            let ptr: *mut u8 = super::phys_ptr(frame.start_address(), self.physical_memory_offset);
+            // Safety: `new` requires all physical memory to be mapped at the offset,
+            // and the frame was just handed to us, so nothing else uses it
+            unsafe { core::ptr::write_bytes(ptr, 0, 4096) };
         }
+        Some(frame)
+    }
+}
+
+impl<A: FrameDeallocator<Size4KiB>> FrameDeallocator<Size4KiB> for ZeroingFrameAllocator<A> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        unsafe { self.inner.deallocate_frame(frame) }
     }
-}
\ No newline at end of file
+}
@@ -1,32 +1,251 @@
 
 use crate::println;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
 use x86_64::{
     structures::paging::{
         PageTable, OffsetPageTable, PhysFrame, Size4KiB, 
-        FrameAllocator, 
+        FrameAllocator, FrameDeallocator, 
         page_table::{FrameError, PageTableEntry, PageTableIndex}, PageTableFlags, 
-        Page, Mapper, 
-        page::PageRangeInclusive
+        Page, Mapper, Translate,
+        page::PageRangeInclusive, mapper::{CleanUp, MapperFlush},
     },
+    structures::idt::PageFaultErrorCode,
     PhysAddr, VirtAddr,
     registers::control::Cr3,
 };
 
+use frame_allocator::{BitmapFrameAllocator, BootInfoFrameAllocator};
+
 pub mod frame_allocator;
+pub mod address_space;
+
+pub use address_space::AddressSpace;
+
+/// Page table entry bit (available to the OS) marking a copy-on-write page
+pub const COW_FLAG: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Flags for memory-mapped device memory (framebuffers, APIC registers):
+/// writes bypass the cache so the device sees them immediately
+pub const DEVICE_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::WRITE_THROUGH);
+
+/// Kernel page table mapper, set by `install` during kernel init
+pub static MAPPER: Mutex<Option<OffsetPageTable<'static>>> = Mutex::new(None);
+
+/// Kernel frame allocator, set by `install` during kernel init. Unlike the
+/// boot allocator it takes back any frame, so task stacks and address spaces
+/// can be freed in any order
+pub static FRAME_ALLOCATOR: Mutex<Option<BitmapFrameAllocator>> = Mutex::new(None);
+
+// Virtual address at which the bootloader maps all physical memory
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+// End of the physical memory mapping (highest memory map address), 0 until installed
+static PHYSICAL_MEMORY_END: AtomicU64 = AtomicU64::new(0);
+
+// Physical address of the kernel's level 4 page table
+static KERNEL_PML4: AtomicU64 = AtomicU64::new(0);
+
+// Start of the unmapped guard page below the kernel stack, 0 until installed
+static STACK_GUARD: AtomicU64 = AtomicU64::new(0);
+
+// How far below the current stack pointer to look for the guard page
+const MAX_KERNEL_STACK_PAGES: u64 = 1024;
+
+// Virtual arena handed out by `reserve`
+const RESERVE_START: u64 = 0x_6666_0000_0000;
+const RESERVE_END: u64 = RESERVE_START + 64 * 1024 * 1024 * 1024; // 64 GiB
+
+// Most ranges `reserve` hands out at once. The table is fixed in size
+// because reservations are made with MAPPER held, where a heap allocation
+// touching a lazily mapped heap page couldn't be resolved
+const MAX_RESERVATIONS: usize = 256;
+
+// A range handed out by `reserve`
+#[derive(Debug, Clone, Copy)]
+struct Reservation {
+    start: u64,
+    end: u64,
+    // Mapped by `mmap`, so `munmap` may take it back
+    mmapped: bool,
+}
+
+// Fixed-size table of reserved ranges, in no particular order
+struct Reservations {
+    ranges: [Reservation; MAX_RESERVATIONS],
+    len: usize,
+}
+
+impl Reservations {
+    const fn new() -> Self {
+        Reservations {
+            ranges: [Reservation { start: 0, end: 0, mmapped: false }; MAX_RESERVATIONS],
+            len: 0,
+        }
+    }
+    
+    fn iter(&self) -> impl Iterator<Item = &Reservation> {
+        self.ranges[..self.len].iter()
+    }
+    
+    fn push(&mut self, reservation: Reservation) -> Result<(), &'static str> {
+        if self.len == MAX_RESERVATIONS {
+            return Err("Too many reserved ranges");
+        }
+        self.ranges[self.len] = reservation;
+        self.len += 1;
+        Ok(())
+    }
+    
+    // The reservation starting at `start`
+    fn get_mut(&mut self, start: u64) -> Option<&mut Reservation> {
+        self.ranges[..self.len].iter_mut().find(|r| r.start == start)
+    }
+    
+    fn remove(&mut self, start: u64) {
+        let index = self.iter().position(|r| r.start == start);
+        if let Some(index) = index {
+            self.len -= 1;
+            self.ranges[index] = self.ranges[self.len];
+        }
+    }
+}
+
+// Virtual ranges handed out by `reserve`
+static RESERVED: Mutex<Reservations> = Mutex::new(Reservations::new());
+
+// Scratch page used to preserve a page's contents while it is remapped
+static COW_BUFFER: Mutex<[u8; 4096]> = Mutex::new([0; 4096]);
 
+// Frames `AddressSpace::fork` shared between address spaces, by physical
+// address, with the number of mappings still using each
+static SHARED_FRAMES: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
 
 /// Initialize a new OffsetPageTable
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
+    KERNEL_PML4.store(active_page_table_frame().start_address().as_u64(), Ordering::SeqCst);
     let level_4_table = unsafe { active_level_4_table(physical_memory_offset) };
     unsafe { OffsetPageTable::new(level_4_table, physical_memory_offset) }
 }
 
+/// Hands the kernel's mapper and frame allocator over so the page-fault
+/// handler can map frames on its own. The boot allocator's remaining frames
+/// move into a `BitmapFrameAllocator`.
+pub fn install(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    PHYSICAL_MEMORY_END.store(frame_allocator.max_phys_addr().as_u64(), Ordering::SeqCst);
+    let frame_allocator = frame_allocator
+        .into_bitmap_allocator(physical_memory_offset())
+        .expect("No frames left for the frame bitmap");
+    *MAPPER.lock() = Some(mapper);
+    *FRAME_ALLOCATOR.lock() = Some(frame_allocator);
+}
+
+/// Finds the guard page below the kernel stack and remembers it so faults on
+/// it can be reported as stack overflows.
+///
+/// The bootloader leaves the page below the kernel stack unmapped; this walks
+/// down from the current stack pointer to the first unmapped page.
+pub fn install_stack_guard(mapper: &impl Translate) -> Result<Page<Size4KiB>, &'static str> {
+    let rsp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack, preserves_flags));
+    }
+    
+    let mut page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(rsp));
+    for _ in 0..MAX_KERNEL_STACK_PAGES {
+        page -= 1;
+        if mapper.translate_addr(page.start_address()).is_none() {
+            STACK_GUARD.store(page.start_address().as_u64(), Ordering::SeqCst);
+            return Ok(page);
+        }
+    }
+    
+    Err("No guard page below the kernel stack")
+}
+
+/// Returns true if `addr` lies in the kernel stack's guard page
+pub fn stack_guard_contains(addr: VirtAddr) -> bool {
+    let guard = STACK_GUARD.load(Ordering::SeqCst);
+    guard != 0 && (guard..guard + 4096).contains(&addr.as_u64())
+}
+
+/// Returns the frame holding the kernel's level 4 page table
+pub fn kernel_pml4() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PML4.load(Ordering::SeqCst)))
+}
+
+/// Returns the frame holding the active level 4 page table, as loaded in CR3
+pub fn active_page_table_frame() -> PhysFrame {
+    Cr3::read().0
+}
+
+/// Loads `frame` into CR3 as the active level 4 page table, keeping the CR3
+/// flags. Flushes every TLB entry that isn't global.
+///
+/// # Safety
+///
+/// `frame` must hold a valid level 4 table that maps the currently executing
+/// code, stack and data.
+pub unsafe fn switch_page_table(frame: PhysFrame) {
+    let (_, flags) = Cr3::read();
+    unsafe { Cr3::write(frame, flags) };
+}
+
+/// Returns the virtual address at which physical memory is mapped
+pub fn physical_memory_offset() -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst))
+}
+
+// Pointer to `phys` through the mapping of all physical memory at `physical_memory_offset`
+fn phys_ptr<T>(phys: PhysAddr, physical_memory_offset: VirtAddr) -> *mut T {
+    (physical_memory_offset + phys.as_u64()).as_mut_ptr()
+}
+
+/// Reads a `T` from physical address `phys` through the physical memory
+/// mapping, e.g. to inspect a page table or a device register.
+///
+/// # Safety
+///
+/// `phys` must hold a valid `T`. Reading a device register can have side
+/// effects on the device.
+pub unsafe fn read_phys<T: Copy>(phys: PhysAddr) -> T {
+    let ptr: *const T = phys_ptr(phys, checked_physical_memory_offset::<T>(phys));
+    unsafe { core::ptr::read_volatile(ptr) }
+}
+
+/// Writes `value` to physical address `phys` through the physical memory
+/// mapping.
+///
+/// # Safety
+///
+/// Nothing else may rely on the memory at `phys`, or the write must be
+/// what it expects (e.g. a device register).
+pub unsafe fn write_phys<T: Copy>(phys: PhysAddr, value: T) {
+    let ptr: *mut T = phys_ptr(phys, checked_physical_memory_offset::<T>(phys));
+    unsafe { core::ptr::write_volatile(ptr, value) }
+}
+
+// The physical memory offset, checking in debug builds that a `T` at `phys`
+// lies inside the mapped physical memory
+fn checked_physical_memory_offset<T>(phys: PhysAddr) -> VirtAddr {
+    let end = PHYSICAL_MEMORY_END.load(Ordering::SeqCst);
+    debug_assert!(
+        end == 0 || phys.as_u64() + core::mem::size_of::<T>() as u64 <= end,
+        "physical address {:#x} is not mapped", phys.as_u64()
+    );
+    physical_memory_offset()
+}
+
 /// Returns a mutable reference to the active level 4 page table
 unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut PageTable {
-    let (level_4_table_frame, _) = Cr3::read();
-    let phys = level_4_table_frame.start_address();
-    let virt = physical_memory_offset + phys.as_u64();
-    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+    let level_4_table_frame = active_page_table_frame();
+    let page_table_ptr: *mut PageTable = phys_ptr(level_4_table_frame.start_address(), physical_memory_offset);
     unsafe { &mut *page_table_ptr }
 }
 
@@ -49,7 +268,7 @@ unsafe fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
     -> Option<TranslateResult> {
     
     // Read the active level 4 frame from the CR3 register
-    let (level_4_table_frame, _) = Cr3::read();
+    let level_4_table_frame = active_page_table_frame();
     
     let table_indexes = [
         addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()
@@ -59,8 +278,7 @@ unsafe fn translate_addr_inner(addr: VirtAddr, physical_memory_offset: VirtAddr)
     // Walk the page tables
     for (level, &index) in table_indexes.iter().enumerate() {
         // Convert the frame into a page table reference
-        let virt = physical_memory_offset + frame.start_address().as_u64();
-        let table_ptr: *const PageTable = virt.as_ptr();
+        let table_ptr: *const PageTable = phys_ptr(frame.start_address(), physical_memory_offset);
         let table = unsafe { &*table_ptr };
         
         // Read the page table entry
@@ -152,12 +370,154 @@ pub fn print_memory_mapping(addr: VirtAddr, physical_memory_offset: VirtAddr) {
     }
 }
 
+/// A present page table entry found by `walk_page_tables`
+#[derive(Debug, Clone, Copy)]
+pub struct PageTableVisit {
+    /// Table level, from 4 (the PML4) down to 1 (the tables of 4KiB pages)
+    pub level: u8,
+    /// First virtual address the entry covers
+    pub start: VirtAddr,
+    /// The next level table, or the page the entry maps
+    pub phys: PhysAddr,
+    pub flags: PageTableFlags,
+}
+
+/// Calls `visit` for every present entry of the active page tables, each
+/// entry before the ones in the table below it. Descends at most `max_depth`
+/// levels from the PML4 and stops at huge pages.
+pub fn walk_page_tables(
+    physical_memory_offset: VirtAddr,
+    max_depth: usize,
+    mut visit: impl FnMut(&PageTableVisit),
+) {
+    let level_4_table_frame = active_page_table_frame();
+    walk_table(level_4_table_frame, 4, 0, physical_memory_offset, max_depth.min(4), &mut visit);
+}
+
+// Visit the present entries of the table in `frame`, whose first entry
+// starts at virtual address `base`
+fn walk_table<F: FnMut(&PageTableVisit)>(
+    frame: PhysFrame,
+    level: u8,
+    base: u64,
+    physical_memory_offset: VirtAddr,
+    depth: usize,
+    visit: &mut F,
+) {
+    if depth == 0 {
+        return;
+    }
+    
+    let table_ptr: *const PageTable = phys_ptr(frame.start_address(), physical_memory_offset);
+    let table = unsafe { &*table_ptr };
+    for (index, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        
+        let start = base | (index as u64) << (12 + 9 * (level as u64 - 1));
+        visit(&PageTableVisit { level, start: VirtAddr::new_truncate(start), phys: entry.addr(), flags });
+        
+        // Level 1 entries and huge pages map memory, not another table
+        if level > 1 && !flags.contains(PageTableFlags::HUGE_PAGE) {
+            let next = PhysFrame::containing_address(entry.addr());
+            walk_table(next, level - 1, start, physical_memory_offset, depth - 1, visit);
+        }
+    }
+}
+
+/// Most entries `dump_page_tables` prints before summarizing the rest
+pub const DUMP_ENTRY_LIMIT: usize = 256;
+
+/// Prints the present entries of the active page tables, indented by level,
+/// descending at most `max_depth` levels from the PML4. Output stops after
+/// `DUMP_ENTRY_LIMIT` entries.
+pub fn dump_page_tables(physical_memory_offset: VirtAddr, max_depth: usize) {
+    let level_4_table_frame = active_page_table_frame();
+    println!("PML4 at {:?}", level_4_table_frame.start_address());
+    
+    let mut printed = 0;
+    let mut skipped = 0;
+    walk_page_tables(physical_memory_offset, max_depth, |entry| {
+        if printed == DUMP_ENTRY_LIMIT {
+            skipped += 1;
+            return;
+        }
+        printed += 1;
+        
+        let indent = 2 * (5 - entry.level as usize);
+        println!(
+            "{:indent$}L{} {:#018x} -> {:#x} {:?}",
+            "", entry.level, entry.start.as_u64(), entry.phys.as_u64(), entry.flags,
+            indent = indent
+        );
+    });
+    if skipped > 0 {
+        println!("... {} more entries", skipped);
+    }
+}
+
+/// Ranges longer than this many pages are flushed with a single full TLB
+/// flush instead of one `invlpg` per page
+pub const FLUSH_ALL_THRESHOLD: u64 = 32;
+
+// TLB flushes issued by `map_range`, `unmap_range` and `flush_all`
+static TLB_FLUSHES: AtomicU64 = AtomicU64::new(0);
+
+/// Flushes the whole TLB (except global pages) by reloading CR3
+pub fn flush_all() {
+    TLB_FLUSHES.fetch_add(1, Ordering::Relaxed);
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// Returns the number of TLB flushes issued by `map_range`, `unmap_range`
+/// and `flush_all` so far
+pub fn tlb_flush_count() -> u64 {
+    TLB_FLUSHES.load(Ordering::Relaxed)
+}
+
+// Flush one page's TLB entry, or defer to a `flush_all` when batching
+fn flush_page(flush: MapperFlush<Size4KiB>, batched: bool) {
+    if batched {
+        flush.ignore();
+    } else {
+        TLB_FLUSHES.fetch_add(1, Ordering::Relaxed);
+        flush.flush();
+    }
+}
+
+// Whether a range is long enough to be flushed with `flush_all`
+fn batch_flush(range: &PageRangeInclusive<Size4KiB>) -> bool {
+    !range.is_empty() && range.end - range.start >= FLUSH_ALL_THRESHOLD
+}
+
 /// Maps a range of pages to physical frames with given flags
+///
+/// Ranges longer than `FLUSH_ALL_THRESHOLD` pages are flushed with a single
+/// `flush_all` once every page is mapped.
 pub fn map_range(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     range: PageRangeInclusive<Size4KiB>,
     flags: PageTableFlags,
+) -> Result<(), &'static str> {
+    let batched = batch_flush(&range);
+    let result = map_range_pages(mapper, frame_allocator, range, flags, batched);
+    
+    // Pages mapped before a failure still need their stale entries flushed
+    if batched {
+        flush_all();
+    }
+    result
+}
+
+fn map_range_pages(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    range: PageRangeInclusive<Size4KiB>,
+    flags: PageTableFlags,
+    batched: bool,
 ) -> Result<(), &'static str> {
     for page in range {
         // Allocate a physical frame
@@ -169,7 +529,7 @@ pub fn map_range(
         unsafe {
             // Handle the error without using ? operator
             match mapper.map_to(page, frame, flags, frame_allocator) {
-                Ok(tlb) => tlb.flush(),
+                Ok(tlb) => flush_page(tlb, batched),
                 Err(_) => return Err("Failed to map page"),
             }
         }
@@ -178,6 +538,140 @@ pub fn map_range(
     Ok(())
 }
 
+/// Maps fresh frames into `range` like `map_range`, but if that fails part
+/// way everything it mapped is unmapped again and every frame it took is
+/// freed, page tables included. Frames go back in the reverse of the order
+/// they were allocated, so even a bump `BootInfoFrameAllocator` gets them all
+/// back.
+pub fn map_fresh_pages(
+    mapper: &mut (impl Mapper<Size4KiB> + CleanUp),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    range: PageRangeInclusive<Size4KiB>,
+    flags: PageTableFlags,
+) -> Result<(), &'static str> {
+    let batched = batch_flush(&range);
+    let mut result = Ok(());
+    for page in range {
+        let frame = match frame_allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => {
+                roll_back_fresh_pages(mapper, frame_allocator, range.start, page, None);
+                result = Err("Failed to allocate physical frame");
+                break;
+            }
+        };
+        
+        unsafe {
+            match mapper.map_to(page, frame, flags, frame_allocator) {
+                Ok(tlb) => flush_page(tlb, batched),
+                Err(_) => {
+                    roll_back_fresh_pages(mapper, frame_allocator, range.start, page, Some(frame));
+                    result = Err("Failed to map page");
+                    break;
+                }
+            }
+        }
+    }
+    
+    if batched {
+        flush_all();
+    }
+    result
+}
+
+// Undo a `map_fresh_pages` that failed at `failed`, newest first. Each page
+// took its frame and then any page tables it needed, so the tables it left
+// empty are freed before the frame. `frame` is the failed page's frame, if it
+// got one.
+fn roll_back_fresh_pages(
+    mapper: &mut (impl Mapper<Size4KiB> + CleanUp),
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    start: Page<Size4KiB>,
+    failed: Page<Size4KiB>,
+    frame: Option<PhysFrame<Size4KiB>>,
+) {
+    // Tables are only freed once nothing else is mapped through them, which
+    // for a table this call created means it held only our pages
+    unsafe {
+        mapper.clean_up_addr_range(Page::range_inclusive(failed, failed), frame_allocator);
+        if let Some(frame) = frame {
+            frame_allocator.deallocate_frame(frame);
+        }
+    }
+    
+    let mut page = failed;
+    while page > start {
+        page -= 1;
+        if let Ok((frame, tlb)) = mapper.unmap(page) {
+            tlb.flush();
+            unsafe {
+                mapper.clean_up_addr_range(Page::range_inclusive(page, page), frame_allocator);
+                frame_allocator.deallocate_frame(frame);
+            }
+        }
+    }
+}
+
+/// Maps a range of pages to the given frames, in order, e.g. for MMIO or
+/// memory shared with another mapping. `frame_allocator` only provides frames
+/// for intermediate page tables.
+///
+/// Fails without mapping anything unless there is exactly one frame per page.
+pub fn map_range_to_frames(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    range: PageRangeInclusive<Size4KiB>,
+    frames: impl Iterator<Item = PhysFrame<Size4KiB>>,
+    flags: PageTableFlags,
+) -> Result<(), &'static str> {
+    let frames: Vec<PhysFrame<Size4KiB>> = frames.collect();
+    if frames.len() != range.count() {
+        return Err("Number of frames does not match number of pages");
+    }
+    
+    let batched = batch_flush(&range);
+    let mut result = Ok(());
+    for (page, frame) in range.zip(frames) {
+        unsafe {
+            match mapper.map_to(page, frame, flags, frame_allocator) {
+                Ok(tlb) => flush_page(tlb, batched),
+                Err(_) => {
+                    result = Err("Failed to map page");
+                    break;
+                }
+            }
+        }
+    }
+    
+    if batched {
+        flush_all();
+    }
+    result
+}
+
+/// Unmaps a range of pages, flushing like `map_range`
+pub fn unmap_range(
+    mapper: &mut impl Mapper<Size4KiB>,
+    range: PageRangeInclusive<Size4KiB>,
+) -> Result<(), &'static str> {
+    let batched = batch_flush(&range);
+    let mut result = Ok(());
+    for page in range {
+        match mapper.unmap(page) {
+            Ok((_frame, flush)) => flush_page(flush, batched),
+            Err(_) => {
+                result = Err("Failed to unmap page");
+                break;
+            }
+        }
+    }
+    
+    if batched {
+        flush_all();
+    }
+    result
+}
+
 /// Maps a specific virtual page to a specific physical frame
 pub fn map_page_to_frame(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -197,6 +691,167 @@ pub fn map_page_to_frame(
     Ok(())
 }
 
+/// Maps a page to a device frame (MMIO) with `DEVICE_FLAGS`
+pub fn map_device(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    page: Page<Size4KiB>,
+    frame: PhysFrame<Size4KiB>,
+) -> Result<(), &'static str> {
+    map_page_to_frame(mapper, frame_allocator, page, frame, DEVICE_FLAGS)
+}
+
+/// Translates a virtual address, also returning the leaf page table flags
+pub fn translate_with_flags(mapper: &impl Translate, addr: VirtAddr) -> Option<(PhysAddr, PageTableFlags)> {
+    match mapper.translate(addr) {
+        x86_64::structures::paging::mapper::TranslateResult::Mapped { frame, offset, flags } => {
+            Some((frame.start_address() + offset, flags))
+        }
+        _ => None,
+    }
+}
+
+/// Reserves `pages` contiguous pages of virtual address space without
+/// mapping any frames. Frames are mapped into the range later with `commit`.
+///
+/// Ranges already reserved, or containing mapped pages, are skipped.
+pub fn reserve(mapper: &impl Translate, pages: u64) -> Result<VirtAddr, &'static str> {
+    if pages == 0 {
+        return Err("Cannot reserve zero pages");
+    }
+    
+    let size = pages.checked_mul(4096).ok_or("Reserved address space exhausted")?;
+    let mut reserved = RESERVED.lock();
+    let mut start = RESERVE_START;
+    loop {
+        let end = match start.checked_add(size) {
+            Some(end) if end <= RESERVE_END => end,
+            _ => return Err("Reserved address space exhausted"),
+        };
+        
+        // Move past any reservation overlapping the candidate range
+        if let Some(range) = reserved.iter().find(|r| r.start < end && start < r.end) {
+            start = range.end;
+            continue;
+        }
+        
+        // Also move past pages someone mapped without reserving them
+        let mapped = (start..end)
+            .step_by(4096)
+            .find(|&addr| mapper.translate_addr(VirtAddr::new(addr)).is_some());
+        if let Some(addr) = mapped {
+            start = addr + 4096;
+            continue;
+        }
+        
+        reserved.push(Reservation { start, end, mmapped: false })?;
+        return Ok(VirtAddr::new(start));
+    }
+}
+
+/// Returns the range reserved at `addr` to the arena. Its pages must already
+/// be unmapped.
+pub fn release(addr: VirtAddr) {
+    RESERVED.lock().remove(addr.as_u64());
+}
+
+/// Maps fresh frames into `pages` pages starting at `addr`, which must lie
+/// within a range returned by `reserve`
+pub fn commit(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    addr: VirtAddr,
+    pages: u64,
+) -> Result<(), &'static str> {
+    if pages == 0 {
+        return Ok(());
+    }
+    if !addr.is_aligned(4096u64) {
+        return Err("Commit address not page aligned");
+    }
+    
+    let start = addr.as_u64();
+    let end = start + pages * 4096;
+    let reserved = RESERVED.lock();
+    if !reserved.iter().any(|r| r.start <= start && end <= r.end) {
+        return Err("Range was not reserved");
+    }
+    
+    let first = Page::containing_address(addr);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    map_range(mapper, frame_allocator, Page::range_inclusive(first, first + (pages - 1)), flags)
+}
+
+/// Reserves `pages` pages of address space, maps fresh frames into them and
+/// returns the memory as a slice. `PRESENT` and `WRITABLE` are added to
+/// `flags`. Give the slice back with `munmap`.
+pub fn mmap(
+    mapper: &mut (impl Mapper<Size4KiB> + Translate + CleanUp),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    pages: u64,
+    flags: PageTableFlags,
+) -> Result<&'static mut [u8], &'static str> {
+    let start = reserve(mapper, pages)?;
+    let first = Page::containing_address(start);
+    let range = Page::range_inclusive(first, first + (pages - 1));
+    let flags = flags | PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    
+    if let Err(err) = map_fresh_pages(mapper, frame_allocator, range, flags) {
+        release(start);
+        return Err(err);
+    }
+    
+    let size = pages * 4096;
+    if let Some(reservation) = RESERVED.lock().get_mut(start.as_u64()) {
+        reservation.mmapped = true;
+    }
+    Ok(unsafe { core::slice::from_raw_parts_mut(start.as_mut_ptr(), size as usize) })
+}
+
+/// Unmaps memory returned by `mmap`, frees its frames and releases its
+/// address range
+pub fn munmap(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    memory: &'static mut [u8],
+) -> Result<(), &'static str> {
+    let start = memory.as_ptr() as u64;
+    let range = {
+        let mut reserved = RESERVED.lock();
+        let reservation = reserved.get_mut(start)
+            .filter(|r| r.mmapped && r.end - r.start == memory.len() as u64)
+            .ok_or("Memory was not returned by mmap")?;
+        // Taken back once, even if another munmap races this one
+        reservation.mmapped = false;
+        reservation.start..reservation.end
+    };
+    
+    let first = Page::containing_address(VirtAddr::new(range.start));
+    let last = Page::containing_address(VirtAddr::new(range.end - 1));
+    unmap_and_free(mapper, frame_allocator, Page::range_inclusive(first, last));
+    release(VirtAddr::new(range.start));
+    Ok(())
+}
+
+// Unmap the pages of a range and free their frames
+fn unmap_and_free(
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    range: PageRangeInclusive<Size4KiB>,
+) {
+    let batched = batch_flush(&range);
+    for page in range {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush_page(flush, batched);
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+    
+    if batched {
+        flush_all();
+    }
+}
+
 /// Unmaps a page and frees its frame
 pub fn unmap_page(
     mapper: &mut impl Mapper<Size4KiB>,
@@ -213,6 +868,132 @@ pub fn unmap_page(
     Ok(())
 }
 
+/// Returns the leaf page table flags of a mapped page
+fn page_flags(mapper: &impl Translate, page: Page<Size4KiB>) -> Result<PageTableFlags, &'static str> {
+    match mapper.translate(page.start_address()) {
+        x86_64::structures::paging::mapper::TranslateResult::Mapped { flags, .. } => Ok(flags),
+        _ => Err("Page not mapped"),
+    }
+}
+
+/// Marks a mapped page copy-on-write: clears `WRITABLE` and sets `COW_FLAG`
+pub fn make_cow(
+    mapper: &mut (impl Mapper<Size4KiB> + Translate),
+    page: Page<Size4KiB>,
+) -> Result<(), &'static str> {
+    let flags = page_flags(mapper, page)?;
+    let cow_flags = (flags - PageTableFlags::WRITABLE) | COW_FLAG;
+    
+    unsafe {
+        match mapper.update_flags(page, cow_flags) {
+            Ok(flush) => flush.flush(),
+            Err(_) => return Err("Failed to update page flags"),
+        }
+    }
+    
+    Ok(())
+}
+
+// Record one more mapping of `frame` besides the one it already had
+fn share_frame(frame: PhysFrame) {
+    *SHARED_FRAMES.lock().entry(frame.start_address().as_u64()).or_insert(1) += 1;
+}
+
+// Drop one mapping's share of `frame`. Returns `Some(true)` once no mapping
+// is left, so the frame may be freed, and `None` for a frame never shared
+// by `share_frame`
+fn unshare_frame(frame: PhysFrame) -> Option<bool> {
+    let addr = frame.start_address().as_u64();
+    let mut shared = SHARED_FRAMES.lock();
+    let count = shared.get_mut(&addr)?;
+    *count -= 1;
+    if *count == 0 {
+        shared.remove(&addr);
+        return Some(true);
+    }
+    Some(false)
+}
+
+/// Resolves a write fault on a copy-on-write page by giving it a private,
+/// writable copy of its frame
+pub fn handle_cow_fault(
+    mapper: &mut (impl Mapper<Size4KiB> + Translate),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    page: Page<Size4KiB>,
+) -> Result<(), &'static str> {
+    let flags = page_flags(mapper, page)?;
+    if !flags.contains(COW_FLAG) {
+        return Err("Not a copy-on-write page");
+    }
+    
+    let mut buffer = COW_BUFFER.try_lock().ok_or("Copy-on-write buffer busy")?;
+    let page_ptr: *mut [u8; 4096] = page.start_address().as_mut_ptr();
+    
+    // Save the contents through the existing read-only mapping
+    unsafe {
+        buffer.copy_from_slice(&*page_ptr);
+    }
+    
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or("Failed to allocate frame for copy-on-write")?;
+    
+    let (old_frame, flush) = mapper
+        .unmap(page)
+        .map_err(|_err| "Failed to unmap copy-on-write page")?;
+    flush.flush();
+    
+    // Freed once the last address space sharing it has its own copy. Other
+    // copy-on-write frames may still be mapped elsewhere and are kept.
+    if unshare_frame(old_frame) == Some(true) {
+        unsafe { frame_allocator.deallocate_frame(old_frame) };
+    }
+    
+    let new_flags = (flags - COW_FLAG) | PageTableFlags::WRITABLE;
+    unsafe {
+        match mapper.map_to(page, frame, new_flags, frame_allocator) {
+            Ok(tlb) => tlb.flush(),
+            Err(_) => return Err("Failed to map copy-on-write page"),
+        }
+        (*page_ptr).copy_from_slice(&buffer[..]);
+    }
+    
+    Ok(())
+}
+
+/// Tries to resolve a page fault, returning an error if it is a genuine fault
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> Result<(), &'static str> {
+    let page = Page::containing_address(addr);
+    
+    // Faulting while the mapper is held can't be resolved without deadlocking
+    let mut mapper = MAPPER.try_lock().ok_or("Mapper busy")?;
+    let mut frame_allocator = FRAME_ALLOCATOR.try_lock().ok_or("Frame allocator busy")?;
+    let mapper = mapper.as_mut().ok_or("Mapper not installed")?;
+    let frame_allocator = frame_allocator.as_mut().ok_or("Frame allocator not installed")?;
+    
+    let write_to_present = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(write_to_present) {
+        // A forked task's copy-on-write pages are in its own address space,
+        // so go through whichever tables are loaded
+        if active_page_table_frame() != kernel_pml4() {
+            let offset = physical_memory_offset();
+            let mut active = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+            return handle_cow_fault(&mut active, frame_allocator, page);
+        }
+        return handle_cow_fault(mapper, frame_allocator, page);
+    }
+    
+    // First touch of a lazily mapped heap page
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && crate::slab_allocator::lazy_heap_contains(addr)
+    {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        return map_range(mapper, frame_allocator, Page::range_inclusive(page, page), flags);
+    }
+    
+    Err("Unhandled page fault")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,192 @@
+use core::arch::asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use crate::io::{serial, Port, PortReadOnly};
+
+// Size of the stack buffer used to format a panic report
+const REPORT_BUFFER_SIZE: usize = 1024;
+
+// Function registered with `set_hook`, null if none
+static HOOK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+// Set while the hook runs, so a panic inside the hook doesn't run it again
+static IN_HOOK: AtomicBool = AtomicBool::new(false);
+
+/// Registers a function for the panic handler to call before it reports the
+/// panic and halts, replacing any previous hook.
+pub fn set_hook(hook: fn(&PanicInfo)) {
+    HOOK.store(hook as *mut (), Ordering::SeqCst);
+}
+
+/// Calls the registered panic hook, if any.
+///
+/// A panic raised by the hook itself skips the hook instead of recursing.
+pub fn run_hook(info: &PanicInfo) {
+    let hook = HOOK.load(Ordering::SeqCst);
+    if hook.is_null() || IN_HOOK.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    
+    // Safety: only `set_hook` stores into HOOK, and it stores a `fn(&PanicInfo)`
+    let hook: fn(&PanicInfo) = unsafe { core::mem::transmute(hook) };
+    hook(info);
+    
+    IN_HOOK.store(false, Ordering::SeqCst);
+}
+
+/// General-purpose registers, instruction pointer and flags captured at panic entry.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+pub struct RegisterDump {
+    pub rax: u64,
+    pub rbx: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub rbp: u64,
+    pub rsp: u64,
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+impl RegisterDump {
+    /// Captures the current register state.
+    ///
+    /// This is inlined into the caller so that `rip`, `rsp` and `rbp` describe
+    /// the panic handler's frame rather than a helper's.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let mut regs = RegisterDump::default();
+        let regs_ptr: *mut RegisterDump = &mut regs;
+        unsafe {
+            asm!(
+                // Store the general-purpose registers before the scratch register is used
+                "mov [{0} + 0x00], rax",
+                "mov [{0} + 0x08], rbx",
+                "mov [{0} + 0x10], rcx",
+                "mov [{0} + 0x18], rdx",
+                "mov [{0} + 0x20], rsi",
+                "mov [{0} + 0x28], rdi",
+                "mov [{0} + 0x30], rbp",
+                "mov [{0} + 0x38], rsp",
+                "mov [{0} + 0x40], r8",
+                "mov [{0} + 0x48], r9",
+                "mov [{0} + 0x50], r10",
+                "mov [{0} + 0x58], r11",
+                "mov [{0} + 0x60], r12",
+                "mov [{0} + 0x68], r13",
+                "mov [{0} + 0x70], r14",
+                "mov [{0} + 0x78], r15",
+
+                // Save RIP
+                "lea {1}, [rip]",
+                "mov [{0} + 0x80], {1}",
+
+                // Save RFLAGS
+                "pushfq",
+                "pop {1}",
+                "mov [{0} + 0x88], {1}",
+
+                in(reg) regs_ptr,
+                out(reg) _,
+            );
+        }
+        regs
+    }
+}
+
+/// A fixed-size formatting buffer that lives on the stack.
+///
+/// Output that does not fit is silently truncated, so formatting never fails
+/// and never allocates.
+pub struct StackBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> StackBuffer<N> {
+    pub const fn new() -> Self {
+        StackBuffer {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Truncation may split a multi-byte character, keep the valid prefix
+        match core::str::from_utf8(self.as_bytes()) {
+            Ok(s) => s,
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&self.buf[..e.valid_up_to()]) },
+        }
+    }
+}
+
+impl<const N: usize> Default for StackBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Write for StackBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(N - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Writes bytes straight to COM1, bypassing the `SERIAL1` mutex.
+pub fn emergency_write(bytes: &[u8]) {
+    // Safety: only ever writes bytes out, racing SERIAL1 at worst garbles them
+    let (mut data, mut line_status): (Port<u8>, PortReadOnly<u8>) = unsafe {
+        (
+            Port::new(serial::COM1_BASE + serial::REG_DATA),
+            PortReadOnly::new(serial::COM1_BASE + serial::REG_LINE_STATUS),
+        )
+    };
+
+    for &byte in bytes {
+        // Wait until the transmit holding register is empty
+        while line_status.read() & serial::LSR_TRANSMIT_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        data.write(byte);
+    }
+}
+
+/// Formats the panic message and register dump into `w`.
+pub fn write_report(w: &mut impl Write, info: &PanicInfo, regs: &RegisterDump) -> fmt::Result {
+    writeln!(w, "KERNEL PANIC: {}", info)?;
+    write_registers(w, regs)
+}
+
+/// Formats a register dump into `w`, three registers per line.
+pub fn write_registers(w: &mut impl Write, regs: &RegisterDump) -> fmt::Result {
+    writeln!(w, "RIP={:#018x} RSP={:#018x} RFLAGS={:#018x}", regs.rip, regs.rsp, regs.rflags)?;
+    writeln!(w, "RAX={:#018x} RBX={:#018x} RCX={:#018x}", regs.rax, regs.rbx, regs.rcx)?;
+    writeln!(w, "RDX={:#018x} RSI={:#018x} RDI={:#018x}", regs.rdx, regs.rsi, regs.rdi)?;
+    writeln!(w, "RBP={:#018x} R8 ={:#018x} R9 ={:#018x}", regs.rbp, regs.r8, regs.r9)?;
+    writeln!(w, "R10={:#018x} R11={:#018x} R12={:#018x}", regs.r10, regs.r11, regs.r12)?;
+    writeln!(w, "R13={:#018x} R14={:#018x} R15={:#018x}", regs.r13, regs.r14, regs.r15)
+}
+
+/// Reports a panic over COM1 without allocating or taking any lock.
+pub fn report(info: &PanicInfo, regs: &RegisterDump) {
+    let mut buffer = StackBuffer::<REPORT_BUFFER_SIZE>::new();
+    let _ = write_report(&mut buffer, info, regs);
+    emergency_write(buffer.as_bytes());
+}
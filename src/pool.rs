@@ -0,0 +1,131 @@
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use spin::Mutex;
+
+// Marks the end of the free list
+const NIL: usize = usize::MAX;
+
+// A slot holds either a live object or the index of the next free slot,
+// the same trick the slab allocator plays with its free blocks
+union Slot<T> {
+    value: ManuallyDrop<T>,
+    next: usize,
+}
+
+struct FreeList {
+    head: usize,
+    available: usize,
+}
+
+/// A fixed-capacity pool of `T`s.
+///
+/// All slots are allocated up front, so handing one out or returning it is a
+/// free-list push or pop under a short lock and never touches the global
+/// allocator. Meant for objects that are created and dropped often, like task
+/// control blocks or open file state.
+pub struct Pool<T> {
+    slots: Box<[UnsafeCell<Slot<T>>]>,
+    free: Mutex<FreeList>,
+}
+
+// Slots are only reached through the free list lock or the one PoolBox
+// that owns them
+unsafe impl<T: Send> Send for Pool<T> {}
+unsafe impl<T: Send> Sync for Pool<T> {}
+
+impl<T> Pool<T> {
+    /// Creates a pool with room for `capacity` objects.
+    pub fn new(capacity: usize) -> Self {
+        let slots = (0..capacity)
+            .map(|i| {
+                let next = if i + 1 < capacity { i + 1 } else { NIL };
+                UnsafeCell::new(Slot { next })
+            })
+            .collect();
+        
+        Pool {
+            slots,
+            free: Mutex::new(FreeList {
+                head: if capacity > 0 { 0 } else { NIL },
+                available: capacity,
+            }),
+        }
+    }
+    
+    /// Returns the number of objects the pool can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+    
+    /// Returns the number of free slots.
+    pub fn available(&self) -> usize {
+        self.free.lock().available
+    }
+    
+    /// Moves `value` into a free slot, failing when every slot is in use.
+    ///
+    /// The slot goes back to the pool when the returned `PoolBox` is dropped.
+    pub fn alloc(&self, value: T) -> Result<PoolBox<'_, T>, &'static str> {
+        let index = {
+            let mut free = self.free.lock();
+            let index = free.head;
+            if index == NIL {
+                return Err("Pool exhausted");
+            }
+            free.head = unsafe { (*self.slots[index].get()).next };
+            free.available -= 1;
+            index
+        };
+        
+        // The slot is off the free list, so nothing else can touch it
+        unsafe {
+            (*self.slots[index].get()).value = ManuallyDrop::new(value);
+        }
+        Ok(PoolBox { pool: self, index })
+    }
+}
+
+/// An object living in a `Pool`, returned to it on drop.
+pub struct PoolBox<'a, T> {
+    pool: &'a Pool<T>,
+    index: usize,
+}
+
+impl<T> Deref for PoolBox<'_, T> {
+    type Target = T;
+    
+    fn deref(&self) -> &T {
+        unsafe { &(*self.pool.slots[self.index].get()).value }
+    }
+}
+
+impl<T> DerefMut for PoolBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut (*self.pool.slots[self.index].get()).value }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for PoolBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T> Drop for PoolBox<'_, T> {
+    fn drop(&mut self) {
+        let slot = self.pool.slots[self.index].get();
+        unsafe {
+            ManuallyDrop::drop(&mut (*slot).value);
+        }
+        
+        let mut free = self.pool.free.lock();
+        unsafe {
+            (*slot).next = free.head;
+        }
+        free.head = self.index;
+        free.available += 1;
+    }
+}
@@ -0,0 +1,85 @@
+use crate::println;
+use x86_64::instructions::port::Port;
+
+// Poweroff ports of the ACPI PM1a control block on common virtual machines,
+// with the value that sets SLP_EN and the S5 sleep type on each
+const POWEROFF_PORTS: [(u16, u16); 3] = [
+    (0x604, 0x2000),  // QEMU
+    (0xB004, 0x2000), // Bochs and older QEMU
+    (0x4004, 0x3400), // VirtualBox
+];
+
+// How long `try_shutdown` gives the machine to power off
+const POWEROFF_WAIT_NS: u64 = 1_000_000_000;
+
+// 8042 keyboard controller status/command port, its input-buffer-full bit,
+// and the command that pulses the CPU reset line
+const KBC_STATUS: u16 = 0x64;
+const KBC_INPUT_FULL: u8 = 0x02;
+const KBC_RESET: u8 = 0xFE;
+
+/// Powers the machine off.
+///
+/// There is no ACPI table parser yet, so this writes the S5 sleep command to
+/// the PM1a control ports that QEMU, Bochs and VirtualBox use. On anything
+/// else it falls back to halting forever. Tests should keep using
+/// `exit_qemu`, which reports an exit code.
+pub fn shutdown() -> ! {
+    x86_64::instructions::interrupts::disable();
+    println!("Powering off");
+    
+    try_shutdown();
+    
+    println!("Poweroff failed, halting");
+    crate::hlt_loop();
+}
+
+/// Writes the S5 sleep command like `shutdown` and gives the machine
+/// `POWEROFF_WAIT_NS` to go off, since a virtual machine may take a moment to
+/// act on it. Returns only if the poweroff failed.
+pub fn try_shutdown() {
+    for &(port, value) in POWEROFF_PORTS.iter() {
+        unsafe {
+            Port::<u16>::new(port).write(value);
+        }
+    }
+    
+    let deadline = crate::time::monotonic_ns().saturating_add(POWEROFF_WAIT_NS);
+    while crate::time::monotonic_ns() < deadline {
+        core::hint::spin_loop();
+    }
+}
+
+/// Resets the machine.
+///
+/// Asks the 8042 keyboard controller to pulse the reset line and, if the
+/// machine is still running after that, triple-faults it by loading an empty
+/// IDT and raising an exception.
+pub fn reboot() -> ! {
+    use x86_64::structures::DescriptorTablePointer;
+    use x86_64::VirtAddr;
+    
+    x86_64::instructions::interrupts::disable();
+    println!("Rebooting");
+    
+    let mut status: Port<u8> = Port::new(KBC_STATUS);
+    unsafe {
+        // Wait (briefly) for the controller to accept a command
+        for _ in 0..100_000 {
+            if status.read() & KBC_INPUT_FULL == 0 {
+                break;
+            }
+        }
+        status.write(KBC_RESET);
+    }
+    
+    // No IDT means the next exception can't be delivered, nor can the
+    // double fault that follows, so the CPU resets
+    let empty = DescriptorTablePointer { limit: 0, base: VirtAddr::new(0) };
+    unsafe {
+        x86_64::instructions::tables::lidt(&empty);
+    }
+    x86_64::instructions::interrupts::int3();
+    
+    crate::hlt_loop();
+}
@@ -0,0 +1,95 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use spin::Mutex;
+use crate::fs::FileSystem;
+use crate::{console, print, println};
+
+// Longest command line accepted from the console
+const LINE_LENGTH: usize = 128;
+// Bytes read from a file per `read` call by `cat`
+const CAT_CHUNK: usize = 512;
+
+// Filesystem the shell's `ls` and `cat` operate on
+static ROOT: Mutex<Option<Box<dyn FileSystem + Send>>> = Mutex::new(None);
+
+/// Sets the filesystem used by `ls` and `cat`.
+pub fn mount(fs: Box<dyn FileSystem + Send>) {
+    *ROOT.lock() = Some(fs);
+}
+
+/// Shell entry point, suitable for `task::spawn`.
+pub fn shell_task() -> ! {
+    let mut line = [0u8; LINE_LENGTH];
+    loop {
+        print!("> ");
+        let len = console::read_line(&mut line);
+        
+        let command = match core::str::from_utf8(&line[..len]) {
+            Ok(command) => command,
+            Err(_) => {
+                println!("invalid input");
+                continue;
+            }
+        };
+        
+        if let Err(e) = execute(command) {
+            println!("error: {}", e);
+        }
+    }
+}
+
+/// Parses a command line and runs the matching command.
+///
+/// Supported commands are `ls [path]`, `cat <path>`, `free` and `ps`. Blank
+/// lines are ignored.
+pub fn execute(line: &str) -> Result<(), &'static str> {
+    let mut args = line.split_whitespace();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    
+    match command {
+        "ls" => ls(args.next().unwrap_or("/")),
+        "cat" => cat(args.next().ok_or("Usage: cat <path>")?),
+        "free" => {
+            crate::slab_allocator::print_heap_status();
+            Ok(())
+        }
+        "ps" => {
+            crate::task::list();
+            Ok(())
+        }
+        _ => Err("Unknown command"),
+    }
+}
+
+// Print the names in a directory, one per line
+fn ls(path: &str) -> Result<(), &'static str> {
+    let root = ROOT.lock();
+    let fs = root.as_ref().ok_or("No filesystem mounted")?;
+    
+    for name in fs.readdir(path)? {
+        println!("{}", name);
+    }
+    Ok(())
+}
+
+// Print the contents of a file
+fn cat(path: &str) -> Result<(), &'static str> {
+    let mut root = ROOT.lock();
+    let fs = root.as_mut().ok_or("No filesystem mounted")?;
+    
+    let mut handle = fs.open(path)?;
+    let mut buffer = [0u8; CAT_CHUNK];
+    let result = loop {
+        match fs.read(&mut handle, &mut buffer) {
+            Ok(0) => break Ok(()),
+            Ok(n) => print!("{}", String::from_utf8_lossy(&buffer[..n])),
+            Err(e) => break Err(e.into()),
+        }
+    };
+    
+    fs.close(handle)?;
+    result
+}
@@ -1,16 +1,41 @@
 // src/slab_allocator.rs
 
 use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
 use core::ptr::NonNull;
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use spin::Mutex;
 use x86_64::VirtAddr;
 use x86_64::structures::paging::{
-    FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB, Translate,
+    mapper::CleanUp,
 };
 
 // Define the fixed sizes we'll support in our slab allocator
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048, 4096];
+
+// find_slab_index takes the first size that fits and blocks are aligned to
+// their size, so the sizes must ascend and be powers of two; every block must
+// also hold a free list pointer
+const _: () = assert!(check_block_sizes(BLOCK_SIZES).is_ok(), "invalid BLOCK_SIZES");
+
+const fn check_block_sizes(sizes: &[usize]) -> Result<(), &'static str> {
+    let mut i = 0;
+    while i < sizes.len() {
+        if !sizes[i].is_power_of_two() {
+            return Err("Block size is not a power of two");
+        }
+        if sizes[i] < core::mem::size_of::<FreeBlock>() {
+            return Err("Block size too small for a free list pointer");
+        }
+        if i > 0 && sizes[i] <= sizes[i - 1] {
+            return Err("Block sizes are not strictly ascending");
+        }
+        i += 1;
+    }
+    Ok(())
+}
 // Heap configuration
 pub const HEAP_START: usize = 0x_4444_4444_0000;
 pub const HEAP_SIZE: usize = 512 * 1024; // 512 KiB
@@ -19,6 +44,9 @@ struct Slab {
     block_size: usize,
     free_blocks: NonNull<FreeBlock>,
     blocks_count: usize,
+    // Blocks from here to region_end have never been handed out and aren't on the free list
+    next_unused: usize,
+    region_end: usize,
     // Add PhantomData to make NonNull Send/Sync
     _phantom: PhantomData<FreeBlock>,
 }
@@ -32,46 +60,48 @@ impl Slab {
             block_size: 0,
             free_blocks: NonNull::dangling(),
             blocks_count: 0,
+            next_unused: 0,
+            region_end: 0,
             _phantom: PhantomData,
         }
     }
 
     fn init(&mut self, block_size: usize, heap_start: usize, heap_size: usize) {
         self.block_size = block_size;
-        let blocks_count = heap_size / block_size;
-        self.blocks_count = blocks_count;
-        
-        if blocks_count == 0 {
-            self.free_blocks = NonNull::dangling();
-            return;
-        }
-        
-        // Initialize free block list
-        let mut current_block = heap_start as *mut FreeBlock;
-        
-        for i in 0..blocks_count - 1 {
-            unsafe {
-                // Initialize the current block's next pointer
-                (*current_block).next = NonNull::new(
-                    (heap_start + (i + 1) * block_size) as *mut FreeBlock
-                ).unwrap();
-                
-                // Move to the next block
-                current_block = (heap_start + (i + 1) * block_size) as *mut FreeBlock;
-            }
-        }
         
-        // Set the last block's next pointer to dangling
-        unsafe {
-            (*current_block).next = NonNull::dangling();
-        }
+        // Align the first block to the block size: find_slab_index routes
+        // layouts by max(size, align), so every block must be size-aligned
+        let region_end = heap_start + heap_size;
+        let first_block = heap_start.next_multiple_of(block_size).min(region_end);
+        let blocks_count = (region_end - first_block) / block_size;
+        self.blocks_count = blocks_count;
         
-        self.free_blocks = NonNull::new(heap_start as *mut FreeBlock).unwrap();
+        // Blocks are carved out on first use, so initialization never touches
+        // the heap memory itself (this is what makes a lazily mapped heap work)
+        self.free_blocks = NonNull::dangling();
+        self.next_unused = first_block;
+        self.region_end = first_block + blocks_count * block_size;
+    }
+    
+    // Number of blocks that have never been handed out
+    fn unused_blocks(&self) -> usize {
+        (self.region_end - self.next_unused) / self.block_size.max(1)
+    }
+    
+    // Whether a block can be handed out right now
+    fn has_free_block(&self) -> bool {
+        self.free_blocks.as_ptr() != NonNull::dangling().as_ptr() || self.unused_blocks() > 0
     }
     
     fn allocate(&mut self) -> Option<NonNull<u8>> {
         if self.free_blocks.as_ptr() == NonNull::dangling().as_ptr() {
-            return None; // No free blocks available
+            // Free list empty, carve a fresh block if any are left
+            if self.next_unused + self.block_size > self.region_end {
+                return None; // No free blocks available
+            }
+            let block = self.next_unused;
+            self.next_unused += self.block_size;
+            return NonNull::new(block as *mut u8);
         }
         
         // Take the first free block
@@ -83,6 +113,34 @@ impl Slab {
         Some(NonNull::new(block.as_ptr() as *mut u8).unwrap())
     }
     
+    // Walk the free list checking that every block lies in `region`, is
+    // aligned to the block size, and that the list ends within blocks_count steps
+    fn validate(&self, region: (usize, usize)) -> Result<(), &'static str> {
+        let (start, end) = region;
+        let mut current = self.free_blocks.as_ptr();
+        let mut steps = 0;
+        
+        while current != NonNull::<FreeBlock>::dangling().as_ptr() {
+            if steps == self.blocks_count {
+                return Err("Free list longer than the slab (cycle?)");
+            }
+            
+            let addr = current as usize;
+            if addr < start || addr + self.block_size > end {
+                return Err("Free block outside its slab region");
+            }
+            if addr % self.block_size != 0 {
+                return Err("Free block not aligned to the block size");
+            }
+            
+            // Only dereferenced once it is known to point into the slab
+            current = unsafe { (*current).next.as_ptr() };
+            steps += 1;
+        }
+        
+        Ok(())
+    }
+    
     fn deallocate(&mut self, ptr: NonNull<u8>) {
         let block = NonNull::new(ptr.as_ptr() as *mut FreeBlock).unwrap();
         unsafe {
@@ -101,11 +159,54 @@ struct FreeBlock {
 unsafe impl Send for FreeBlock {}
 unsafe impl Sync for FreeBlock {}
 
+/// Why an allocation request could not be satisfied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// Every block in the slab serving this size class is in use
+    SlabFull { block_size: usize },
+    /// The fallback allocator has no hole large enough
+    FallbackExhausted { size: usize },
+    /// The allocator cannot serve this layout at all (e.g. zero-sized)
+    UnsupportedSize { size: usize },
+    /// A lock the allocation needs is held elsewhere (see `alloc_from_irq`)
+    Contended,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AllocError::SlabFull { block_size } => write!(f, "{}-byte slab is full", block_size),
+            AllocError::FallbackExhausted { size } => {
+                write!(f, "fallback allocator exhausted ({} bytes requested)", size)
+            }
+            AllocError::UnsupportedSize { size } => write!(f, "unsupported allocation size {}", size),
+            AllocError::Contended => write!(f, "allocator lock held elsewhere"),
+        }
+    }
+}
+
 // Slab allocator structure with tracking for heap regions
 pub struct SlabAllocator {
     slabs: [Mutex<Slab>; BLOCK_SIZES.len()],
     slab_heap_regions: [Mutex<(usize, usize)>; BLOCK_SIZES.len()], // (start, end) for each slab region
     fallback_allocator: Mutex<linked_list_allocator::Heap>,
+    // (start, size) of the fallback region, initialized on first use
+    fallback_region: Mutex<(usize, usize)>,
+    // Statistics, kept outside the locks so reading them never contends
+    alloc_count: AtomicUsize,
+    dealloc_count: AtomicUsize,
+    slab_used: [AtomicUsize; BLOCK_SIZES.len()],
+}
+
+/// Allocation counters of a `SlabAllocator`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocStats {
+    /// Successful allocations, from the slabs and the fallback
+    pub alloc_count: usize,
+    /// Deallocations
+    pub dealloc_count: usize,
+    /// Blocks currently handed out by each slab, smallest block size first
+    pub slab_used: [usize; BLOCK_SIZES.len()],
 }
 
 // Explicitly implement Send and Sync for SlabAllocator
@@ -121,6 +222,10 @@ impl SlabAllocator {
             slabs: [EMPTY_SLAB; BLOCK_SIZES.len()],
             slab_heap_regions: [EMPTY_REGION; BLOCK_SIZES.len()],
             fallback_allocator: Mutex::new(linked_list_allocator::Heap::empty()),
+            fallback_region: Mutex::new((0, 0)),
+            alloc_count: AtomicUsize::new(0),
+            dealloc_count: AtomicUsize::new(0),
+            slab_used: [const { AtomicUsize::new(0) }; BLOCK_SIZES.len()],
         }
     }
     
@@ -128,10 +233,77 @@ impl SlabAllocator {
     pub unsafe fn init(&self, heap_start: usize, heap_size: usize) {
         // Split the heap into equal parts for each slab size
         let slab_heap_size = heap_size / (BLOCK_SIZES.len() + 1); // +1 for fallback allocator
+        unsafe { self.init_split(heap_start, heap_size, [slab_heap_size; BLOCK_SIZES.len()]) }
+    }
+    
+    /// Initializes the allocator splitting the slabs' part of the heap in
+    /// proportion to `weights`, one per block size, so classes a workload
+    /// uses more get more room. The fallback keeps the share `init` gives it,
+    /// and equal weights split the rest like `init` does.
+    ///
+    /// # Safety
+    ///
+    /// The heap area must be mapped, writable and not used for anything else.
+    pub unsafe fn init_with_weights(
+        &self,
+        heap_start: usize,
+        heap_size: usize,
+        weights: &[usize],
+    ) -> Result<(), &'static str> {
+        if weights.len() != BLOCK_SIZES.len() {
+            return Err("Need one weight per block size");
+        }
+        let total_weight: u128 = weights.iter().map(|&weight| weight as u128).sum();
+        if total_weight == 0 {
+            return Err("Slab weights must not all be zero");
+        }
+        
+        let slabs_size = (heap_size - heap_size / (BLOCK_SIZES.len() + 1)) as u128;
+        let slab_sizes = core::array::from_fn(|i| (slabs_size * weights[i] as u128 / total_weight) as usize);
+        unsafe { self.init_split(heap_start, heap_size, slab_sizes) };
+        Ok(())
+    }
+    
+    /// Initializes the allocator giving `fallback_fraction` of the heap to the
+    /// fallback allocator and splitting the rest equally between the slabs.
+    /// `init` gives the fallback one equal share, 1/11 of the heap.
+    ///
+    /// # Safety
+    ///
+    /// The heap area must be mapped, writable and not used for anything else.
+    pub unsafe fn init_with_fallback_fraction(
+        &self,
+        heap_start: usize,
+        heap_size: usize,
+        fallback_fraction: f32,
+    ) -> Result<(), &'static str> {
+        if !(fallback_fraction > 0.0 && fallback_fraction < 1.0) {
+            return Err("Fallback fraction must be between 0 and 1");
+        }
+        
+        let fallback_size = (heap_size as f32 * fallback_fraction) as usize;
+        let slab_heap_size = (heap_size - fallback_size) / BLOCK_SIZES.len();
+        unsafe { self.init_split(heap_start, heap_size, [slab_heap_size; BLOCK_SIZES.len()]) };
+        Ok(())
+    }
+    
+    // Give each slab its size from `slab_sizes` and the fallback whatever is left
+    unsafe fn init_split(&self, heap_start: usize, heap_size: usize, slab_sizes: [usize; BLOCK_SIZES.len()]) {
+        debug_assert_eq!(check_block_sizes(BLOCK_SIZES), Ok(()));
+        
         let mut current_heap_start = heap_start;
         
         // Initialize each slab with its portion of the heap
         for (i, &block_size) in BLOCK_SIZES.iter().enumerate() {
+            // A share too small for one aligned block (a tiny heap) would just
+            // be wasted, so the slab gets nothing and the fallback gets it
+            let first_block = current_heap_start.next_multiple_of(block_size);
+            let slab_heap_size = if first_block + block_size <= current_heap_start + slab_sizes[i] {
+                slab_sizes[i]
+            } else {
+                0
+            };
+            
             // Store the region bounds
             *self.slab_heap_regions[i].lock() = (current_heap_start, current_heap_start + slab_heap_size);
             
@@ -140,63 +312,229 @@ impl SlabAllocator {
             current_heap_start += slab_heap_size;
         }
         
-        // Initialize fallback allocator with remaining space
-        let remaining_size = heap_size - (slab_heap_size * BLOCK_SIZES.len());
-        if remaining_size > 0 {
-            // Fix: Pass usize directly instead of *mut u8
-            unsafe {
-                self.fallback_allocator.lock().init(current_heap_start, remaining_size);
+        // Reserve the remaining space for the fallback allocator; it writes its
+        // first hole header on first use rather than now
+        let remaining_size = heap_start + heap_size - current_heap_start;
+        *self.fallback_region.lock() = (current_heap_start, remaining_size);
+    }
+    
+    // Lock the fallback allocator, initializing it on first use
+    fn fallback(&self) -> spin::MutexGuard<'_, linked_list_allocator::Heap> {
+        let mut fallback = self.fallback_allocator.lock();
+        if fallback.size() == 0 {
+            let (start, size) = *self.fallback_region.lock();
+            if size > 0 {
+                // Fix: Pass usize directly instead of *mut u8
+                unsafe {
+                    fallback.init(start, size);
+                }
+            }
+        }
+        fallback
+    }
+    
+    /// Like `try_alloc`, but never spins: if a lock the allocation needs is
+    /// already held (e.g. by the code an interrupt handler interrupted) it
+    /// returns `AllocError::Contended` instead of deadlocking. A full slab
+    /// falls back to the fallback allocator, as `GlobalAlloc::alloc` does.
+    pub fn alloc_from_irq(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::UnsupportedSize { size: layout.size() });
+        }
+        
+        if let Some(index) = self.find_slab_index(&layout) {
+            let mut slab = self.slabs[index].try_lock().ok_or(AllocError::Contended)?;
+            if let Some(ptr) = slab.allocate() {
+                self.count_alloc(Some(index));
+                return Ok(ptr);
+            }
+        }
+        
+        let mut fallback = self.fallback_allocator.try_lock().ok_or(AllocError::Contended)?;
+        if fallback.size() == 0 {
+            let (start, size) = *self.fallback_region.try_lock().ok_or(AllocError::Contended)?;
+            if size > 0 {
+                unsafe {
+                    fallback.init(start, size);
+                }
+            }
+        }
+        let ptr = fallback
+            .allocate_first_fit(layout)
+            .map_err(|_| AllocError::FallbackExhausted { size: layout.size() })?;
+        self.count_alloc(None);
+        Ok(ptr)
+    }
+    
+    /// Allocates from the slab for `layout`'s size class, or from the fallback
+    /// allocator if it is larger than every slab. Unlike `GlobalAlloc::alloc`
+    /// a full slab is reported rather than silently falling back.
+    pub fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return Err(AllocError::UnsupportedSize { size: layout.size() });
+        }
+        
+        match self.find_slab_index(&layout) {
+            Some(index) => {
+                let ptr = self.slabs[index]
+                    .lock()
+                    .allocate()
+                    .ok_or(AllocError::SlabFull { block_size: BLOCK_SIZES[index] })?;
+                self.count_alloc(Some(index));
+                Ok(ptr)
             }
+            None => self.try_fallback(layout),
         }
     }
     
-    // Find the appropriate slab for a given layout
+    fn try_fallback(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let ptr = self.fallback()
+            .allocate_first_fit(layout)
+            .map_err(|_| AllocError::FallbackExhausted { size: layout.size() })?;
+        self.count_alloc(None);
+        Ok(ptr)
+    }
+    
+    // Record an allocation, from slab `slab` or the fallback
+    fn count_alloc(&self, slab: Option<usize>) {
+        self.alloc_count.fetch_add(1, Ordering::Relaxed);
+        if let Some(index) = slab {
+            self.slab_used[index].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    
+    /// Returns the allocation counters. They are updated without locking, so
+    /// reading them never waits for an allocation in progress, but counters
+    /// read while other allocations run needn't be consistent with each other.
+    pub fn stats(&self) -> AllocStats {
+        AllocStats {
+            alloc_count: self.alloc_count.load(Ordering::Relaxed),
+            dealloc_count: self.dealloc_count.load(Ordering::Relaxed),
+            slab_used: core::array::from_fn(|i| self.slab_used[i].load(Ordering::Relaxed)),
+        }
+    }
+    
+    /// Returns the largest allocation size that could currently succeed: the
+    /// biggest slab class with a free block, or the fallback allocator's free
+    /// space. The fallback's free list isn't exposed, so its free space is an
+    /// upper bound on its largest hole.
+    pub fn largest_free_block(&self) -> usize {
+        let slab_largest = BLOCK_SIZES.iter()
+            .zip(self.slabs.iter())
+            .rev()
+            .find(|(_, slab)| slab.lock().has_free_block())
+            .map_or(0, |(&size, _)| size);
+        
+        let fallback = self.fallback_allocator.lock();
+        let fallback_largest = if fallback.size() == 0 {
+            // Not initialized yet, the whole region is one hole
+            self.fallback_region.lock().1
+        } else {
+            fallback.free()
+        };
+        
+        slab_largest.max(fallback_largest)
+    }
+    
+    /// Checks every slab's free list for corruption, returning a description
+    /// of the first anomaly found.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        for (slab, region) in self.slabs.iter().zip(self.slab_heap_regions.iter()) {
+            slab.lock().validate(*region.lock())?;
+        }
+        Ok(())
+    }
+    
+    /// Returns the index in `BLOCK_SIZES` of the slab whose region holds
+    /// `ptr`, or `None` for the fallback region (or memory outside the heap).
+    pub fn slab_of(&self, ptr: *const u8) -> Option<usize> {
+        let addr = ptr as usize;
+        self.slab_heap_regions.iter().position(|region| {
+            let (region_start, region_end) = *region.lock();
+            addr >= region_start && addr < region_end
+        })
+    }
+    
+    // Find the appropriate slab for a given layout, or None to use the fallback
     fn find_slab_index(&self, layout: &Layout) -> Option<usize> {
-        // Consider both size and alignment requirements
-        let required_block_size = layout.size().max(layout.align());
-        BLOCK_SIZES.iter()
-            .position(|&size| size >= required_block_size)
+        if !SLABS_ENABLED.load(Ordering::Relaxed) {
+            return None;
+        }
+        size_class(layout)
     }
 }
 
+// The slab class whose blocks fit `layout`, whether or not slabs are enabled.
+// Zero-size layouts never get a slab block; `alloc` handles them itself.
+fn size_class(layout: &Layout) -> Option<usize> {
+    if layout.size() == 0 {
+        return None;
+    }
+    
+    // Consider both size and alignment requirements
+    let required_block_size = layout.size().max(layout.align());
+    BLOCK_SIZES.iter()
+        .position(|&size| size >= required_block_size)
+}
+
 // Implement the global allocator trait
 unsafe impl GlobalAlloc for SlabAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        // Try to find a fitting slab
-        if let Some(index) = self.find_slab_index(&layout) {
-            if let Some(ptr) = self.slabs[index].lock().allocate() {
-                return ptr.as_ptr();
-            }
+        // Nothing is read or written through a zero-size allocation, so any
+        // non-null, aligned address will do, and no memory is used for it
+        if layout.size() == 0 {
+            return layout.align() as *mut u8;
         }
         
-        // If no slab fits or all slabs are full, use fallback allocator
-        self.fallback_allocator.lock().allocate_first_fit(layout)
-            .ok()
-            .map_or(core::ptr::null_mut(), |allocation| allocation.as_ptr())
+        // Try the fitting slab, falling back if it is full
+        let result = self.try_alloc(layout).or_else(|err| match err {
+            AllocError::SlabFull { .. } => self.try_fallback(layout).map_err(|_| err),
+            other => Err(other),
+        });
+        
+        match result {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(err) => {
+                // Remember why for the alloc_error_handler
+                if let Some(mut last) = LAST_ALLOC_ERROR.try_lock() {
+                    *last = Some(err);
+                }
+                core::ptr::null_mut()
+            }
+        }
     }
     
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        // Find which region this pointer belongs to
-        let ptr_addr = ptr as usize;
+        // Dangling pointer from a zero-size `alloc`
+        if layout.size() == 0 {
+            return;
+        }
+        
+        self.dealloc_count.fetch_add(1, Ordering::Relaxed);
         
-        // Check each slab region
-        for i in 0..BLOCK_SIZES.len() {
-            let (region_start, region_end) = *self.slab_heap_regions[i].lock();
+        // Find which slab region this pointer belongs to
+        if let Some(i) = self.slab_of(ptr) {
+            // A block freed with another class's layout was allocated or freed
+            // with the wrong layout; pushing it on this list hides the bug
+            // until the block's real size overlaps a neighbour
+            debug_assert!(
+                size_class(&layout) == Some(i),
+                "dealloc of {:p} with {:?}, but it is a {}-byte slab block",
+                ptr, layout, BLOCK_SIZES[i]
+            );
             
-            if ptr_addr >= region_start && ptr_addr < region_end {
-                // Found the slab this pointer belongs to
-                // Wrap unsafe functions in unsafe blocks
-                unsafe {
-                    self.slabs[i].lock().deallocate(NonNull::new_unchecked(ptr));
-                }
-                return;
+            // Wrap unsafe functions in unsafe blocks
+            unsafe {
+                self.slabs[i].lock().deallocate(NonNull::new_unchecked(ptr));
             }
+            self.slab_used[i].fetch_sub(1, Ordering::Relaxed);
+            return;
         }
         
         // If not in any slab region, use fallback allocator
         // Wrap unsafe functions in unsafe blocks
         unsafe {
-            self.fallback_allocator.lock().deallocate(
+            self.fallback().deallocate(
                 NonNull::new_unchecked(ptr),
                 layout
             );
@@ -204,60 +542,204 @@ unsafe impl GlobalAlloc for SlabAllocator {
     }
 }
 
+// Whether allocations are served from the slabs at all, see `set_slab_enabled`
+static SLABS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Routes every new allocation to the fallback allocator when `false`, for
+/// telling slab bugs apart from other heap corruption. Blocks already handed
+/// out by a slab are still returned to it, since `dealloc` goes by address.
+pub fn set_slab_enabled(enabled: bool) {
+    SLABS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+// Reason for the most recent failed global allocation
+static LAST_ALLOC_ERROR: Mutex<Option<AllocError>> = Mutex::new(None);
+
+/// Returns why the most recent global allocation failed, if any has
+pub fn last_alloc_error() -> Option<AllocError> {
+    *LAST_ALLOC_ERROR.lock()
+}
+
+/// Allocates from the kernel heap without ever spinning on a lock, for use
+/// from interrupt handlers.
+///
+/// The allocator's locks don't disable interrupts, so a handler that
+/// allocates through `GlobalAlloc` while the interrupted code holds one of
+/// them spins forever. This fails with `AllocError::Contended` instead. The
+/// memory is freed through the global allocator as usual, but not from
+/// interrupt context, since `dealloc` takes the same locks.
+pub fn alloc_from_irq(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    ALLOCATOR.alloc_from_irq(layout)
+}
+
+/// Returns the kernel heap's allocation counters, without taking any lock
+pub fn alloc_stats() -> AllocStats {
+    ALLOCATOR.stats()
+}
+
+/// Returns the slab class (index into the block sizes) of a kernel heap
+/// pointer, or `None` if it was served by the fallback allocator
+pub fn slab_of(ptr: *const u8) -> Option<usize> {
+    ALLOCATOR.slab_of(ptr)
+}
+
 // Define global allocator instance
 #[global_allocator]
 static ALLOCATOR: SlabAllocator = SlabAllocator::new();
 
-// Maps the virtual heap pages to physical frames
+// Where the kernel heap actually lives, set when it is initialized
+static HEAP_BASE: AtomicUsize = AtomicUsize::new(0);
+static HEAP_LEN: AtomicUsize = AtomicUsize::new(0);
+
+// Returns the (start, size) of the kernel heap, or (0, 0) before init
+pub fn heap_region() -> (usize, usize) {
+    (HEAP_BASE.load(Ordering::SeqCst), HEAP_LEN.load(Ordering::SeqCst))
+}
+
+// Backs the kernel heap with a static arena, for the lib's own tests, which
+// run without the bootloader's memory map to map a heap from
+#[cfg(test)]
+pub fn init_test_heap() {
+    #[repr(align(4096))]
+    struct Arena([u8; 64 * 4096]);
+    static mut ARENA: Arena = Arena([0; 64 * 4096]);
+    
+    let heap_start = unsafe { core::ptr::addr_of_mut!(ARENA.0) } as usize;
+    HEAP_BASE.store(heap_start, Ordering::SeqCst);
+    HEAP_LEN.store(64 * 4096, Ordering::SeqCst);
+    unsafe {
+        ALLOCATOR.init(heap_start, 64 * 4096);
+    }
+}
+
+// Maps the heap at the default HEAP_START/HEAP_SIZE
+pub fn init_heap_default(
+    mapper: &mut (impl Mapper<Size4KiB> + CleanUp),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<(), &'static str> {
+    init_heap(mapper, frame_allocator, VirtAddr::new(HEAP_START as u64), HEAP_SIZE)
+}
+
+// Maps the virtual heap pages to physical frames. If it fails part way, the
+// pages mapped so far are unmapped and every frame taken, including new page
+// tables, is freed newest first before returning.
 pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    mapper: &mut (impl Mapper<Size4KiB> + CleanUp),
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    heap_start: VirtAddr,
+    heap_size: usize,
 ) -> Result<(), &'static str> {
+    if heap_size == 0 || !heap_start.is_aligned(4096u64) {
+        return Err("Heap must be a non-empty, page-aligned range");
+    }
+    
     // Map heap pages to physical frames
     let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
+        let heap_end = heap_start + heap_size - 1u64;
         let heap_start_page = Page::containing_address(heap_start);
         let heap_end_page = Page::containing_address(heap_end);
         Page::range_inclusive(heap_start_page, heap_end_page)
     };
+    
+    // The pages either side must stay unmapped so running off the heap faults
+    if heap_start.as_u64() < 4096 {
+        return Err("No room for a guard page below the heap");
+    }
+    for guard in [page_range.start - 1, page_range.end + 1] {
+        if mapper.translate_page(guard).is_ok() {
+            return Err("Heap guard page is already mapped");
+        }
+    }
 
     // Allocate and map frames for the heap
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    crate::memory::map_fresh_pages(mapper, frame_allocator, page_range, flags)?;
+
+    // Initialize the allocator
+    let heap_start = heap_start.as_u64() as usize;
+    HEAP_BASE.store(heap_start, Ordering::SeqCst);
+    HEAP_LEN.store(heap_size, Ordering::SeqCst);
+    HEAP_GUARDED.store(true, Ordering::SeqCst);
+    unsafe {
+        ALLOCATOR.init(heap_start, heap_size);
+    }
+
+    Ok(())
+}
+
+// Whether init_heap checked the pages either side of the heap are unmapped
+static HEAP_GUARDED: AtomicBool = AtomicBool::new(false);
+
+// Returns true if `addr` lies in the unmapped page just below or just above
+// a heap mapped by `init_heap`, so a fault there is a heap overrun
+pub fn heap_guard_contains(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64() as usize;
+    let (start, size) = heap_region();
+    HEAP_GUARDED.load(Ordering::SeqCst)
+        && ((start - 4096..start).contains(&addr) || (start + size..start + size + 4096).contains(&addr))
+}
+
+// Whether the heap is mapped on demand by the page-fault handler
+static LAZY_HEAP: AtomicBool = AtomicBool::new(false);
+
+// Reserves the heap's virtual range without mapping it; pages are mapped by
+// the page-fault handler when first touched
+pub fn init_heap_lazy(
+    mapper: &mut impl Translate,
+    _frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), &'static str> {
+    // The whole range must be free, otherwise a mapped page would never fault in
+    let heap_start = VirtAddr::new(HEAP_START as u64);
+    let heap_end = heap_start + HEAP_SIZE - 1u64;
+    let page_range = Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(heap_start),
+        Page::containing_address(heap_end),
+    );
     for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or("Failed to allocate frame for heap")?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        
-        // Fix: Handle the Result without using ? operator
-        unsafe {
-            match mapper.map_to(page, frame, flags, frame_allocator) {
-                Ok(tlb) => tlb.flush(),
-                Err(_) => return Err("Failed to map page"),
-            }
+        if mapper.translate_addr(page.start_address()).is_some() {
+            return Err("Heap range already mapped");
         }
     }
-
-    // Initialize the allocator
+    
+    LAZY_HEAP.store(true, Ordering::SeqCst);
+    HEAP_BASE.store(HEAP_START, Ordering::SeqCst);
+    HEAP_LEN.store(HEAP_SIZE, Ordering::SeqCst);
+    
+    // Initializing the allocator does not touch heap memory
     unsafe {
         ALLOCATOR.init(HEAP_START, HEAP_SIZE);
     }
-
+    
     Ok(())
 }
 
+// Returns true if `addr` lies in a heap that is mapped on demand
+pub fn lazy_heap_contains(addr: VirtAddr) -> bool {
+    let addr = addr.as_u64() as usize;
+    let (start, size) = heap_region();
+    LAZY_HEAP.load(Ordering::SeqCst) && (start..start + size).contains(&addr)
+}
+
+// Heap debugging function - checks the kernel heap's free lists
+pub fn validate_heap() -> Result<(), &'static str> {
+    ALLOCATOR.validate()
+}
+
 // Heap debugging function - prints the status of the allocator
 pub fn print_heap_status() {
+    let (start, size) = heap_region();
+    crate::println!("Heap: {:#x}-{:#x} ({} KiB)", start, start + size, size / 1024);
+    
     // Calculate used blocks for each slab size
     for (i, &size) in BLOCK_SIZES.iter().enumerate() {
-        // Remove unnecessary unsafe block
-        let (start, end) = *ALLOCATOR.slab_heap_regions[i].lock();
-        let blocks_total = (end - start) / size;
+        let slab = ALLOCATOR.slabs[i].lock();
+        let blocks_total = slab.blocks_count;
         
-        // Count free blocks (approximation since we don't store count)
-        let mut free_count = 0;
+        // Count free blocks (approximation since we don't store count):
+        // blocks never handed out plus the free list
+        let mut free_count = slab.unused_blocks();
         // Remove unnecessary unsafe block
-        let mut current = ALLOCATOR.slabs[i].lock().free_blocks.as_ptr();
+        let mut current = slab.free_blocks.as_ptr();
         while current != NonNull::<FreeBlock>::dangling().as_ptr() {
             free_count += 1;
             // Need to keep this unsafe because we're dereferencing a raw pointer
@@ -277,4 +759,164 @@ pub fn print_heap_status() {
     // Print fallback allocator stats (if available)
     // Note: linked_list_allocator doesn't expose stats directly
     crate::println!("Fallback allocator: stats not available");
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_alloc_from_irq_fails_fast_when_locked() {
+    // Ten slabs plus the fallback share the arena, one page each
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 4096]);
+    static mut ARENA: Arena = Arena([0; 11 * 4096]);
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 4096);
+    }
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let large = Layout::from_size_align(8192, 8).unwrap();
+    
+    // An interrupt arriving while the interrupted code holds the lock
+    {
+        let _slab = allocator.slabs[0].lock();
+        assert_eq!(allocator.alloc_from_irq(small), Err(AllocError::Contended));
+    }
+    {
+        let _fallback = allocator.fallback_allocator.lock();
+        assert_eq!(allocator.alloc_from_irq(large), Err(AllocError::Contended));
+    }
+    
+    // Once the locks are free the same requests succeed
+    assert!(allocator.alloc_from_irq(small).is_ok());
+    assert_eq!(allocator.alloc_from_irq(large), Err(AllocError::FallbackExhausted { size: 8192 }));
+    assert!(allocator.alloc_from_irq(Layout::from_size_align(4096, 8).unwrap()).is_ok());
+}
+
+#[test_case]
+fn test_fallback_fraction_fits_larger_allocations() {
+    #[repr(align(4096))]
+    struct Arena([u8; 20 * 4096]);
+    static mut ARENA: Arena = Arena([0; 20 * 4096]);
+    let arena = unsafe { core::ptr::addr_of_mut!(ARENA.0) as usize };
+    let large = Layout::from_size_align(16 * 1024, 8).unwrap();
+    
+    // The default split leaves the fallback 1/11 of the arena, under 8 KiB
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(arena, 20 * 4096);
+    }
+    assert_eq!(allocator.try_alloc(large), Err(AllocError::FallbackExhausted { size: 16 * 1024 }));
+    
+    // Half of it is plenty
+    let allocator = SlabAllocator::new();
+    unsafe {
+        assert_eq!(allocator.init_with_fallback_fraction(arena, 20 * 4096, 0.0), Err("Fallback fraction must be between 0 and 1"));
+        assert!(allocator.init_with_fallback_fraction(arena, 20 * 4096, 1.0).is_err());
+        allocator.init_with_fallback_fraction(arena, 20 * 4096, 0.5).expect("init failed");
+    }
+    assert!(allocator.try_alloc(large).is_ok());
+    
+    // The slabs still get a share each
+    assert!(allocator.try_alloc(Layout::from_size_align(4096, 8).unwrap()).is_ok());
+}
+
+#[test_case]
+fn test_check_block_sizes() {
+    assert_eq!(check_block_sizes(BLOCK_SIZES), Ok(()));
+    assert_eq!(check_block_sizes(&[8, 24, 32]), Err("Block size is not a power of two"));
+    assert_eq!(check_block_sizes(&[4, 8, 16]), Err("Block size too small for a free list pointer"));
+    assert_eq!(check_block_sizes(&[8, 32, 16]), Err("Block sizes are not strictly ascending"));
+    assert_eq!(check_block_sizes(&[8, 8]), Err("Block sizes are not strictly ascending"));
+}
+
+#[test_case]
+fn test_slab_of_classifies_allocations() {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    
+    let small = Box::new([0u8; 32]);
+    let class = BLOCK_SIZES.iter().position(|&size| size == 32);
+    assert_eq!(slab_of(small.as_ptr()), class);
+    
+    // Too big for any slab
+    let large: Vec<u8> = Vec::with_capacity(8192);
+    assert_eq!(slab_of(large.as_ptr()), None);
+}
+
+#[test_case]
+fn test_weights_split_heap_proportionally() {
+    #[repr(align(4096))]
+    struct Arena([u8; 40 * 4096]);
+    static mut ARENA: Arena = Arena([0; 40 * 4096]);
+    let arena = unsafe { core::ptr::addr_of_mut!(ARENA.0) as usize };
+    
+    // Almost all of the slabs' space goes to the 4096-byte class
+    let mut weights = [1; BLOCK_SIZES.len()];
+    weights[BLOCK_SIZES.len() - 1] = 1024;
+    let allocator = SlabAllocator::new();
+    unsafe {
+        assert_eq!(allocator.init_with_weights(arena, 40 * 4096, &[1, 2]), Err("Need one weight per block size"));
+        assert!(allocator.init_with_weights(arena, 40 * 4096, &[0; BLOCK_SIZES.len()]).is_err());
+        allocator.init_with_weights(arena, 40 * 4096, &weights).expect("init failed");
+    }
+    
+    let smallest = allocator.slabs[0].lock().blocks_count;
+    let largest = allocator.slabs[BLOCK_SIZES.len() - 1].lock().blocks_count;
+    assert!(largest > smallest, "4096-byte class has {} blocks, 8-byte class {}", largest, smallest);
+    
+    // The fallback still gets its usual share
+    assert!(allocator.fallback_region.lock().1 >= 40 * 4096 / (BLOCK_SIZES.len() + 1));
+}
+
+#[test_case]
+fn test_zero_size_alloc_uses_no_memory() {
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 4096]);
+    static mut ARENA: Arena = Arena([0; 11 * 4096]);
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 4096);
+    }
+    let free_before = allocator.slabs[0].lock().unused_blocks();
+    
+    let layout = Layout::from_size_align(0, 16).unwrap();
+    let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(ptr as usize % 16, 0);
+    assert_eq!(allocator.slabs[0].lock().unused_blocks(), free_before);
+    assert_eq!(allocator.stats().slab_used, [0; BLOCK_SIZES.len()]);
+    
+    unsafe { GlobalAlloc::dealloc(&allocator, ptr, layout) };
+    assert_eq!(allocator.stats().dealloc_count, 0);
+}
+
+#[test_case]
+fn test_tiny_heap_gives_unusable_slabs_to_fallback() {
+    #[repr(align(4096))]
+    struct Arena([u8; 1024]);
+    static mut ARENA: Arena = Arena([0; 1024]);
+    let arena = unsafe { core::ptr::addr_of_mut!(ARENA.0) as usize };
+    
+    // A 1 KiB heap gives each slab 93 bytes, which once aligned is too
+    // little for a block from the 64-byte class up
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(arena, 1024);
+    }
+    for (i, &block_size) in BLOCK_SIZES.iter().enumerate() {
+        let (start, end) = *allocator.slab_heap_regions[i].lock();
+        assert_eq!(block_size < 64, end > start, "{}-byte slab", block_size);
+    }
+    let (_, fallback_size) = *allocator.fallback_region.lock();
+    assert_eq!(fallback_size, 1024 - 3 * (1024 / (BLOCK_SIZES.len() + 1)));
+    
+    // The 256-byte class has no blocks, so this comes from the fallback
+    let layout = Layout::from_size_align(200, 8).unwrap();
+    let ptr = unsafe { GlobalAlloc::alloc(&allocator, layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(allocator.slab_of(ptr), None);
+    unsafe { GlobalAlloc::dealloc(&allocator, ptr, layout) };
+    
+    // Small sizes still use their slabs
+    assert!(allocator.try_alloc(Layout::from_size_align(8, 8).unwrap()).is_ok());
+}
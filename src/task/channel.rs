@@ -0,0 +1,145 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+use super::scheduler::{block_current_task, current_task_id, unblock_task};
+use super::sync::BlockingMutex;
+
+// Capacity of channels created with `channel`
+pub const CHANNEL_CAPACITY: usize = 16;
+
+// State shared by both ends of a channel
+struct Shared<T> {
+    queue: BlockingMutex<VecDeque<T>>,
+    capacity: usize,
+    // IDs of tasks blocked waiting for space / for a value
+    send_waiters: Mutex<VecDeque<usize>>,
+    recv_waiters: Mutex<VecDeque<usize>>,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+impl<T> Shared<T> {
+    fn wake_one(waiters: &Mutex<VecDeque<usize>>) {
+        if let Some(id) = waiters.lock().pop_front() {
+            unblock_task(id);
+        }
+    }
+    
+    fn wake_all(waiters: &Mutex<VecDeque<usize>>) {
+        while let Some(id) = waiters.lock().pop_front() {
+            unblock_task(id);
+        }
+    }
+}
+
+// Sending half of a channel; can be cloned for multiple producers
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Receiving half of a channel
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+// Create a bounded channel holding up to `CHANNEL_CAPACITY` values
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    channel_with_capacity(CHANNEL_CAPACITY)
+}
+
+// Create a bounded channel holding up to `capacity` values
+pub fn channel_with_capacity<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "channel capacity must be non-zero");
+    
+    let shared = Arc::new(Shared {
+        queue: BlockingMutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        send_waiters: Mutex::new(VecDeque::new()),
+        recv_waiters: Mutex::new(VecDeque::new()),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    
+    (Sender { shared: shared.clone() }, Receiver { shared })
+}
+
+impl<T> Sender<T> {
+    // Send a value, blocking while the channel is full.
+    // Returns the value back if the receiver has been dropped.
+    pub fn send(&self, value: T) -> Result<(), T> {
+        loop {
+            if !self.shared.receiver_alive.load(Ordering::SeqCst) {
+                return Err(value);
+            }
+            
+            let mut queue = self.shared.queue.lock();
+            if queue.len() < self.shared.capacity {
+                queue.push_back(value);
+                drop(queue);
+                Shared::<T>::wake_one(&self.shared.recv_waiters);
+                return Ok(());
+            }
+            
+            // Register before releasing the queue so a receiver can't miss us
+            self.shared.send_waiters.lock().push_back(current_task_id());
+            drop(queue);
+            block_current_task();
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::SeqCst);
+        Sender { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // The last sender going away wakes receivers so they see the disconnect
+        if self.shared.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            Shared::<T>::wake_all(&self.shared.recv_waiters);
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    // Receive a value, blocking while the channel is empty.
+    // Returns `None` once the channel is empty and every sender is gone.
+    pub fn recv(&self) -> Option<T> {
+        loop {
+            let mut queue = self.shared.queue.lock();
+            if let Some(value) = queue.pop_front() {
+                drop(queue);
+                Shared::<T>::wake_one(&self.shared.send_waiters);
+                return Some(value);
+            }
+            
+            if self.shared.senders.load(Ordering::SeqCst) == 0 {
+                return None;
+            }
+            
+            self.shared.recv_waiters.lock().push_back(current_task_id());
+            drop(queue);
+            block_current_task();
+        }
+    }
+    
+    // Receive a value if one is queued, without blocking
+    pub fn try_recv(&self) -> Option<T> {
+        let value = self.shared.queue.lock().pop_front();
+        if value.is_some() {
+            Shared::<T>::wake_one(&self.shared.send_waiters);
+        }
+        value
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::SeqCst);
+        Shared::<T>::wake_all(&self.shared.send_waiters);
+    }
+}
@@ -1,6 +1,10 @@
 use core::arch::asm;
+use core::sync::atomic::{compiler_fence, Ordering};
 use x86_64::registers::rflags::RFlags;
 
+// Marks a context that has been filled in by `init`, `save` or `switch`
+const CONTEXT_MAGIC: u64 = 0x5441_534B_4354_5854; // "TASKCTXT"
+
 #[derive(Debug, Default, Clone, Copy)]
 #[repr(C)]
 pub struct TaskContext {
@@ -16,22 +20,47 @@ pub struct TaskContext {
     pub rip: u64,    // Instruction pointer
     pub rflags: u64, // CPU flags
     pub rsp: u64,    // Stack pointer
+    
+    // Page table root to run with, 0 to keep the current one
+    pub cr3: u64,
+    
+    // CONTEXT_MAGIC once the context holds something to resume, 0 in a
+    // default context
+    pub magic: u64,
 }
 
 impl TaskContext {
     // Initialize a new task context
     pub fn init(&mut self, entry_point: fn() -> !, stack_top: usize) {
-        self.rip = entry_point as u64;
-        self.rsp = stack_top as u64;
+        self.init_at(entry_point as usize as u64, stack_top);
+    }
+    
+    // Initialize a task context that starts at a raw code address
+    pub fn init_at(&mut self, entry_point: u64, stack_top: usize) {
+        self.rip = entry_point;
+        // Leave room for a return address, as if the entry point had been called
+        self.rsp = stack_top as u64 - 8;
         self.rflags = RFlags::INTERRUPT_FLAG.bits(); // Enable interrupts
-        self.rbp = stack_top as u64; // Set the base pointer to the top of the stack
+        self.rbp = 0; // End of the frame pointer chain
+        self.magic = CONTEXT_MAGIC;
     }
     
-    // Switch from the current context to the next context
-    pub unsafe fn switch(current: &mut TaskContext, next: &TaskContext) {
+    // Whether the context was ever initialized or saved into
+    pub fn is_initialized(&self) -> bool {
+        self.magic == CONTEXT_MAGIC
+    }
+    
+    /// Save the current context so it can later be resumed with `switch`.
+    /// Returns 0 when saving and 1 when execution resumes from the saved context.
+    ///
+    /// # Safety
+    /// Resuming is only sound while the frame that called `save` is still live.
+    #[inline(always)]
+    pub unsafe fn save(context: &mut TaskContext) -> u64 {
+        context.magic = CONTEXT_MAGIC;
+        let resumed: u64;
         unsafe {
             asm!(
-                // Save the current context
                 "mov [{0} + 0x00], r15",
                 "mov [{0} + 0x08], r14",
                 "mov [{0} + 0x10], r13",
@@ -39,40 +68,112 @@ impl TaskContext {
                 "mov [{0} + 0x20], rbx",
                 "mov [{0} + 0x28], rbp",
                 
-                // Save RIP (return address is on the stack)
-                "mov rax, [rsp]",
+                // Resume at the label below
+                "lea rax, [rip + 2f]",
                 "mov [{0} + 0x30], rax",
                 
-                // Save RFLAGS
                 "pushfq",
                 "pop rax",
                 "mov [{0} + 0x38], rax",
                 
-                // Save RSP (must be adjusted for the return address on stack)
-                "lea rax, [rsp + 8]",
-                "mov [{0} + 0x40], rax",
+                "mov [{0} + 0x40], rsp",
+                
+                "mov rax, cr3",
+                "mov [{0} + 0x48], rax",
+                
+                "xor eax, eax",
+                "jmp 3f",
+                "2:",
+                "mov eax, 1",
+                "3:",
+                
+                in(reg) context as *mut TaskContext,
+                out("rax") resumed,
+                clobber_abi("sysv64"),
+            );
+        }
+        resumed
+    }
+    
+    // Switch from the current context to the next context. In debug builds,
+    // panics instead of jumping into a context that was never initialized,
+    // naming the task the scheduler has already made current.
+    pub unsafe fn switch(current: &mut TaskContext, next: &TaskContext) {
+        if cfg!(debug_assertions) && !next.is_initialized() {
+            panic!("task {} has an uninitialized context", crate::task::current_task_id());
+        }
+        current.magic = CONTEXT_MAGIC;
+        
+        // Another task runs in between, and the scheduler's bookkeeping (current
+        // task, task states) was written just before: keep memory accesses on
+        // their side of the switch
+        compiler_fence(Ordering::SeqCst);
+        unsafe { Self::switch_registers(current, next) }
+        compiler_fence(Ordering::SeqCst);
+    }
+    
+    // The register swap itself. Like `save`, the resume point is a label
+    // inside the asm and the saved stack pointer is the one at that label, so
+    // the result doesn't depend on the prologue the compiler gives this
+    // function; the frame is unwound by its own epilogue after resuming
+    #[inline(never)]
+    unsafe fn switch_registers(current: &mut TaskContext, next: &TaskContext) {
+        unsafe {
+            asm!(
+                // Save the current context
+                "mov [rdi + 0x00], r15",
+                "mov [rdi + 0x08], r14",
+                "mov [rdi + 0x10], r13",
+                "mov [rdi + 0x18], r12",
+                "mov [rdi + 0x20], rbx",
+                "mov [rdi + 0x28], rbp",
+                
+                // Resume at the label below
+                "lea rax, [rip + 3f]",
+                "mov [rdi + 0x30], rax",
+                
+                // Save RFLAGS
+                "pushfq",
+                "pop rax",
+                "mov [rdi + 0x38], rax",
+                
+                // Save RSP, balanced again after the pushfq/pop above
+                "mov [rdi + 0x40], rsp",
+                
+                // Save CR3, and load the next task's if it differs. Every
+                // address space shares the kernel's mappings, so `next` (on the
+                // kernel heap) and the next task's stack stay reachable
+                "mov rax, cr3",
+                "mov [rdi + 0x48], rax",
+                "mov rcx, [rsi + 0x48]",
+                "test rcx, rcx",
+                "jz 2f",
+                "cmp rax, rcx",
+                "je 2f",
+                "mov cr3, rcx",
+                "2:",
                 
                 // Load the next context
-                "mov r15, [{1} + 0x00]",
-                "mov r14, [{1} + 0x08]",
-                "mov r13, [{1} + 0x10]",
-                "mov r12, [{1} + 0x18]",
-                "mov rbx, [{1} + 0x20]",
-                "mov rbp, [{1} + 0x28]",
+                "mov r15, [rsi + 0x00]",
+                "mov r14, [rsi + 0x08]",
+                "mov r13, [rsi + 0x10]",
+                "mov r12, [rsi + 0x18]",
+                "mov rbx, [rsi + 0x20]",
+                "mov rbp, [rsi + 0x28]",
                 
                 // Load RFLAGS
-                "mov rax, [{1} + 0x38]",
+                "mov rax, [rsi + 0x38]",
                 "push rax",
                 "popfq",
                 
                 // Set up stack and jump to next task
-                "mov rsp, [{1} + 0x40]",
-                "mov rax, [{1} + 0x30]",
-                "push rax",
-                "ret",
+                "mov rsp, [rsi + 0x40]",
+                "jmp qword ptr [rsi + 0x30]",
+                "3:",
                 
-                in(reg) current,
-                in(reg) next,
+                // Pinned so rax and rcx are free as scratch registers
+                in("rdi") current,
+                in("rsi") next,
                 clobber_abi("sysv64"),
             );
         } 
@@ -0,0 +1,339 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// Async tasks: futures polled by an executor, without a stack of their own.
+// Separate from the stackful tasks the scheduler switches between.
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+// Most tasks an executor runs at once, and the capacity of its ready queue
+const MAX_TASKS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    fn new() -> Self {
+        TaskId(NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+// A future the executor runs to completion
+pub struct Task {
+    id: TaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+impl Task {
+    pub fn new(future: impl Future<Output = ()> + 'static) -> Self {
+        Task {
+            id: TaskId::new(),
+            future: Box::pin(future),
+        }
+    }
+    
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+    
+    fn poll(&mut self, context: &mut Context) -> Poll<()> {
+        self.future.as_mut().poll(context)
+    }
+}
+
+// An asynchronous sequence of values, the async counterpart of `Iterator`
+pub trait Stream {
+    type Item;
+    
+    // Returns the next value if one is available, `Ready(None)` once the
+    // stream has ended, or `Pending` after arranging for the waker in
+    // `context` to be woken when there may be more
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<Self::Item>>;
+    
+    // A future resolving to the next value, for `while let Some(x) = s.next().await`
+    fn next(&mut self) -> Next<'_, Self>
+    where
+        Self: Unpin,
+    {
+        Next { stream: self }
+    }
+}
+
+// Future returned by `Stream::next`
+pub struct Next<'a, S: ?Sized> {
+    stream: &'a mut S,
+}
+
+impl<S: Stream + Unpin + ?Sized> Future for Next<'_, S> {
+    type Output = Option<S::Item>;
+    
+    fn poll(mut self: Pin<&mut Self>, context: &mut Context) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(context)
+    }
+}
+
+// Fixed-size FIFO of woken task ids. Wakers may run in interrupt handlers,
+// so it never allocates; a task is queued at most once at a time, which keeps
+// it from filling up while the executor holds at most `MAX_TASKS` tasks.
+struct TaskQueue {
+    ids: [TaskId; MAX_TASKS],
+    head: usize,
+    len: usize,
+}
+
+impl TaskQueue {
+    const fn new() -> Self {
+        TaskQueue {
+            ids: [TaskId(0); MAX_TASKS],
+            head: 0,
+            len: 0,
+        }
+    }
+    
+    // Append an id, returning false if the queue is full
+    fn push(&mut self, id: TaskId) -> bool {
+        if self.len == MAX_TASKS {
+            return false;
+        }
+        self.ids[(self.head + self.len) % MAX_TASKS] = id;
+        self.len += 1;
+        true
+    }
+    
+    fn pop(&mut self) -> Option<TaskId> {
+        if self.len == 0 {
+            return None;
+        }
+        let id = self.ids[self.head];
+        self.head = (self.head + 1) % MAX_TASKS;
+        self.len -= 1;
+        Some(id)
+    }
+    
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// Ids of tasks that were woken and need polling. Wakers may run in interrupt
+// handlers, so the lock is only taken with interrupts off.
+type ReadyQueue = Arc<Mutex<TaskQueue>>;
+
+// Runs async tasks, polling each one only after it has been woken
+pub struct SimpleExecutor {
+    tasks: BTreeMap<TaskId, Task>,
+    ready: ReadyQueue,
+    wakers: BTreeMap<TaskId, Arc<TaskWaker>>,
+}
+
+impl SimpleExecutor {
+    pub fn new() -> Self {
+        SimpleExecutor {
+            tasks: BTreeMap::new(),
+            ready: Arc::new(Mutex::new(TaskQueue::new())),
+            wakers: BTreeMap::new(),
+        }
+    }
+    
+    // Add a task; it is polled for the first time on the next run. Fails if
+    // the executor already holds `MAX_TASKS` tasks.
+    pub fn spawn(&mut self, task: Task) -> Result<TaskId, &'static str> {
+        if self.tasks.len() == MAX_TASKS {
+            return Err("Too many async tasks");
+        }
+        
+        let id = task.id;
+        let waker = Arc::new(TaskWaker::new(id, self.ready.clone()));
+        waker.wake_task();
+        self.tasks.insert(id, task);
+        self.wakers.insert(id, waker);
+        Ok(id)
+    }
+    
+    // Number of tasks that haven't completed
+    pub fn task_count(&self) -> usize {
+        self.tasks.len()
+    }
+    
+    // Poll every woken task until none is left to poll. Returns the number of
+    // tasks still waiting to be woken.
+    pub fn run_until_idle(&mut self) -> usize {
+        while let Some(id) = interrupts::without_interrupts(|| self.ready.lock().pop()) {
+            // Woken after completing
+            let (task, waker) = match (self.tasks.get_mut(&id), self.wakers.get(&id)) {
+                (Some(task), Some(waker)) => (task, waker),
+                _ => continue,
+            };
+            
+            // Off the queue now, so a wake during the poll queues it again
+            waker.queued.store(false, Ordering::SeqCst);
+            let waker = Waker::from(waker.clone());
+            let mut context = Context::from_waker(&waker);
+            
+            if task.poll(&mut context).is_ready() {
+                self.tasks.remove(&id);
+                self.wakers.remove(&id);
+            }
+        }
+        
+        self.tasks.len()
+    }
+    
+    // Run until every task has completed, halting while all of them wait on
+    // an interrupt to wake them
+    pub fn run(&mut self) {
+        while self.run_until_idle() > 0 {
+            // Check for wakeups with interrupts off, so one arriving in between
+            // isn't slept through
+            interrupts::disable();
+            if self.ready.lock().is_empty() {
+                interrupts::enable_and_hlt();
+            } else {
+                interrupts::enable();
+            }
+        }
+    }
+}
+
+impl Default for SimpleExecutor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Puts its task back on the ready queue when woken. Waking never allocates,
+// but dropping the last reference frees the waker, so interrupt handlers
+// should wake by reference.
+struct TaskWaker {
+    id: TaskId,
+    ready: ReadyQueue,
+    // Whether the id is on the ready queue already
+    queued: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new(id: TaskId, ready: ReadyQueue) -> Self {
+        TaskWaker {
+            id,
+            ready,
+            queued: AtomicBool::new(false),
+        }
+    }
+    
+    fn wake_task(&self) {
+        if self.queued.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if !interrupts::without_interrupts(|| self.ready.lock().push(self.id)) {
+            // Only wakers outliving their task can fill the queue
+            self.queued.store(false, Ordering::SeqCst);
+        }
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_task();
+    }
+    
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.wake_task();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+    
+    // A future that completes once `fire` has been called
+    #[derive(Clone, Default)]
+    struct Trigger {
+        state: Arc<TriggerState>,
+    }
+    
+    #[derive(Default)]
+    struct TriggerState {
+        fired: AtomicBool,
+        waker: Mutex<Option<Waker>>,
+    }
+    
+    impl Trigger {
+        fn fire(&self) {
+            self.state.fired.store(true, Ordering::SeqCst);
+            if let Some(waker) = self.state.waker.lock().take() {
+                waker.wake();
+            }
+        }
+    }
+    
+    impl Future for Trigger {
+        type Output = ();
+        
+        fn poll(self: Pin<&mut Self>, context: &mut Context) -> Poll<()> {
+            if self.state.fired.load(Ordering::SeqCst) {
+                return Poll::Ready(());
+            }
+            *self.state.waker.lock() = Some(context.waker().clone());
+            Poll::Pending
+        }
+    }
+    
+    #[test_case]
+    fn test_executor_runs_woken_tasks() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let (a, b) = (Trigger::default(), Trigger::default());
+        
+        let mut executor = SimpleExecutor::new();
+        for trigger in [a.clone(), b.clone()] {
+            let done = done.clone();
+            executor.spawn(Task::new(async move {
+                trigger.await;
+                done.fetch_add(1, Ordering::SeqCst);
+            })).unwrap();
+        }
+        
+        // Both wait on their trigger
+        assert_eq!(executor.run_until_idle(), 2);
+        assert_eq!(done.load(Ordering::SeqCst), 0);
+        
+        b.fire();
+        assert_eq!(executor.run_until_idle(), 1);
+        assert_eq!(done.load(Ordering::SeqCst), 1);
+        
+        a.fire();
+        assert_eq!(executor.run_until_idle(), 0);
+        assert_eq!(done.load(Ordering::SeqCst), 2);
+    }
+    
+    #[test_case]
+    fn test_ready_queue_holds_each_task_once() {
+        let trigger = Trigger::default();
+        let mut executor = SimpleExecutor::new();
+        executor.spawn(Task::new(trigger.clone())).unwrap();
+        executor.run_until_idle();
+        
+        // However often it is woken, the task is queued once
+        let waker = trigger.state.waker.lock().clone().unwrap();
+        for _ in 0..MAX_TASKS * 2 {
+            waker.wake_by_ref();
+        }
+        assert_eq!(executor.ready.lock().len, 1);
+        assert_eq!(executor.run_until_idle(), 1);
+        
+        // The queue has room for every task the executor accepts
+        for _ in 1..MAX_TASKS {
+            executor.spawn(Task::new(Trigger::default())).unwrap();
+        }
+        assert!(executor.spawn(Task::new(Trigger::default())).is_err());
+        assert_eq!(executor.run_until_idle(), MAX_TASKS);
+    }
+}
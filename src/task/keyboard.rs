@@ -0,0 +1,148 @@
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use crate::panic::emergency_write;
+use super::executor::Stream;
+
+// Capacity of the scancode queue
+const SCANCODE_QUEUE_SIZE: usize = 128;
+
+// Fixed-size FIFO of scancodes that drops the oldest when full
+struct ScancodeQueue {
+    scancodes: [u8; SCANCODE_QUEUE_SIZE],
+    head: usize,
+    len: usize,
+}
+
+impl ScancodeQueue {
+    const fn new() -> Self {
+        ScancodeQueue {
+            scancodes: [0; SCANCODE_QUEUE_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+    
+    // Append a scancode, returning false if the oldest had to make room
+    fn push(&mut self, scancode: u8) -> bool {
+        let dropped = self.len == SCANCODE_QUEUE_SIZE;
+        if dropped {
+            self.head = (self.head + 1) % SCANCODE_QUEUE_SIZE;
+            self.len -= 1;
+        }
+        self.scancodes[(self.head + self.len) % SCANCODE_QUEUE_SIZE] = scancode;
+        self.len += 1;
+        !dropped
+    }
+    
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let scancode = self.scancodes[self.head];
+        self.head = (self.head + 1) % SCANCODE_QUEUE_SIZE;
+        self.len -= 1;
+        Some(scancode)
+    }
+}
+
+// Filled by the keyboard interrupt handler. Everything else locks these with
+// interrupts off, so the handler never finds them held.
+static SCANCODES: Mutex<ScancodeQueue> = Mutex::new(ScancodeQueue::new());
+// Waker of the task waiting on a `ScancodeStream`
+static WAKER: Mutex<Option<Waker>> = Mutex::new(None);
+
+// Queue a scancode and wake the task reading them. Called from the keyboard
+// interrupt handler, so it neither allocates nor waits.
+pub fn add_scancode(scancode: u8) {
+    let queued = interrupts::without_interrupts(|| SCANCODES.lock().push(scancode));
+    if !queued {
+        // No console locks in interrupt context
+        emergency_write(b"Warning: scancode queue full, dropped the oldest\n");
+    }
+    
+    // Wake by reference: taking the waker could drop its last reference here,
+    // and freeing it would enter the allocator in interrupt context
+    interrupts::without_interrupts(|| {
+        if let Some(waker) = WAKER.lock().as_ref() {
+            waker.wake_by_ref();
+        }
+    });
+}
+
+// Scancodes from the keyboard as an async stream. It never ends; with more
+// than one stream, only the last task to wait is woken.
+pub struct ScancodeStream {
+    _private: (),
+}
+
+impl ScancodeStream {
+    pub fn new() -> Self {
+        ScancodeStream { _private: () }
+    }
+}
+
+impl Default for ScancodeStream {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stream for ScancodeStream {
+    type Item = u8;
+    
+    fn poll_next(self: Pin<&mut Self>, context: &mut Context) -> Poll<Option<u8>> {
+        let pop = || interrupts::without_interrupts(|| SCANCODES.lock().pop());
+        if let Some(scancode) = pop() {
+            return Poll::Ready(Some(scancode));
+        }
+        
+        // Register before checking again, so a scancode arriving in between
+        // still wakes us
+        interrupts::without_interrupts(|| *WAKER.lock() = Some(context.waker().clone()));
+        match pop() {
+            Some(scancode) => {
+                interrupts::without_interrupts(|| WAKER.lock().take());
+                Poll::Ready(Some(scancode))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test_case]
+fn test_scancode_stream_yields_in_order() {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use super::executor::{SimpleExecutor, Task};
+    
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let mut executor = SimpleExecutor::new();
+    {
+        let received = received.clone();
+        executor.spawn(Task::new(async move {
+            let mut scancodes = ScancodeStream::new();
+            while let Some(scancode) = scancodes.next().await {
+                received.lock().push(scancode);
+            }
+        })).unwrap();
+    }
+    
+    // Nothing queued yet, so the task waits
+    executor.run_until_idle();
+    assert!(received.lock().is_empty());
+    
+    for scancode in [0x1E, 0x9E, 0x30, 0xB0] {
+        add_scancode(scancode);
+    }
+    executor.run_until_idle();
+    assert_eq!(*received.lock(), [0x1E, 0x9E, 0x30, 0xB0]);
+    
+    // A full queue keeps the newest scancodes
+    let mut queue = ScancodeQueue::new();
+    for scancode in 0..SCANCODE_QUEUE_SIZE as u8 + 2 {
+        queue.push(scancode);
+    }
+    assert_eq!(queue.pop(), Some(2));
+}
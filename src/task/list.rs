@@ -0,0 +1,182 @@
+use core::ops::{Index, IndexMut};
+
+// Intrusive doubly linked lists of nodes kept in indexable storage (like the
+// scheduler's task queue). The links live in the nodes themselves, so moving
+// a node between lists or checking which list it is on never searches.
+
+// Identifies a list, so a node can tell which one it is on
+pub type ListId = u8;
+
+// A node's place in at most one list at a time, as indices into the storage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Links {
+    prev: Option<usize>,
+    next: Option<usize>,
+    list: Option<ListId>,
+}
+
+impl Links {
+    pub const fn new() -> Self {
+        Links { prev: None, next: None, list: None }
+    }
+    
+    // The list the node is on, if any
+    pub fn list(&self) -> Option<ListId> {
+        self.list
+    }
+    
+    // Index of the node after this one on its list
+    pub fn next(&self) -> Option<usize> {
+        self.next
+    }
+}
+
+// Nodes that can be threaded onto a `List`
+pub trait Linked {
+    fn links(&self) -> &Links;
+    fn links_mut(&mut self) -> &mut Links;
+}
+
+// Head and tail of one list. The nodes are passed to every call, so a single
+// storage can hold nodes of several lists; indices must stay stable while
+// nodes are linked.
+pub struct List {
+    id: ListId,
+    head: Option<usize>,
+    tail: Option<usize>,
+    len: usize,
+}
+
+impl List {
+    pub const fn new(id: ListId) -> Self {
+        List { id, head: None, tail: None, len: 0 }
+    }
+    
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    
+    // Index of the first node
+    pub fn front(&self) -> Option<usize> {
+        self.head
+    }
+    
+    // Whether `node` is on this list
+    pub fn contains<T: Linked>(&self, node: &T) -> bool {
+        node.links().list == Some(self.id)
+    }
+    
+    // Append the node at `index`, which must not be on any list
+    pub fn push_back<T, S>(&mut self, nodes: &mut S, index: usize)
+    where
+        T: Linked,
+        S: IndexMut<usize, Output = T> + ?Sized,
+    {
+        debug_assert_eq!(nodes[index].links().list, None, "node {} is already on a list", index);
+        
+        *nodes[index].links_mut() = Links { prev: self.tail, next: None, list: Some(self.id) };
+        match self.tail {
+            Some(tail) => nodes[tail].links_mut().next = Some(index),
+            None => self.head = Some(index),
+        }
+        self.tail = Some(index);
+        self.len += 1;
+    }
+    
+    // Unlink the node at `index`, returning false if it isn't on this list
+    pub fn remove<T, S>(&mut self, nodes: &mut S, index: usize) -> bool
+    where
+        T: Linked,
+        S: IndexMut<usize, Output = T> + ?Sized,
+    {
+        let links = *nodes[index].links();
+        if links.list != Some(self.id) {
+            return false;
+        }
+        
+        match links.prev {
+            Some(prev) => nodes[prev].links_mut().next = links.next,
+            None => self.head = links.next,
+        }
+        match links.next {
+            Some(next) => nodes[next].links_mut().prev = links.prev,
+            None => self.tail = links.prev,
+        }
+        *nodes[index].links_mut() = Links::new();
+        self.len -= 1;
+        true
+    }
+    
+    // Indices of the nodes, front to back
+    pub fn iter<'a, T, S>(&self, nodes: &'a S) -> Iter<'a, S>
+    where
+        T: Linked + 'a,
+        S: Index<usize, Output = T> + ?Sized,
+    {
+        Iter { nodes, next: self.head }
+    }
+}
+
+// Iterator over a list's node indices, from `List::iter`
+pub struct Iter<'a, S: ?Sized> {
+    nodes: &'a S,
+    next: Option<usize>,
+}
+
+impl<'a, T, S> Iterator for Iter<'a, S>
+where
+    T: Linked + 'a,
+    S: Index<usize, Output = T> + ?Sized,
+{
+    type Item = usize;
+    
+    fn next(&mut self) -> Option<usize> {
+        let index = self.next?;
+        self.next = self.nodes[index].links().next;
+        Some(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+    
+    #[derive(Default)]
+    struct Node(Links);
+    
+    impl Linked for Node {
+        fn links(&self) -> &Links {
+            &self.0
+        }
+        
+        fn links_mut(&mut self) -> &mut Links {
+            &mut self.0
+        }
+    }
+    
+    #[test_case]
+    fn test_list_moves_nodes() {
+        let mut nodes: Vec<Node> = (0..4).map(|_| Node::default()).collect();
+        let (mut a, mut b) = (List::new(0), List::new(1));
+        for index in 0..4 {
+            a.push_back(&mut nodes[..], index);
+        }
+        
+        // Middle, head and tail removals keep the rest linked in order
+        assert!(a.remove(&mut nodes[..], 1));
+        assert!(a.remove(&mut nodes[..], 0));
+        assert!(!b.remove(&mut nodes[..], 2));
+        b.push_back(&mut nodes[..], 1);
+        assert_eq!(a.iter(&nodes[..]).collect::<Vec<_>>(), [2, 3]);
+        assert_eq!(b.iter(&nodes[..]).collect::<Vec<_>>(), [1]);
+        assert!(b.contains(&nodes[1]) && !a.contains(&nodes[1]));
+        
+        assert!(a.remove(&mut nodes[..], 3));
+        assert_eq!((a.front(), a.len()), (Some(2), 1));
+    }
+}
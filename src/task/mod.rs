@@ -1,4 +1,5 @@
-use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::sync::Arc;
+use core::ops::Range;
 
 use x86_64::VirtAddr;
 
@@ -6,15 +7,25 @@ use x86_64::VirtAddr;
 // Multitasking components
 pub mod context;
 pub mod scheduler;
+pub mod sync;
+pub mod channel;
+pub mod watchdog;
+pub mod executor;
+pub mod keyboard;
+pub mod list;
+pub mod pid;
+mod stack;
 // Add these lines to src/task/mod.rs
-pub use scheduler::{spawn, yield_task, current_task_id};
+pub use scheduler::{spawn, exit, yield_task, yield_to, yield_for, yield_until, checkpoint, sleep, current_task_id, active_count, set_policy, list, fork, SchedulePolicy};
+pub use channel::{channel, channel_with_capacity, Sender, Receiver};
+pub use sync::BlockingMutex;
 
 use context::TaskContext;
+use list::{Linked, Links};
+use stack::TaskStack;
+use crate::memory::AddressSpace;
 
 
-// Global process ID counter
-static NEXT_PID: AtomicUsize = AtomicUsize::new(1);
-
 // Process states
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TaskState {
@@ -30,34 +41,170 @@ pub struct Task {
     pub id: usize,
     pub name: &'static str,
     pub state: TaskState,
+    // Higher runs first under the priority policy
+    pub priority: u8,
+    // Tick at which a sleeping task becomes ready again
+    pub wake_tick: Option<u64>,
     
     // Memory management
     pub stack: VirtAddr,
     pub stack_size: usize,
+    // Backing memory for the stack, freed with the task
+    stack_memory: TaskStack,
+    
+    // Page tables the task runs with; None shares the kernel's
+    pub address_space: Option<Arc<AddressSpace>>,
     
     // CPU context for task switching
     pub context: TaskContext,
+    
+    // Place in the scheduler's ready or blocked list
+    links: Links,
+}
+
+// The ID can be recycled once the task is gone
+impl Drop for Task {
+    fn drop(&mut self) {
+        pid::free(self.id);
+    }
+}
+
+impl Linked for Task {
+    fn links(&self) -> &Links {
+        &self.links
+    }
+    
+    fn links_mut(&mut self) -> &mut Links {
+        &mut self.links
+    }
+}
+
+// Snapshot of a task for process listings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: usize,
+    pub name: &'static str,
+    pub state: TaskState,
+    pub stack_size: usize,
 }
 
 // Task implementation
 impl Task {
     pub fn new(name: &'static str, entry_point: fn() -> !, stack_size: usize) -> Self {
-        // Allocate a stack for the task
-        let stack_bottom = crate::slab_allocator::HEAP_START + NEXT_PID.load(Ordering::SeqCst) * stack_size;
-        let stack_top = stack_bottom + stack_size;
+        let mut task = Self::with_stack(name, stack_size);
+        
+        // Initialize the context for the task
+        task.context.init(entry_point, task.stack.as_u64() as usize);
+        
+        task
+    }
+    
+    /// Creates a task that starts executing at `entry_point`, e.g. the entry
+    /// of a loaded program.
+    ///
+    /// # Safety
+    /// `entry_point` must be mapped, executable code that never returns.
+    pub unsafe fn from_entry_address(name: &'static str, entry_point: VirtAddr, stack_size: usize) -> Self {
+        let mut task = Self::with_stack(name, stack_size);
+        task.context.init_at(entry_point.as_u64(), task.stack.as_u64() as usize);
+        task
+    }
+    
+    // Create a task with a fresh stack and an empty context
+    fn with_stack(name: &'static str, stack_size: usize) -> Self {
+        // Allocate a stack for the task, guarded against overflow when possible
+        let stack_memory = TaskStack::new(stack_size);
+        
+        // The ABI wants a 16-byte aligned stack
+        let stack_top = stack_memory.range().end & !0xF;
         
-        let mut task = Task {
-            id: NEXT_PID.fetch_add(1, Ordering::SeqCst),
+        Task {
+            id: pid::allocate(),
             name,
             state: TaskState::Ready,
-            stack: VirtAddr::new(stack_top as u64),
+            priority: 0,
+            wake_tick: None,
+            stack: VirtAddr::new(stack_top),
             stack_size,
-            context: TaskContext::default(),
+            stack_memory,
+            address_space: None,
+            context: TaskContext {
+                cr3: crate::memory::kernel_pml4().start_address().as_u64(),
+                ..TaskContext::default()
+            },
+            links: Links::new(),
+        }
+    }
+    
+    // Run the task in `address_space`; takes effect when it is next switched to
+    pub fn set_address_space(&mut self, address_space: Arc<AddressSpace>) {
+        self.context.cr3 = address_space.pml4_frame().start_address().as_u64();
+        self.address_space = Some(address_space);
+    }
+    
+    // Address range of the task's stack memory
+    pub fn stack_range(&self) -> Range<u64> {
+        self.stack_memory.range()
+    }
+    
+    // Whether `addr` lies in the unmapped guard page below the task's stack
+    pub fn stack_guard_contains(&self, addr: VirtAddr) -> bool {
+        self.stack_memory.guard_contains(addr)
+    }
+    
+    // Create a child task whose stack is a copy of this task's stack and which
+    // resumes from `context` (captured on this task's stack). If this task
+    // has an address space of its own, the child gets a copy-on-write fork of
+    // it. Stack words are relocated by value, with the limits `scheduler::fork`
+    // describes.
+    pub fn fork(&self, context: &TaskContext) -> Result<Self, &'static str> {
+        let mut child = Self::with_stack(self.name, self.stack_size);
+        child.priority = self.priority;
+        child.stack_memory.copy_from(&self.stack_memory);
+        child.context = *context;
+        
+        if let Some(space) = &self.address_space {
+            let mut frame_allocator = crate::memory::FRAME_ALLOCATOR.lock();
+            let frame_allocator = frame_allocator.as_mut().ok_or("Frame allocator not installed")?;
+            child.set_address_space(Arc::new(space.fork(frame_allocator)?));
+        }
+        
+        // Same layout, different place: shift everything that points into the
+        // stack. Which words hold pointers isn't known, so every saved register
+        // and every word of the live stack holding a parent stack address is
+        // taken for one.
+        let parent_stack = self.stack_range();
+        let delta = child.stack_range().start.wrapping_sub(parent_stack.start);
+        let relocate = |addr: u64| {
+            if parent_stack.contains(&addr) { addr.wrapping_add(delta) } else { addr }
         };
         
-        // Initialize the context for the task
-        task.context.init(entry_point, stack_top);
+        let registers = &mut child.context;
+        for register in [
+            &mut registers.rsp, &mut registers.rbp, &mut registers.rbx,
+            &mut registers.r12, &mut registers.r13, &mut registers.r14, &mut registers.r15,
+        ] {
+            *register = relocate(*register);
+        }
         
-        task
+        let child_stack = child.stack_range();
+        let mut addr = child.context.rsp & !7;
+        while addr + 8 <= child_stack.end {
+            let word = addr as *mut u64;
+            unsafe { *word = relocate(*word) };
+            addr += 8;
+        }
+        
+        Ok(child)
+    }
+    
+    // Snapshot of this task's identity and state
+    pub fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.id,
+            name: self.name,
+            state: self.state,
+            stack_size: self.stack_size,
+        }
     }
 }
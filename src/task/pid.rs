@@ -0,0 +1,113 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+// Task IDs are a slot number in the low bits with the slot's generation
+// above it. Freeing an ID moves its slot to the next generation, so an ID
+// kept after its task exited never names the slot's next task.
+const SLOT_BITS: u32 = 16;
+const SLOT_MASK: usize = (1 << SLOT_BITS) - 1;
+
+// Freed slots wait behind this many others before they are handed out again
+const REUSE_DELAY: usize = 8;
+
+// Global task ID allocator
+static PIDS: Mutex<PidAllocator> = Mutex::new(PidAllocator::new());
+
+// Hands out task IDs, recycling the slots of freed ones
+pub struct PidAllocator {
+    // Generation of the ID each slot currently stands for
+    generations: Vec<usize>,
+    // Freed slots, oldest first
+    free: VecDeque<usize>,
+}
+
+impl PidAllocator {
+    const fn new() -> Self {
+        PidAllocator {
+            generations: Vec::new(),
+            free: VecDeque::new(),
+        }
+    }
+    
+    // Hand out an ID, never 0
+    pub fn allocate(&mut self) -> usize {
+        // Reuse the longest-freed slot once enough are waiting, so a freed
+        // ID's slot doesn't come straight back
+        let slot = if self.free.len() > REUSE_DELAY {
+            self.free.pop_front().unwrap()
+        } else {
+            assert!(self.generations.len() < SLOT_MASK, "out of task IDs");
+            self.generations.push(0);
+            self.generations.len() - 1
+        };
+        
+        (self.generations[slot] << SLOT_BITS) | (slot + 1)
+    }
+    
+    // Give back an ID. Stale or unknown IDs are ignored, so freeing twice is harmless.
+    pub fn free(&mut self, id: usize) {
+        if !self.is_live(id) {
+            return;
+        }
+        
+        let slot = (id & SLOT_MASK) - 1;
+        self.generations[slot] = self.generations[slot].wrapping_add(1) & (usize::MAX >> SLOT_BITS);
+        self.free.push_back(slot);
+    }
+    
+    // Whether `id` was handed out and hasn't been freed since
+    pub fn is_live(&self, id: usize) -> bool {
+        let slot = match (id & SLOT_MASK).checked_sub(1) {
+            Some(slot) => slot,
+            None => return false,
+        };
+        self.generations.get(slot) == Some(&(id >> SLOT_BITS)) && !self.free.contains(&slot)
+    }
+    
+    // Number of slots ever handed out, which bounds the IDs in use at once
+    pub fn slots(&self) -> usize {
+        self.generations.len()
+    }
+}
+
+// Allocate a task ID
+pub fn allocate() -> usize {
+    PIDS.lock().allocate()
+}
+
+// Free a task ID, once its task is gone
+pub fn free(id: usize) {
+    PIDS.lock().free(id);
+}
+
+// Number of task ID slots ever handed out
+pub fn slots() -> usize {
+    PIDS.lock().slots()
+}
+
+#[test_case]
+fn test_freed_ids_are_recycled_with_a_new_generation() {
+    let mut pids = PidAllocator::new();
+    let first = pids.allocate();
+    assert_ne!(first, 0);
+    pids.free(first);
+    
+    // The freed slot waits behind others before it comes back
+    let mut ids = Vec::new();
+    for _ in 0..1000 {
+        let id = pids.allocate();
+        assert_ne!(id, first, "stale ID handed out again");
+        ids.push(id);
+        if ids.len() > 3 {
+            pids.free(ids.remove(0));
+        }
+    }
+    assert!(pids.slots() <= REUSE_DELAY + 5, "{} slots", pids.slots());
+    
+    // Freeing a stale ID doesn't disturb the slot's current owner
+    let live = ids[0];
+    assert!(pids.is_live(live) && !pids.is_live(first));
+    pids.free(first);
+    assert!(pids.is_live(live));
+}
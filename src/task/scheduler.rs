@@ -1,8 +1,16 @@
-use super::{Task, TaskState};
-use alloc::collections::VecDeque;
+use super::{Task, TaskInfo, TaskState};
+use super::list::{Linked, List, ListId};
+use alloc::vec::Vec;
+use alloc::collections::{BTreeMap, VecDeque};
 use spin::Mutex;
 use lazy_static::lazy_static;
 use crate::task::context::TaskContext;
+use crate::panic::StackBuffer;
+use crate::println;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::interrupts;
+use x86_64::VirtAddr;
 
 lazy_static! {
     pub static ref SCHEDULER: Mutex<Scheduler> = Mutex::new(Scheduler::new());
@@ -11,22 +19,155 @@ lazy_static! {
 // Current task ID
 static mut CURRENT_TASK_ID: usize = 0;
 
+// Default number of ticks a task may run before `checkpoint` yields
+pub const DEFAULT_QUANTUM_TICKS: u64 = 1;
+
+// Ticks a task may run before `checkpoint` yields, 0 never yields
+static QUANTUM_TICKS: AtomicU64 = AtomicU64::new(DEFAULT_QUANTUM_TICKS);
+// Tick count when the running task last got the CPU
+static SLICE_START: AtomicU64 = AtomicU64::new(0);
+
+// How the scheduler picks the next task to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SchedulePolicy {
+    // Rotate through ready tasks on every yield
+    #[default]
+    RoundRobin,
+    // Run each task until it blocks or exits, in arrival order
+    Fcfs,
+    // Always run the highest-priority ready task
+    Priority,
+}
+
+// Lists threaded through the tasks, so moving a task between them or
+// checking which one it's on takes constant time
+const READY_LIST: ListId = 0;
+const BLOCKED_LIST: ListId = 1;
+const TERMINATED_LIST: ListId = 2;
+
 pub struct Scheduler {
+    // Tasks are never removed, so their indices stay valid; `add_task`
+    // reuses the slots of terminated tasks instead
     tasks: VecDeque<Task>,
+    // Index in `tasks` of each task ID
+    task_index: BTreeMap<usize, usize>,
+    // Tasks in the Ready, Blocked and Terminated states, in the order they
+    // entered them
+    ready: List,
+    blocked: List,
+    terminated: List,
     current_task_index: Option<usize>,
+    policy: SchedulePolicy,
+    // The idle task only runs when nothing else can
+    idle_task_id: Option<usize>,
+    // Task to switch to on the next schedule instead of asking the policy
+    handoff: Option<usize>,
 }
 
 impl Scheduler {
     pub const fn new() -> Self {
         Scheduler {
             tasks: VecDeque::new(),
+            task_index: BTreeMap::new(),
+            ready: List::new(READY_LIST),
+            blocked: List::new(BLOCKED_LIST),
+            terminated: List::new(TERMINATED_LIST),
             current_task_index: None,
+            policy: SchedulePolicy::RoundRobin,
+            idle_task_id: None,
+            handoff: None,
         }
     }
     
+    // Get the scheduling policy
+    pub fn policy(&self) -> SchedulePolicy {
+        self.policy
+    }
+    
+    // Change the scheduling policy; takes effect on the next schedule
+    pub fn set_policy(&mut self, policy: SchedulePolicy) {
+        self.policy = policy;
+    }
+    
     // Add a new task to the scheduler
+    // Terminated tasks are dropped here, freeing their stacks and IDs, when
+    // the new task takes their slot.
     pub fn add_task(&mut self, task: Task) {
-        self.tasks.push_back(task);
+        let state = task.state;
+        let id = task.id;
+        let index = match self.reusable_slot() {
+            Some(index) => {
+                self.terminated.remove(&mut self.tasks, index);
+                let old = core::mem::replace(&mut self.tasks[index], task);
+                self.task_index.remove(&old.id);
+                index
+            }
+            None => {
+                self.tasks.push_back(task);
+                self.tasks.len() - 1
+            }
+        };
+        self.task_index.insert(id, index);
+        self.link(index, state);
+    }
+    
+    // Slot of a terminated task that can be given to a new one. A task that
+    // just exited may still be running on its stack, so it isn't one.
+    fn reusable_slot(&self) -> Option<usize> {
+        let current_task_id = unsafe { CURRENT_TASK_ID };
+        self.terminated.iter(&self.tasks).find(|&index| self.tasks[index].id != current_task_id)
+    }
+    
+    // Put the task at `index` on the list for `state`, if it has one
+    fn link(&mut self, index: usize, state: TaskState) {
+        match state {
+            TaskState::Ready => self.ready.push_back(&mut self.tasks, index),
+            TaskState::Blocked => self.blocked.push_back(&mut self.tasks, index),
+            TaskState::Terminated => self.terminated.push_back(&mut self.tasks, index),
+            TaskState::Running => {}
+        }
+    }
+    
+    // Change the state of the task at `index`, moving it to the matching list.
+    // Every state change goes through here to keep the lists in step.
+    fn set_state(&mut self, index: usize, state: TaskState) {
+        // Only the list it is on does anything
+        self.ready.remove(&mut self.tasks, index);
+        self.blocked.remove(&mut self.tasks, index);
+        self.terminated.remove(&mut self.tasks, index);
+        
+        let task = &mut self.tasks[index];
+        task.state = state;
+        // Woken some other way, so the pending wake tick is stale
+        if state != TaskState::Blocked {
+            task.wake_tick = None;
+        }
+        self.link(index, state);
+    }
+    
+    // Whether task `id` is waiting to run
+    pub fn is_ready(&self, id: usize) -> bool {
+        self.task_index.get(&id).is_some_and(|&index| self.ready.contains(&self.tasks[index]))
+    }
+    
+    // Whether task `id` is blocked
+    pub fn is_blocked(&self, id: usize) -> bool {
+        self.task_index.get(&id).is_some_and(|&index| self.blocked.contains(&self.tasks[index]))
+    }
+    
+    // Number of tasks waiting to run
+    pub fn ready_count(&self) -> usize {
+        self.ready.len()
+    }
+    
+    // Number of blocked tasks
+    pub fn blocked_count(&self) -> usize {
+        self.blocked.len()
+    }
+    
+    // Number of tasks that haven't terminated
+    pub fn active_count(&self) -> usize {
+        self.tasks.len() - self.terminated.len()
     }
     
     // Get the current task
@@ -45,135 +186,187 @@ impl Scheduler {
         }
     }
     
-    // Get the next task to run (round-robin scheduler)
+    // Get the next task to run according to the scheduling policy
     pub fn next_task(&mut self) -> Option<&mut Task> {
-        let task_count = self.tasks.len();
+        let index = self.next_task_index()?;
+        self.current_task_index = Some(index);
+        unsafe { CURRENT_TASK_ID = self.tasks[index].id; }
+        Some(&mut self.tasks[index])
+    }
+    
+    // Make task `id` the next one to run if it is still ready at the next
+    // schedule, bypassing the policy once
+    pub fn hand_off(&mut self, id: usize) {
+        self.handoff = Some(id);
+    }
+    
+    // Block task `id` until the tick count reaches `wake_tick`
+    pub fn sleep(&mut self, id: usize, wake_tick: u64) {
+        if let Some(&index) = self.task_index.get(&id) {
+            self.set_state(index, TaskState::Blocked);
+            self.tasks[index].wake_tick = Some(wake_tick);
+        }
+    }
+    
+    // Make sleeping tasks whose wake tick has come ready again
+    fn wake_sleepers(&mut self, now: u64) {
+        let mut next = self.blocked.front();
+        while let Some(index) = next {
+            // Read before the task is moved off the list
+            next = self.tasks[index].links().next();
+            if self.tasks[index].wake_tick.is_some_and(|tick| tick <= now) {
+                self.set_state(index, TaskState::Ready);
+            }
+        }
+    }
+    
+    // Pick the index of the task that should run next, if any
+    fn next_task_index(&mut self) -> Option<usize> {
+        self.wake_sleepers(crate::time::ticks());
         
-        if task_count == 0 {
-            return None;
+        // A pending handoff wins if its target is ready
+        if let Some(id) = self.handoff.take() {
+            if self.is_ready(id) {
+                return self.task_index.get(&id).copied();
+            }
         }
         
-        // Start from the current task or 0 if no current task
-        let start_index = match self.current_task_index {
-            Some(index) => (index + 1) % task_count,
-            None => 0,
-        };
+        match self.policy {
+            SchedulePolicy::RoundRobin => self.next_round_robin(),
+            SchedulePolicy::Fcfs => self.next_fcfs(),
+            SchedulePolicy::Priority => self.next_priority(),
+        }
+    }
+    
+    // The ready task that has waited longest, passing over the current one
+    // while it is still listed as ready
+    fn next_round_robin(&mut self) -> Option<usize> {
+        let index = match self.ready.front() {
+            Some(front) if Some(front) == self.current_task_index => {
+                self.tasks[front].links().next().or(Some(front))
+            }
+            front => front,
+        }?;
+        self.requeue(index);
+        Some(index)
+    }
+    
+    // The current task until it blocks or exits, then the task that became
+    // ready first
+    fn next_fcfs(&mut self) -> Option<usize> {
+        if let Some(index) = self.current_task_index {
+            let task = &self.tasks[index];
+            let runnable = matches!(task.state, TaskState::Ready | TaskState::Running);
+            if runnable && Some(task.id) != self.idle_task_id {
+                return Some(index);
+            }
+        }
         
-        // Find the next ready task
-        for offset in 0..task_count {
-            let index = (start_index + offset) % task_count;
-            
-            if self.tasks[index].state == TaskState::Ready {
-                self.current_task_index = Some(index);
-                unsafe { CURRENT_TASK_ID = self.tasks[index].id; }
-                return Some(&mut self.tasks[index]);
+        self.ready
+            .iter(&self.tasks)
+            .find(|&index| Some(self.tasks[index].id) != self.idle_task_id)
+            .or_else(|| self.ready.front())
+    }
+    
+    // Highest-priority ready task, the one waiting longest among equals
+    fn next_priority(&mut self) -> Option<usize> {
+        let current = self.current_task_index;
+        let mut best: Option<usize> = None;
+        for index in self.ready.iter(&self.tasks) {
+            let better = best.is_none_or(|b| {
+                let (priority, best_priority) = (self.tasks[index].priority, self.tasks[b].priority);
+                priority > best_priority || (priority == best_priority && Some(b) == current)
+            });
+            if better {
+                best = Some(index);
             }
         }
         
-        None
+        let index = best?;
+        self.requeue(index);
+        Some(index)
+    }
+    
+    // Move the ready task at `index` to the back of the ready list, so a task
+    // picked but left ready doesn't keep the front
+    fn requeue(&mut self, index: usize) {
+        self.ready.remove(&mut self.tasks, index);
+        self.ready.push_back(&mut self.tasks, index);
+    }
+    
+    // Snapshot of every task the scheduler knows about
+    pub fn task_info(&self) -> Vec<TaskInfo> {
+        self.tasks.iter().map(Task::info).collect()
     }
     
     // Get task by ID
     pub fn get_task_by_id(&mut self, id: usize) -> Option<&mut Task> {
-        self.tasks.iter_mut().find(|task| task.id == id)
+        let index = *self.task_index.get(&id)?;
+        self.tasks.get_mut(index)
     }
     
     // Set task state
     pub fn set_task_state(&mut self, id: usize, state: TaskState) {
-        if let Some(task) = self.get_task_by_id(id) {
-            task.state = state;
+        if let Some(&index) = self.task_index.get(&id) {
+            self.set_state(index, state);
         }
     }
     
-    // Schedule next task and perform context switch
-    pub fn schedule(&mut self) {
+    // Do the bookkeeping for a task switch and return the contexts to switch
+    // between, without switching. Lets callers release the scheduler lock first.
+    pub fn prepare_switch(&mut self) -> Option<(*mut TaskContext, *const TaskContext)> {
         let current_task_id = unsafe { CURRENT_TASK_ID };
         
-        // Check if there are any tasks to schedule
-        if self.tasks.is_empty() {
-            return;
-        }
+        // Whichever task runs next starts a fresh quantum
+        SLICE_START.store(crate::time::ticks(), Ordering::SeqCst);
         
-        // Find the next ready task's index
-        let task_count = self.tasks.len();
-        let start_index = match self.current_task_index {
-            Some(index) => (index + 1) % task_count,
-            None => 0,
-        };
+        let next_task_index = self.next_task_index()?;
+        let current_index = self.task_index.get(&current_task_id).copied();
         
-        // Find the next ready task's index
-        let mut next_task_index = None;
-        for offset in 0..task_count {
-            let index = (start_index + offset) % task_count;
-            if self.tasks[index].state == TaskState::Ready {
-                next_task_index = Some(index);
-                break;
-            }
+        // The current task keeps running (FCFS)
+        if current_index == Some(next_task_index) {
+            self.set_state(next_task_index, TaskState::Running);
+            return None;
         }
         
-        // If no ready task is found, return
-        let next_task_index = match next_task_index {
-            Some(index) => index,
-            None => return,
-        };
+        self.set_state(next_task_index, TaskState::Running);
+        self.current_task_index = Some(next_task_index);
+        unsafe { CURRENT_TASK_ID = self.tasks[next_task_index].id; }
         
-        // Update next task state and get its ID
-        self.tasks[next_task_index].state = TaskState::Running;
-        let next_task_id = self.tasks[next_task_index].id;
+        // Without a current task there is nothing to save the context into
+        let current_index = current_index?;
+        if self.tasks[current_index].state == TaskState::Running {
+            self.set_state(current_index, TaskState::Ready);
+        }
         
-        // Update current task's state if there is one
-        if current_task_id > 0 {
-            // Find the current task's index
-            let mut current_task_index = None;
-            for (i, task) in self.tasks.iter().enumerate() {
-                if task.id == current_task_id {
-                    current_task_index = Some(i);
-                    break;
-                }
-            }
-            
-            if let Some(current_index) = current_task_index {
-                // Update current task state
-                if self.tasks[current_index].state == TaskState::Running {
-                    self.tasks[current_index].state = TaskState::Ready;
-                }
-                
-                // Update scheduler state and task ID BEFORE context switch
-                self.current_task_index = Some(next_task_index);
-                
-                // Important: Update the global task ID before switching context
-                unsafe {
-                    CURRENT_TASK_ID = next_task_id;
-                }
-                
-                // Get raw pointers to the contexts to avoid borrow issues
-                let current_context_ptr: *mut TaskContext = &mut self.tasks[current_index].context;
-                let next_context_ptr: *const TaskContext = &self.tasks[next_task_index].context;
-                
-                // Perform context switch using raw pointers
-                unsafe {
-                    TaskContext::switch(&mut *current_context_ptr, &*next_context_ptr);
-                }
-                
-                // No code should run here until we switch back to this task
+        let current_context: *mut TaskContext = &mut self.tasks[current_index].context;
+        let next_context: *const TaskContext = &self.tasks[next_task_index].context;
+        Some((current_context, next_context))
+    }
+    
+    // Schedule next task and perform context switch
+    pub fn schedule(&mut self) {
+        crate::task::watchdog::feed();
+        if let Some((current_context, next_context)) = self.prepare_switch() {
+            // Perform context switch using raw pointers
+            unsafe {
+                TaskContext::switch(&mut *current_context, &*next_context);
             }
         }
-        
-        // No current task, just update the scheduler state
-        self.current_task_index = Some(next_task_index);
-        unsafe { CURRENT_TASK_ID = next_task_id; }
     }
 }
 
 // Initialize the scheduler with an idle task
 pub fn init() {
     let idle_task = Task::new("idle", idle_task, 4096);
+    let idle_task_id = idle_task.id;
     SCHEDULER.lock().add_task(idle_task);
+    SCHEDULER.lock().idle_task_id = Some(idle_task_id);
     
     // Initialize the first task as the current
     let mut scheduler = SCHEDULER.lock();
-    if let Some(task) = scheduler.next_task() {
-        task.state = TaskState::Running;
+    if let Some(id) = scheduler.next_task().map(|task| task.id) {
+        scheduler.set_task_state(id, TaskState::Running);
     }
 }
 
@@ -184,7 +377,7 @@ fn idle_task() -> ! {
         x86_64::instructions::hlt();
         
         // Schedule the next task
-        SCHEDULER.lock().schedule();
+        yield_task();
     }
 }
 
@@ -194,9 +387,230 @@ pub fn spawn(name: &'static str, entry_point: fn() -> !) {
     SCHEDULER.lock().add_task(task);
 }
 
+// End the current task and switch away from it for good. Its stack and ID
+// are freed when a later task takes its slot.
+pub fn exit() -> ! {
+    let contexts = {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.set_task_state(current_task_id(), TaskState::Terminated);
+        scheduler.prepare_switch()
+    };
+    if let Some((current, next)) = contexts {
+        unsafe {
+            TaskContext::switch(&mut *current, &*next);
+        }
+    }
+    
+    // Nothing else could run
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+// Number of tasks that haven't terminated
+pub fn active_count() -> usize {
+    SCHEDULER.lock().active_count()
+}
+
+// Change the global scheduler's policy
+pub fn set_policy(policy: SchedulePolicy) {
+    SCHEDULER.lock().set_policy(policy);
+}
+
+// Print every task (like `ps`) and return the listing
+pub fn list() -> Vec<TaskInfo> {
+    let tasks = SCHEDULER.lock().task_info();
+    
+    println!("  PID  STATE       STACK  NAME");
+    for task in &tasks {
+        let state = alloc::format!("{:?}", task.state);
+        println!("{:>5}  {:<10} {:>6}  {}", task.id, state, task.stack_size, task.name);
+    }
+    
+    tasks
+}
+
 // Yield the current task
 pub fn yield_task() {
-    SCHEDULER.lock().schedule();
+    crate::task::watchdog::feed();
+    
+    // The lock must be released before switching, or the next task would
+    // deadlock on it
+    let contexts = SCHEDULER.lock().prepare_switch();
+    match contexts {
+        Some((current, next)) => unsafe {
+            TaskContext::switch(&mut *current, &*next);
+        },
+        // Blocked with nothing else to run, as when the idle task waits on a
+        // channel: halt until an interrupt can change that instead of spinning
+        None if !current_task_running() && interrupts::are_enabled() => x86_64::instructions::hlt(),
+        None => {}
+    }
+}
+
+// Whether the current task is still marked as running, rather than blocked
+fn current_task_running() -> bool {
+    SCHEDULER.lock().current_task().is_none_or(|task| task.state == TaskState::Running)
+}
+
+// Switch straight to task `id` if it is ready, skipping the tasks round-robin
+// would run first; otherwise yield as usual
+pub fn yield_to(id: usize) {
+    crate::task::watchdog::feed();
+    
+    let contexts = {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.hand_off(id);
+        scheduler.prepare_switch()
+    };
+    if let Some((current, next)) = contexts {
+        unsafe {
+            TaskContext::switch(&mut *current, &*next);
+        }
+    }
+}
+
+// Duplicate the current task. The child gets a copy of the stack and resumes
+// from this call with `Ok(0)`; the parent gets `Ok(child_id)`.
+//
+// Pages private to the task's address space are shared copy-on-write, so
+// writes to them after the fork are only seen by the side that made them. The
+// kernel's mappings, including its heap and statics, stay shared by all tasks;
+// a task without an address space of its own shares everything but its stack.
+//
+// The child's stack lives at a different address, so `Task::fork` guesses at
+// which words of it are pointers into the stack and moves them: any value in
+// the parent's stack range is rewritten, even an integer that merely looks
+// like such an address. Pointers to the stack held anywhere else, e.g. in a
+// heap object, still point at the parent's stack in the child.
+pub fn fork() -> Result<usize, &'static str> {
+    let mut context = TaskContext::default();
+    
+    // Nothing may be borrowed across this point, the child resumes here
+    if unsafe { TaskContext::save(&mut context) } != 0 {
+        return Ok(0);
+    }
+    
+    let mut scheduler = SCHEDULER.lock();
+    let parent = scheduler.current_task().ok_or("No current task")?;
+    if !parent.stack_range().contains(&context.rsp) {
+        return Err("Not running on a task stack");
+    }
+    
+    let child = parent.fork(&context)?;
+    let child_id = child.id;
+    scheduler.add_task(child);
+    
+    Ok(child_id)
+}
+
+// Yield to other tasks until at least `duration_ns` nanoseconds have passed
+pub fn yield_for(duration_ns: u64) {
+    let deadline = crate::time::monotonic_ns().saturating_add(duration_ns);
+    while crate::time::monotonic_ns() < deadline {
+        yield_task();
+        core::hint::spin_loop();
+    }
+}
+
+// Yield to other tasks until `done` returns true or `timeout_ns` nanoseconds
+// have passed. Returns whether `done` was met.
+pub fn yield_until(mut done: impl FnMut() -> bool, timeout_ns: u64) -> bool {
+    let deadline = crate::time::monotonic_ns().saturating_add(timeout_ns);
+    while !done() {
+        if crate::time::monotonic_ns() >= deadline {
+            return false;
+        }
+        yield_task();
+    }
+    true
+}
+
+// Block the current task for at least `ticks` timer ticks
+pub fn sleep(ticks: u64) {
+    let wake_tick = crate::time::ticks().saturating_add(ticks);
+    
+    // Outside any task (before `init`) there is nothing to block, so wait
+    // for the timer here
+    if SCHEDULER.lock().current_task().is_none() {
+        assert!(interrupts::are_enabled() || ticks == 0, "sleep outside a task with interrupts disabled never wakes");
+        while crate::time::ticks() < wake_tick {
+            x86_64::instructions::hlt();
+        }
+        return;
+    }
+    
+    SCHEDULER.lock().sleep(current_task_id(), wake_tick);
+    yield_task();
+    
+    // With nothing else to run the yield comes straight back, so wait here
+    // (halting in `yield_task`) until the wake tick makes the task ready
+    while !current_task_running() && interrupts::are_enabled() {
+        yield_task();
+    }
+}
+
+// Set how many ticks a task may run before `checkpoint` yields; 0 turns
+// checkpoints off
+pub fn set_quantum(ticks: u64) {
+    QUANTUM_TICKS.store(ticks, Ordering::SeqCst);
+}
+
+// Get the checkpoint quantum in ticks
+pub fn quantum() -> u64 {
+    QUANTUM_TICKS.load(Ordering::SeqCst)
+}
+
+// Yield if the current task has run for its quantum or longer since it last
+// got the CPU. Cheap enough to call on every iteration of a long loop, which
+// bounds how long the task keeps others waiting without preempting it.
+pub fn checkpoint() {
+    let quantum = quantum();
+    if quantum == 0 {
+        return;
+    }
+    
+    let elapsed = crate::time::ticks().saturating_sub(SLICE_START.load(Ordering::SeqCst));
+    if elapsed >= quantum {
+        yield_task();
+    }
+}
+
+// Called by the fault handlers with the faulting address. If it lies in the
+// current task's stack guard page, reports the overflow and switches away from
+// the task for good; otherwise, or if nothing else can run, returns.
+//
+// Locks the task held stay held, e.g. the console's if it overflowed while
+// printing.
+pub fn handle_stack_overflow(addr: VirtAddr) {
+    let contexts = {
+        // The task may have overflowed inside the scheduler
+        let mut scheduler = match SCHEDULER.try_lock() {
+            Some(scheduler) => scheduler,
+            None => return,
+        };
+        let current_id = unsafe { CURRENT_TASK_ID };
+        let task = match scheduler.get_task_by_id(current_id) {
+            Some(task) if task.stack_guard_contains(addr) => task,
+            _ => return,
+        };
+        
+        // Report without the console locks, the task may hold them
+        let mut report = StackBuffer::<128>::new();
+        let _ = writeln!(report, "task {} stack overflow ({})", task.id, task.name);
+        crate::panic::emergency_write(report.as_bytes());
+        crate::vga_buffer::emergency_print(report.as_str());
+        
+        scheduler.set_task_state(current_id, TaskState::Terminated);
+        scheduler.prepare_switch()
+    };
+    
+    // A terminated task is never switched back to
+    if let Some((current, next)) = contexts {
+        unsafe {
+            TaskContext::switch(&mut *current, &*next);
+        }
+    }
 }
 
 // Block the current task
@@ -214,4 +628,133 @@ pub fn unblock_task(id: usize) {
 // Get the current task ID
 pub fn current_task_id() -> usize {
     unsafe { CURRENT_TASK_ID }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn parked_task() -> ! {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn test_sleeping_task_wakes_at_deadline() {
+    let mut scheduler = Scheduler::new();
+    let sleeper = Task::new("sleeper", parked_task, 4096);
+    let other = Task::new("other", parked_task, 4096);
+    let (sleeper_id, other_id) = (sleeper.id, other.id);
+    scheduler.add_task(sleeper);
+    scheduler.add_task(other);
+    
+    // The clock only moves when the test says so
+    scheduler.sleep(sleeper_id, crate::time::ticks() + 5);
+    assert_eq!(scheduler.next_task().unwrap().id, other_id);
+    crate::time::test_advance(4);
+    assert_eq!(scheduler.next_task().unwrap().id, other_id);
+    
+    crate::time::test_advance(1);
+    assert_eq!(scheduler.next_task().unwrap().id, sleeper_id);
+    assert_eq!(scheduler.get_task_by_id(sleeper_id).unwrap().wake_tick, None);
+}
+
+#[test_case]
+fn test_state_changes_move_tasks_between_lists() {
+    let mut scheduler = Scheduler::new();
+    let tasks: Vec<Task> = (0..3).map(|_| Task::new("listed", parked_task, 4096)).collect();
+    let ids: Vec<usize> = tasks.iter().map(|task| task.id).collect();
+    for task in tasks {
+        scheduler.add_task(task);
+    }
+    assert_eq!((scheduler.ready_count(), scheduler.blocked_count()), (3, 0));
+    
+    scheduler.set_task_state(ids[1], TaskState::Blocked);
+    assert!(scheduler.is_blocked(ids[1]) && !scheduler.is_ready(ids[1]));
+    assert!(scheduler.is_ready(ids[0]) && scheduler.is_ready(ids[2]));
+    assert_eq!((scheduler.ready_count(), scheduler.blocked_count()), (2, 1));
+    
+    // Unblocked tasks join the back of the ready list
+    scheduler.set_task_state(ids[1], TaskState::Ready);
+    let order: Vec<usize> = scheduler.ready.iter(&scheduler.tasks).map(|index| scheduler.tasks[index].id).collect();
+    assert_eq!(order, [ids[0], ids[2], ids[1]]);
+    
+    // Running and terminated tasks are on neither list
+    scheduler.set_task_state(ids[0], TaskState::Running);
+    scheduler.set_task_state(ids[2], TaskState::Terminated);
+    assert!(!scheduler.is_ready(ids[0]) && !scheduler.is_blocked(ids[2]));
+    assert_eq!((scheduler.ready_count(), scheduler.blocked_count()), (1, 0));
+}
+
+#[test_case]
+fn test_round_robin_follows_ready_list() {
+    let mut scheduler = Scheduler::new();
+    let tasks: Vec<Task> = (0..3).map(|_| Task::new("queued", parked_task, 4096)).collect();
+    let ids: Vec<usize> = tasks.iter().map(|task| task.id).collect();
+    for task in tasks {
+        scheduler.add_task(task);
+    }
+    
+    // The first task waits behind the others once it has been blocked
+    scheduler.set_task_state(ids[0], TaskState::Blocked);
+    scheduler.set_task_state(ids[0], TaskState::Ready);
+    for &id in &[ids[1], ids[2], ids[0]] {
+        assert_eq!(scheduler.next_task().unwrap().id, id);
+    }
+}
+
+#[test_case]
+fn test_exited_tasks_make_room_for_new_ones() {
+    let mut scheduler = Scheduler::new();
+    let slots = super::pid::slots();
+    
+    let mut last_id = None;
+    for _ in 0..100 {
+        let task = Task::new("short-lived", parked_task, 4096);
+        let id = task.id;
+        scheduler.add_task(task);
+        
+        // Exiting leaves the task's ID and slot to the next one
+        scheduler.set_task_state(id, TaskState::Terminated);
+        assert_eq!(scheduler.active_count(), 0);
+        if let Some(last_id) = last_id {
+            assert!(scheduler.get_task_by_id(last_id).is_none(), "stale ID still names a task");
+        }
+        last_id = Some(id);
+    }
+    
+    assert_eq!(scheduler.tasks.len(), 1);
+    assert!(super::pid::slots() <= slots + 16, "ID pool grew to {}", super::pid::slots());
+}
+
+#[test_case]
+fn test_scheduler_settles_on_idle() {
+    let mut scheduler = Scheduler::new();
+    let idle = Task::new("idle", parked_task, 4096);
+    let idle_id = idle.id;
+    scheduler.add_task(idle);
+    scheduler.idle_task_id = Some(idle_id);
+    let (a, b) = (Task::new("a", parked_task, 4096), Task::new("b", parked_task, 4096));
+    let (a_id, b_id) = (a.id, b.id);
+    scheduler.add_task(a);
+    scheduler.add_task(b);
+    
+    // Like `init`, start on the idle task
+    assert_eq!(scheduler.next_task().unwrap().id, idle_id);
+    scheduler.set_task_state(idle_id, TaskState::Running);
+    
+    // With every other task blocked, idle keeps running without switching to itself
+    scheduler.set_task_state(a_id, TaskState::Blocked);
+    scheduler.set_task_state(b_id, TaskState::Blocked);
+    for _ in 0..3 {
+        assert!(scheduler.prepare_switch().is_none());
+        assert_eq!(current_task_id(), idle_id);
+    }
+    
+    // Nor when idle blocks too, it just carries on until something is ready
+    scheduler.set_task_state(idle_id, TaskState::Blocked);
+    assert!(scheduler.prepare_switch().is_none());
+    assert_eq!(current_task_id(), idle_id);
+    
+    scheduler.set_task_state(b_id, TaskState::Ready);
+    assert!(scheduler.prepare_switch().is_some());
+    assert_eq!(current_task_id(), b_id);
+}
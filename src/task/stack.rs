@@ -0,0 +1,109 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use core::ops::Range;
+use x86_64::structures::paging::{FrameDeallocator, Mapper, Page, Size4KiB};
+use x86_64::VirtAddr;
+use crate::memory::{self, FRAME_ALLOCATOR, MAPPER};
+
+// Memory backing a task's stack
+enum Memory {
+    // Pages of their own with an unmapped guard page below, so an overflow
+    // faults instead of running into whatever lies below
+    Guarded { guard: u64, size: usize },
+    // Plain heap memory, when pages can't be mapped (before `memory::install`)
+    Heap(Box<[u8]>),
+}
+
+// A task's stack
+pub(super) struct TaskStack {
+    memory: Memory,
+}
+
+impl TaskStack {
+    // Allocate a stack of at least `size` bytes, with a guard page if possible
+    pub fn new(size: usize) -> Self {
+        let memory = match Self::map_guarded(size) {
+            Some((guard, size)) => Memory::Guarded { guard, size },
+            None => Memory::Heap(vec![0u8; size].into_boxed_slice()),
+        };
+        TaskStack { memory }
+    }
+    
+    // Reserve room for the stack and its guard page and map all but the guard
+    fn map_guarded(size: usize) -> Option<(u64, usize)> {
+        let pages = size.div_ceil(4096).max(1) as u64;
+        let (guard, committed) = {
+            let mut mapper = MAPPER.lock();
+            let mut frame_allocator = FRAME_ALLOCATOR.lock();
+            let mapper = mapper.as_mut()?;
+            let frame_allocator = frame_allocator.as_mut()?;
+            
+            let guard = memory::reserve(mapper, pages + 1).ok()?;
+            (guard, memory::commit(mapper, frame_allocator, guard + 4096u64, pages).is_ok())
+        };
+        
+        // Undo whatever part of the commit succeeded
+        if !committed {
+            Self::unmap(guard.as_u64() + 4096, pages);
+            memory::release(guard);
+            return None;
+        }
+        Some((guard.as_u64(), pages as usize * 4096))
+    }
+    
+    // Unmap whichever of `pages` pages from `start` are mapped, freeing their frames
+    fn unmap(start: u64, pages: u64) {
+        let mut mapper = MAPPER.lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.lock();
+        let (mapper, frame_allocator) = match (mapper.as_mut(), frame_allocator.as_mut()) {
+            (Some(mapper), Some(frame_allocator)) => (mapper, frame_allocator),
+            _ => return,
+        };
+        
+        // Last page first, so a bump allocator can take the frames back
+        for i in (0..pages).rev() {
+            let page: Page<Size4KiB> = Page::containing_address(VirtAddr::new(start + i * 4096));
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+        }
+    }
+    
+    // Address range of the usable stack memory
+    pub fn range(&self) -> Range<u64> {
+        match &self.memory {
+            Memory::Guarded { guard, size } => guard + 4096..guard + 4096 + *size as u64,
+            Memory::Heap(memory) => {
+                let bottom = memory.as_ptr() as u64;
+                bottom..bottom + memory.len() as u64
+            }
+        }
+    }
+    
+    // Whether `addr` lies in the guard page below the stack
+    pub fn guard_contains(&self, addr: VirtAddr) -> bool {
+        match self.memory {
+            Memory::Guarded { guard, .. } => (guard..guard + 4096).contains(&addr.as_u64()),
+            Memory::Heap(_) => false,
+        }
+    }
+    
+    // Copy the contents of `other`, a stack of the same size
+    pub fn copy_from(&mut self, other: &TaskStack) {
+        let (range, other_range) = (self.range(), other.range());
+        let len = (range.end - range.start).min(other_range.end - other_range.start) as usize;
+        unsafe {
+            core::ptr::copy_nonoverlapping(other_range.start as *const u8, range.start as *mut u8, len);
+        }
+    }
+}
+
+impl Drop for TaskStack {
+    fn drop(&mut self) {
+        if let Memory::Guarded { guard, size } = self.memory {
+            Self::unmap(guard + 4096, (size / 4096) as u64);
+            memory::release(VirtAddr::new(guard));
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use core::ops::{Deref, DerefMut};
+use spin::{Mutex, MutexGuard};
+use super::scheduler::yield_task;
+
+// A mutex that yields to other tasks instead of spinning while it is held
+pub struct BlockingMutex<T> {
+    inner: Mutex<T>,
+}
+
+pub struct BlockingMutexGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> BlockingMutex<T> {
+    pub const fn new(value: T) -> Self {
+        BlockingMutex { inner: Mutex::new(value) }
+    }
+    
+    // Lock the mutex, letting other tasks run until it is free
+    pub fn lock(&self) -> BlockingMutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.inner.try_lock() {
+                return BlockingMutexGuard { guard };
+            }
+            yield_task();
+        }
+    }
+    
+    // Lock the mutex only if it is free
+    pub fn try_lock(&self) -> Option<BlockingMutexGuard<'_, T>> {
+        self.inner.try_lock().map(|guard| BlockingMutexGuard { guard })
+    }
+}
+
+impl<T> Deref for BlockingMutexGuard<'_, T> {
+    type Target = T;
+    
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for BlockingMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
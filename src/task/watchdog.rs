@@ -0,0 +1,87 @@
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::panic::{emergency_write, StackBuffer};
+
+/// Default number of timer ticks a task may run without yielding, about ten
+/// seconds at the PIT's power-on rate of 18.2 Hz
+pub const DEFAULT_WATCHDOG_TICKS: u64 = 182;
+
+// Marks that no task has been reported since the last feed
+const NO_TASK: usize = usize::MAX;
+
+// Ticks allowed between feeds, 0 disables the watchdog
+static THRESHOLD: AtomicU64 = AtomicU64::new(DEFAULT_WATCHDOG_TICKS);
+// Tick count at the last yield
+static LAST_FEED: AtomicU64 = AtomicU64::new(0);
+// Task reported stuck since the last feed
+static STUCK_TASK: AtomicUsize = AtomicUsize::new(NO_TASK);
+
+/// Sets how many ticks a task may run without yielding; 0 disables the watchdog
+pub fn set_threshold(ticks: u64) {
+    THRESHOLD.store(ticks, Ordering::SeqCst);
+}
+
+/// Returns the current watchdog threshold in ticks
+pub fn threshold() -> u64 {
+    THRESHOLD.load(Ordering::SeqCst)
+}
+
+/// Resets the watchdog, called whenever the running task gives up the CPU
+pub fn feed() {
+    // The count `check` is given, even where `ticks` reads a stand-in
+    LAST_FEED.store(crate::time::timer_ticks(), Ordering::SeqCst);
+    STUCK_TASK.store(NO_TASK, Ordering::SeqCst);
+}
+
+/// Returns the task reported stuck since the last feed, if any
+pub fn stuck_task() -> Option<usize> {
+    match STUCK_TASK.load(Ordering::SeqCst) {
+        NO_TASK => None,
+        id => Some(id),
+    }
+}
+
+/// Called from the timer interrupt with the current tick count. Reports the
+/// running task over serial once it has gone `threshold` ticks without
+/// yielding. Tasks are not preempted, so the report is all it can do.
+pub fn check(now: u64) {
+    let threshold = threshold();
+    if threshold == 0 || stuck_task().is_some() {
+        return;
+    }
+    if now.saturating_sub(LAST_FEED.load(Ordering::SeqCst)) < threshold {
+        return;
+    }
+    
+    // Runs in interrupt context, so stay off the serial lock and the heap
+    let id = crate::task::current_task_id();
+    STUCK_TASK.store(id, Ordering::SeqCst);
+    let mut line = StackBuffer::<48>::new();
+    let _ = writeln!(line, "watchdog: task {} stuck", id);
+    emergency_write(line.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test_case]
+    fn test_feed_and_check_count_the_same_ticks() {
+        // No real tick may land between feeding and checking
+        x86_64::instructions::interrupts::without_interrupts(|| {
+            set_threshold(1);
+            
+            // Just fed, whatever the stand-in tick count says
+            crate::time::test_advance(DEFAULT_WATCHDOG_TICKS);
+            feed();
+            check(crate::time::timer_ticks());
+            assert_eq!(stuck_task(), None);
+            
+            check(crate::time::timer_ticks() + 1);
+            assert_eq!(stuck_task(), Some(crate::task::current_task_id()));
+            
+            feed();
+            set_threshold(DEFAULT_WATCHDOG_TICKS);
+        });
+    }
+}
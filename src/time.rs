@@ -0,0 +1,248 @@
+use core::fmt;
+use core::sync::atomic::{AtomicU64, Ordering};
+use x86_64::instructions::port::Port;
+
+// PIT input clock frequency in Hz
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// Length of a timer tick in nanoseconds: the period of the PIT at its
+/// power-on divisor of 65536 (about 18.2 Hz), which the APIC timer matches
+pub const TICK_NS: u64 = 65536 * 1_000_000_000 / PIT_FREQUENCY;
+// Length of the TSC calibration window in milliseconds
+const CALIBRATION_MS: u64 = 10;
+
+// TSC ticks per millisecond, 0 until calibrated
+static TSC_PER_MS: AtomicU64 = AtomicU64::new(0);
+// TSC value at calibration time, used as the monotonic epoch
+static TSC_EPOCH: AtomicU64 = AtomicU64::new(0);
+// Timer interrupts (PIT channel 0) received so far
+static TICKS: AtomicU64 = AtomicU64::new(0);
+// Stand-in tick count for the lib's tests, moved only by `test_advance`
+#[cfg(test)]
+static TEST_TICKS: AtomicU64 = AtomicU64::new(0);
+
+// The tick count `ticks` reports: the timer's, or under test the stand-in,
+// so sleeps and timeouts can be driven without a running timer
+#[cfg(not(test))]
+static CLOCK: &AtomicU64 = &TICKS;
+#[cfg(test)]
+static CLOCK: &AtomicU64 = &TEST_TICKS;
+
+/// Reads the CPU time-stamp counter.
+pub fn read_tsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Calibrates the TSC against PIT channel 2 and sets the monotonic epoch.
+///
+/// Channel 2 is polled through the speaker gate port, so no timer interrupt
+/// is required.
+pub fn init() {
+    let tsc_per_ms = calibrate_tsc();
+    TSC_EPOCH.store(read_tsc(), Ordering::SeqCst);
+    TSC_PER_MS.store(tsc_per_ms, Ordering::SeqCst);
+}
+
+/// Returns the number of TSC ticks per millisecond, calibrating on first use.
+pub fn tsc_per_ms() -> u64 {
+    if TSC_PER_MS.load(Ordering::SeqCst) == 0 {
+        init();
+    }
+    TSC_PER_MS.load(Ordering::SeqCst)
+}
+
+/// Returns the nanoseconds elapsed since the clock was calibrated.
+pub fn monotonic_ns() -> u64 {
+    let tsc_per_ms = tsc_per_ms();
+    let elapsed = read_tsc().saturating_sub(TSC_EPOCH.load(Ordering::SeqCst));
+    (elapsed as u128 * 1_000_000 / tsc_per_ms as u128) as u64
+}
+
+/// Returns the number of timer interrupts received so far.
+pub fn ticks() -> u64 {
+    CLOCK.load(Ordering::SeqCst)
+}
+
+/// Returns the number of timer interrupts received so far. Unlike `ticks`,
+/// never the stand-in count under test, so it stays in step with the value
+/// `on_timer_tick` returns.
+pub fn timer_ticks() -> u64 {
+    TICKS.load(Ordering::SeqCst)
+}
+
+/// Moves the tick count seen by `ticks` forward by `ticks`, as if that many
+/// timer interrupts had arrived.
+#[cfg(test)]
+pub fn test_advance(ticks: u64) {
+    TEST_TICKS.fetch_add(ticks, Ordering::SeqCst);
+}
+
+/// Counts a timer interrupt, returning the new tick count.
+pub fn on_timer_tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+// Measure how many TSC ticks elapse during a fixed PIT channel 2 countdown
+fn calibrate_tsc() -> u64 {
+    let mut gate: Port<u8> = Port::new(0x61);
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel2: Port<u8> = Port::new(0x42);
+
+    let count = PIT_FREQUENCY * CALIBRATION_MS / 1000;
+
+    unsafe {
+        // Enable the channel 2 gate, keep the speaker disconnected
+        let value = gate.read();
+        gate.write((value & 0xFD) | 0x01);
+
+        // Channel 2, lobyte/hibyte access, mode 0 (interrupt on terminal count)
+        command.write(0xB0);
+        channel2.write((count & 0xFF) as u8);
+        channel2.write((count >> 8) as u8);
+
+        // Restart the countdown by pulsing the gate
+        let value = gate.read();
+        gate.write(value & 0xFE);
+        gate.write(value | 0x01);
+    }
+
+    let start = read_tsc();
+
+    // OUT2 (bit 5) goes high once the counter reaches zero
+    while unsafe { gate.read() } & 0x20 == 0 {
+        core::hint::spin_loop();
+    }
+
+    let end = read_tsc();
+    ((end - start) / CALIBRATION_MS).max(1)
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A calendar date and time of day in UTC, to the second, as kept by
+/// clocks like the RTC and in FAT timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    /// 1 to 12
+    pub month: u8,
+    /// 1 to the length of the month
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The Unix epoch, 1970-01-01 00:00:00
+    pub const EPOCH: DateTime = DateTime { year: 1970, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    
+    /// Returns whether `year` has a 29th of February.
+    pub const fn is_leap_year(year: u16) -> bool {
+        (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+    }
+    
+    /// Returns the number of days in `month` (1 to 12) of `year`, 0 for an
+    /// invalid month.
+    pub const fn days_in_month(year: u16, month: u8) -> u8 {
+        match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if Self::is_leap_year(year) => 29,
+            2 => 28,
+            _ => 0,
+        }
+    }
+    
+    /// Returns whether every field is in range and the date is not before
+    /// the Unix epoch.
+    pub fn is_valid(&self) -> bool {
+        self.year >= 1970
+            && (1..=Self::days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+    
+    /// Returns the seconds since the Unix epoch, or `None` for an invalid date.
+    pub fn to_unix_timestamp(&self) -> Option<u64> {
+        if !self.is_valid() {
+            return None;
+        }
+        
+        let mut days = 0u64;
+        for year in 1970..self.year {
+            days += if Self::is_leap_year(year) { 366 } else { 365 };
+        }
+        for month in 1..self.month {
+            days += Self::days_in_month(self.year, month) as u64;
+        }
+        days += self.day as u64 - 1;
+        
+        Some(days * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64)
+    }
+    
+    /// Returns the date and time `timestamp` seconds after the Unix epoch.
+    pub fn from_unix_timestamp(timestamp: u64) -> DateTime {
+        let mut days = timestamp / SECONDS_PER_DAY;
+        let seconds = timestamp % SECONDS_PER_DAY;
+        
+        let mut year = 1970;
+        loop {
+            let year_days = if Self::is_leap_year(year) { 366 } else { 365 };
+            if days < year_days {
+                break;
+            }
+            days -= year_days;
+            year += 1;
+        }
+        
+        let mut month = 1;
+        while days >= Self::days_in_month(year, month) as u64 {
+            days -= Self::days_in_month(year, month) as u64;
+            month += 1;
+        }
+        
+        DateTime {
+            year,
+            month,
+            day: days as u8 + 1,
+            hour: (seconds / 3600) as u8,
+            minute: (seconds / 60 % 60) as u8,
+            second: (seconds % 60) as u8,
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+#[test_case]
+fn test_date_time_unix_timestamps() {
+    let new_year = DateTime { year: 2000, month: 1, day: 1, hour: 0, minute: 0, second: 0 };
+    let leap_day = DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 34, second: 56 };
+    
+    assert_eq!(DateTime::EPOCH.to_unix_timestamp(), Some(0));
+    assert_eq!(new_year.to_unix_timestamp(), Some(946_684_800));
+    assert_eq!(leap_day.to_unix_timestamp(), Some(1_709_210_096));
+    
+    assert_eq!(DateTime::from_unix_timestamp(946_684_800), new_year);
+    assert_eq!(DateTime::from_unix_timestamp(1_709_210_096), leap_day);
+    // The day after the leap day
+    assert_eq!(DateTime::from_unix_timestamp(1_709_251_200).month, 3);
+    
+    // 1900 and 2023 have no 29th of February, 2000 does
+    assert!(!DateTime::is_leap_year(1900));
+    assert!(DateTime::is_leap_year(2000));
+    assert_eq!(DateTime { year: 2023, ..leap_day }.to_unix_timestamp(), None);
+}
@@ -4,6 +4,7 @@ use spin::Mutex;
 use lazy_static::lazy_static;
 
 use volatile::Volatile;
+use x86_64::instructions::interrupts;
 
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -39,47 +40,111 @@ impl ColorCode {
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(C)]
-struct ScreenChar {
+pub struct ScreenChar {
     ascii_character: u8,
     color_code: ColorCode,
 }
 
-const BUFFER_WIDTH: usize = 80;
-const BUFFER_HEIGHT: usize = 25;
-
-#[repr(transparent)]
-struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+impl ScreenChar {
+    /// An empty cell, for filling buffers passed to `Writer::new_with_buffer`
+    pub const BLANK: ScreenChar = ScreenChar {
+        ascii_character: b' ',
+        color_code: ColorCode(0),
+    };
 }
 
+// Default text mode dimensions
+pub const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_HEIGHT: usize = 25;
+
+// Physical address of the VGA text buffer
+const VGA_BUFFER_ADDRESS: usize = 0xb8000;
+
 pub struct Writer {
     column_position: usize,
     color_code: ColorCode,
-    buffer: &'static mut Buffer,
+    width: usize,
+    height: usize,
+    // Row-major character cells, `width * height` of them
+    buffer: &'static mut [Volatile<ScreenChar>],
 }
 
 lazy_static! {
-    pub static ref WRITER:Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::Yellow, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-    });
+    pub static ref WRITER:Mutex<Writer> = Mutex::new(Writer::new());
 }
 
 impl Writer {
+    // Writer over the standard 80x25 text buffer. Only for `WRITER`, which
+    // must be the one writer owning the VGA memory
+    fn new() -> Writer {
+        unsafe { Writer::from_raw(VGA_BUFFER_ADDRESS, BUFFER_WIDTH, BUFFER_HEIGHT) }
+    }
+    
+    /// Creates a writer over a `width` x `height` text buffer at `address`.
+    ///
+    /// # Safety
+    /// `address` must point to `width * height` writable text cells that stay
+    /// valid for the lifetime of the writer.
+    pub unsafe fn from_raw(address: usize, width: usize, height: usize) -> Writer {
+        Writer {
+            column_position: 0,
+            color_code: ColorCode::new(Color::Yellow, Color::Black),
+            width,
+            height,
+            buffer: unsafe {
+                core::slice::from_raw_parts_mut(address as *mut Volatile<ScreenChar>, width * height)
+            },
+        }
+    }
+    
+    /// Creates a standard 80x25 writer over an in-memory buffer instead of
+    /// the VGA hardware, so tests can read back what was written.
+    pub fn new_with_buffer(buffer: &'static mut [ScreenChar; BUFFER_WIDTH * BUFFER_HEIGHT]) -> Writer {
+        // Safety: the buffer is exclusively borrowed for 'static and has the right size
+        unsafe { Writer::from_raw(buffer.as_mut_ptr() as usize, BUFFER_WIDTH, BUFFER_HEIGHT) }
+    }
+    
+    /// Copies the text of `row` into `buf` and returns it without trailing
+    /// blanks. Characters outside printable ASCII read back as `?`.
+    pub fn read_row<'a>(&self, row: usize, buf: &'a mut [u8]) -> &'a str {
+        let len = self.width.min(buf.len());
+        for (col, byte) in buf[..len].iter_mut().enumerate() {
+            *byte = match self.read_char(row, col).ascii_character {
+                c @ 0x20..=0x7e => c,
+                _ => b'?',
+            };
+        }
+        
+        let end = buf[..len].iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+        core::str::from_utf8(&buf[..end]).unwrap_or("")
+    }
+    
+    // Screen size in characters as (columns, rows)
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+    
+    fn read_char(&self, row: usize, col: usize) -> ScreenChar {
+        self.buffer[row * self.width + col].read()
+    }
+    
+    fn write_char(&mut self, row: usize, col: usize, character: ScreenChar) {
+        self.buffer[row * self.width + col].write(character);
+    }
+    
     pub fn write_byte(&mut self, byte: u8) {
         match byte {
             b'\n' => self.new_line(),
             byte => {
-                if self.column_position >= BUFFER_WIDTH {
+                if self.column_position >= self.width {
                     self.new_line();
                 }
 
-                let row = BUFFER_HEIGHT - 1;
+                let row = self.height - 1;
                 let col = self.column_position;
 
                 let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
+                self.write_char(row, col, ScreenChar {
                     ascii_character: byte,
                     color_code,
                 });
@@ -88,6 +153,21 @@ impl Writer {
         }
     }
     
+    // Erase the character before the cursor on the current line
+    pub fn backspace(&mut self) {
+        if self.column_position == 0 {
+            return;
+        }
+        self.column_position -= 1;
+        
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        let (row, col) = (self.height - 1, self.column_position);
+        self.write_char(row, col, blank);
+    }
+    
     fn write_string(&mut self, s: &str) {
         for byte in s.bytes() {
             match byte {
@@ -99,13 +179,13 @@ impl Writer {
     }
 
     fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
+        for row in 1..self.height {
+            for col in 0..self.width {
+                let character = self.read_char(row, col);
+                self.write_char(row - 1, col, character);
             }
         }
-        self.clear_row(BUFFER_HEIGHT - 1);
+        self.clear_row(self.height - 1);
         self.column_position = 0;
 
     }
@@ -114,8 +194,8 @@ impl Writer {
             ascii_character: b' ',
             color_code: self.color_code,
         };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
+        for col in 0..self.width {
+            self.write_char(row, col, blank);
         }
     }
 }
@@ -127,6 +207,24 @@ impl Write for Writer {
     }
 }
 
+/// Releases the `WRITER` lock regardless of who holds it.
+///
+/// # Safety
+/// Only for the panic path, where the holder will never run again. Any other
+/// use lets two writers race on the screen.
+pub unsafe fn force_unlock() {
+    unsafe { WRITER.force_unlock() };
+}
+
+/// Prints `s` through a fresh writer over the VGA buffer, without taking the
+/// `WRITER` lock. Output may interleave with a writer that is still active.
+pub fn emergency_print(s: &str) {
+    // Safety: the VGA buffer is always mapped. This aliases `WRITER`'s
+    // buffer, accepted here as the last way to get a message out
+    let mut writer = unsafe { Writer::from_raw(VGA_BUFFER_ADDRESS, BUFFER_WIDTH, BUFFER_HEIGHT) };
+    writer.write_string(s);
+}
+
 //--------------------------------
 
 #[macro_export]
@@ -143,8 +241,13 @@ macro_rules! println {
 
 #[doc(hidden)]
 pub fn _print(args: Arguments) {
-    use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
+    // An interrupt handler printing while we hold the lock would deadlock
+    interrupts::without_interrupts(|| {
+        WRITER.lock().write_fmt(args).unwrap();
+        
+        // Keep a copy for later review, see `klog::dump`
+        crate::klog::record(args);
+    });
 }
 
 #[test_case]
@@ -164,7 +267,52 @@ fn test_println_output() {
     let s = "Some test string that fits on a single line";
     println!("{}", s);
     for (i, c) in s.chars().enumerate() {
-        let screen_char = WRITER.lock().buffer.chars[BUFFER_HEIGHT - 2][i].read();
+        let screen_char = WRITER.lock().read_char(BUFFER_HEIGHT - 2, i);
         assert_eq!(char::from(screen_char.ascii_character), c);
     }
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_custom_dimensions_wrap() {
+    const WIDTH: usize = 30;
+    const HEIGHT: usize = 10;
+    static mut MOCK_BUFFER: [u16; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+    
+    let mut writer = unsafe {
+        Writer::from_raw(core::ptr::addr_of_mut!(MOCK_BUFFER) as usize, WIDTH, HEIGHT)
+    };
+    assert_eq!(writer.dimensions(), (WIDTH, HEIGHT));
+    
+    // 35 characters wrap after the 30th onto a new line
+    for i in 0..35 {
+        writer.write_byte(b'a' + (i % 26) as u8);
+    }
+    for col in 0..WIDTH {
+        let expected = b'a' + (col % 26) as u8;
+        assert_eq!(writer.read_char(HEIGHT - 2, col).ascii_character, expected);
+    }
+    for col in 0..5 {
+        let expected = b'a' + ((WIDTH + col) % 26) as u8;
+        assert_eq!(writer.read_char(HEIGHT - 1, col).ascii_character, expected);
+    }
+    assert_eq!(writer.read_char(HEIGHT - 1, 5).ascii_character, b' ');
+}
+
+#[test_case]
+fn test_writer_over_memory_buffer() {
+    static mut BUFFER: [ScreenChar; BUFFER_WIDTH * BUFFER_HEIGHT] =
+        [ScreenChar::BLANK; BUFFER_WIDTH * BUFFER_HEIGHT];
+    
+    let mut writer = Writer::new_with_buffer(unsafe { &mut *core::ptr::addr_of_mut!(BUFFER) });
+    write!(writer, "hello").unwrap();
+    
+    let mut row = [0u8; BUFFER_WIDTH];
+    assert_eq!(writer.read_row(BUFFER_HEIGHT - 1, &mut row), "hello");
+    
+    // A new line scrolls the text up a row
+    writeln!(writer).unwrap();
+    write!(writer, "world").unwrap();
+    assert_eq!(writer.read_row(BUFFER_HEIGHT - 2, &mut row), "hello");
+    assert_eq!(writer.read_row(BUFFER_HEIGHT - 1, &mut row), "world");
+    assert_eq!(writer.read_row(0, &mut row), "");
+}
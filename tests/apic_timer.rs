@@ -0,0 +1,52 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::{apic, cpu, time};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_apic_timer_ticks() {
+    if !cpu::features().apic {
+        // The PIT stays the tick source
+        assert!(!apic::timer_active());
+        return;
+    }
+    assert!(apic::timer_active());
+    
+    // The PIT is masked, so only the APIC timer can advance the tick count
+    // Start on a tick boundary, then time three more
+    let first = time::ticks();
+    while time::ticks() == first {
+        x86_64::instructions::hlt();
+    }
+    let start = time::ticks();
+    let start_ns = time::monotonic_ns();
+    let deadline = start_ns + 1_000_000_000;
+    while time::ticks() < start + 3 && time::monotonic_ns() < deadline {
+        x86_64::instructions::hlt();
+    }
+    assert!(time::ticks() >= start + 3, "APIC timer stopped ticking");
+    
+    // Calibrated to the PIT's rate, not firing as fast as it can
+    let elapsed_ns = time::monotonic_ns() - start_ns;
+    assert!(elapsed_ns >= 2 * time::TICK_NS, "three ticks in {} ns", elapsed_ns);
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use rust_kernel::slab_allocator::SlabAllocator;
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+// Ten slabs plus the fallback, one page each
+#[repr(align(4096))]
+struct Arena([u8; 11 * 4096]);
+static mut ARENA: Arena = Arena([0; 11 * 4096]);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("dealloc_layout_mismatch::wrong_layout_panics...\t");
+    
+    // Release builds skip the check
+    if !cfg!(debug_assertions) {
+        serial_println!("[ok] (skipped, no debug assertions)");
+        exit_qemu(QemuExitCode::Success);
+        rust_kernel::hlt_loop();
+    }
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 4096);
+        let ptr = allocator.alloc(Layout::from_size_align(16, 8).unwrap());
+        allocator.dealloc(ptr, Layout::from_size_align(512, 8).unwrap());
+    }
+    
+    serial_println!("[failed]");
+    serial_println!("Error: freeing with the wrong layout went unnoticed");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    rust_kernel::hlt_loop();
+}
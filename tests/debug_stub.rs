@@ -0,0 +1,77 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::arch::asm;
+use core::panic::PanicInfo;
+use rust_kernel::debug_stub;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_breakpoint_captures_registers() {
+    // No debugger is attached, so the breakpoint only records the registers
+    debug_stub::set_enabled(false);
+    
+    let (rsp, after): (u64, u64);
+    unsafe {
+        asm!(
+            "mov {rsp}, rsp",
+            "lea {after}, [rip + 2f]",
+            "int3",
+            "2:",
+            rsp = out(reg) rsp,
+            after = out(reg) after,
+            in("rax") 0x1111_2222_3333_4444u64,
+            in("r12") 0x5555_6666_7777_8888u64,
+            in("r15") 0xdead_beefu64,
+        );
+    }
+    
+    let regs = debug_stub::last_breakpoint().expect("breakpoint handler did not run");
+    assert_eq!(regs.rax, 0x1111_2222_3333_4444);
+    assert_eq!(regs.r12, 0x5555_6666_7777_8888);
+    assert_eq!(regs.r15, 0xdead_beef);
+    assert_eq!(regs.rsp, rsp);
+    
+    // Execution resumes right after the int3
+    assert_eq!(regs.rip, after);
+}
+
+#[test_case]
+fn test_breakpoint_continues() {
+    debug_stub::set_enabled(false);
+    
+    // The instruction after the int3 must run, and the handler must have seen the int3
+    let (ran_after, after): (u64, u64);
+    unsafe {
+        asm!(
+            "xor {ran}, {ran}",
+            "lea {after}, [rip + 2f]",
+            "int3",
+            "2:",
+            "mov {ran}, 1",
+            ran = out(reg) ran_after,
+            after = out(reg) after,
+        );
+    }
+    
+    assert_eq!(ran_after, 1);
+    assert_eq!(debug_stub::last_breakpoint().map(|regs| regs.rip), Some(after));
+}
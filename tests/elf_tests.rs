@@ -0,0 +1,220 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::elf;
+use rust_kernel::memory::{self, AddressSpace};
+use rust_kernel::fs::fat32::{self, MemoryDisk};
+use rust_kernel::fs::{Fat32FileSystem, FileSystem};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+// Unused, canonical user-half address for the test program
+const LOAD_ADDRESS: u64 = 0x_7777_0000_0000;
+// ELF header (64 bytes) plus one program header (56 bytes)
+const CODE_OFFSET: u64 = 64 + 56;
+
+// A PT_LOAD segment for `elf_with_segments`
+struct Segment {
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+}
+
+// An executable with the given PT_LOAD segments, followed by `contents`
+fn elf_with_segments(machine: u16, entry: u64, segments: &[Segment], contents: &[u8]) -> Vec<u8> {
+    let mut image = Vec::new();
+    
+    // ELF header
+    image.extend_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1, 0]);
+    image.extend_from_slice(&[0; 8]);
+    image.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    image.extend_from_slice(&machine.to_le_bytes());
+    image.extend_from_slice(&1u32.to_le_bytes()); // version
+    image.extend_from_slice(&entry.to_le_bytes());
+    image.extend_from_slice(&64u64.to_le_bytes()); // phoff
+    image.extend_from_slice(&0u64.to_le_bytes()); // shoff
+    image.extend_from_slice(&0u32.to_le_bytes()); // flags
+    image.extend_from_slice(&64u16.to_le_bytes()); // ehsize
+    image.extend_from_slice(&56u16.to_le_bytes()); // phentsize
+    image.extend_from_slice(&(segments.len() as u16).to_le_bytes()); // phnum
+    image.extend_from_slice(&[0; 6]); // no section headers
+    
+    for segment in segments {
+        image.extend_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+        image.extend_from_slice(&segment.flags.to_le_bytes());
+        image.extend_from_slice(&segment.offset.to_le_bytes());
+        image.extend_from_slice(&segment.vaddr.to_le_bytes());
+        image.extend_from_slice(&segment.vaddr.to_le_bytes()); // paddr
+        image.extend_from_slice(&segment.filesz.to_le_bytes());
+        image.extend_from_slice(&segment.memsz.to_le_bytes());
+        image.extend_from_slice(&4096u64.to_le_bytes()); // align
+    }
+    
+    image.extend_from_slice(contents);
+    image
+}
+
+// A minimal executable: one read-execute PT_LOAD segment holding `jmp $`
+fn tiny_elf(machine: u16) -> Vec<u8> {
+    // The whole file, with some .bss
+    let file_size = CODE_OFFSET + 2;
+    let text = Segment { flags: 5, offset: 0, vaddr: LOAD_ADDRESS, filesz: file_size, memsz: file_size + 64 };
+    elf_with_segments(machine, LOAD_ADDRESS + CODE_OFFSET, &[text], &[0xEB, 0xFE])
+}
+
+// Reads a `T` at `addr` in a task's address space
+fn read_in<T: Copy>(space: &AddressSpace, addr: u64) -> T {
+    let phys = space.translate_addr(VirtAddr::new(addr)).expect("address not mapped");
+    unsafe { memory::read_phys(phys) }
+}
+
+fn filesystem_with(name: &str, contents: &[u8]) -> Fat32FileSystem<MemoryDisk> {
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let mut handle = fs.create(name).expect("create failed");
+    fs.write(&mut handle, contents).expect("write failed");
+    fs.close(handle).expect("close failed");
+    fs
+}
+
+#[test_case]
+fn test_load_sets_entry_point() {
+    let mut fs = filesystem_with("HELLO.ELF", &tiny_elf(0x3E));
+    
+    let task = elf::load_task(&mut fs, "HELLO.ELF", "hello").expect("load failed");
+    assert_eq!(task.context.rip, LOAD_ADDRESS + CODE_OFFSET);
+    
+    // The segment's contents were copied to its virtual address in the task's
+    // own address space, and the .bss after them is zeroed
+    let space = task.address_space.as_ref().expect("no address space");
+    assert_eq!(read_in::<[u8; 2]>(space, LOAD_ADDRESS + CODE_OFFSET), [0xEB, 0xFE]);
+    assert_eq!(read_in::<[u8; 64]>(space, LOAD_ADDRESS + CODE_OFFSET + 2), [0; 64]);
+}
+
+#[test_case]
+fn test_load_maps_user_pages_in_own_address_space() {
+    use x86_64::structures::paging::{Page, PageTableFlags, Translate};
+    
+    let mut fs = filesystem_with("USER.ELF", &tiny_elf(0x3E));
+    let task = elf::load_task(&mut fs, "USER.ELF", "user").expect("load failed");
+    let space = task.address_space.as_ref().expect("no address space");
+    assert_eq!(task.context.cr3, space.pml4_frame().start_address().as_u64());
+    
+    let page = Page::containing_address(VirtAddr::new(LOAD_ADDRESS));
+    let (_, flags) = space.translate_page(page).expect("segment not mapped");
+    assert!(flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert!(!flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PageTableFlags::NO_EXECUTE));
+    
+    // Nothing of the program shows up in the kernel's page tables
+    let mapper = memory::MAPPER.lock();
+    assert!(mapper.as_ref().unwrap().translate_addr(VirtAddr::new(LOAD_ADDRESS)).is_none());
+}
+
+#[test_case]
+fn test_rejects_other_architectures() {
+    // EM_AARCH64
+    let mut fs = filesystem_with("ARM.ELF", &tiny_elf(0xB7));
+    assert_eq!(elf::load_task(&mut fs, "ARM.ELF", "arm").err(), Some("Not an x86-64 executable"));
+    
+    let image = tiny_elf(0x3E);
+    let mut shared_object = image.clone();
+    shared_object[16] = 3; // ET_DYN
+    assert_eq!(elf::ElfFile::parse(&shared_object).err(), Some("Not an executable ELF file"));
+    assert!(elf::ElfFile::parse(&image[..32]).is_err());
+}
+
+#[test_case]
+fn test_rejects_overflowing_offsets() {
+    // A program header table that wraps around the end of the address space
+    let mut image = tiny_elf(0x3E);
+    image[32..40].copy_from_slice(&(u64::MAX - 8).to_le_bytes()); // phoff
+    assert_eq!(elf::ElfFile::parse(&image).err(), Some("Program headers past the end of the file"));
+    
+    // A segment whose end wraps around
+    let top = Segment { flags: 6, offset: 0, vaddr: 0xFFFF_FFFF_FFFF_F000, filesz: 0, memsz: 0x2000 };
+    let image = elf_with_segments(0x3E, LOAD_ADDRESS, &[top], &[]);
+    let mut fs = filesystem_with("WRAP.ELF", &image);
+    assert_eq!(elf::load_task(&mut fs, "WRAP.ELF", "wrap").err(), Some("Invalid segment address"));
+}
+
+#[test_case]
+fn test_adjacent_segments_share_a_page() {
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    
+    // Code then data straight after it, both on the first page
+    let code_offset = 64 + 2 * 56;
+    let data_offset = code_offset + 2;
+    let contents = [0xEB, 0xFE, 0xDE, 0xAD, 0xBE, 0xEF];
+    let text = Segment { flags: 5, offset: 0, vaddr: LOAD_ADDRESS, filesz: data_offset, memsz: data_offset };
+    let data = Segment { flags: 6, offset: data_offset, vaddr: LOAD_ADDRESS + data_offset, filesz: 4, memsz: 16 };
+    let image = elf_with_segments(0x3E, LOAD_ADDRESS + code_offset, &[text, data], &contents);
+    let mut fs = filesystem_with("SHARED.ELF", &image);
+    
+    let task = elf::load_task(&mut fs, "SHARED.ELF", "shared").expect("load failed");
+    let space = task.address_space.as_ref().expect("no address space");
+    assert_eq!(read_in::<[u8; 6]>(space, LOAD_ADDRESS + code_offset), contents);
+    
+    // The page allows what both segments need
+    let (_, flags) = space.translate_page(Page::containing_address(VirtAddr::new(LOAD_ADDRESS))).unwrap();
+    assert!(flags.contains(PageTableFlags::WRITABLE) && !flags.contains(PageTableFlags::NO_EXECUTE));
+    
+    // Segments that overlap, rather than just share a page, are refused
+    let text = Segment { flags: 5, offset: 0, vaddr: LOAD_ADDRESS, filesz: data_offset, memsz: data_offset };
+    let data = Segment { flags: 6, offset: data_offset, vaddr: LOAD_ADDRESS + data_offset - 1, filesz: 4, memsz: 16 };
+    let image = elf_with_segments(0x3E, LOAD_ADDRESS + code_offset, &[text, data], &contents);
+    let mut fs = filesystem_with("OVERLAP.ELF", &image);
+    assert_eq!(elf::load_task(&mut fs, "OVERLAP.ELF", "overlap").err(), Some("Segments overlap"));
+}
+
+#[test_case]
+fn test_failed_load_frees_every_frame() {
+    use x86_64::structures::paging::Page;
+    
+    // The second segment lands on the kernel heap, which the program's
+    // address space shares, after the first one was mapped
+    let heap = rust_kernel::slab_allocator::HEAP_START as u64;
+    let code_offset = 64 + 2 * 56;
+    let text = Segment { flags: 5, offset: 0, vaddr: LOAD_ADDRESS, filesz: code_offset + 2, memsz: code_offset + 2 };
+    let clash = Segment { flags: 6, offset: 0, vaddr: heap, filesz: 0, memsz: 16 };
+    let image = elf_with_segments(0x3E, LOAD_ADDRESS + code_offset, &[text, clash], &[0xEB, 0xFE]);
+    let elf = elf::ElfFile::parse(&image).expect("parse failed");
+    
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    let allocated = frame_allocator.allocated_count();
+    
+    let mut space = AddressSpace::new(frame_allocator).expect("address space");
+    assert_eq!(elf.load(&mut space, frame_allocator).err(), Some("Address is shared with the kernel"));
+    assert!(space.translate_page(Page::containing_address(VirtAddr::new(LOAD_ADDRESS))).is_none());
+    
+    // The first segment's frame and page tables came back, then the level 4 table
+    space.free(frame_allocator);
+    assert_eq!(frame_allocator.allocated_count(), allocated);
+}
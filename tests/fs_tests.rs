@@ -7,9 +7,12 @@
 extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
-use rust_kernel::{println, fs::{FileSystem, Fat32FileSystem}};
+use rust_kernel::{println, task, fs::{FileSystem, Fat32FileSystem, FsError, SharedFileSystem}};
+use rust_kernel::fs::fat32::MemoryDisk;
 use core::panic::PanicInfo;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
 
 entry_point!(main);
 
@@ -41,6 +44,61 @@ fn create_test_disk() -> impl rust_kernel::fs::fat32::Disk {
     disk
 }
 
+// Place a file in the root directory of a freshly formatted disk by hand,
+// laying its data out in consecutive clusters starting at `first_cluster`
+fn add_test_file(disk: &mut impl rust_kernel::fs::fat32::Disk, name: &[u8; 11], first_cluster: u32, contents: &[u8]) {
+    let mut boot = [0u8; 512];
+    disk.read_sector(0, &mut boot).expect("boot sector read failed");
+    let sectors_per_cluster = boot[13] as u32;
+    let reserved = u16::from_le_bytes([boot[14], boot[15]]) as u32;
+    let fat_count = boot[16] as u32;
+    let fat_size = u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]]);
+    let root_cluster = u32::from_le_bytes([boot[44], boot[45], boot[46], boot[47]]);
+    let data_start = reserved + fat_count * fat_size;
+    let cluster_to_sector = |cluster: u32| data_start + (cluster - 2) * sectors_per_cluster;
+    
+    // Write the data and chain the clusters in every FAT copy
+    let cluster_size = (sectors_per_cluster * 512) as usize;
+    let cluster_count = contents.len().div_ceil(cluster_size).max(1) as u32;
+    for i in 0..cluster_count {
+        let cluster = first_cluster + i;
+        for s in 0..sectors_per_cluster {
+            let mut sector = [0u8; 512];
+            let offset = i as usize * cluster_size + s as usize * 512;
+            if offset < contents.len() {
+                let end = (offset + 512).min(contents.len());
+                sector[..end - offset].copy_from_slice(&contents[offset..end]);
+            }
+            disk.write_sector(cluster_to_sector(cluster) + s, &sector).expect("data write failed");
+        }
+        
+        let next = if i + 1 == cluster_count { 0x0FFFFFFF } else { cluster + 1 };
+        for fat in 0..fat_count {
+            let fat_sector = reserved + fat * fat_size + cluster * 4 / 512;
+            let entry = (cluster * 4 % 512) as usize;
+            let mut sector = [0u8; 512];
+            disk.read_sector(fat_sector, &mut sector).expect("FAT read failed");
+            sector[entry..entry + 4].copy_from_slice(&next.to_le_bytes());
+            disk.write_sector(fat_sector, &sector).expect("FAT write failed");
+        }
+    }
+    
+    // Add the directory entry in the first free slot of the root directory
+    let root_sector = cluster_to_sector(root_cluster);
+    let mut sector = [0u8; 512];
+    disk.read_sector(root_sector, &mut sector).expect("root directory read failed");
+    let slot = (0..16).find(|&i| sector[i * 32] == 0x00 || sector[i * 32] == 0xE5)
+        .expect("root directory full");
+    let entry = &mut sector[slot * 32..slot * 32 + 32];
+    entry.fill(0);
+    entry[..11].copy_from_slice(name);
+    entry[11] = 0x20; // Archive
+    entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+    entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&(contents.len() as u32).to_le_bytes());
+    disk.write_sector(root_sector, &sector).expect("root directory write failed");
+}
+
 #[test_case]
 fn test_filesystem_init() {
     println!("Testing filesystem initialization");
@@ -48,11 +106,873 @@ fn test_filesystem_init() {
     let disk = create_test_disk();
     let mut fs = Fat32FileSystem::new(disk);
     
-    // This will likely fail with a real error message since we haven't 
-    // formatted the disk, but it should run without panicking
-    match fs.init() {
-        Ok(_) => println!("Filesystem initialized successfully"),
-        Err(e) => println!("Filesystem initialization failed as expected: {}", e),
+    // The disk was never formatted
+    assert_eq!(fs.init(), Err(FsError::Io("Not a FAT32 volume")));
+}
+
+#[test_case]
+fn test_boot_sector_signature_is_checked() {
+    use rust_kernel::fs::fat32::{self, Disk, FatBootSector};
+    
+    // An all-zeros disk has no boot signature
+    let mut disk = MemoryDisk::new(512, 64);
+    let mut fs = Fat32FileSystem::new(MemoryDisk::new(512, 64));
+    assert_eq!(fs.init(), Err(FsError::Io("Not a FAT32 volume")));
+    
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut sector = [0u8; 512];
+    disk.read_sector(0, &mut sector).expect("boot sector read failed");
+    assert!(FatBootSector::from_slice(&sector).is_ok());
+    
+    // A signed sector of another filesystem type
+    let mut fat16 = sector;
+    fat16[82..90].copy_from_slice(b"FAT16   ");
+    assert!(FatBootSector::from_slice(&fat16).is_err());
+    
+    assert!(FatBootSector::from_slice(&sector[..100]).is_err());
+}
+
+#[test_case]
+fn test_directory_entry_from_bytes() {
+    use rust_kernel::fs::fat32::{self, DirectoryEntry};
+    
+    let mut bytes = [0u8; 32];
+    bytes[..11].copy_from_slice(b"HELLO   TXT");
+    bytes[11] = 0x20; // Archive
+    bytes[20..22].copy_from_slice(&0x0001u16.to_le_bytes());
+    bytes[26..28].copy_from_slice(&0x0203u16.to_le_bytes());
+    bytes[28..32].copy_from_slice(&1234u32.to_le_bytes());
+    
+    let entry: DirectoryEntry = fat32::from_bytes(&bytes).expect("decode failed");
+    assert_eq!(entry.get_name(), "HELLO.TXT");
+    assert!(entry.is_file());
+    assert_eq!(entry.get_first_cluster(), 0x0001_0203);
+    assert_eq!(entry.get_file_size(), 1234);
+    assert_eq!(fat32::as_bytes(&entry), &bytes[..]);
+    
+    assert!(fat32::from_bytes::<DirectoryEntry>(&bytes[..31]).is_none());
+}
+
+#[test_case]
+fn test_sector_arithmetic_overflow_is_an_error() {
+    use rust_kernel::fs::fat32::{self, Disk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut boot = [0u8; 512];
+    disk.read_sector(0, &mut boot).expect("boot sector read failed");
+    
+    // A boot sector claiming 2^32 - 1 sectors has clusters whose FAT entry
+    // offset (cluster * 4) no longer fits in 32 bits
+    let mut huge = boot;
+    huge[32..36].copy_from_slice(&u32::MAX.to_le_bytes());
+    disk.write_sector(0, &huge).expect("boot sector write failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    assert_eq!(fs.fat_entry(0x4000_0000), Err("Cluster's FAT entry offset overflows"));
+    
+    // Two FATs of 2^31 sectors each don't fit before the data region
+    let mut disk = MemoryDisk::new(512, 64);
+    let mut bad_fat_size = boot;
+    bad_fat_size[36..40].copy_from_slice(&0x8000_0000u32.to_le_bytes());
+    disk.write_sector(0, &bad_fat_size).expect("boot sector write failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    assert_eq!(fs.init(), Err(FsError::Io("FAT size overflows the volume")));
+}
+
+// A FAT12/16 boot sector: fixed root directory region, 16-bit FAT size and
+// the type string at offset 54
+fn fat16_style_boot_sector(total_sectors: u32, sectors_per_cluster: u8, sectors_per_fat: u16) -> [u8; 512] {
+    let mut sector = [0u8; 512];
+    sector[11..13].copy_from_slice(&512u16.to_le_bytes());
+    sector[13] = sectors_per_cluster;
+    sector[14..16].copy_from_slice(&1u16.to_le_bytes()); // Reserved sectors
+    sector[16] = 2; // FATs
+    sector[17..19].copy_from_slice(&512u16.to_le_bytes()); // Root entries
+    sector[22..24].copy_from_slice(&sectors_per_fat.to_le_bytes());
+    sector[32..36].copy_from_slice(&total_sectors.to_le_bytes());
+    sector[54..62].copy_from_slice(b"FAT16   ");
+    sector[510] = 0x55;
+    sector[511] = 0xAA;
+    sector
+}
+
+#[test_case]
+fn test_fat16_volume_is_rejected() {
+    use rust_kernel::fs::fat32::{Disk, FatBootSector, FatType};
+    
+    // About 50,000 clusters: FAT16 by the spec's thresholds
+    let fat16 = fat16_style_boot_sector(200_000, 4, 200);
+    let mut disk = MemoryDisk::new(512, 64);
+    disk.write_sector(0, &fat16).expect("boot sector write failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    assert_eq!(fs.init(), Err(FsError::Io("FAT12/16 not supported")));
+    
+    // A 1.44 MB floppy layout is FAT12
+    let fat12 = fat16_style_boot_sector(2880, 1, 9);
+    let boot_sector = unsafe { core::ptr::read_unaligned(fat12.as_ptr() as *const FatBootSector) };
+    assert_eq!(boot_sector.fat_type(), FatType::Fat12);
+    assert_eq!(FatBootSector::from_slice(&fat12).err(), Some("FAT12/16 not supported"));
+}
+
+#[test_case]
+fn test_memory_disk_partial_read() {
+    use rust_kernel::fs::fat32::{Disk, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 4);
+    let mut sector = [0u8; 512];
+    for (i, byte) in sector.iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    disk.write_sector(1, &sector).expect("full sector write failed");
+    
+    // A short buffer receives only the first part of the sector
+    let mut half = [0u8; 256];
+    disk.read_sector(1, &mut half).expect("partial read failed");
+    assert_eq!(&half[..], &sector[..256]);
+}
+
+#[test_case]
+fn test_memory_disk_partial_write() {
+    use rust_kernel::fs::fat32::{Disk, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 4);
+    disk.write_sector(2, &[0xAAu8; 256]).expect("partial write failed");
+    
+    // Only the first 256 bytes of the sector change
+    let mut sector = [0u8; 512];
+    disk.read_sector(2, &mut sector).expect("full sector read failed");
+    assert!(sector[..256].iter().all(|&b| b == 0xAA));
+    assert!(sector[256..].iter().all(|&b| b == 0));
+}
+
+#[test_case]
+fn test_memory_disk_out_of_bounds() {
+    use rust_kernel::fs::fat32::{Disk, MemoryDisk};
+    
+    let disk = MemoryDisk::new(512, 4);
+    let mut buffer = [0u8; 256];
+    assert!(disk.read_sector(4, &mut buffer).is_err());
+}
+
+// ATA registers that never report an error, logging every register write
+struct MockAtaBus {
+    writes: alloc::rc::Rc<core::cell::RefCell<Vec<(u16, u8)>>>,
+}
+
+impl rust_kernel::fs::disk::AtaBus for MockAtaBus {
+    fn read_register(&mut self, _offset: u16) -> u8 {
+        0x58 // RDY | DSC | DRQ
+    }
+    
+    fn write_register(&mut self, offset: u16, value: u8) {
+        self.writes.borrow_mut().push((offset, value));
+    }
+    
+    fn read_data(&mut self) -> u16 {
+        0
+    }
+    
+    fn write_data(&mut self, _value: u16) {}
+}
+
+#[test_case]
+fn test_ata_lba48_read() {
+    use rust_kernel::fs::disk::AtaPioDisk;
+    
+    let writes = alloc::rc::Rc::new(core::cell::RefCell::new(Vec::new()));
+    let disk = AtaPioDisk::with_bus(MockAtaBus { writes: writes.clone() }, true);
+    let mut buffer = [0u8; 512];
+    
+    // Sectors inside the 28-bit range keep using READ SECTORS
+    writes.borrow_mut().clear();
+    disk.read_sectors_lba(0x0FFF_FFFF, 1, &mut buffer).expect("28-bit read failed");
+    assert_eq!(writes.borrow().first(), Some(&(6, 0xEF)));
+    assert_eq!(writes.borrow().last(), Some(&(7, 0x20)));
+    
+    // High bytes then low bytes through each FIFO register, then READ SECTORS EXT
+    writes.borrow_mut().clear();
+    disk.read_sectors_lba(0x1_0000_0000, 1, &mut buffer).expect("48-bit read failed");
+    assert_eq!(*writes.borrow(), [
+        (6, 0x40),
+        (2, 0), (3, 0x00), (4, 0x01), (5, 0x00),
+        (2, 1), (3, 0x00), (4, 0x00), (5, 0x00),
+        (7, 0x24),
+    ]);
+    
+    assert!(disk.read_sectors_lba(1 << 48, 1, &mut buffer).is_err());
+}
+
+#[test_case]
+fn test_ata_multi_sector_read() {
+    use rust_kernel::fs::disk::AtaPioDisk;
+    use rust_kernel::fs::fat32::DiskIO;
+    
+    let writes = alloc::rc::Rc::new(core::cell::RefCell::new(Vec::new()));
+    let disk = AtaPioDisk::with_bus(MockAtaBus { writes: writes.clone() }, true);
+    let mut buffer = alloc::vec![0u8; 300 * 512];
+    
+    // One command with the sector count register set to 16
+    writes.borrow_mut().clear();
+    disk.read_sectors(100, 16, &mut buffer).expect("read failed");
+    assert_eq!(writes.borrow().iter().filter(|&&w| w == (7, 0x20)).count(), 1);
+    assert!(writes.borrow().contains(&(2, 16)));
+    
+    // 300 sectors take a full 256-sector command (count 0) and one of 44
+    writes.borrow_mut().clear();
+    disk.read_sectors(100, 300, &mut buffer).expect("read failed");
+    let counts: Vec<u8> = writes.borrow().iter().filter(|w| w.0 == 2).map(|w| w.1).collect();
+    assert_eq!(counts, [0, 44]);
+    assert_eq!(writes.borrow().iter().filter(|&&w| w == (7, 0x20)).count(), 2);
+}
+
+#[test_case]
+fn test_disk_flush() {
+    use rust_kernel::fs::disk::{self, AtaPioDisk};
+    use rust_kernel::fs::fat32::DiskIO;
+    
+    // Memory disks have no cache, flushing leaves them as they were
+    let disk = disk::MemoryDisk::new(4, 512);
+    disk.write_sectors(1, 1, &[0x5Au8; 512]).expect("write failed");
+    DiskIO::flush(&disk).expect("flush failed");
+    let mut sector = [0u8; 512];
+    disk.read_sectors(1, 1, &mut sector).expect("read failed");
+    assert!(sector.iter().all(|&b| b == 0x5A));
+    
+    // The ATA driver sends CACHE FLUSH
+    let writes = alloc::rc::Rc::new(core::cell::RefCell::new(Vec::new()));
+    let disk = AtaPioDisk::with_bus(MockAtaBus { writes: writes.clone() }, true);
+    writes.borrow_mut().clear();
+    DiskIO::flush(&disk).expect("flush failed");
+    assert_eq!(*writes.borrow(), [(7, 0xE7)]);
+}
+
+#[test_case]
+fn test_ram_disk_from_image() {
+    use rust_kernel::fs::disk::RamDisk;
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    // Build a formatted image and hand it to the RAM disk as a static slice
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"HELLO   TXT", 4, b"Hello from a RAM disk");
+    let image: &'static [u8] = disk.into_bytes().leak();
+    
+    let mut fs = Fat32FileSystem::new(RamDisk::from_static(image, 512));
+    fs.init().expect("init failed");
+    
+    let mut handle = fs.open("HELLO.TXT").expect("open failed");
+    let mut buffer = [0u8; 64];
+    let n = fs.read(&mut handle, &mut buffer).expect("read failed");
+    assert_eq!(&buffer[..n], b"Hello from a RAM disk");
+    fs.close(handle).expect("close failed");
+}
+
+#[test_case]
+fn test_ram_disk_copy_on_write() {
+    use rust_kernel::fs::disk::RamDisk;
+    use rust_kernel::fs::fat32::DiskIO;
+    
+    let image: &'static [u8] = &[0u8; 2048];
+    
+    // Read-only disks reject writes
+    let read_only = RamDisk::from_static(image, 512);
+    assert!(read_only.write_sectors(0, 1, &[1u8; 512]).is_err());
+    
+    // Copy-on-write disks copy the image on the first write, leaving it untouched
+    let disk = RamDisk::from_static_cow(image, 512);
+    assert!(!disk.is_copied());
+    disk.write_sectors(1, 1, &[0x5Au8; 512]).expect("write failed");
+    assert!(disk.is_copied());
+    
+    let mut buffer = [0u8; 512];
+    disk.read_sectors(1, 1, &mut buffer).expect("read failed");
+    assert!(buffer.iter().all(|&b| b == 0x5A));
+    assert!(image.iter().all(|&b| b == 0));
+}
+
+// Read a raw FAT entry straight from the disk
+fn fat_entry(disk: &impl rust_kernel::fs::fat32::Disk, fat: u32, cluster: u32) -> u32 {
+    let mut boot = [0u8; 512];
+    disk.read_sector(0, &mut boot).expect("boot sector read failed");
+    let reserved = u16::from_le_bytes([boot[14], boot[15]]) as u32;
+    let fat_size = u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]]);
+    
+    let mut sector = [0u8; 512];
+    disk.read_sector(reserved + fat * fat_size + cluster * 4 / 512, &mut sector)
+        .expect("FAT read failed");
+    let offset = (cluster * 4 % 512) as usize;
+    u32::from_le_bytes([sector[offset], sector[offset + 1], sector[offset + 2], sector[offset + 3]])
+}
+
+#[test_case]
+fn test_allocate_and_link_clusters() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let a = fs.allocate_cluster().expect("allocation failed");
+    let b = fs.allocate_cluster().expect("allocation failed");
+    let c = fs.allocate_cluster().expect("allocation failed");
+    
+    // Clusters 2 and 3 hold the root directory
+    assert!(a >= 4 && b >= 4 && c >= 4);
+    assert!(a != b && b != c && a != c);
+    
+    fs.link_clusters(a, b).expect("link failed");
+    fs.link_clusters(b, c).expect("link failed");
+    
+    // Both FAT copies hold the chain a -> b -> c -> end
+    for fat in 0..2 {
+        assert_eq!(fat_entry(fs.disk(), fat, a), b);
+        assert_eq!(fat_entry(fs.disk(), fat, b), c);
+        assert_eq!(fat_entry(fs.disk(), fat, c), 0x0FFFFFF8);
+    }
+}
+
+#[test_case]
+fn test_flush_writes_through_before_close() {
+    use rust_kernel::fs::fat32::{self, Disk, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"NOTES   TXT", 4, b"old contents");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let mut handle = fs.open("NOTES.TXT").expect("open failed");
+    fs.write(&mut handle, b"new contents, longer").expect("write failed");
+    fs.flush(&mut handle).expect("flush failed");
+    
+    // The file's cluster and directory entry are on disk while it is still open
+    let mut boot = [0u8; 512];
+    fs.disk().read_sector(0, &mut boot).expect("boot sector read failed");
+    let reserved = u16::from_le_bytes([boot[14], boot[15]]) as u32;
+    let fat_size = u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]]);
+    let data_start = reserved + 2 * fat_size;
+    
+    let mut sector = [0u8; 512];
+    fs.disk().read_sector(data_start + 2, &mut sector).expect("data read failed");
+    assert_eq!(&sector[..20], b"new contents, longer");
+    
+    fs.disk().read_sector(data_start, &mut sector).expect("root directory read failed");
+    assert_eq!(&sector[..11], b"NOTES   TXT");
+    assert_eq!(u32::from_le_bytes([sector[28], sector[29], sector[30], sector[31]]), 20);
+    
+    fs.close(handle).expect("close failed");
+}
+
+#[test_case]
+fn test_write_extends_directory_entry_size() {
+    use rust_kernel::fs::fat32;
+    
+    // 5000 bytes span ten 512-byte clusters
+    let contents: Vec<u8> = (0..5000u32).map(|i| (i % 241) as u8).collect();
+    
+    let mut disk = MemoryDisk::new(512, 128);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let mut handle = fs.create("GROW.BIN").expect("create failed");
+    assert_eq!(handle.size, 0);
+    assert_eq!(fs.write(&mut handle, &contents), Ok(contents.len()));
+    fs.close(handle).expect("close failed");
+    
+    // The size comes back from the directory entry
+    let mut handle = fs.open("GROW.BIN").expect("reopen failed");
+    assert_eq!(handle.size, 5000);
+    let mut buffer = alloc::vec![0u8; 6000];
+    let n = fs.read_all(&mut handle, &mut buffer).expect("read_all failed");
+    assert_eq!(&buffer[..n], &contents[..]);
+    fs.close(handle).expect("close failed");
+}
+
+#[test_case]
+fn test_cached_disk_flush() {
+    use rust_kernel::fs::cache::CachedDisk;
+    use rust_kernel::fs::fat32::{Disk, MemoryDisk};
+    
+    let mut disk = CachedDisk::with_capacity(MemoryDisk::new(512, 8), 512, 2);
+    disk.write_sector(3, &[0x11u8; 512]).expect("write failed");
+    
+    // The write is only in the cache until flushed
+    let mut sector = [0u8; 512];
+    disk.inner().read_sector(3, &mut sector).expect("read failed");
+    assert!(sector.iter().all(|&b| b == 0));
+    disk.read_sector(3, &mut sector).expect("read failed");
+    assert!(sector.iter().all(|&b| b == 0x11));
+    
+    disk.flush().expect("flush failed");
+    assert_eq!(disk.dirty_count(), 0);
+    disk.inner().read_sector(3, &mut sector).expect("read failed");
+    assert!(sector.iter().all(|&b| b == 0x11));
+}
+
+#[test_case]
+fn test_paranoid_cache_detects_changed_sector() {
+    use rust_kernel::fs::cache::CachedDisk;
+    use rust_kernel::fs::fat32::{Disk, MemoryDisk};
+    
+    let mut disk = CachedDisk::with_capacity(MemoryDisk::new(512, 8), 512, 2);
+    disk.write_sector(3, &[0x11u8; 512]).expect("write failed");
+    disk.flush().expect("flush failed");
+    
+    // Corrupt the sector without the cache knowing
+    disk.inner_mut().write_sector(3, &[0xEEu8; 512]).expect("write failed");
+    
+    // By default the stale cached copy is served
+    let mut sector = [0u8; 512];
+    disk.read_sector(3, &mut sector).expect("read failed");
+    assert!(sector.iter().all(|&b| b == 0x11));
+    
+    disk.set_paranoid(true);
+    assert_eq!(disk.read_sector(3, &mut sector), Err("Cached sector does not match the disk"));
+    
+    // Dirty lines differ from the disk on purpose
+    disk.write_sector(3, &[0x22u8; 512]).expect("write failed");
+    disk.read_sector(3, &mut sector).expect("read failed");
+    assert!(sector.iter().all(|&b| b == 0x22));
+}
+
+#[test_case]
+fn test_read_all_across_clusters() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    // 1200 bytes span three 512-byte clusters
+    let contents: Vec<u8> = (0..1200u32).map(|i| (i % 251) as u8).collect();
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"BIG     BIN", 4, &contents);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let mut handle = fs.open("BIG.BIN").expect("open failed");
+    let mut buffer = alloc::vec![0u8; 1500];
+    let n = fs.read_all(&mut handle, &mut buffer).expect("read_all failed");
+    
+    assert_eq!(n, contents.len());
+    assert_eq!(&buffer[..n], &contents[..]);
+    fs.close(handle).expect("close failed");
+}
+
+#[test_case]
+fn test_create_preserves_lowercase() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let handle = fs.create("readme.txt").expect("create failed");
+    fs.close(handle).expect("close failed");
+    
+    let entry = fs.lookup("README.TXT").expect("lookup failed").expect("file missing");
+    assert_eq!(entry.get_name(), "readme.txt");
+    
+    // Mixed case falls back to the uppercase 8.3 name
+    let handle = fs.create("MyFile.txt").expect("create failed");
+    fs.close(handle).expect("close failed");
+    let entry = fs.lookup("myfile.txt").expect("lookup failed").expect("file missing");
+    assert_eq!(entry.get_name(), "MYFILE.txt");
+}
+
+#[test_case]
+fn test_tmpfs_nested_directories() {
+    use rust_kernel::fs::TmpFs;
+    
+    let mut fs = TmpFs::new();
+    fs.init().expect("init failed");
+    
+    fs.mkdir("/docs").expect("mkdir failed");
+    fs.mkdir("/docs/notes").expect("nested mkdir failed");
+    assert!(fs.mkdir("/missing/dir").is_err());
+    
+    let mut handle = fs.create("/docs/notes/todo.txt").expect("create failed");
+    fs.write(&mut handle, b"write more tests").expect("write failed");
+    fs.close(handle).expect("close failed");
+    let handle = fs.create("/top.txt").expect("create failed");
+    fs.close(handle).expect("close failed");
+    
+    let mut handle = fs.open("/docs/notes/todo.txt").expect("open failed");
+    let mut buffer = [0u8; 32];
+    let n = fs.read_all(&mut handle, &mut buffer).expect("read failed");
+    assert_eq!(&buffer[..n], b"write more tests");
+    fs.close(handle).expect("close failed");
+    
+    assert_eq!(fs.readdir("/").expect("readdir failed"), ["docs", "top.txt"]);
+    assert_eq!(fs.readdir("/docs").expect("readdir failed"), ["notes"]);
+    assert_eq!(fs.readdir("/docs/notes").expect("readdir failed"), ["todo.txt"]);
+    assert!(fs.open("/docs").is_err());
+}
+
+#[test_case]
+fn test_non_default_cluster_size() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    // Four sectors per cluster: 2048-byte clusters, the root directory takes two
+    let contents: Vec<u8> = (0..5000u32).map(|i| (i % 253) as u8).collect();
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 4).expect("format failed");
+    add_test_file(&mut disk, b"DATA    BIN", 4, &contents);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let mut handle = fs.open("DATA.BIN").expect("open failed");
+    let mut buffer = alloc::vec![0u8; 6000];
+    let n = fs.read_all(&mut handle, &mut buffer).expect("read_all failed");
+    assert_eq!(&buffer[..n], &contents[..]);
+    fs.close(handle).expect("close failed");
+    
+    // Files written through the filesystem use the same geometry
+    let written: Vec<u8> = (0..3000u32).map(|i| (i % 7) as u8).collect();
+    let mut handle = fs.create("NEW.BIN").expect("create failed");
+    fs.write(&mut handle, &written).expect("write failed");
+    fs.close(handle).expect("close failed");
+    
+    let mut handle = fs.open("NEW.BIN").expect("open failed");
+    let n = fs.read_all(&mut handle, &mut buffer).expect("read_all failed");
+    assert_eq!(&buffer[..n], &written[..]);
+    fs.close(handle).expect("close failed");
+}
+
+#[test_case]
+fn test_fs_info_matches_fat_scan() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let info = fs.read_fs_info().expect("no FsInfo");
+    assert_eq!(info.free_count, fs.count_free_clusters().unwrap());
+    assert_eq!(fs.free_cluster_count().unwrap(), info.free_count);
+    
+    // Allocating and freeing keep the on-disk count in step with the FAT
+    let first = fs.allocate_cluster().expect("allocate failed");
+    let second = fs.allocate_cluster().expect("allocate failed");
+    fs.link_clusters(first, second).expect("link failed");
+    assert_eq!(fs.read_fs_info().unwrap().free_count, info.free_count - 2);
+    assert_eq!(fs.count_free_clusters().unwrap(), info.free_count - 2);
+    
+    fs.free_chain(first).expect("free failed");
+    assert_eq!(fs.read_fs_info().unwrap().free_count, info.free_count);
+    assert_eq!(fs.free_cluster_count().unwrap(), fs.count_free_clusters().unwrap());
+}
+
+#[test_case]
+fn test_check_crosslinks_finds_shared_cluster() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"FIRST   TXT", 4, &[b'a'; 1024]);
+    add_test_file(&mut disk, b"SECOND  TXT", 6, &[b'b'; 1024]);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    assert_eq!(fs.fat_entry(4).unwrap(), 5);
+    assert_eq!(fs.chain_length(4).unwrap(), 2);
+    assert!(fs.fat_entry(1).is_err());
+    assert!(fs.check_crosslinks().unwrap().is_empty());
+    
+    // Point the end of FIRST's chain into SECOND's, so cluster 6 onwards is shared
+    fs.link_clusters(5, 6).expect("link failed");
+    assert_eq!(fs.chain_length(4).unwrap(), 4);
+    assert_eq!(fs.check_crosslinks().unwrap(), [6, 7]);
+}
+
+#[test_case]
+fn test_reserved_clusters_are_rejected() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    // Clusters 0 and 1 are reserved and must not be read as data
+    assert!(fs.get_next_cluster(0).is_err());
+    assert!(fs.get_next_cluster(1).is_err());
+    assert!(fs.get_next_cluster(64).is_err());
+    
+    // The root directory is a valid chain of two clusters
+    assert_eq!(fs.get_next_cluster(2), Ok(3));
+}
+
+#[test_case]
+fn test_read_to_vec() {
+    use rust_kernel::fs::fat32::{self, MemoryDisk};
+    
+    let contents: Vec<u8> = (0..1200u32).map(|i| (i % 253) as u8).collect();
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"CONFIG  TXT", 4, &contents);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let data = fs.read_to_vec("CONFIG.TXT").expect("read_to_vec failed");
+    assert_eq!(data, contents);
+    assert!(fs.read_to_vec("MISSING.TXT").is_err());
+}
+
+#[test_case]
+fn test_errors_say_what_went_wrong() {
+    use rust_kernel::fs::fat32;
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    let handle = fs.create("FILE.TXT").expect("create failed");
+    fs.close(handle).expect("close failed");
+    
+    assert_eq!(fs.open("MISSING.TXT").err(), Some(FsError::NotFound));
+    assert_eq!(fs.open("FILE.TXT/INNER.TXT").err(), Some(FsError::NotADirectory));
+    assert_eq!(fs.create("FILE.TXT").err(), Some(FsError::AlreadyExists));
+    assert_eq!(fs.close(handle), Err(FsError::InvalidHandle));
+}
+
+#[test_case]
+fn test_read_hole_returns_zeros() {
+    use rust_kernel::fs::fat32;
+    
+    // Three 512-byte clusters, 4 -> 5 -> 6
+    let contents: Vec<u8> = (0..1536u32).map(|i| (i % 251) as u8 + 1).collect();
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"SPARSE  BIN", 4, &contents);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    // Cluster 4 still points at 5, which is now free
+    fs.free_chain(5).expect("free_chain failed");
+    
+    let mut handle = fs.open("SPARSE.BIN").expect("open failed");
+    let mut buffer = alloc::vec![0xFFu8; 2048];
+    let n = fs.read_all(&mut handle, &mut buffer).expect("read_all failed");
+    
+    assert_eq!(n, contents.len());
+    assert_eq!(&buffer[..512], &contents[..512]);
+    assert!(buffer[512..n].iter().all(|&b| b == 0));
+    fs.close(handle).expect("close failed");
+}
+
+#[test_case]
+fn test_directory_entry_timestamps() {
+    use rust_kernel::fs::fat32::{self, Disk};
+    use rust_kernel::time::DateTime;
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"STAMPED TXT", 4, b"hello");
+    
+    // Stamp the entry: created 1999-12-31 23:59:59, modified 2024-02-29 12:34:56
+    let mut boot = [0u8; 512];
+    disk.read_sector(0, &mut boot).expect("boot sector read failed");
+    let reserved = u16::from_le_bytes([boot[14], boot[15]]) as u32;
+    let fat_size = u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]]);
+    let root_sector = reserved + boot[16] as u32 * fat_size;
+    let mut sector = [0u8; 512];
+    disk.read_sector(root_sector, &mut sector).expect("root directory read failed");
+    let slot = (0..16).find(|&i| &sector[i * 32..i * 32 + 11] == b"STAMPED TXT")
+        .expect("entry missing");
+    let entry = &mut sector[slot * 32..slot * 32 + 32];
+    entry[13] = 100;
+    entry[14..16].copy_from_slice(&((23u16 << 11) | (59 << 5) | 29).to_le_bytes());
+    entry[16..18].copy_from_slice(&((19u16 << 9) | (12 << 5) | 31).to_le_bytes());
+    entry[22..24].copy_from_slice(&((12u16 << 11) | (34 << 5) | 28).to_le_bytes());
+    entry[24..26].copy_from_slice(&((44u16 << 9) | (2 << 5) | 29).to_le_bytes());
+    disk.write_sector(root_sector, &sector).expect("root directory write failed");
+    
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    let entry = fs.lookup("STAMPED.TXT").expect("lookup failed").expect("file missing");
+    
+    let created = DateTime { year: 1999, month: 12, day: 31, hour: 23, minute: 59, second: 59 };
+    let modified = DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 34, second: 56 };
+    assert_eq!(entry.created(), Some(created));
+    assert_eq!(entry.modified(), Some(modified));
+    
+    // Files created by this driver carry no timestamps yet
+    let handle = fs.create("NEW.TXT").expect("create failed");
+    fs.close(handle).expect("close failed");
+    let entry = fs.lookup("NEW.TXT").expect("lookup failed").expect("file missing");
+    assert_eq!(entry.modified(), None);
+}
+
+#[test_case]
+fn test_dir_iter_counts_entries() {
+    use rust_kernel::fs::fat32;
+    
+    // One sector per cluster holds 16 entries, so 20 files span two clusters
+    let mut disk = MemoryDisk::new(512, 128);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    for i in 0..20 {
+        let handle = fs.create(&alloc::format!("FILE{}.TXT", i)).expect("create failed");
+        fs.close(handle).expect("close failed");
     }
+    
+    let count = fs.dir_iter("/").expect("dir_iter failed")
+        .map(|entry| entry.expect("directory read failed"))
+        .filter(|entry| entry.is_file())
+        .count();
+    assert_eq!(count, 20);
+    
+    // Stops at the first match without reading the rest of the directory
+    let found = fs.dir_iter("/").expect("dir_iter failed")
+        .map(|entry| entry.expect("directory read failed"))
+        .find(|entry| entry.get_name() == "FILE3.TXT");
+    assert!(found.is_some());
+    
+    assert_eq!(fs.dir_iter("FILE0.TXT").err(), Some(FsError::NotADirectory));
 }
 
+#[test_case]
+fn test_volume_label_is_not_a_file() {
+    use rust_kernel::fs::fat32::{self, Disk};
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    
+    // A volume label named like the file after it, in the first root slot
+    add_test_file(&mut disk, b"README  TXT", 3, b"");
+    let mut boot = [0u8; 512];
+    disk.read_sector(0, &mut boot).expect("boot sector read failed");
+    let reserved = u16::from_le_bytes([boot[14], boot[15]]) as u32;
+    let fat_size = u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]]);
+    let root_sector = reserved + boot[16] as u32 * fat_size; // Root directory is cluster 2
+    let mut sector = [0u8; 512];
+    disk.read_sector(root_sector, &mut sector).expect("root directory read failed");
+    sector[11] = 0x08; // Volume ID
+    sector[28..32].fill(0);
+    disk.write_sector(root_sector, &sector).expect("root directory write failed");
+    add_test_file(&mut disk, b"README  TXT", 4, b"hello");
+    
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    assert_eq!(fs.readdir("/").expect("readdir failed"), ["README.TXT"]);
+    let entry = fs.lookup("README.TXT").expect("lookup failed").expect("file missing");
+    assert!(entry.is_file() && !entry.is_volume_label());
+    assert_eq!(entry.get_file_size(), 5);
+}
+
+#[test_case]
+fn test_buffered_file_reads_lines() {
+    use rust_kernel::fs::{fat32, BufferedFile};
+    use alloc::string::String;
+    use core::fmt::Write;
+    
+    // Lines cross the 512-byte cluster boundaries, the last has no newline
+    let mut text = String::new();
+    for i in 0..60 {
+        writeln!(text, "line {} of the test file", i).unwrap();
+    }
+    text.push_str("no newline");
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"LINES   TXT", 4, text.as_bytes());
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let handle = fs.open("LINES.TXT").expect("open failed");
+    let mut file = BufferedFile::new(&fs, handle, fs.cluster_size());
+    assert_eq!(file.read_byte(), Ok(Some(b'l')));
+    
+    let mut lines = Vec::new();
+    let mut line = String::from("l");
+    while file.read_line(&mut line).expect("read_line failed") > 0 {
+        lines.push(line.clone());
+        line.clear();
+    }
+    
+    assert_eq!(lines, text.split_inclusive('\n').collect::<Vec<_>>());
+    assert_eq!(file.read_byte(), Ok(None));
+    
+    let handle = file.into_handle();
+    fs.close(handle).expect("close failed");
+    
+    // Bytes that aren't UTF-8 fail the line as an I/O error
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"BINARY  BIN", 4, &[0xFF, 0xFE, b'\n']);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    
+    let handle = fs.open("BINARY.BIN").expect("open failed");
+    let mut file = BufferedFile::new(&fs, handle, fs.cluster_size());
+    let mut line = String::new();
+    assert_eq!(file.read_line(&mut line), Err(FsError::Io("Line is not valid UTF-8")));
+}
+
+// Filesystem shared by the reader tasks, and what each of them read
+static SHARED_FS: Mutex<Option<SharedFileSystem<Fat32FileSystem<MemoryDisk>>>> = Mutex::new(None);
+static READ_A: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static READ_B: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+static DONE_A: AtomicBool = AtomicBool::new(false);
+static DONE_B: AtomicBool = AtomicBool::new(false);
+
+// Read `path` in small chunks, yielding between them so the tasks interleave
+fn read_in_chunks(path: &str, out: &Mutex<Vec<u8>>) {
+    let mut fs = SHARED_FS.lock().as_ref().unwrap().clone();
+    let mut handle = fs.open(path).expect("open failed");
+    let mut chunk = [0u8; 100];
+    loop {
+        let n = fs.read(&mut handle, &mut chunk).expect("read failed");
+        if n == 0 {
+            break;
+        }
+        out.lock().extend_from_slice(&chunk[..n]);
+        task::yield_task();
+    }
+    fs.close(handle).expect("close failed");
+}
+
+fn reader_a() -> ! {
+    read_in_chunks("A.TXT", &READ_A);
+    DONE_A.store(true, Ordering::SeqCst);
+    task::exit()
+}
+
+fn reader_b() -> ! {
+    read_in_chunks("B.TXT", &READ_B);
+    DONE_B.store(true, Ordering::SeqCst);
+    task::exit()
+}
+
+#[test_case]
+fn test_shared_filesystem_concurrent_reads() {
+    use rust_kernel::fs::fat32;
+    
+    let contents_a: Vec<u8> = (0..1200u32).map(|i| (i % 251) as u8).collect();
+    let contents_b: Vec<u8> = (0..1200u32).map(|i| (i % 13) as u8 ^ 0xA5).collect();
+    
+    let mut disk = MemoryDisk::new(512, 64);
+    fat32::format_with_cluster_size(&mut disk, 1).expect("format failed");
+    add_test_file(&mut disk, b"A       TXT", 4, &contents_a);
+    add_test_file(&mut disk, b"B       TXT", 8, &contents_b);
+    let mut fs = Fat32FileSystem::new(disk);
+    fs.init().expect("init failed");
+    *SHARED_FS.lock() = Some(SharedFileSystem::new(fs));
+    
+    task::spawn("reader_a", reader_a);
+    task::spawn("reader_b", reader_b);
+    
+    task::yield_until(|| DONE_A.load(Ordering::SeqCst) && DONE_B.load(Ordering::SeqCst), 1_000_000_000);
+    
+    assert_eq!(*READ_A.lock(), contents_a);
+    assert_eq!(*READ_B.lock(), contents_b);
+}
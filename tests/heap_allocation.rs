@@ -8,7 +8,8 @@ extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use rust_kernel::{println, memory, slab_allocator};
+use rust_kernel::{println, memory, slab_allocator, task};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use x86_64::VirtAddr;
 use alloc::{boxed::Box, vec::Vec, rc::Rc};
 use rust_kernel::memory::frame_allocator::BootInfoFrameAllocator;
@@ -129,4 +130,247 @@ fn test_reference_counting() {
     
     drop(rc_val);
     assert_eq!(Rc::strong_count(&rc_clone), 1);
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_try_alloc_reports_full_slab() {
+    use core::alloc::Layout;
+    use slab_allocator::{AllocError, SlabAllocator};
+    
+    // Ten slabs plus the fallback share the arena, so each slab gets 64 bytes
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 64]);
+    static mut ARENA: Arena = Arena([0; 11 * 64]);
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 64);
+    }
+    
+    // The 8-byte slab holds eight blocks
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    for _ in 0..8 {
+        assert!(allocator.try_alloc(layout).is_ok());
+    }
+    assert_eq!(allocator.try_alloc(layout), Err(AllocError::SlabFull { block_size: 8 }));
+    
+    let empty = Layout::from_size_align(0, 1).unwrap();
+    assert_eq!(allocator.try_alloc(empty), Err(AllocError::UnsupportedSize { size: 0 }));
+}
+
+#[test_case]
+fn test_largest_free_block_shrinks() {
+    use core::alloc::{GlobalAlloc, Layout};
+    use slab_allocator::SlabAllocator;
+    
+    // Each of the ten slabs and the fallback get one page
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 4096]);
+    static mut ARENA: Arena = Arena([0; 11 * 4096]);
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 4096);
+    }
+    assert_eq!(allocator.largest_free_block(), 4096);
+    
+    // The first page-sized block empties the 4096 slab, the second the fallback
+    let layout = Layout::from_size_align(4096, 1).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let second = unsafe { allocator.alloc(layout) };
+    assert!(!first.is_null() && !second.is_null());
+    
+    // Only the 2048-byte slab is left to serve large requests
+    assert_eq!(allocator.largest_free_block(), 2048);
+}
+
+#[test_case]
+fn test_over_aligned_allocation_uses_fallback() {
+    use core::alloc::{GlobalAlloc, Layout};
+    use slab_allocator::SlabAllocator;
+    
+    // Each slab and the fallback get 12 KiB, so the fallback region starts
+    // halfway between two 16 KiB boundaries and has to pad the allocation
+    #[repr(align(16384))]
+    struct Arena([u8; 11 * 12288]);
+    static mut ARENA: Arena = Arena([0; 11 * 12288]);
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 12288);
+    }
+    let free_before = allocator.largest_free_block();
+    
+    // Alignment above the largest slab class must bypass the slabs
+    let layout = Layout::from_size_align(100, 16384).unwrap();
+    let ptr = unsafe { allocator.alloc(layout) };
+    assert!(!ptr.is_null());
+    assert_eq!(ptr as usize % 16384, 0);
+    assert!(allocator.largest_free_block() < free_before);
+    
+    // Freeing hands the whole block back to the fallback allocator
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert_eq!(allocator.largest_free_block(), free_before);
+}
+
+#[test_case]
+fn test_slab_blocks_are_size_aligned() {
+    use core::alloc::Layout;
+    
+    // The slab regions of the kernel heap don't start on block boundaries
+    for &align in &[8usize, 64, 512, 4096] {
+        let layout = Layout::from_size_align(align, align).unwrap();
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % align, 0);
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
+    }
+}
+
+#[test_case]
+fn test_validate_after_mixed_use() {
+    let mut kept = Vec::new();
+    for i in 0..64usize {
+        let boxed = Box::new([i as u8; 24]);
+        let vec: Vec<u64> = (0..i as u64).collect();
+        // Free every other box right away so the free lists interleave
+        if i % 2 == 0 {
+            kept.push(boxed);
+        }
+        drop(vec);
+    }
+    drop(kept);
+    
+    assert_eq!(slab_allocator::validate_heap(), Ok(()));
+}
+
+#[test_case]
+fn test_validate_catches_corrupt_free_list() {
+    use core::alloc::{GlobalAlloc, Layout};
+    use slab_allocator::SlabAllocator;
+    
+    // Each of the ten slabs and the fallback get one page
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 4096]);
+    static mut ARENA: Arena = Arena([0; 11 * 4096]);
+    
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 4096);
+    }
+    
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let first = unsafe { allocator.alloc(layout) };
+    let second = unsafe { allocator.alloc(layout) };
+    unsafe {
+        allocator.dealloc(first, layout);
+        allocator.dealloc(second, layout);
+    }
+    assert_eq!(allocator.validate(), Ok(()));
+    
+    // The free list is now second -> first; point second's next into the middle of a block
+    let next = second as *mut usize;
+    unsafe { next.write(first as usize + 8) };
+    assert_eq!(allocator.validate(), Err("Free block not aligned to the block size"));
+    
+    // A block linking to itself is a cycle
+    unsafe { next.write(second as usize) };
+    assert_eq!(allocator.validate(), Err("Free list longer than the slab (cycle?)"));
+}
+
+#[test_case]
+fn test_slabs_disabled_uses_fallback() {
+    use core::alloc::{GlobalAlloc, Layout};
+    use slab_allocator::SlabAllocator;
+    
+    // Ten slabs of one page each, then the fallback's page
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 4096]);
+    static mut ARENA: Arena = Arena([0; 11 * 4096]);
+    
+    let start = unsafe { core::ptr::addr_of_mut!(ARENA.0) as usize };
+    let allocator = SlabAllocator::new();
+    unsafe {
+        allocator.init(start, 11 * 4096);
+    }
+    let fallback_start = start + 10 * 4096;
+    
+    let layout = Layout::from_size_align(8, 8).unwrap();
+    slab_allocator::set_slab_enabled(false);
+    let ptr = unsafe { allocator.alloc(layout) };
+    slab_allocator::set_slab_enabled(true);
+    
+    // A small allocation that would fit the 8-byte slab lands past every slab region
+    assert!(!ptr.is_null());
+    assert!(ptr as usize >= fallback_start);
+    
+    // Freeing goes by address, so it returns to the fallback with slabs back on
+    unsafe { allocator.dealloc(ptr, layout) };
+    assert!((unsafe { allocator.alloc(layout) } as usize) < fallback_start);
+}
+
+#[test_case]
+fn test_pool_reuses_freed_slot() {
+    use rust_kernel::pool::Pool;
+    
+    let pool: Pool<u64> = Pool::new(4);
+    let mut boxes: Vec<_> = (0..4u64).map(|i| pool.alloc(i).expect("pool alloc failed")).collect();
+    assert_eq!(pool.available(), 0);
+    assert!(pool.alloc(4).is_err());
+    
+    // Freeing one slot makes room for exactly one more
+    let freed = boxes.remove(1);
+    let freed_addr = &*freed as *const u64;
+    drop(freed);
+    assert_eq!(pool.available(), 1);
+    
+    let reused = pool.alloc(42).expect("pool alloc after free failed");
+    assert_eq!(&*reused as *const u64, freed_addr);
+    assert_eq!(*reused, 42);
+    assert!(pool.alloc(5).is_err());
+    
+    // The other objects were left alone
+    assert_eq!(boxes.iter().map(|b| **b).collect::<Vec<_>>(), [0, 2, 3]);
+}
+
+// Allocator shared by the two allocating tasks, over its own arena so nothing
+// else shows up in its counters
+static STATS_ALLOCATOR: slab_allocator::SlabAllocator = slab_allocator::SlabAllocator::new();
+static STATS_TASKS_DONE: AtomicUsize = AtomicUsize::new(0);
+const STATS_ROUNDS: usize = 100;
+
+fn allocating_task() -> ! {
+    use core::alloc::{GlobalAlloc, Layout};
+    
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    for _ in 0..STATS_ROUNDS {
+        let ptr = STATS_ALLOCATOR.try_alloc(layout).expect("allocation failed");
+        task::yield_task();
+        unsafe { STATS_ALLOCATOR.dealloc(ptr.as_ptr(), layout) };
+    }
+    STATS_TASKS_DONE.fetch_add(1, Ordering::SeqCst);
+    task::exit()
+}
+
+#[test_case]
+fn test_alloc_stats_count_concurrent_tasks() {
+    #[repr(align(4096))]
+    struct Arena([u8; 11 * 4096]);
+    static mut ARENA: Arena = Arena([0; 11 * 4096]);
+    unsafe {
+        STATS_ALLOCATOR.init(core::ptr::addr_of_mut!(ARENA.0) as usize, 11 * 4096);
+    }
+    
+    task::spawn("alloc_a", allocating_task);
+    task::spawn("alloc_b", allocating_task);
+    
+    task::yield_until(|| STATS_TASKS_DONE.load(Ordering::SeqCst) == 2, 1_000_000_000);
+    
+    let stats = STATS_ALLOCATOR.stats();
+    assert_eq!(stats.alloc_count, 2 * STATS_ROUNDS);
+    assert_eq!(stats.dealloc_count, 2 * STATS_ROUNDS);
+    assert!(stats.slab_used.iter().all(|&used| used == 0));
+    
+    // The kernel heap's counters can be read at any time
+    assert!(slab_allocator::alloc_stats().alloc_count > 0);
+}
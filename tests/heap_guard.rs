@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rust_kernel::panic::StackBuffer;
+use rust_kernel::slab_allocator;
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_guard::overrun_faults...\t");
+    rust_kernel::init(boot_info);
+    
+    // The first byte past the heap is in its guard page
+    let (start, size) = slab_allocator::heap_region();
+    unsafe {
+        core::ptr::write_volatile((start + size) as *mut u8, 0xAA);
+    }
+    
+    serial_println!("[failed]");
+    serial_println!("Error: writing past the heap didn't fault");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = StackBuffer::<256>::new();
+    let _ = write!(message, "{}", info.message());
+    
+    if message.as_str().starts_with("heap overrun") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: {}", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_kernel::hlt_loop();
+}
@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::slab_allocator;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+// Well away from the default HEAP_START
+const HEAP_START: u64 = 0x_5555_5555_0000;
+const HEAP_SIZE: usize = 256 * 1024;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init_with_heap_at(boot_info, VirtAddr::new(HEAP_START), HEAP_SIZE);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+fn in_heap(ptr: *const u8) -> bool {
+    let start = HEAP_START as usize;
+    (start..start + HEAP_SIZE).contains(&(ptr as usize))
+}
+
+#[test_case]
+fn test_heap_region_is_runtime_value() {
+    assert_eq!(slab_allocator::heap_region(), (HEAP_START as usize, HEAP_SIZE));
+}
+
+#[test_case]
+fn test_allocations_land_in_custom_heap() {
+    // Small blocks come from the slabs, large ones from the fallback
+    let small = Box::new(7u64);
+    let large: Vec<u8> = Vec::with_capacity(8192);
+    
+    assert!(in_heap(&*small as *const u64 as *const u8));
+    assert!(in_heap(large.as_ptr()));
+}
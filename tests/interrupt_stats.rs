@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::Ordering;
+use rust_kernel::{debug_stub, interrupts};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+fn breakpoint_count() -> u64 {
+    interrupts::counts()[usize::from(interrupts::BREAKPOINT_VECTOR)].load(Ordering::SeqCst)
+}
+
+#[test_case]
+fn test_breakpoints_are_counted() {
+    // No debugger is attached, so each breakpoint just continues
+    debug_stub::set_enabled(false);
+    
+    let before = breakpoint_count();
+    for _ in 0..5 {
+        x86_64::instructions::interrupts::int3();
+    }
+    assert_eq!(breakpoint_count() - before, 5);
+}
@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use rust_kernel::{klog, println};
+use rust_kernel::panic::StackBuffer;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_klog_reads_back_in_order() {
+    let start = klog::latest_seq();
+    
+    for i in 0..10 {
+        println!("klog line {}", i);
+    }
+    
+    let lines = klog::since(start);
+    assert_eq!(lines.len(), 10);
+    for (i, line) in lines.iter().enumerate() {
+        let mut expected = StackBuffer::<32>::new();
+        write!(expected, "klog line {}", i).unwrap();
+        assert_eq!(line.text(), expected.as_str());
+        assert_eq!(line.seq, start + 1 + i as u64);
+    }
+}
+
+#[test_case]
+fn test_klog_keeps_most_recent() {
+    for i in 0..klog::KLOG_ENTRIES + 5 {
+        println!("wrap {}", i);
+    }
+    
+    // Only the newest lines survive once the buffer wraps
+    let lines = klog::since(0);
+    assert_eq!(lines.len(), klog::KLOG_ENTRIES);
+    assert_eq!(lines.last().unwrap().seq, klog::latest_seq());
+    let mut expected = StackBuffer::<32>::new();
+    write!(expected, "wrap {}", klog::KLOG_ENTRIES + 4).unwrap();
+    assert_eq!(lines.last().unwrap().text(), expected.as_str());
+}
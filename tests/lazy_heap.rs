@@ -0,0 +1,78 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::memory::FRAME_ALLOCATOR;
+use rust_kernel::slab_allocator::HEAP_SIZE;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initialize the kernel with a demand-paged heap
+    rust_kernel::init_lazy_heap(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+fn allocated_frames() -> usize {
+    FRAME_ALLOCATOR.lock().as_ref().unwrap().allocated_count()
+}
+
+#[test_case]
+fn test_lazy_heap_allocations() {
+    let value = Box::new(41);
+    assert_eq!(*value + 1, 42);
+    
+    let mut numbers = Vec::new();
+    for i in 0..100 {
+        numbers.push(i);
+    }
+    assert_eq!(numbers.iter().sum::<u64>(), 4950);
+}
+
+#[test_case]
+fn test_lazy_heap_uses_fewer_frames() {
+    // The eager path maps one frame per heap page up front
+    let eager_frames = HEAP_SIZE / 4096;
+    
+    let before = allocated_frames();
+    let value = Box::new([0u8; 64]);
+    assert_eq!(value[63], 0);
+    let after = allocated_frames();
+    
+    // Touching one small block maps at most a page (plus page tables)
+    assert!(after - before <= 4);
+    assert!(after < eager_frames);
+}
+
+#[test_case]
+fn test_reserve_with_mapper_held() {
+    use rust_kernel::memory::{self, MAPPER};
+    
+    // Reserving must not touch the heap, whose next page would have to be
+    // mapped through the mapper held here
+    let mut mapper = MAPPER.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let mut starts = [x86_64::VirtAddr::zero(); 64];
+    for start in starts.iter_mut() {
+        *start = memory::reserve(mapper, 1).expect("reserve failed");
+    }
+    for &start in starts.iter() {
+        memory::release(start);
+    }
+}
@@ -9,13 +9,20 @@ extern crate alloc;
 use bootloader::{entry_point, BootInfo};
 use rust_kernel::{println, memory};
 use core::panic::PanicInfo;
+use rust_kernel::memory::frame_allocator::BitmapFrameAllocator;
 use x86_64::VirtAddr;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use spin::Once;
 
 entry_point!(main);
 
+// Boot information shared with the test cases
+static BOOT_INFO: Once<&'static BootInfo> = Once::new();
+
 fn main(boot_info: &'static BootInfo) -> ! {
     // Initialize the kernel
     rust_kernel::init(boot_info);
+    BOOT_INFO.call_once(|| boot_info);
     
     println!("Running memory tests...");
     test_main();
@@ -28,6 +35,16 @@ fn panic(info: &PanicInfo) -> ! {
     rust_kernel::test_panic_handler(info)
 }
 
+#[test_case]
+fn test_read_phys_matches_vga_buffer() {
+    use x86_64::PhysAddr;
+    
+    // The VGA text buffer is identity mapped as well as in the physical memory mapping
+    let expected = unsafe { core::ptr::read_volatile(0xb8000 as *const u16) };
+    let word: u16 = unsafe { memory::read_phys(PhysAddr::new(0xb8000)) };
+    assert_eq!(word, expected);
+}
+
 #[test_case]
 fn test_virt_to_phys() {
     // Test the virtual to physical address translation
@@ -64,4 +81,615 @@ fn test_slab_allocator() {
     
     // Print allocator status for debugging
     slab_allocator::print_heap_status();
-}
\ No newline at end of file
+}
+
+#[test_case]
+fn test_frame_allocator_counts() {
+    use rust_kernel::memory::frame_allocator::BootInfoFrameAllocator;
+    use x86_64::structures::paging::FrameAllocator;
+    
+    let boot_info = BOOT_INFO.r#try().expect("boot info not set");
+    let mut allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let free_before = allocator.free_count();
+    
+    for _ in 0..10 {
+        assert!(allocator.allocate_frame().is_some());
+    }
+    
+    assert_eq!(allocator.allocated_count(), 10);
+    assert_eq!(allocator.free_count(), free_before - 10);
+}
+
+#[test_case]
+fn test_frame_allocator_ordering() {
+    use rust_kernel::memory::frame_allocator::BootInfoFrameAllocator;
+    use x86_64::structures::paging::FrameAllocator;
+    
+    let boot_info = BOOT_INFO.r#try().expect("boot info not set");
+    let mut allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    
+    // Frames come out in ascending address order, so they are also distinct
+    let mut previous = None;
+    for _ in 0..1000 {
+        let frame = allocator.allocate_frame().expect("out of frames");
+        let addr = frame.start_address().as_u64();
+        assert_eq!(addr % 4096, 0);
+        if let Some(previous) = previous {
+            assert!(addr > previous, "frame {:#x} not after {:#x}", addr, previous);
+        }
+        previous = Some(addr);
+    }
+}
+
+#[test_case]
+fn test_reserved_ranges_are_never_allocated() {
+    use rust_kernel::memory::frame_allocator::{BitmapFrameAllocator, BootInfoFrameAllocator};
+    use x86_64::structures::paging::FrameAllocator;
+    use x86_64::PhysAddr;
+    
+    let boot_info = BOOT_INFO.r#try().expect("boot info not set");
+    let mut allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let first = allocator.allocate_frame().expect("out of frames").start_address().as_u64();
+    
+    // Overlapping, unaligned reservations covering frames 4-39 after the first
+    let reserved = first + 4 * 4096..first + 40 * 4096;
+    allocator.reserve_range(PhysAddr::new(first + 4 * 4096 + 100), PhysAddr::new(first + 24 * 4096));
+    allocator.reserve_range(PhysAddr::new(first + 16 * 4096), PhysAddr::new(first + 40 * 4096 - 1));
+    allocator.reserve_range(PhysAddr::new(first + 4 * 4096), PhysAddr::new(first + 8 * 4096));
+    for _ in 0..1000 {
+        let addr = allocator.allocate_frame().expect("out of frames").start_address().as_u64();
+        assert!(!reserved.contains(&addr), "reserved frame {:#x} returned", addr);
+    }
+    
+    let mut bitmap_allocator = unsafe { BitmapFrameAllocator::with_heap_bitmap(0x10_0000, 64) };
+    let start = 0x10_0000 * 4096u64;
+    bitmap_allocator.reserve_range(PhysAddr::new(start + 8 * 4096), PhysAddr::new(start + 20 * 4096));
+    bitmap_allocator.reserve_range(PhysAddr::new(start + 12 * 4096), PhysAddr::new(start + 32 * 4096));
+    let mut count = 0;
+    while let Some(frame) = bitmap_allocator.allocate_frame() {
+        let addr = frame.start_address().as_u64();
+        assert!(!(start + 8 * 4096..start + 32 * 4096).contains(&addr), "reserved frame {:#x} returned", addr);
+        count += 1;
+    }
+    assert_eq!(count, 64 - 24);
+}
+
+#[test_case]
+fn test_bitmap_allocator_skips_reserved() {
+    use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+    use rust_kernel::memory::frame_allocator::BitmapFrameAllocator;
+    use x86_64::structures::paging::FrameAllocator;
+    
+    // Frames 0-3 usable, 4-7 reserved, 8-15 usable
+    let mut memory_map = MemoryMap::new();
+    memory_map.add_region(MemoryRegion {
+        range: FrameRange::new(0x0000, 0x4000),
+        region_type: MemoryRegionType::Usable,
+    });
+    memory_map.add_region(MemoryRegion {
+        range: FrameRange::new(0x4000, 0x8000),
+        region_type: MemoryRegionType::Reserved,
+    });
+    memory_map.add_region(MemoryRegion {
+        range: FrameRange::new(0x8000, 0x10000),
+        region_type: MemoryRegionType::Usable,
+    });
+    
+    let bitmap = alloc::boxed::Box::leak(alloc::vec![0u8; 2].into_boxed_slice());
+    let mut allocator = unsafe { BitmapFrameAllocator::from_memory_map(bitmap, &memory_map) };
+    
+    let mut count = 0;
+    while let Some(frame) = allocator.allocate_frame() {
+        let frame_number = frame.start_address().as_u64() / 4096;
+        assert!(!(4..8).contains(&frame_number), "reserved frame {} returned", frame_number);
+        count += 1;
+    }
+    assert_eq!(count, 12);
+}
+
+#[test_case]
+fn test_bitmap_allocator_with_heap_bitmap() {
+    use rust_kernel::memory::frame_allocator::BitmapFrameAllocator;
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+    use x86_64::PhysAddr;
+    
+    // Frames past physical memory, only handed out and never used
+    let start = 0x10_0000;
+    let mut allocator = unsafe { BitmapFrameAllocator::with_heap_bitmap(start, 1024) };
+    for i in 0..1024 {
+        let frame = allocator.allocate_frame().expect("out of frames");
+        assert_eq!(frame.start_address().as_u64(), (start + i) as u64 * 4096);
+    }
+    assert!(allocator.allocate_frame().is_none());
+    
+    let frame = PhysFrame::containing_address(PhysAddr::new((start as u64 + 7) * 4096));
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.allocate_frame(), Some(frame));
+}
+
+// Translate a virtual address through the kernel's installed mapper
+fn translate(addr: VirtAddr) -> Option<x86_64::PhysAddr> {
+    use x86_64::structures::paging::Translate;
+    
+    let mapper = memory::MAPPER.lock();
+    mapper.as_ref().expect("mapper not installed").translate_addr(addr)
+}
+
+#[test_case]
+fn test_copy_on_write() {
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    
+    let page: Page = Page::containing_address(VirtAddr::new(0x_5555_0000_0000));
+    let ptr: *mut u8 = page.start_address().as_mut_ptr();
+    
+    // Map a fresh writable page and fill it with a pattern
+    {
+        let mut mapper = memory::MAPPER.lock();
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        memory::map_range(
+            mapper.as_mut().unwrap(),
+            frame_allocator.as_mut().unwrap(),
+            Page::range_inclusive(page, page),
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+        ).expect("map failed");
+    }
+    unsafe { core::ptr::write_bytes(ptr, 0xAA, 4096) };
+    let old_frame = translate(page.start_address()).expect("page not mapped");
+    
+    memory::make_cow(memory::MAPPER.lock().as_mut().unwrap(), page).expect("make_cow failed");
+    
+    // Writing through the read-only mapping makes the fault handler copy the page
+    unsafe { ptr.write_volatile(0x55) };
+    let new_frame = translate(page.start_address()).expect("page not mapped");
+    
+    assert_ne!(old_frame, new_frame);
+    assert_eq!(unsafe { ptr.read_volatile() }, 0x55);
+    assert_eq!(unsafe { ptr.add(1).read_volatile() }, 0xAA);
+    
+    // The original frame still holds the old contents
+    let old_ptr: *const u8 = (memory::physical_memory_offset() + old_frame.as_u64()).as_ptr();
+    assert_eq!(unsafe { old_ptr.read_volatile() }, 0xAA);
+}
+
+#[test_case]
+fn test_map_device_sets_no_cache() {
+    use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame};
+    use x86_64::PhysAddr;
+    
+    // Map the VGA text buffer as device memory at an unused address
+    let page: Page = Page::containing_address(VirtAddr::new(0x_5555_0001_0000));
+    let frame = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    memory::map_device(mapper, frame_allocator.as_mut().unwrap(), page, frame)
+        .expect("map_device failed");
+    
+    let (phys, flags) = memory::translate_with_flags(mapper, page.start_address())
+        .expect("page not mapped");
+    assert_eq!(phys, frame.start_address());
+    assert!(flags.contains(PageTableFlags::NO_CACHE));
+    assert!(flags.contains(PageTableFlags::WRITE_THROUGH));
+}
+
+#[test_case]
+fn test_reserve_rejects_overflowing_sizes() {
+    let mapper = memory::MAPPER.lock();
+    let mapper = mapper.as_ref().unwrap();
+    
+    // Sizes whose byte count, or end address, doesn't fit in 64 bits
+    for pages in [u64::MAX, u64::MAX / 4096, u64::MAX / 4096 - 1] {
+        assert_eq!(memory::reserve(mapper, pages).err(), Some("Reserved address space exhausted"));
+    }
+}
+
+#[test_case]
+fn test_reserve_then_commit() {
+    use x86_64::structures::paging::Translate;
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    
+    // Reserving maps nothing
+    let base = memory::reserve(mapper, 16).expect("reserve failed");
+    for i in 0..16 {
+        assert!(mapper.translate_addr(base + i * 4096u64).is_none());
+    }
+    
+    // A second reservation must not overlap the first
+    let other = memory::reserve(mapper, 4).expect("reserve failed");
+    assert!(other >= base + 16 * 4096u64 || other + 4 * 4096u64 <= base);
+    
+    // Only the committed middle pages get frames
+    memory::commit(mapper, frame_allocator.as_mut().unwrap(), base + 6 * 4096u64, 4)
+        .expect("commit failed");
+    for i in 0..16 {
+        let committed = (6..10).contains(&i);
+        assert_eq!(mapper.translate_addr(base + i * 4096u64).is_some(), committed);
+    }
+}
+
+#[test_case]
+fn test_mmap_round_trip() {
+    use x86_64::structures::paging::{PageTableFlags, Translate};
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    
+    let memory = memory::mmap(mapper, frame_allocator, 2, PageTableFlags::empty()).expect("mmap failed");
+    assert_eq!(memory.len(), 2 * 4096);
+    let start = VirtAddr::from_ptr(memory.as_ptr());
+    
+    // A pattern straddling the page boundary
+    for (i, byte) in memory[4000..4200].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    assert!(memory[4000..4200].iter().enumerate().all(|(i, &byte)| byte == i as u8));
+    
+    memory::munmap(mapper, frame_allocator, memory).expect("munmap failed");
+    assert!(mapper.translate_addr(start).is_none());
+    assert!(mapper.translate_addr(start + 4096u64).is_none());
+    
+    // The address range is free for the next reservation
+    let again = memory::reserve(mapper, 2).expect("reserve failed");
+    assert_eq!(again, start);
+    memory::release(again);
+}
+
+#[test_case]
+fn test_memory_summary_totals() {
+    use bootloader::bootinfo::{FrameRange, MemoryMap, MemoryRegion, MemoryRegionType};
+    use rust_kernel::memory::frame_allocator::memory_summary;
+    
+    const MIB: u64 = 1024 * 1024;
+    let regions = [
+        (0, 0x1000, MemoryRegionType::FrameZero),
+        (0x1000, 0x9_F000, MemoryRegionType::Usable),
+        (0x9_F000, 0x10_0000, MemoryRegionType::Reserved),
+        (0x10_0000, 0x20_0000, MemoryRegionType::Kernel),
+        (0x20_0000, 0x21_0000, MemoryRegionType::PageTable),
+        (0x21_0000, 0x22_0000, MemoryRegionType::Bootloader),
+        (0x22_0000, 16 * MIB, MemoryRegionType::Usable),
+    ];
+    let mut memory_map = MemoryMap::new();
+    for &(start, end, region_type) in regions.iter() {
+        memory_map.add_region(MemoryRegion { range: FrameRange::new(start, end), region_type });
+    }
+    
+    let summary = memory_summary(&memory_map);
+    assert_eq!(summary.region_count, regions.len());
+    assert_eq!(summary.total, 16 * MIB);
+    assert_eq!(summary.usable, (0x9_F000 - 0x1000) + (16 * MIB - 0x22_0000));
+    assert_eq!(summary.reserved, 0x1000 + (0x10_0000 - 0x9_F000));
+    assert_eq!(summary.kernel, MIB);
+    assert_eq!(summary.bootloader, 0x2_0000);
+    assert_eq!(summary.usable + summary.reserved + summary.kernel + summary.bootloader, summary.total);
+}
+
+#[test_case]
+fn test_active_page_table_frame() {
+    let frame = memory::active_page_table_frame();
+    assert_ne!(frame.start_address().as_u64(), 0);
+    assert!(frame.start_address().is_aligned(4096u64));
+    
+    // The tests run on the kernel's own tables
+    assert_eq!(frame, memory::kernel_pml4());
+}
+
+#[test_case]
+fn test_address_spaces_are_isolated() {
+    use memory::AddressSpace;
+    use x86_64::instructions::interrupts;
+    use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+    
+    // A level 4 slot the kernel doesn't use
+    let page: Page = Page::containing_address(VirtAddr::new(0x_5000_0000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    let mut first = AddressSpace::new(frame_allocator).expect("address space");
+    let mut second = AddressSpace::new(frame_allocator).expect("address space");
+    let first_frame = frame_allocator.allocate_frame().unwrap();
+    let second_frame = frame_allocator.allocate_frame().unwrap();
+    first.map_page(page, first_frame, flags, frame_allocator).expect("map failed");
+    second.map_page(page, second_frame, flags, frame_allocator).expect("map failed");
+    
+    // Same virtual address, different frames, invisible to the kernel
+    assert_eq!(first.translate_addr(page.start_address()), Some(first_frame.start_address()));
+    assert_eq!(second.translate_addr(page.start_address()), Some(second_frame.start_address()));
+    assert!(translate(page.start_address()).is_none());
+    
+    // Kernel-shared addresses can't be remapped privately
+    let heap_page = Page::containing_address(VirtAddr::new(rust_kernel::slab_allocator::HEAP_START as u64));
+    assert!(first.map_page(heap_page, first_frame, flags, frame_allocator).is_err());
+    
+    // Write through each address space, then read back through the first
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    let kernel_frame = memory::active_page_table_frame();
+    let value = interrupts::without_interrupts(|| unsafe {
+        first.activate();
+        ptr.write_volatile(1);
+        second.activate();
+        ptr.write_volatile(2);
+        first.activate();
+        let value = ptr.read_volatile();
+        memory::switch_page_table(kernel_frame);
+        value
+    });
+    assert_eq!(value, 1);
+    
+    // Dropping would lock the frame allocator held here
+    first.free(frame_allocator);
+    second.free(frame_allocator);
+}
+
+#[test_case]
+fn test_dropped_address_spaces_free_their_frames() {
+    use memory::AddressSpace;
+    use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+    
+    let page: Page = Page::containing_address(VirtAddr::new(0x_5000_0000_0000));
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let allocated = || memory::FRAME_ALLOCATOR.lock().as_ref().unwrap().allocated_count();
+    let before = allocated();
+    
+    let (parent, child) = {
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().unwrap();
+        let mut parent = AddressSpace::new(frame_allocator).expect("address space");
+        let frame = frame_allocator.allocate_frame().unwrap();
+        unsafe { *(memory::physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr::<u64>() = 7 };
+        parent.map_page(page, frame, flags, frame_allocator).expect("map failed");
+        let child = parent.fork(frame_allocator).expect("fork failed");
+        (parent, child)
+    };
+    
+    // The child still maps the shared frame, so it outlives the parent
+    let (frame, _) = child.translate_page(page).expect("page not mapped");
+    drop(parent);
+    let value = unsafe { *(memory::physical_memory_offset() + frame.start_address().as_u64()).as_ptr::<u64>() };
+    assert_eq!(value, 7);
+    assert!(allocated() > before);
+    
+    // With the last one gone, the frame and every page table are back
+    drop(child);
+    assert_eq!(allocated(), before);
+}
+
+#[test_case]
+fn test_batched_range_flushes_once() {
+    use x86_64::structures::paging::{Page, PageTableFlags};
+    
+    let first: Page = Page::containing_address(VirtAddr::new(0x_5555_1000_0000));
+    let range = Page::range_inclusive(first, first + 255);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    
+    // One page at a time stays under the threshold: one flush per page
+    let before = memory::tlb_flush_count();
+    for page in range {
+        memory::map_range(mapper, frame_allocator, Page::range_inclusive(page, page), flags)
+            .expect("map failed");
+    }
+    let per_page = memory::tlb_flush_count() - before;
+    
+    memory::unmap_range(mapper, range).expect("unmap failed");
+    
+    // The whole range at once is flushed with a single flush_all
+    let before = memory::tlb_flush_count();
+    memory::map_range(mapper, frame_allocator, range, flags).expect("map failed");
+    let batched = memory::tlb_flush_count() - before;
+    
+    assert_eq!(per_page, 256);
+    assert_eq!(batched, 1);
+    
+    // The batched mapping is usable
+    let ptr: *mut u64 = (first + 255).start_address().as_mut_ptr();
+    unsafe { ptr.write_volatile(42) };
+    assert_eq!(unsafe { ptr.read_volatile() }, 42);
+    memory::unmap_range(mapper, range).expect("unmap failed");
+}
+
+#[test_case]
+fn test_map_range_to_frames() {
+    use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags, PhysFrame, Translate};
+    
+    let first: Page = Page::containing_address(VirtAddr::new(0x_5555_3000_0000));
+    let range = Page::range_inclusive(first, first + 3);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    
+    // Four known frames, handed over in reverse order
+    let mut frames: [PhysFrame; 4] = core::array::from_fn(|_| frame_allocator.allocate_frame().expect("out of frames"));
+    frames.reverse();
+    
+    // Mismatched lengths map nothing
+    assert!(memory::map_range_to_frames(mapper, frame_allocator, range, frames[..3].iter().copied(), flags).is_err());
+    assert!(mapper.translate_addr(first.start_address()).is_none());
+    
+    memory::map_range_to_frames(mapper, frame_allocator, range, frames.iter().copied(), flags)
+        .expect("map failed");
+    for (page, frame) in range.zip(frames) {
+        assert_eq!(mapper.translate_addr(page.start_address()), Some(frame.start_address()));
+    }
+    
+    memory::unmap_range(mapper, range).expect("unmap failed");
+}
+
+#[test_case]
+fn test_zeroing_allocator_clears_reused_frame() {
+    use rust_kernel::memory::frame_allocator::{BitmapFrameAllocator, ZeroingFrameAllocator};
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator};
+    
+    // Hand a single frame from the kernel's allocator to a private bitmap allocator
+    let frame = memory::FRAME_ALLOCATOR.lock().as_mut().unwrap()
+        .allocate_frame().expect("out of frames");
+    let frame_number = (frame.start_address().as_u64() / 4096) as usize;
+    let bitmap = alloc::boxed::Box::leak(alloc::vec![0u8; 1].into_boxed_slice());
+    let mut allocator = unsafe { BitmapFrameAllocator::new(bitmap, frame_number, 1) };
+    let ptr: *mut u8 = (memory::physical_memory_offset() + frame.start_address().as_u64()).as_mut_ptr();
+    
+    // Leave a pattern behind in the frame, then free it
+    let dirty = allocator.allocate_frame().expect("frame not available");
+    assert_eq!(dirty, frame);
+    unsafe {
+        core::ptr::write_bytes(ptr, 0xAB, 4096);
+        allocator.deallocate_frame(dirty);
+    }
+    
+    // Reallocated through the zeroing wrapper, none of it is visible
+    let mut zeroing = unsafe { ZeroingFrameAllocator::new(allocator, memory::physical_memory_offset()) };
+    let clean = zeroing.allocate_frame().expect("frame not available");
+    assert_eq!(clean, frame);
+    let contents = unsafe { core::slice::from_raw_parts(ptr, 4096) };
+    assert!(contents.iter().all(|&b| b == 0));
+    
+    // With zeroing off the previous contents survive
+    unsafe {
+        core::ptr::write_bytes(ptr, 0xCD, 4096);
+        zeroing.deallocate_frame(clean);
+    }
+    zeroing.set_zeroing(false);
+    zeroing.allocate_frame().expect("frame not available");
+    assert_eq!(unsafe { ptr.read_volatile() }, 0xCD);
+}
+
+#[test_case]
+fn test_kernel_allocator_takes_back_any_frame() {
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    let allocated = frame_allocator.allocated_count();
+    let first = frame_allocator.allocate_frame().expect("out of frames");
+    let second = frame_allocator.allocate_frame().expect("out of frames");
+    
+    // Not the most recent frame, which a bump allocator would leak
+    unsafe { frame_allocator.deallocate_frame(first) };
+    assert_eq!(frame_allocator.allocated_count(), allocated + 1);
+    assert_eq!(frame_allocator.allocate_frame(), Some(first));
+    
+    unsafe {
+        frame_allocator.deallocate_frame(first);
+        frame_allocator.deallocate_frame(second);
+    }
+    assert_eq!(frame_allocator.allocated_count(), allocated);
+}
+
+// Hands out at most `left` frames from the kernel's allocator, to force a
+// failure part way through mapping
+struct Capped<'a> {
+    inner: &'a mut BitmapFrameAllocator,
+    left: usize,
+}
+
+unsafe impl FrameAllocator<Size4KiB> for Capped<'_> {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        self.left = self.left.checked_sub(1)?;
+        self.inner.allocate_frame()
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for Capped<'_> {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+        unsafe { self.inner.deallocate_frame(frame) };
+    }
+}
+
+#[test_case]
+fn test_failed_init_heap_leaves_nothing_mapped() {
+    use rust_kernel::memory::frame_allocator::EmptyFrameAllocator;
+    use rust_kernel::slab_allocator;
+    use x86_64::structures::paging::Translate;
+    
+    let heap_start = VirtAddr::new(0x_5555_2000_0000);
+    let heap_size = 16 * 4096;
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    let allocated = frame_allocator.allocated_count();
+    
+    // Runs out of frames after a few pages (some go to new page tables)
+    let mut capped = Capped { inner: frame_allocator, left: 8 };
+    let result = slab_allocator::init_heap(mapper, &mut capped, heap_start, heap_size);
+    assert!(result.is_err());
+    
+    // Every frame came back, the new page tables' included
+    assert_eq!(frame_allocator.allocated_count(), allocated);
+    
+    // No frames at all fails on the first page
+    let result = slab_allocator::init_heap(mapper, &mut EmptyFrameAllocator, heap_start, heap_size);
+    assert!(result.is_err());
+    
+    // The helper `translate` would deadlock on the mapper we hold
+    for i in 0..16u64 {
+        assert!(mapper.translate_addr(heap_start + i * 4096).is_none());
+    }
+}
+
+#[test_case]
+fn test_failed_mmap_frees_every_frame() {
+    use x86_64::structures::paging::PageTableFlags;
+    
+    let mut mapper = memory::MAPPER.lock();
+    let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+    let mapper = mapper.as_mut().unwrap();
+    let frame_allocator = frame_allocator.as_mut().unwrap();
+    let allocated = frame_allocator.allocated_count();
+    let start = memory::reserve(mapper, 1024).expect("reserve failed");
+    memory::release(start);
+    
+    // Enough pages to cross into a page table of their own
+    let mut capped = Capped { inner: frame_allocator, left: 600 };
+    let result = memory::mmap(mapper, &mut capped, 1024, PageTableFlags::empty());
+    assert!(result.is_err());
+    assert_eq!(frame_allocator.allocated_count(), allocated);
+    
+    // The address range was released again
+    let again = memory::reserve(mapper, 1024).expect("reserve failed");
+    assert_eq!(again, start);
+    memory::release(again);
+}
+
+#[test_case]
+fn test_walk_page_tables_finds_heap() {
+    use x86_64::structures::paging::PageTableFlags;
+    
+    let heap = VirtAddr::new(rust_kernel::slab_allocator::HEAP_START as u64);
+    let offset = memory::physical_memory_offset();
+    
+    // One level deep only reaches the PML4, which maps the heap's region
+    let mut heap_pml4_entry = None;
+    memory::walk_page_tables(offset, 1, |entry| {
+        assert_eq!(entry.level, 4);
+        if entry.start.p4_index() == heap.p4_index() {
+            heap_pml4_entry = Some(entry.flags);
+        }
+    });
+    let flags = heap_pml4_entry.expect("heap's PML4 entry is not present");
+    assert!(flags.contains(PageTableFlags::PRESENT));
+    
+    // The full walk reaches the heap's first page
+    let mut heap_page = None;
+    memory::walk_page_tables(offset, 4, |entry| {
+        if entry.level == 1 && entry.start == heap {
+            heap_page = Some(entry.phys);
+        }
+    });
+    assert_eq!(heap_page, translate(heap));
+    
+    memory::dump_page_tables(offset, 2);
+}
@@ -0,0 +1,59 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::backtrace;
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("panic_backtrace::nested_panic_has_backtrace...\t");
+    outer();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+#[inline(never)]
+fn outer() {
+    middle();
+}
+
+#[inline(never)]
+fn middle() {
+    inner();
+}
+
+#[inline(never)]
+fn inner() {
+    panic!("nested panic");
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    backtrace::unwind();
+
+    // Collect the return addresses and count the distinct ones
+    let mut addresses = [0u64; backtrace::MAX_DEPTH];
+    let depth = backtrace::walk(|i, addr| addresses[i] = addr);
+
+    let mut distinct = 0;
+    for i in 0..depth {
+        if !addresses[..i].contains(&addresses[i]) {
+            distinct += 1;
+        }
+    }
+
+    // panic handler <- inner <- middle <- outer <- main
+    if distinct >= 3 {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: only {} distinct return addresses", distinct);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_kernel::hlt_loop();
+}
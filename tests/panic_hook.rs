@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use rust_kernel::panic as kpanic;
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+// Set by the hook when it runs
+static HOOK_RAN: AtomicBool = AtomicBool::new(false);
+
+fn hook(_info: &PanicInfo) {
+    HOOK_RAN.store(true, Ordering::SeqCst);
+}
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("panic_hook::hook_runs_on_panic...\t");
+    kpanic::set_hook(hook);
+    
+    panic!("intentional panic");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // Same order as the kernel's panic handler: hook first, then the report
+    kpanic::run_hook(info);
+    
+    if HOOK_RAN.load(Ordering::SeqCst) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_kernel::hlt_loop();
+}
@@ -0,0 +1,41 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::panic::{self as kpanic, RegisterDump, StackBuffer};
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("panic_registers::should_panic_dumps_registers...\t");
+    should_panic_dumps_registers();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+fn should_panic_dumps_registers() {
+    panic!("intentional panic");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let regs = RegisterDump::capture();
+
+    // Format the report the same way the kernel panic path does
+    let mut report = StackBuffer::<1024>::new();
+    let _ = kpanic::write_report(&mut report, info, &regs);
+    kpanic::emergency_write(report.as_bytes());
+
+    let text = report.as_str();
+    if text.contains("intentional panic") && text.contains("RIP=0x") && text.contains("RSP=0x") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_kernel::hlt_loop();
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::vga_buffer::{self, WRITER};
+use rust_kernel::{exit_qemu, println, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+// Text of the row above the cursor, read straight from VGA memory
+fn screen_row(row: usize) -> [u8; 80] {
+    let buffer = 0xb8000 as *const u16;
+    let mut text = [0u8; 80];
+    for (col, byte) in text.iter_mut().enumerate() {
+        *byte = unsafe { buffer.add(row * 80 + col).read_volatile() } as u8;
+    }
+    text
+}
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("panic_vga_lock::panic_while_writer_locked...\t");
+    
+    // Hold the screen lock across the panic, as a fault in write_byte would
+    let _guard = WRITER.lock();
+    panic!("screen locked");
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    unsafe { vga_buffer::force_unlock() };
+    println!("PANIC: {}", info.message());
+    vga_buffer::emergency_print("EMERGENCY");
+    
+    // println! ended its line, so its text is now two rows up
+    let text = screen_row(23);
+    let emergency = screen_row(24);
+    if text.starts_with(b"PANIC: screen locked") && emergency.starts_with(b"EMERGENCY") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_kernel::hlt_loop();
+}
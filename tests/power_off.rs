@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::{exit_qemu, power, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+// Passes on QEMU's own exit once the machine is off. Nothing runs after a
+// poweroff, so the shutdown is only confirmed by QEMU exiting and no "[ok]" is
+// printed; a machine still running afterwards reports the failure.
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    serial_print!("power_off::shutdown_exits...\t");
+    power::try_shutdown();
+    
+    serial_println!("[failed]\n");
+    serial_println!("Error: still running after poweroff\n");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
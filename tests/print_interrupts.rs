@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rust_kernel::{interrupts, klog, println, serial_println};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+static TICKS_LOGGED: AtomicUsize = AtomicUsize::new(0);
+
+fn log_from_timer() {
+    println!("timer tick");
+    serial_println!("timer tick");
+    TICKS_LOGGED.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn test_print_from_timer_interrupt() {
+    assert!(x86_64::instructions::interrupts::are_enabled());
+    
+    let start = klog::latest_seq();
+    interrupts::set_timer_callback(Some(log_from_timer));
+    
+    // Keep both locks busy until the handler has printed a few times
+    let mut line = 0;
+    while TICKS_LOGGED.load(Ordering::SeqCst) < 5 {
+        println!("main line {}", line);
+        serial_println!("main line {}", line);
+        line += 1;
+    }
+    interrupts::set_timer_callback(None);
+    
+    // Every line is whole: nothing printed in the middle of another line
+    let lines = klog::since(start);
+    assert!(lines.iter().any(|entry| entry.text() == "timer tick"));
+    for entry in lines.iter() {
+        let text = entry.text();
+        assert!(text == "timer tick" || text.starts_with("main line "), "garbled line {:?}", text);
+    }
+}
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::{klog, shell};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_free_prints_heap_stats() {
+    let start = klog::latest_seq();
+    
+    shell::execute("free").unwrap();
+    
+    // The heap-stats report is written through println, so it lands in the log
+    let lines = klog::since(start);
+    assert!(lines.iter().any(|line| line.text().starts_with("Slab size")));
+}
+
+#[test_case]
+fn test_unknown_command_is_rejected() {
+    assert_eq!(shell::execute("frobnicate"), Err("Unknown command"));
+}
@@ -0,0 +1,43 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::panic::StackBuffer;
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use core::fmt::Write;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_overflow::deep_recursion_is_caught...\t");
+    rust_kernel::init(boot_info);
+    
+    recurse(0);
+    
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+#[allow(unconditional_recursion)]
+fn recurse(depth: u64) -> u64 {
+    // Keep the frame alive so the recursion isn't turned into a loop
+    core::hint::black_box(recurse(core::hint::black_box(depth) + 1)) + 1
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let mut message = StackBuffer::<256>::new();
+    let _ = write!(message, "{}", info.message());
+    
+    if message.as_str().starts_with("kernel stack overflow") {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: {}", info);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    rust_kernel::hlt_loop();
+}
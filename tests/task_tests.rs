@@ -0,0 +1,448 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use rust_kernel::{println, task, time};
+use core::panic::PanicInfo;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initialize the kernel
+    rust_kernel::init(boot_info);
+    
+    println!("Running task tests...");
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_yield_for_waits_deadline() {
+    // yield_for must not return before the requested time has passed
+    let start = time::monotonic_ns();
+    task::yield_for(1_000_000);
+    let elapsed = time::monotonic_ns() - start;
+    
+    assert!(elapsed >= 1_000_000, "yield_for returned after {} ns", elapsed);
+}
+
+#[test_case]
+fn test_yield_until_stops_at_condition_or_timeout() {
+    let mut polls = 0;
+    assert!(task::yield_until(|| { polls += 1; polls == 3 }, 1_000_000_000));
+    assert_eq!(polls, 3);
+    
+    // A condition that never holds gives up once the timeout has passed
+    let start = time::monotonic_ns();
+    assert!(!task::yield_until(|| false, 1_000_000));
+    assert!(time::monotonic_ns() - start >= 1_000_000);
+}
+
+fn dummy_task() -> ! {
+    loop {
+        task::yield_task();
+    }
+}
+
+#[test_case]
+fn test_fcfs_runs_task_until_it_blocks() {
+    use task::scheduler::Scheduler;
+    use task::{SchedulePolicy, Task, TaskState};
+    
+    let mut scheduler = Scheduler::new();
+    scheduler.set_policy(SchedulePolicy::Fcfs);
+    
+    let first = Task::new("first", dummy_task, 4096);
+    let second = Task::new("second", dummy_task, 4096);
+    let (first_id, second_id) = (first.id, second.id);
+    scheduler.add_task(first);
+    scheduler.add_task(second);
+    
+    // The first arrival keeps the CPU across reschedules
+    assert_eq!(scheduler.next_task().unwrap().id, first_id);
+    scheduler.set_task_state(first_id, TaskState::Running);
+    assert_eq!(scheduler.next_task().unwrap().id, first_id);
+    assert_eq!(scheduler.next_task().unwrap().id, first_id);
+    
+    // Only once it blocks does the next task get scheduled
+    scheduler.set_task_state(first_id, TaskState::Blocked);
+    assert_eq!(scheduler.next_task().unwrap().id, second_id);
+}
+
+#[test_case]
+fn test_round_robin_is_default() {
+    use task::scheduler::Scheduler;
+    use task::{SchedulePolicy, Task};
+    
+    let mut scheduler = Scheduler::new();
+    assert_eq!(scheduler.policy(), SchedulePolicy::RoundRobin);
+    
+    let first = Task::new("first", dummy_task, 4096);
+    let second = Task::new("second", dummy_task, 4096);
+    let (first_id, second_id) = (first.id, second.id);
+    scheduler.add_task(first);
+    scheduler.add_task(second);
+    
+    assert_eq!(scheduler.next_task().unwrap().id, first_id);
+    assert_eq!(scheduler.next_task().unwrap().id, second_id);
+    assert_eq!(scheduler.next_task().unwrap().id, first_id);
+}
+
+#[test_case]
+fn test_hand_off_skips_to_target() {
+    use task::scheduler::Scheduler;
+    use task::{Task, TaskState};
+    
+    let mut scheduler = Scheduler::new();
+    let a = Task::new("a", dummy_task, 4096);
+    let b = Task::new("b", dummy_task, 4096);
+    let c = Task::new("c", dummy_task, 4096);
+    let (a_id, b_id, c_id) = (a.id, b.id, c.id);
+    scheduler.add_task(a);
+    scheduler.add_task(b);
+    scheduler.add_task(c);
+    
+    // A hands off to C, skipping B
+    assert_eq!(scheduler.next_task().unwrap().id, a_id);
+    scheduler.hand_off(c_id);
+    assert_eq!(scheduler.next_task().unwrap().id, c_id);
+    
+    // The handoff only lasts one schedule
+    assert_eq!(scheduler.next_task().unwrap().id, a_id);
+    
+    // A target that isn't ready falls back to round-robin
+    scheduler.set_task_state(c_id, TaskState::Blocked);
+    scheduler.hand_off(c_id);
+    assert_eq!(scheduler.next_task().unwrap().id, b_id);
+}
+
+#[test_case]
+fn test_list_reports_spawned_tasks() {
+    use task::TaskState;
+    
+    task::spawn("list_a", dummy_task);
+    task::spawn("list_b", dummy_task);
+    task::spawn("list_c", dummy_task);
+    
+    let tasks = task::list();
+    for name in ["list_a", "list_b", "list_c"] {
+        let info = tasks.iter().find(|info| info.name == name).expect("task missing from list");
+        assert_eq!(info.state, TaskState::Ready);
+        assert_eq!(info.stack_size, 4096);
+    }
+}
+
+// Values seen by the parent (0) and child (1) after forking
+static FORK_RESULTS: Mutex<[Option<u64>; 2]> = Mutex::new([None, None]);
+
+fn forking_task() -> ! {
+    let mut shared: u64 = 1;
+    
+    let child_id = task::fork().expect("fork failed");
+    
+    // Each branch updates its own copy of the stack variable
+    if child_id == 0 {
+        shared += 100;
+        FORK_RESULTS.lock()[1] = Some(shared);
+    } else {
+        shared += 200;
+        FORK_RESULTS.lock()[0] = Some(shared);
+    }
+    task::exit()
+}
+
+#[test_case]
+fn test_fork_isolates_stack_writes() {
+    task::spawn("forker", forking_task);
+    
+    // Let the forker and its child run
+    task::yield_until(|| FORK_RESULTS.lock().iter().all(Option::is_some), 1_000_000_000);
+    
+    let results = *FORK_RESULTS.lock();
+    assert_eq!(results, [Some(201), Some(101)]);
+}
+
+// What the parent (0) and child (1) read from their local after writing
+// through a pointer to it taken before the fork
+static LOCAL_POINTER_RESULTS: Mutex<[Option<u64>; 2]> = Mutex::new([None, None]);
+
+fn local_pointer_task() -> ! {
+    let mut local: u64 = 1;
+    let pointer = core::hint::black_box(&mut local as *mut u64);
+    
+    let child_id = task::fork().expect("fork failed");
+    let (slot, value) = if child_id == 0 { (1, 10) } else { (0, 20) };
+    
+    // The child's copy of the pointer must name the child's copy of the local
+    unsafe { pointer.write_volatile(value) };
+    task::yield_task();
+    LOCAL_POINTER_RESULTS.lock()[slot] = Some(unsafe { core::ptr::read_volatile(&local) });
+    task::exit()
+}
+
+#[test_case]
+fn test_fork_relocates_pointers_to_locals() {
+    task::spawn("pointer_forker", local_pointer_task);
+    
+    task::yield_until(|| LOCAL_POINTER_RESULTS.lock().iter().all(Option::is_some), 1_000_000_000);
+    assert_eq!(*LOCAL_POINTER_RESULTS.lock(), [Some(20), Some(10)]);
+}
+
+// An integer the parent (0) and child (1) kept on their stacks, and the
+// address of their copy of the local it was taken from
+static STACK_INTEGER_RESULTS: Mutex<[Option<(u64, u64)>; 2]> = Mutex::new([None, None]);
+
+fn stack_integer_task() -> ! {
+    let local: u64 = 0;
+    let number = core::hint::black_box(&local as *const u64 as u64);
+    
+    let child_id = task::fork().expect("fork failed");
+    let slot = if child_id == 0 { 1 } else { 0 };
+    STACK_INTEGER_RESULTS.lock()[slot] = Some((number, &local as *const u64 as u64));
+    task::exit()
+}
+
+#[test_case]
+fn test_fork_relocates_integers_in_stack_range() {
+    task::spawn("integer_forker", stack_integer_task);
+    
+    task::yield_until(|| STACK_INTEGER_RESULTS.lock().iter().all(Option::is_some), 1_000_000_000);
+    let [parent, child] = *STACK_INTEGER_RESULTS.lock();
+    let (parent, child) = (parent.unwrap(), child.unwrap());
+    
+    // Only a guess at what is a pointer: the child's copy of a plain number
+    // with a parent stack address as its value was moved like one
+    assert_eq!(parent.0, parent.1);
+    assert_eq!(child.0, child.1);
+    assert_ne!(parent.0, child.0);
+}
+
+// A page private to the copy-on-write forker's address space
+const PRIVATE_PAGE: u64 = 0x_7777_4000_0000;
+
+// What the parent (0) and child (1) read from the private page in the end
+static COW_RESULTS: Mutex<[Option<u64>; 2]> = Mutex::new([None, None]);
+
+fn cow_forking_task() -> ! {
+    let value = PRIVATE_PAGE as *mut u64;
+    unsafe { value.write_volatile(1) };
+    
+    let child_id = task::fork().expect("fork failed");
+    if child_id == 0 {
+        // Faults on the shared page and gets a copy of its own
+        unsafe { value.write_volatile(2) };
+        COW_RESULTS.lock()[1] = Some(unsafe { value.read_volatile() });
+    } else {
+        // Look only once the child has written
+        task::yield_until(|| COW_RESULTS.lock()[1].is_some(), 1_000_000_000);
+        COW_RESULTS.lock()[0] = Some(unsafe { value.read_volatile() });
+    }
+    task::exit()
+}
+
+#[test_case]
+fn test_fork_copies_private_pages_on_write() {
+    use alloc::sync::Arc;
+    use rust_kernel::memory::{self, AddressSpace};
+    use task::Task;
+    use x86_64::structures::paging::{FrameAllocator, Page, PageTableFlags};
+    use x86_64::VirtAddr;
+    
+    // The task's stack is mapped before the address space shares the kernel's slots
+    let mut forker = Task::new("cow_forker", cow_forking_task, 4096);
+    let space = {
+        let mut frame_allocator = memory::FRAME_ALLOCATOR.lock();
+        let frame_allocator = frame_allocator.as_mut().unwrap();
+        let mut space = AddressSpace::new(frame_allocator).expect("address space");
+        let frame = frame_allocator.allocate_frame().expect("no frame");
+        let page = Page::containing_address(VirtAddr::new(PRIVATE_PAGE));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        space.map_page(page, frame, flags, frame_allocator).expect("map failed");
+        space
+    };
+    forker.set_address_space(Arc::new(space));
+    task::scheduler::SCHEDULER.lock().add_task(forker);
+    
+    // The child's write stays out of the parent's page
+    task::yield_until(|| COW_RESULTS.lock().iter().all(Option::is_some), 1_000_000_000);
+    assert_eq!(*COW_RESULTS.lock(), [Some(1), Some(2)]);
+}
+
+// Channel ends handed to the producer and consumer tasks
+static PRODUCER_END: Mutex<Option<task::Sender<u32>>> = Mutex::new(None);
+static CONSUMER_END: Mutex<Option<task::Receiver<u32>>> = Mutex::new(None);
+static RECEIVED: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+static CONSUMER_DONE: AtomicBool = AtomicBool::new(false);
+
+fn producer_task() -> ! {
+    let sender = PRODUCER_END.lock().take().unwrap();
+    for i in 0..100 {
+        sender.send(i).expect("receiver dropped");
+    }
+    drop(sender);
+    task::exit()
+}
+
+fn consumer_task() -> ! {
+    let receiver = CONSUMER_END.lock().take().unwrap();
+    while let Some(value) = receiver.recv() {
+        RECEIVED.lock().push(value);
+    }
+    CONSUMER_DONE.store(true, Ordering::SeqCst);
+    task::exit()
+}
+
+#[test_case]
+fn test_channel_delivers_in_order() {
+    // A small capacity makes the producer block on a full channel
+    let (sender, receiver) = task::channel_with_capacity(4);
+    *PRODUCER_END.lock() = Some(sender);
+    *CONSUMER_END.lock() = Some(receiver);
+    
+    task::spawn("producer", producer_task);
+    task::spawn("consumer", consumer_task);
+    
+    task::yield_until(|| CONSUMER_DONE.load(Ordering::SeqCst), 1_000_000_000);
+    
+    let received = RECEIVED.lock();
+    assert_eq!(received.len(), 100);
+    assert!(received.iter().copied().eq(0..100));
+}
+
+// Iterations each busy task got through, and the signal for them to stop
+static BUSY_COUNTS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+static BUSY_STOP: AtomicBool = AtomicBool::new(false);
+
+// Spins without ever yielding explicitly, relying on checkpoint
+fn busy_loop(slot: usize) -> ! {
+    while !BUSY_STOP.load(Ordering::SeqCst) {
+        BUSY_COUNTS[slot].fetch_add(1, Ordering::SeqCst);
+        task::checkpoint();
+    }
+    
+    // Leave the run queue for the remaining tests
+    loop {
+        task::scheduler::block_current_task();
+    }
+}
+
+fn busy_task_a() -> ! {
+    busy_loop(0)
+}
+
+fn busy_task_b() -> ! {
+    busy_loop(1)
+}
+
+#[test_case]
+fn test_checkpoint_shares_cpu() {
+    task::spawn("busy_a", busy_task_a);
+    task::spawn("busy_b", busy_task_b);
+    
+    // Each busy task only gives up the CPU once its quantum is used up
+    let end = time::ticks() + 10 * task::scheduler::quantum();
+    while time::ticks() < end {
+        task::yield_task();
+    }
+    BUSY_STOP.store(true, Ordering::SeqCst);
+    
+    let a = BUSY_COUNTS[0].load(Ordering::SeqCst);
+    let b = BUSY_COUNTS[1].load(Ordering::SeqCst);
+    assert!(a > 0 && b > 0, "busy tasks made {} and {} iterations", a, b);
+    
+    // Let them see the stop flag and block
+    task::yield_task();
+    task::yield_task();
+}
+
+// What the stack task read back after resuming, None until it has
+static SURVIVED: Mutex<Option<u64>> = Mutex::new(None);
+
+fn stack_value_task() -> ! {
+    // Written to the task's stack, then read back once it is switched back in
+    let mut value = 0u64;
+    unsafe { core::ptr::write_volatile(&mut value, 0xC0FF_EE00_D15C_0123) };
+    task::yield_task();
+    task::yield_task();
+    *SURVIVED.lock() = Some(unsafe { core::ptr::read_volatile(&value) });
+    
+    loop {
+        task::scheduler::block_current_task();
+    }
+}
+
+#[test_case]
+fn test_stack_value_survives_switch() {
+    task::spawn("stack_value", stack_value_task);
+    
+    task::yield_until(|| SURVIVED.lock().is_some(), 1_000_000_000);
+    
+    assert_eq!(*SURVIVED.lock(), Some(0xC0FF_EE00_D15C_0123));
+}
+
+#[allow(unconditional_recursion)]
+fn recurse(depth: u64) -> u64 {
+    // Keep the frame alive so the recursion isn't turned into a loop
+    core::hint::black_box(recurse(core::hint::black_box(depth) + 1)) + 1
+}
+
+fn overflowing_task() -> ! {
+    recurse(0);
+    task::exit()
+}
+
+#[test_case]
+fn test_task_stack_overflow_terminates_task() {
+    use task::TaskState;
+    
+    task::spawn("overflower", overflowing_task);
+    
+    let overflower_state = || {
+        task::scheduler::SCHEDULER.lock().task_info().into_iter()
+            .find(|info| info.name == "overflower")
+            .map(|info| info.state)
+    };
+    
+    task::yield_until(|| overflower_state() == Some(TaskState::Terminated), 1_000_000_000);
+    
+    // Only the task died, the kernel carries on scheduling
+    assert_eq!(overflower_state(), Some(TaskState::Terminated));
+    task::yield_for(1_000_000);
+}
+
+static EXITED: AtomicU64 = AtomicU64::new(0);
+
+fn exiting_task() -> ! {
+    EXITED.fetch_add(1, Ordering::SeqCst);
+    task::exit()
+}
+
+#[test_case]
+fn test_exited_task_ids_are_recycled() {
+    let active = task::active_count();
+    let slots = task::pid::slots();
+    
+    for round in 1..=50 {
+        task::spawn("exiter", exiting_task);
+        task::yield_until(|| EXITED.load(Ordering::SeqCst) == round, 1_000_000_000);
+        assert_eq!(EXITED.load(Ordering::SeqCst), round);
+    }
+    
+    // Each new task takes the slot and ID of one that exited
+    assert_eq!(task::active_count(), active);
+    assert!(task::pid::slots() <= slots + 16, "ID pool grew to {}", task::pid::slots());
+}
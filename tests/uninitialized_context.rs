@@ -0,0 +1,38 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::task::context::TaskContext;
+use rust_kernel::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(_boot_info: &'static BootInfo) -> ! {
+    serial_print!("uninitialized_context::switch_panics...\t");
+    
+    // Release builds skip the check and would triple-fault
+    if !cfg!(debug_assertions) {
+        serial_println!("[ok] (skipped, no debug assertions)");
+        exit_qemu(QemuExitCode::Success);
+        rust_kernel::hlt_loop();
+    }
+    
+    let mut current = TaskContext::default();
+    let next = TaskContext::default();
+    unsafe {
+        TaskContext::switch(&mut current, &next);
+    }
+    
+    serial_println!("[failed]");
+    serial_println!("Error: switched into an uninitialized context");
+    exit_qemu(QemuExitCode::Failed);
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    rust_kernel::hlt_loop();
+}
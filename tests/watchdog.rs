@@ -0,0 +1,61 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use rust_kernel::task::{self, watchdog};
+use rust_kernel::time;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    rust_kernel::init(boot_info);
+    
+    test_main();
+    
+    rust_kernel::hlt_loop();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_kernel::test_panic_handler(info)
+}
+
+#[test_case]
+fn test_watchdog_reports_spinning_task() {
+    watchdog::set_threshold(3);
+    watchdog::feed();
+    
+    // Spin without yielding; there is no preemption to rescue us
+    let start = time::ticks();
+    let deadline = time::monotonic_ns() + 2_000_000_000;
+    while watchdog::stuck_task().is_none() && time::monotonic_ns() < deadline {
+        core::hint::spin_loop();
+    }
+    
+    assert_eq!(watchdog::stuck_task(), Some(task::current_task_id()));
+    assert!(time::ticks() - start >= 3);
+    
+    // Feeding the watchdog clears the report
+    watchdog::feed();
+    assert_eq!(watchdog::stuck_task(), None);
+    watchdog::set_threshold(watchdog::DEFAULT_WATCHDOG_TICKS);
+}
+
+#[test_case]
+fn test_watchdog_disabled() {
+    watchdog::set_threshold(0);
+    watchdog::feed();
+    
+    let start = time::ticks();
+    while time::ticks() - start < 5 {
+        core::hint::spin_loop();
+    }
+    assert_eq!(watchdog::stuck_task(), None);
+    watchdog::set_threshold(watchdog::DEFAULT_WATCHDOG_TICKS);
+}